@@ -1,9 +1,6 @@
-# Run ass
-RUST_BACKTRACE=1 cargo run input.c > output.s && \
-
-# Assemble x86_64 assembly into a binary
-# (ass is _not_ an assembler or linker)
-gcc output.s -o output && \
+# Compile, assemble and link with ass
+# (ass assembles objects itself, but uses the system's cc to link them)
+RUST_BACKTRACE=1 cargo run input.c -o output && \
 
 # Run output
 ./output
@@ -5,8 +5,9 @@ int main () {
   return 1;
 }
 
-// It will not warn you about conditional returns. It doesn't know
-// for sure whether this will be hit
+// It will not warn you about conditional returns, since it doesn't know
+// for sure whether they will be hit. That's also why func needs a return
+// at its end, or ass would refuse to compile it.
 int func () {
   if (0) {
     return 2;
@@ -15,4 +16,5 @@ int func () {
   for (int i = 0; i < 1; i = i + 1) {
     return 3;
   }
+  return 4;
 }
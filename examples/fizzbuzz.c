@@ -1,22 +1,22 @@
 int putchar (int c);
 
-int printFizz () {
+void printFizz (void) {
   putchar(70);
   putchar(105);
   putchar(122);
   putchar(122);
 }
-int printBuzz () {
+void printBuzz (void) {
   putchar(66);
   putchar(117);
   putchar(122);
   putchar(122);
 }
-int printNewLine () {
+void printNewLine (void) {
   putchar(10);
 }
 
-int printNumber (int n) {
+void printNumber (int n) {
   if (n > 9) {
     printNumber(n / 10);
     n = n - n / 10 * 10;
@@ -1,11 +1,11 @@
 int myVar = 3;
 
 // Sets myVar to 4
-int realAssignment () {
+void realAssignment (void) {
   myVar = 4;
 }
 // Creates a local variable called myVar with the value 5
-int shadowedAssignment () {
+void shadowedAssignment (void) {
   int myVar = 5;
 }
 
@@ -1,21 +1,21 @@
 int putchar (int c);
 
-int printFizz () {
+void printFizz (void) {
   putchar(70);
   putchar(105);
   putchar(122);
   putchar(122);
 }
-int printBuzz () {
+void printBuzz (void) {
   putchar(66);
   putchar(117);
   putchar(122);
   putchar(122);
 }
-int printNewLine () {
+void printNewLine (void) {
   putchar(10);
 }
-int printNumber (int n) {
+void printNumber (int n) {
   if (n > 9) {
     printNumber(n / 10);
     n = n - n / 10 * 10;
@@ -23,7 +23,7 @@ int printNumber (int n) {
   putchar(n + 48);
 }
 
-int doFizzBuzz (int n) {
+void doFizzBuzz (int n) {
   int didPrint = 0;
   if (n % 3 == 0) {
     printFizz(); didPrint = 1;
@@ -0,0 +1,394 @@
+// Turns the AT&T assembly Codegen emits into machine code, so that we
+// don't need a system assembler. It understands the instructions and
+// directives ass generates (plus a few common ones for inline asm),
+// not everything GNU as does.
+use std::collections::hash_map::HashMap;
+use crate::codegen::instruction::AsmLine;
+use crate::codegen::initialiser::decoded_string_literal;
+use super::object::*;
+
+// A field in a section that refers to a symbol. Ones we can work out
+// ourselves are filled in at the end, the rest become relocations.
+struct Fixup {
+    section: usize,
+    offset: usize,
+    kind: RelocationKind,
+    symbol: usize,
+    addend: i64
+}
+
+pub struct Assembler {
+    object: ObjectFile,
+    current_section: usize,
+    symbol_indices: HashMap<String, usize>,
+    fixups: Vec<Fixup>
+}
+
+pub fn assemble_source (source: &str) -> ObjectFile {
+    let mut assembler = Assembler::new();
+    for line in source.lines() {
+        for statement in split_statements(line) {
+            assembler.assemble_statement(statement);
+        }
+    }
+    assembler.resolve_fixups();
+    assembler.object
+}
+
+// Strips a # comment and splits "nop; nop" into two statements,
+// leaving anything inside a string alone
+fn split_statements (line: &str) -> Vec<&str> {
+    let mut statements = vec![];
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => {
+                statements.push(&line[start..i]);
+                start = i + 1;
+            },
+            '#' if !in_string => {
+                statements.push(&line[start..i]);
+                start = line.len();
+                break
+            },
+            _ => {}
+        }
+    }
+    statements.push(&line[start..]);
+    statements.into_iter().map(|st| st.trim()).filter(|st| !st.is_empty()).collect()
+}
+
+fn is_symbol_char (c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+// Splits on commas that aren't inside a string
+fn split_arguments (args: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in args.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    if !args[start..].trim().is_empty() {
+        parts.push(args[start..].trim());
+    }
+    parts
+}
+
+pub fn parse_integer (s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s)
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?
+    } as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+// A data directive's value, eg. "5", "label" or "label+16"
+pub enum Expression {
+    Constant(i64),
+    Symbol(String, i64)
+}
+
+pub fn parse_expression (s: &str) -> Expression {
+    if let Some(value) = parse_integer(s) {
+        return Expression::Constant(value)
+    }
+    match s[1..].find(['+', '-']) {
+        Some(idx) => {
+            let (symbol, offset) = s.split_at(idx + 1);
+            let offset = offset.strip_prefix('+').unwrap_or(offset);
+            match parse_integer(offset.trim()) {
+                Some(offset) => Expression::Symbol(symbol.trim().to_string(), offset),
+                None => panic!("The assembler can't evaluate \"{}\"", s)
+            }
+        },
+        None => Expression::Symbol(s.to_string(), 0)
+    }
+}
+
+fn parse_string (s: &str) -> Vec<u8> {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(contents) => decoded_string_literal(contents),
+        None => panic!("Expected a string, got {}", s)
+    }
+}
+
+// The kind of section a name implies, when it isn't given flags
+fn default_section_kind (name: &str) -> SectionKind {
+    if name.starts_with(".text") { SectionKind::Text }
+    else if name.starts_with(".data") { SectionKind::Data }
+    else if name.starts_with(".bss") { SectionKind::Bss }
+    else if name.starts_with(".rodata") { SectionKind::ReadOnly }
+    else { SectionKind::NotLoaded }
+}
+
+// Eg. ".section .rodata" or ".section .note.GNU-stack,"",@progbits"
+fn section_kind (name: &str, flags: Option<&str>, section_type: Option<&str>) -> SectionKind {
+    let flags = match flags {
+        Some(flags) => flags.trim_matches('"'),
+        None => return default_section_kind(name)
+    };
+    if flags.contains('x') { SectionKind::Text }
+    else if flags.contains('w') && section_type == Some("@nobits") { SectionKind::Bss }
+    else if flags.contains('w') { SectionKind::Data }
+    else if flags.contains('a') { SectionKind::ReadOnly }
+    else { SectionKind::NotLoaded }
+}
+
+impl Assembler {
+    fn new () -> Assembler {
+        let mut assembler = Assembler {
+            object: ObjectFile::default(),
+            current_section: 0,
+            symbol_indices: HashMap::new(),
+            fixups: vec![]
+        };
+        assembler.switch_section(".text", SectionKind::Text);
+        assembler
+    }
+
+    fn assemble_statement (&mut self, statement: &str) {
+        // A label can share its line with what follows it
+        let label_end = statement.find(|c| !is_symbol_char(c)).unwrap_or(statement.len());
+        if label_end > 0 && statement[label_end..].starts_with(':') {
+            self.define_label(&statement[..label_end]);
+            let rest = statement[label_end + 1..].trim();
+            if !rest.is_empty() {
+                self.assemble_statement(rest);
+            }
+            return
+        }
+
+        match AsmLine::parse(statement) {
+            AsmLine::Directive(directive) => self.assemble_directive(&directive),
+            AsmLine::Instruction(inst) => self.encode(&inst),
+            _ => panic!("The assembler doesn't understand \"{}\"", statement)
+        }
+    }
+
+    fn assemble_directive (&mut self, directive: &str) {
+        let (name, args) = match directive.find(char::is_whitespace) {
+            Some(idx) => (&directive[..idx], directive[idx..].trim()),
+            None => (directive, "")
+        };
+        let args = split_arguments(args);
+
+        match name {
+            ".text" | ".data" | ".bss" => self.switch_section(name, default_section_kind(name)),
+            ".section" => {
+                let kind = section_kind(args[0], args.get(1).copied(), args.get(2).copied());
+                self.switch_section(args[0], kind)
+            },
+            ".globl" | ".global" => {
+                for symbol in args {
+                    let index = self.symbol_index(symbol);
+                    self.object.symbols[index].global = true;
+                }
+            },
+            ".comm" => self.define_common(&args),
+            ".string" | ".asciz" => {
+                for arg in args {
+                    self.emit_bytes(&parse_string(arg));
+                    self.emit_bytes(&[0]);
+                }
+            },
+            ".ascii" => {
+                for arg in args {
+                    self.emit_bytes(&parse_string(arg));
+                }
+            },
+            ".byte" => self.emit_data(&args, 1),
+            ".short" | ".value" | ".2byte" => self.emit_data(&args, 2),
+            ".long" | ".int" | ".4byte" => self.emit_data(&args, 4),
+            ".quad" | ".8byte" => self.emit_data(&args, 8),
+            ".zero" | ".skip" | ".space" => {
+                let count = self.integer_argument(&args, 0);
+                self.emit_bytes(&vec![0; count as usize]);
+            },
+            ".p2align" => {
+                let power = self.integer_argument(&args, 0);
+                self.align_to(1 << power)
+            },
+            // ELF's .align counts in bytes
+            ".align" | ".balign" => {
+                let bytes = self.integer_argument(&args, 0);
+                self.align_to(bytes as usize)
+            },
+            // Only of interest to debuggers
+            ".type" | ".size" | ".file" | ".ident" => {},
+            _ => panic!("The assembler doesn't support the {} directive", name)
+        }
+    }
+
+    fn integer_argument (&self, args: &[&str], index: usize) -> i64 {
+        match args.get(index).and_then(|arg| parse_integer(arg)) {
+            Some(value) => value,
+            None => panic!("Expected a number, got {:?}", args.get(index))
+        }
+    }
+
+    fn switch_section (&mut self, name: &str, kind: SectionKind) {
+        let existing = self.object.sections.iter().position(|section| section.name == name);
+        self.current_section = match existing {
+            Some(index) => index,
+            None => {
+                self.object.sections.push(Section {
+                    name: name.to_string(),
+                    kind,
+                    data: vec![],
+                    align: 1,
+                    relocations: vec![]
+                });
+                self.object.sections.len() - 1
+            }
+        };
+    }
+
+    pub fn symbol_index (&mut self, name: &str) -> usize {
+        if let Some(index) = self.symbol_indices.get(name) {
+            return *index
+        }
+        self.object.symbols.push(Symbol {
+            name: name.to_string(),
+            global: false,
+            definition: SymbolDefinition::Undefined
+        });
+        let index = self.object.symbols.len() - 1;
+        self.symbol_indices.insert(name.to_string(), index);
+        index
+    }
+
+    fn define_label (&mut self, name: &str) {
+        let index = self.symbol_index(name);
+        let value = self.offset();
+        let symbol = &mut self.object.symbols[index];
+        if symbol.definition != SymbolDefinition::Undefined {
+            panic!("Symbol \"{}\" is already defined", name)
+        }
+        symbol.definition = SymbolDefinition::Defined {
+            section: self.current_section,
+            value
+        };
+    }
+
+    // .comm name, size, alignment in bytes
+    fn define_common (&mut self, args: &[&str]) {
+        let size = self.integer_argument(args, 1) as usize;
+        let align = if args.len() > 2 { self.integer_argument(args, 2) as usize } else { 1 };
+
+        let index = self.symbol_index(args[0]);
+        let symbol = &mut self.object.symbols[index];
+        match symbol.definition {
+            SymbolDefinition::Defined { .. } => {},
+            SymbolDefinition::Common { size: old_size, align: old_align } => {
+                symbol.definition = SymbolDefinition::Common {
+                    size: old_size.max(size),
+                    align: old_align.max(align)
+                };
+            },
+            SymbolDefinition::Undefined => {
+                symbol.definition = SymbolDefinition::Common { size, align };
+                symbol.global = true;
+            }
+        }
+    }
+
+    pub fn offset (&self) -> usize {
+        self.object.sections[self.current_section].data.len()
+    }
+
+    pub fn emit_bytes (&mut self, bytes: &[u8]) {
+        let section = &mut self.object.sections[self.current_section];
+        if section.kind == SectionKind::Bss && bytes.iter().any(|b| *b != 0) {
+            panic!("Only zeroes can go in {}", section.name)
+        }
+        section.data.extend_from_slice(bytes);
+    }
+
+    fn emit_data (&mut self, args: &[&str], width: usize) {
+        for arg in args {
+            match parse_expression(arg) {
+                Expression::Constant(value) => self.emit_bytes(&value.to_le_bytes()[..width]),
+                Expression::Symbol(name, offset) => {
+                    let kind = match width {
+                        8 => RelocationKind::Absolute64,
+                        4 => RelocationKind::Absolute32Signed,
+                        _ => panic!("Addresses don't fit in .byte or .short")
+                    };
+                    let symbol = self.symbol_index(&name);
+                    self.add_fixup(self.offset(), kind, symbol, offset);
+                    self.emit_bytes(&vec![0; width]);
+                }
+            }
+        }
+    }
+
+    fn align_to (&mut self, align: usize) {
+        if align == 0 || !align.is_power_of_two() {
+            panic!("Alignment must be a power of two, not {}", align)
+        }
+        let section = &mut self.object.sections[self.current_section];
+        section.align = section.align.max(align);
+        // Padding in code has to be safe to run through
+        let padding = if section.kind == SectionKind::Text { 0x90 } else { 0 };
+        while !section.data.len().is_multiple_of(align) {
+            section.data.push(padding);
+        }
+    }
+
+    pub fn add_fixup (&mut self, offset: usize, kind: RelocationKind, symbol: usize, addend: i64) {
+        self.fixups.push(Fixup { section: self.current_section, offset, kind, symbol, addend });
+    }
+
+    // Jumps and calls to labels in the same section don't need the linker,
+    // unless they're global, in which case another definition could win
+    fn resolve_fixups (&mut self) {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let symbol = &self.object.symbols[fixup.symbol];
+            let pc_relative = matches!(fixup.kind, RelocationKind::PcRelative32 | RelocationKind::Plt32);
+
+            if let SymbolDefinition::Defined { section, value } = symbol.definition {
+                if pc_relative && section == fixup.section && !symbol.global {
+                    let relative = value as i64 + fixup.addend - fixup.offset as i64;
+                    let field = &mut self.object.sections[section].data[fixup.offset..fixup.offset + 4];
+                    field.copy_from_slice(&(relative as i32).to_le_bytes());
+                    continue
+                }
+            }
+
+            if symbol.definition == SymbolDefinition::Undefined && symbol.is_temporary() {
+                panic!("Undefined label \"{}\"", symbol.name)
+            }
+            // Anything we have to leave to the linker has to be visible to it
+            if symbol.definition == SymbolDefinition::Undefined {
+                self.object.symbols[fixup.symbol].global = true;
+            }
+            self.object.sections[fixup.section].relocations.push(Relocation {
+                offset: fixup.offset,
+                kind: fixup.kind,
+                symbol: fixup.symbol,
+                addend: fixup.addend
+            });
+        }
+    }
+}
@@ -0,0 +1,480 @@
+// Writes an ObjectFile as an ELF64 relocatable object (.o) for x86-64,
+// which any Linux linker can use, and reads them back for ours
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use super::object::*;
+
+pub const ELF_MAGIC: &[u8] = &[0x7f, b'E', b'L', b'F'];
+pub const ELFCLASS64: u8 = 2;
+pub const ELFDATA2LSB: u8 = 1;
+pub const EV_CURRENT: u8 = 1;
+pub const EM_X86_64: u16 = 62;
+pub const ET_REL: u16 = 1;
+
+pub const ELF_HEADER_SIZE: usize = 64;
+pub const SECTION_HEADER_SIZE: usize = 64;
+pub const SYMBOL_SIZE: usize = 24;
+pub const RELA_SIZE: usize = 24;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_INFO_LINK: u64 = 0x40;
+
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_ABS: u16 = 0xfff1;
+pub const SHN_COMMON: u16 = 0xfff2;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_GOTPCREL: u32 = 9;
+pub const R_X86_64_32S: u32 = 11;
+pub const R_X86_64_GOTPCRELX: u32 = 41;
+pub const R_X86_64_REX_GOTPCRELX: u32 = 42;
+
+pub fn push_u16 (out: &mut Vec<u8>, value: u16) { out.extend_from_slice(&value.to_le_bytes()) }
+pub fn push_u32 (out: &mut Vec<u8>, value: u32) { out.extend_from_slice(&value.to_le_bytes()) }
+pub fn push_u64 (out: &mut Vec<u8>, value: u64) { out.extend_from_slice(&value.to_le_bytes()) }
+
+pub fn pad_to (out: &mut Vec<u8>, align: usize) {
+    while !out.len().is_multiple_of(align) {
+        out.push(0);
+    }
+}
+
+// A string table, where names are referred to by their offset
+#[derive(Default)]
+pub struct StringTable {
+    pub data: Vec<u8>
+}
+
+impl StringTable {
+    pub fn new () -> StringTable {
+        // Offset 0 is always the empty string
+        StringTable { data: vec![0] }
+    }
+
+    pub fn add (&mut self, name: &str) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+
+pub struct SectionHeader {
+    pub name: u32,
+    pub section_type: u32,
+    pub flags: u64,
+    pub address: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub align: u64,
+    pub entry_size: u64
+}
+
+impl SectionHeader {
+    pub fn write (&self, out: &mut Vec<u8>) {
+        push_u32(out, self.name);
+        push_u32(out, self.section_type);
+        push_u64(out, self.flags);
+        push_u64(out, self.address);
+        push_u64(out, self.offset);
+        push_u64(out, self.size);
+        push_u32(out, self.link);
+        push_u32(out, self.info);
+        push_u64(out, self.align);
+        push_u64(out, self.entry_size);
+    }
+}
+
+fn section_type_and_flags (kind: SectionKind) -> (u32, u64) {
+    match kind {
+        SectionKind::Text => (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR),
+        SectionKind::Data => (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE),
+        SectionKind::ReadOnly => (SHT_PROGBITS, SHF_ALLOC),
+        SectionKind::Bss => (SHT_NOBITS, SHF_ALLOC | SHF_WRITE),
+        SectionKind::NotLoaded => (SHT_PROGBITS, 0)
+    }
+}
+
+fn relocation_type (kind: RelocationKind) -> u32 {
+    match kind {
+        RelocationKind::Absolute64 => R_X86_64_64,
+        RelocationKind::Absolute32Signed => R_X86_64_32S,
+        RelocationKind::PcRelative32 => R_X86_64_PC32,
+        RelocationKind::Plt32 => R_X86_64_PLT32,
+        RelocationKind::GotPcRelative32 => R_X86_64_GOTPCREL
+    }
+}
+
+struct ElfSymbol {
+    name: u32,
+    info: u8,
+    section: u16,
+    value: u64,
+    size: u64
+}
+
+fn write_symbol (out: &mut Vec<u8>, sym: &ElfSymbol) {
+    push_u32(out, sym.name);
+    out.push(sym.info);
+    // Default visibility
+    out.push(0);
+    push_u16(out, sym.section);
+    push_u64(out, sym.value);
+    push_u64(out, sym.size);
+}
+
+// Section indices in the file are one more than in the ObjectFile,
+// since index 0 is reserved
+fn elf_section_index (section: usize) -> u16 {
+    section as u16 + 1
+}
+
+pub fn write_object (object: &ObjectFile) -> Vec<u8> {
+    let mut strings = StringTable::new();
+    let mut symbols = vec![ElfSymbol { name: 0, info: 0, section: SHN_UNDEF, value: 0, size: 0 }];
+
+    // Relocations against local symbols are made against their section
+    // instead, like other assemblers do, so that only symbols that might
+    // be needed by the linker have to be in the table
+    for index in 0..object.sections.len() {
+        symbols.push(ElfSymbol {
+            name: 0,
+            info: (STB_LOCAL << 4) | STT_SECTION,
+            section: elf_section_index(index),
+            value: 0,
+            size: 0
+        });
+    }
+
+    // GOT entries are made per symbol, so those can't be swapped for a section
+    let through_got: HashSet<usize> = object.sections.iter()
+        .flat_map(|section| &section.relocations)
+        .filter(|reloc| reloc.kind == RelocationKind::GotPcRelative32)
+        .map(|reloc| reloc.symbol)
+        .collect();
+
+    // Locals have to come before globals
+    let mut symbol_indices = HashMap::new();
+    let locals = object.symbols.iter().enumerate()
+        .filter(|(index, sym)| !sym.global && (!sym.is_temporary() || through_got.contains(index)));
+    let globals = object.symbols.iter().enumerate().filter(|(_, sym)| sym.global);
+    let mut first_global = 0;
+    for (index, sym) in locals.chain(globals) {
+        if sym.global && first_global == 0 {
+            first_global = symbols.len();
+        }
+        let binding = if sym.global { STB_GLOBAL } else { STB_LOCAL };
+        let (symbol_type, section, value, size) = match sym.definition {
+            SymbolDefinition::Undefined => (STT_NOTYPE, SHN_UNDEF, 0, 0),
+            SymbolDefinition::Defined { section, value } => {
+                let symbol_type = if object.sections[section].kind == SectionKind::Text { STT_FUNC } else { STT_OBJECT };
+                (symbol_type, elf_section_index(section), value as u64, 0)
+            },
+            // The value of a common symbol is its alignment
+            SymbolDefinition::Common { size, align } => (STT_OBJECT, SHN_COMMON, align as u64, size as u64)
+        };
+        symbol_indices.insert(index, symbols.len());
+        symbols.push(ElfSymbol {
+            name: strings.add(&sym.name),
+            info: (binding << 4) | symbol_type,
+            section,
+            value,
+            size
+        });
+    }
+    if first_global == 0 {
+        first_global = symbols.len();
+    }
+
+    let mut section_names = StringTable::new();
+    let mut headers = vec![SectionHeader {
+        name: 0, section_type: 0, flags: 0, address: 0, offset: 0,
+        size: 0, link: 0, info: 0, align: 0, entry_size: 0
+    }];
+    let mut out = vec![0; ELF_HEADER_SIZE];
+
+    for section in &object.sections {
+        let (section_type, flags) = section_type_and_flags(section.kind);
+        pad_to(&mut out, section.align);
+        let offset = out.len();
+        if section.kind != SectionKind::Bss {
+            out.extend_from_slice(&section.data);
+        }
+        headers.push(SectionHeader {
+            name: section_names.add(&section.name),
+            section_type,
+            flags,
+            address: 0,
+            offset: offset as u64,
+            size: section.data.len() as u64,
+            link: 0,
+            info: 0,
+            align: section.align as u64,
+            entry_size: 0
+        });
+    }
+
+    // The symbol table comes after the sections and their relocations
+    let relocated: Vec<usize> = (0..object.sections.len())
+        .filter(|index| !object.sections[*index].relocations.is_empty()).collect();
+    let symtab_index = object.sections.len() + relocated.len() + 1;
+
+    for index in relocated {
+        let section = &object.sections[index];
+        pad_to(&mut out, 8);
+        let offset = out.len();
+        for reloc in &section.relocations {
+            let target = &object.symbols[reloc.symbol];
+            let (symbol, addend) = match target.definition {
+                SymbolDefinition::Defined { section, value } if !target.global && reloc.kind != RelocationKind::GotPcRelative32 => {
+                    (elf_section_index(section) as u64, reloc.addend + value as i64)
+                },
+                _ => (symbol_indices[&reloc.symbol] as u64, reloc.addend)
+            };
+            push_u64(&mut out, reloc.offset as u64);
+            push_u64(&mut out, (symbol << 32) | relocation_type(reloc.kind) as u64);
+            push_u64(&mut out, addend as u64);
+        }
+        headers.push(SectionHeader {
+            name: section_names.add(&format!(".rela{}", section.name)),
+            section_type: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            address: 0,
+            offset: offset as u64,
+            size: (section.relocations.len() * RELA_SIZE) as u64,
+            link: symtab_index as u32,
+            info: elf_section_index(index) as u32,
+            align: 8,
+            entry_size: RELA_SIZE as u64
+        });
+    }
+
+    pad_to(&mut out, 8);
+    let symtab_offset = out.len();
+    for sym in &symbols {
+        write_symbol(&mut out, sym);
+    }
+    headers.push(SectionHeader {
+        name: section_names.add(".symtab"),
+        section_type: SHT_SYMTAB,
+        flags: 0,
+        address: 0,
+        offset: symtab_offset as u64,
+        size: (symbols.len() * SYMBOL_SIZE) as u64,
+        link: symtab_index as u32 + 1,
+        info: first_global as u32,
+        align: 8,
+        entry_size: SYMBOL_SIZE as u64
+    });
+
+    // .shstrtab holds its own name, so every name is added before it's written
+    let strtab_name = section_names.add(".strtab");
+    let shstrtab_name = section_names.add(".shstrtab");
+    for (name, table) in [(strtab_name, &strings.data), (shstrtab_name, &section_names.data)] {
+        headers.push(SectionHeader {
+            name,
+            section_type: SHT_STRTAB,
+            flags: 0,
+            address: 0,
+            offset: out.len() as u64,
+            size: table.len() as u64,
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0
+        });
+        out.extend_from_slice(table);
+    }
+
+    pad_to(&mut out, 8);
+    let section_headers_offset = out.len();
+    for header in &headers {
+        header.write(&mut out);
+    }
+
+    let mut header = vec![];
+    header.extend_from_slice(ELF_MAGIC);
+    header.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, EV_CURRENT]);
+    header.resize(16, 0);
+    push_u16(&mut header, ET_REL);
+    push_u16(&mut header, EM_X86_64);
+    push_u32(&mut header, EV_CURRENT as u32);
+    // No entry point or program headers
+    push_u64(&mut header, 0);
+    push_u64(&mut header, 0);
+    push_u64(&mut header, section_headers_offset as u64);
+    push_u32(&mut header, 0);
+    push_u16(&mut header, ELF_HEADER_SIZE as u16);
+    push_u16(&mut header, 0);
+    push_u16(&mut header, 0);
+    push_u16(&mut header, SECTION_HEADER_SIZE as u16);
+    push_u16(&mut header, headers.len() as u16);
+    push_u16(&mut header, headers.len() as u16 - 1);
+    out[..ELF_HEADER_SIZE].copy_from_slice(&header);
+    out
+}
+
+fn read_u16 (bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32 (bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64 (bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_string (table: &[u8], offset: usize) -> String {
+    let end = table[offset..].iter().position(|b| *b == 0).map_or(table.len(), |len| offset + len);
+    String::from_utf8_lossy(&table[offset..end]).to_string()
+}
+
+fn read_section_header (bytes: &[u8], offset: usize) -> SectionHeader {
+    SectionHeader {
+        name: read_u32(bytes, offset),
+        section_type: read_u32(bytes, offset + 4),
+        flags: read_u64(bytes, offset + 8),
+        address: read_u64(bytes, offset + 16),
+        offset: read_u64(bytes, offset + 24),
+        size: read_u64(bytes, offset + 32),
+        link: read_u32(bytes, offset + 40),
+        info: read_u32(bytes, offset + 44),
+        align: read_u64(bytes, offset + 48),
+        entry_size: read_u64(bytes, offset + 56)
+    }
+}
+
+fn section_kind_of (header: &SectionHeader) -> SectionKind {
+    if header.section_type == SHT_NOBITS { SectionKind::Bss }
+    else if header.flags & SHF_EXECINSTR != 0 { SectionKind::Text }
+    else if header.flags & SHF_WRITE != 0 { SectionKind::Data }
+    else { SectionKind::ReadOnly }
+}
+
+fn relocation_kind (relocation_type: u32) -> RelocationKind {
+    match relocation_type {
+        R_X86_64_64 => RelocationKind::Absolute64,
+        R_X86_64_32S => RelocationKind::Absolute32Signed,
+        R_X86_64_PC32 => RelocationKind::PcRelative32,
+        R_X86_64_PLT32 => RelocationKind::Plt32,
+        R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => RelocationKind::GotPcRelative32,
+        other => panic!("Unsupported relocation type {}", other)
+    }
+}
+
+// Reads an x86-64 ELF relocatable object. Only the sections that are
+// loaded into memory are kept, along with their symbols and relocations.
+pub fn read_object (bytes: &[u8]) -> ObjectFile {
+    if bytes.len() < ELF_HEADER_SIZE || &bytes[..4] != ELF_MAGIC || bytes[4] != ELFCLASS64 || bytes[5] != ELFDATA2LSB {
+        panic!("Not a 64-bit little-endian ELF file")
+    }
+    if read_u16(bytes, 16) != ET_REL || read_u16(bytes, 18) != EM_X86_64 {
+        panic!("Not an x86-64 relocatable object")
+    }
+
+    let section_headers_offset = read_u64(bytes, 40) as usize;
+    let section_count = read_u16(bytes, 60) as usize;
+    let names_index = read_u16(bytes, 62) as usize;
+    let headers: Vec<SectionHeader> = (0..section_count)
+        .map(|index| read_section_header(bytes, section_headers_offset + index * SECTION_HEADER_SIZE))
+        .collect();
+    let contents = |header: &SectionHeader| &bytes[header.offset as usize..(header.offset + header.size) as usize];
+    let section_names = contents(&headers[names_index]);
+
+    let mut object = ObjectFile::default();
+    // ELF section index -> ObjectFile section index
+    let mut section_map = vec![None; section_count];
+    for (index, header) in headers.iter().enumerate() {
+        let loaded = header.flags & SHF_ALLOC != 0;
+        if !loaded || !(header.section_type == SHT_PROGBITS || header.section_type == SHT_NOBITS) {
+            continue
+        }
+        let data = if header.section_type == SHT_NOBITS { vec![0; header.size as usize] } else { contents(header).to_vec() };
+        section_map[index] = Some(object.sections.len());
+        object.sections.push(Section {
+            name: read_string(section_names, header.name as usize),
+            kind: section_kind_of(header),
+            data,
+            align: header.align.max(1) as usize,
+            relocations: vec![]
+        });
+    }
+
+    // ELF symbol index -> ObjectFile symbol index
+    let mut symbol_map = vec![];
+    if let Some(symtab) = headers.iter().find(|header| header.section_type == SHT_SYMTAB) {
+        let strings = contents(&headers[symtab.link as usize]);
+        let table = contents(symtab);
+        for index in 0..table.len() / SYMBOL_SIZE {
+            let offset = index * SYMBOL_SIZE;
+            let info = table[offset + 4];
+            let section = read_u16(table, offset + 6);
+            let value = read_u64(table, offset + 8);
+            let size = read_u64(table, offset + 16);
+
+            let definition = match section {
+                _ if index == 0 => None,
+                SHN_UNDEF => Some(SymbolDefinition::Undefined),
+                SHN_COMMON => Some(SymbolDefinition::Common { size: size as usize, align: value as usize }),
+                SHN_ABS => panic!("Absolute symbols aren't supported"),
+                // Symbols in sections that aren't loaded, eg. debug info
+                _ => section_map.get(section as usize).copied().flatten()
+                    .map(|section| SymbolDefinition::Defined { section, value: value as usize })
+            };
+            symbol_map.push(definition.map(|definition| {
+                object.symbols.push(Symbol {
+                    name: read_string(strings, read_u32(table, offset) as usize),
+                    // Weak symbols are treated like global ones
+                    global: info >> 4 != STB_LOCAL,
+                    definition
+                });
+                object.symbols.len() - 1
+            }));
+        }
+    }
+
+    for header in headers.iter().filter(|header| header.section_type == SHT_RELA) {
+        let section = match section_map[header.info as usize] {
+            Some(section) => section,
+            None => continue
+        };
+        let table = contents(header);
+        for index in 0..table.len() / RELA_SIZE {
+            let offset = index * RELA_SIZE;
+            let info = read_u64(table, offset + 8);
+            let symbol = match symbol_map.get((info >> 32) as usize).copied().flatten() {
+                Some(symbol) => symbol,
+                None => panic!("Relocation against a symbol that isn't loaded")
+            };
+            object.sections[section].relocations.push(Relocation {
+                offset: read_u64(table, offset) as usize,
+                kind: relocation_kind(info as u32),
+                symbol,
+                addend: read_u64(table, offset + 16) as i64
+            });
+        }
+    }
+    object
+}
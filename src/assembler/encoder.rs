@@ -0,0 +1,635 @@
+// x86-64 machine code for the instructions in AT&T syntax.
+// Each instruction is a few optional prefixes, an opcode, and usually a
+// ModRM byte saying which register and which register-or-memory operand
+// it works on. Jumps and calls always use 32-bit offsets, so nothing's
+// size depends on where labels end up.
+use crate::codegen::instruction::*;
+use super::assembler::{Assembler, Expression, parse_expression};
+use super::object::RelocationKind;
+
+// The number the CPU knows a register by, and how wide this name for it is
+fn register_number (name: &str) -> Option<(u8, usize)> {
+    // The second byte of the legacy registers, which is a different
+    // encoding of the same numbers as spl, bpl, sil and dil
+    if let Some(idx) = ["ah", "ch", "dh", "bh"].iter().position(|reg| *reg == name) {
+        return Some((4 + idx as u8, 1))
+    }
+
+    let (family, width) = register_family(name)?;
+    let legacy = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi"];
+    let number = match legacy.iter().position(|reg| *reg == family) {
+        Some(idx) => idx as u8,
+        None => family[1..].parse().ok()?
+    };
+    Some((number, width))
+}
+
+fn register (op: &Operand) -> Option<(u8, usize)> {
+    op.register().and_then(register_number)
+}
+
+fn expect_register (op: &Operand) -> u8 {
+    match register(op) {
+        Some((number, _)) => number,
+        None => panic!("Expected a register, got {}", op)
+    }
+}
+
+fn fits_i8 (value: i64) -> bool {
+    value >= i8::MIN as i64 && value <= i8::MAX as i64
+}
+
+fn fits_i32 (value: i64) -> bool {
+    value >= i32::MIN as i64 && value <= i32::MAX as i64
+}
+
+// Immediates are sign-extended up to the operand size, except that we
+// accept unsigned values that only fit because they're the full width
+fn check_immediate (value: i64, width: usize, operand_size: usize) {
+    if width == 8 {
+        return
+    }
+    let bits = width * 8;
+    let fits = value >= -(1 << (bits - 1)) && (value < (1 << (bits - 1)) || (width == operand_size && value < (1 << bits)));
+    if !fits {
+        panic!("${} doesn't fit in a {}-bit immediate", value, bits)
+    }
+}
+
+// Condition codes, as used by jcc, setcc and cmovcc
+fn condition_code (name: &str) -> Option<u8> {
+    Some(match name {
+        "o" => 0, "no" => 1,
+        "b" | "c" | "nae" => 2, "ae" | "nb" | "nc" => 3,
+        "e" | "z" => 4, "ne" | "nz" => 5,
+        "be" | "na" => 6, "a" | "nbe" => 7,
+        "s" => 8, "ns" => 9,
+        "p" | "pe" => 10, "np" | "po" => 11,
+        "l" | "nge" => 12, "ge" | "nl" => 13,
+        "le" | "ng" => 14, "g" | "nle" => 15,
+        _ => return None
+    })
+}
+
+// Instructions without operands
+fn fixed_encoding (mnemonic: &str) -> Option<&'static [u8]> {
+    Some(match mnemonic {
+        "ret" | "retq" => &[0xc3],
+        "leave" | "leaveq" => &[0xc9],
+        "nop" => &[0x90],
+        "hlt" => &[0xf4],
+        "int3" => &[0xcc],
+        "ud2" => &[0x0f, 0x0b],
+        "pause" => &[0xf3, 0x90],
+        "syscall" => &[0x0f, 0x05],
+        "cpuid" => &[0x0f, 0xa2],
+        "rdtsc" => &[0x0f, 0x31],
+        "mfence" => &[0x0f, 0xae, 0xf0],
+        "lfence" => &[0x0f, 0xae, 0xe8],
+        "sfence" => &[0x0f, 0xae, 0xf8],
+        "cltq" | "cdqe" => &[0x48, 0x98],
+        "cqto" | "cqo" => &[0x48, 0x99],
+        "cltd" | "cdq" => &[0x99],
+        "cwtl" | "cwde" => &[0x98],
+        _ => return None
+    })
+}
+
+// The ALU instructions share opcodes, differing in the ModRM reg field
+fn arithmetic_number (base: &str) -> Option<u8> {
+    ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"].iter()
+        .position(|name| *name == base).map(|idx| idx as u8)
+}
+
+// Those with one operand, which all start with F6/F7
+fn unary_number (base: &str) -> Option<u8> {
+    Some(match base {
+        "not" => 2, "neg" => 3, "mul" => 4, "div" => 6, "idiv" => 7,
+        _ => return None
+    })
+}
+
+fn shift_number (base: &str) -> Option<u8> {
+    Some(match base {
+        "rol" => 0, "ror" => 1, "shl" | "sal" => 4, "shr" => 5, "sar" => 7,
+        _ => return None
+    })
+}
+
+// Splits an AT&T size suffix from a mnemonic, eg. "addl" -> ("add", Some(4))
+fn split_size_suffix (mnemonic: &str) -> (&str, Option<usize>) {
+    let known = [
+        "mov", "movabs", "add", "or", "adc", "sbb", "and", "sub", "xor", "cmp",
+        "test", "not", "neg", "mul", "div", "idiv", "imul", "inc", "dec",
+        "rol", "ror", "shl", "sal", "shr", "sar", "lea", "push", "pop",
+        "xchg", "call", "jmp"
+    ];
+    if known.contains(&mnemonic) {
+        return (mnemonic, None)
+    }
+    for (suffix, size) in &[("b", 1), ("w", 2), ("l", 4), ("q", 8)] {
+        if let Some(base) = mnemonic.strip_suffix(suffix) {
+            if known.contains(&base) { return (base, Some(*size)) }
+        }
+    }
+    (mnemonic, None)
+}
+
+fn absolute_address (label: &str) -> MemoryOperand {
+    let (symbol, displacement) = match parse_expression(label) {
+        Expression::Symbol(symbol, offset) => (symbol, offset),
+        Expression::Constant(_) => unreachable!("Labels aren't numbers")
+    };
+    MemoryOperand {
+        symbol: Some(symbol),
+        displacement: displacement as isize,
+        base: None,
+        index: None,
+        scale: 1
+    }
+}
+
+// A memory operand's displacement that refers to a symbol,
+// which is fixed up once the whole instruction is written
+struct Displacement {
+    symbol: String,
+    addend: i64,
+    rip_relative: bool
+}
+
+impl Assembler {
+    pub fn encode (&mut self, inst: &Instruction) {
+        let mnemonic = &inst.mnemonic[..];
+        let ops = &inst.operands;
+
+        if let Some(bytes) = fixed_encoding(mnemonic) {
+            if !ops.is_empty() {
+                panic!("{} doesn't take operands", mnemonic)
+            }
+            return self.emit_bytes(bytes)
+        }
+
+        if let Some(cc) = mnemonic.strip_prefix('j').and_then(condition_code) {
+            return self.emit_branch(&[0x0f, 0x80 + cc], RelocationKind::PcRelative32, inst)
+        }
+        if let Some(cc) = mnemonic.strip_prefix("set").and_then(condition_code) {
+            self.expect_operands(inst, 1);
+            return self.emit_modrm(inst, 1, &[0x0f, 0x90 + cc], 0, &ops[0], None)
+        }
+        if let Some(cc) = mnemonic.strip_prefix("cmov").and_then(|cc| condition_code(split_size_suffix_for_cmov(cc))) {
+            self.expect_operands(inst, 2);
+            let size = self.operand_size(inst, None);
+            return self.emit_modrm(inst, size, &[0x0f, 0x40 + cc], expect_register(&ops[1]), &ops[0], None)
+        }
+        if self.encode_extension(inst) {
+            return
+        }
+
+        let (base, suffix_size) = split_size_suffix(mnemonic);
+        match base {
+            "jmp" => self.emit_jump_or_call(inst, 0xe9, 4),
+            "call" => self.emit_jump_or_call(inst, 0xe8, 2),
+            "mov" | "movabs" => self.encode_mov(inst, suffix_size),
+            "lea" => {
+                self.expect_operands(inst, 2);
+                if !matches!(ops[0], Operand::Memory(_)) {
+                    panic!("lea needs a memory operand")
+                }
+                let size = self.operand_size(inst, suffix_size);
+                self.emit_modrm(inst, size, &[0x8d], expect_register(&ops[1]), &ops[0], None)
+            },
+            "test" => {
+                self.expect_operands(inst, 2);
+                let size = self.operand_size(inst, suffix_size);
+                let opcode = if size == 1 { 0x84 } else { 0x85 };
+                match &ops[0] {
+                    Operand::Immediate(imm) => {
+                        let opcode = if size == 1 { 0xf6 } else { 0xf7 };
+                        self.emit_modrm(inst, size, &[opcode], 0, &ops[1], Some((*imm as i64, size.min(4))))
+                    },
+                    Operand::Register(_) => self.emit_modrm(inst, size, &[opcode], expect_register(&ops[0]), &ops[1], None),
+                    _ => self.emit_modrm(inst, size, &[opcode], expect_register(&ops[1]), &ops[0], None)
+                }
+            },
+            "xchg" => {
+                self.expect_operands(inst, 2);
+                let size = self.operand_size(inst, suffix_size);
+                let opcode = if size == 1 { 0x86 } else { 0x87 };
+                match &ops[0] {
+                    Operand::Register(_) => self.emit_modrm(inst, size, &[opcode], expect_register(&ops[0]), &ops[1], None),
+                    _ => self.emit_modrm(inst, size, &[opcode], expect_register(&ops[1]), &ops[0], None)
+                }
+            },
+            "imul" => self.encode_imul(inst, suffix_size),
+            "inc" | "dec" => {
+                self.expect_operands(inst, 1);
+                let size = self.operand_size(inst, suffix_size);
+                let opcode = if size == 1 { 0xfe } else { 0xff };
+                self.emit_modrm(inst, size, &[opcode], if base == "inc" { 0 } else { 1 }, &ops[0], None)
+            },
+            "push" => self.encode_push(inst),
+            "pop" => {
+                self.expect_operands(inst, 1);
+                match register(&ops[0]) {
+                    // push and pop are 64-bit without needing REX.W
+                    Some((number, _)) => self.emit_register_in_opcode(inst, 4, 0x58, number, None),
+                    None => self.emit_modrm(inst, 4, &[0x8f], 0, &ops[0], None)
+                }
+            },
+            _ if arithmetic_number(base).is_some() => {
+                self.encode_arithmetic(inst, arithmetic_number(base).unwrap(), suffix_size)
+            },
+            _ if unary_number(base).is_some() => {
+                self.expect_operands(inst, 1);
+                let size = self.operand_size(inst, suffix_size);
+                let opcode = if size == 1 { 0xf6 } else { 0xf7 };
+                self.emit_modrm(inst, size, &[opcode], unary_number(base).unwrap(), &ops[0], None)
+            },
+            _ if shift_number(base).is_some() => {
+                self.encode_shift(inst, shift_number(base).unwrap(), suffix_size)
+            },
+            _ => panic!("The assembler doesn't support \"{}\"", inst)
+        }
+    }
+
+    fn expect_operands (&self, inst: &Instruction, count: usize) {
+        if inst.operands.len() != count {
+            panic!("\"{}\" should have {} operand(s)", inst, count)
+        }
+    }
+
+    // The suffix wins, otherwise it's the width of the destination
+    // register, or failing that any register
+    fn operand_size (&self, inst: &Instruction, suffix_size: Option<usize>) -> usize {
+        if let Some(size) = suffix_size {
+            return size
+        }
+        let destination = inst.operands.last().and_then(register);
+        match destination.or_else(|| inst.operands.iter().find_map(register)) {
+            Some((_, width)) => width,
+            None => panic!("The operand size of \"{}\" is ambiguous, add a suffix", inst)
+        }
+    }
+
+    // movzbl, movswq, movslq and friends
+    fn encode_extension (&mut self, inst: &Instruction) -> bool {
+        let mnemonic = &inst.mnemonic[..];
+        let (opcode, destination_size): (&[u8], usize) = match mnemonic {
+            "movslq" | "movsxd" => (&[0x63], 8),
+            _ if mnemonic.len() == 6 && (mnemonic.starts_with("movz") || mnemonic.starts_with("movs")) => {
+                let signed = mnemonic.starts_with("movs");
+                let from = &mnemonic[4..5];
+                let to = match &mnemonic[5..] {
+                    "w" => 2, "l" => 4, "q" => 8,
+                    _ => return false
+                };
+                let opcode: &[u8] = match (signed, from) {
+                    (false, "b") => &[0x0f, 0xb6],
+                    (false, "w") => &[0x0f, 0xb7],
+                    (true, "b") => &[0x0f, 0xbe],
+                    (true, "w") => &[0x0f, 0xbf],
+                    _ => return false
+                };
+                (opcode, to)
+            },
+            _ => return false
+        };
+        self.expect_operands(inst, 2);
+        let ops = &inst.operands;
+        self.emit_modrm(inst, destination_size, opcode, expect_register(&ops[1]), &ops[0], None);
+        true
+    }
+
+    fn encode_mov (&mut self, inst: &Instruction, suffix_size: Option<usize>) {
+        self.expect_operands(inst, 2);
+        let ops = &inst.operands;
+        let size = self.operand_size(inst, suffix_size);
+        let byte = size == 1;
+
+        match (&ops[0], &ops[1]) {
+            (Operand::Immediate(imm), Operand::Register(_)) => {
+                let imm = *imm as i64;
+                let number = expect_register(&ops[1]);
+                if size == 8 && fits_i32(imm) {
+                    // Sign-extended, and shorter than the full 64-bit form
+                    self.emit_modrm(inst, 8, &[0xc7], 0, &ops[1], Some((imm, 4)))
+                } else {
+                    let opcode = if byte { 0xb0 } else { 0xb8 };
+                    self.emit_register_in_opcode(inst, size, opcode, number, Some((imm, size)))
+                }
+            },
+            (Operand::Immediate(imm), _) => {
+                let opcode = if byte { 0xc6 } else { 0xc7 };
+                self.emit_modrm(inst, size, &[opcode], 0, &ops[1], Some((*imm as i64, size.min(4))))
+            },
+            (Operand::Register(_), _) => {
+                let opcode = if byte { 0x88 } else { 0x89 };
+                self.emit_modrm(inst, size, &[opcode], expect_register(&ops[0]), &ops[1], None)
+            },
+            (_, Operand::Register(_)) => {
+                let opcode = if byte { 0x8a } else { 0x8b };
+                self.emit_modrm(inst, size, &[opcode], expect_register(&ops[1]), &ops[0], None)
+            },
+            _ => panic!("\"{}\" can't move memory to memory", inst)
+        }
+    }
+
+    fn encode_arithmetic (&mut self, inst: &Instruction, number: u8, suffix_size: Option<usize>) {
+        self.expect_operands(inst, 2);
+        let ops = &inst.operands;
+        let size = self.operand_size(inst, suffix_size);
+        let byte = size == 1;
+
+        match (&ops[0], &ops[1]) {
+            (Operand::Immediate(imm), _) => {
+                let imm = *imm as i64;
+                if byte {
+                    self.emit_modrm(inst, size, &[0x80], number, &ops[1], Some((imm, 1)))
+                } else if fits_i8(imm) {
+                    self.emit_modrm(inst, size, &[0x83], number, &ops[1], Some((imm, 1)))
+                } else {
+                    self.emit_modrm(inst, size, &[0x81], number, &ops[1], Some((imm, size.min(4))))
+                }
+            },
+            (Operand::Register(_), _) => {
+                let opcode = number * 8 + if byte { 0 } else { 1 };
+                self.emit_modrm(inst, size, &[opcode], expect_register(&ops[0]), &ops[1], None)
+            },
+            (_, Operand::Register(_)) => {
+                let opcode = number * 8 + if byte { 2 } else { 3 };
+                self.emit_modrm(inst, size, &[opcode], expect_register(&ops[1]), &ops[0], None)
+            },
+            _ => panic!("\"{}\" can't have two memory operands", inst)
+        }
+    }
+
+    fn encode_imul (&mut self, inst: &Instruction, suffix_size: Option<usize>) {
+        let ops = &inst.operands;
+        let size = self.operand_size(inst, suffix_size);
+        match ops.len() {
+            // %rdx:%rax = %rax * operand
+            1 => {
+                let opcode = if size == 1 { 0xf6 } else { 0xf7 };
+                self.emit_modrm(inst, size, &[opcode], 5, &ops[0], None)
+            },
+            2 => match &ops[0] {
+                // imul $imm, %reg is short for imul $imm, %reg, %reg
+                Operand::Immediate(imm) => self.emit_imul_immediate(inst, size, *imm as i64, &ops[1], &ops[1]),
+                _ => self.emit_modrm(inst, size, &[0x0f, 0xaf], expect_register(&ops[1]), &ops[0], None)
+            },
+            3 => match &ops[0] {
+                Operand::Immediate(imm) => self.emit_imul_immediate(inst, size, *imm as i64, &ops[1], &ops[2]),
+                _ => panic!("The first operand of \"{}\" should be an immediate", inst)
+            },
+            _ => panic!("\"{}\" has too many operands", inst)
+        }
+    }
+
+    fn emit_imul_immediate (&mut self, inst: &Instruction, size: usize, imm: i64, source: &Operand, destination: &Operand) {
+        let reg = expect_register(destination);
+        if fits_i8(imm) {
+            self.emit_modrm(inst, size, &[0x6b], reg, source, Some((imm, 1)))
+        } else {
+            self.emit_modrm(inst, size, &[0x69], reg, source, Some((imm, size.min(4))))
+        }
+    }
+
+    fn encode_shift (&mut self, inst: &Instruction, number: u8, suffix_size: Option<usize>) {
+        let ops = &inst.operands;
+        let size = self.operand_size(inst, suffix_size);
+        let byte = size == 1;
+        let target = ops.last().unwrap_or_else(|| panic!("\"{}\" needs an operand", inst));
+
+        match ops.len() {
+            1 => self.emit_modrm(inst, size, &[if byte { 0xd0 } else { 0xd1 }], number, target, None),
+            2 => match &ops[0] {
+                Operand::Immediate(1) => self.emit_modrm(inst, size, &[if byte { 0xd0 } else { 0xd1 }], number, target, None),
+                Operand::Immediate(imm) => self.emit_modrm(inst, size, &[if byte { 0xc0 } else { 0xc1 }], number, target, Some((*imm as i64, 1))),
+                Operand::Register(reg) if reg == "cl" => self.emit_modrm(inst, size, &[if byte { 0xd2 } else { 0xd3 }], number, target, None),
+                _ => panic!("Shift counts are immediates or %cl, not {}", ops[0])
+            },
+            _ => panic!("\"{}\" has too many operands", inst)
+        }
+    }
+
+    fn encode_push (&mut self, inst: &Instruction) {
+        self.expect_operands(inst, 1);
+        let op = &inst.operands[0];
+        match op {
+            Operand::Immediate(imm) if fits_i8(*imm as i64) => {
+                self.emit_bytes(&[0x6a, *imm as u8])
+            },
+            Operand::Immediate(imm) => {
+                check_immediate(*imm as i64, 4, 8);
+                self.emit_bytes(&[0x68]);
+                self.emit_bytes(&(*imm as i32).to_le_bytes())
+            },
+            Operand::Register(_) => self.emit_register_in_opcode(inst, 4, 0x50, expect_register(op), None),
+            _ => self.emit_modrm(inst, 4, &[0xff], 6, op, None)
+        }
+    }
+
+    fn emit_jump_or_call (&mut self, inst: &Instruction, opcode: u8, indirect_number: u8) {
+        self.expect_operands(inst, 1);
+        match &inst.operands[0] {
+            // Like push and pop, these are 64-bit without REX.W
+            Operand::Indirect(target) => self.emit_modrm(inst, 4, &[0xff], indirect_number, target, None),
+            Operand::Label(_) => {
+                // Calls might go to a shared library, through the PLT
+                let kind = if opcode == 0xe8 { RelocationKind::Plt32 } else { RelocationKind::PcRelative32 };
+                self.emit_branch(&[opcode], kind, inst)
+            },
+            other => panic!("Can't jump to {}", other)
+        }
+    }
+
+    fn emit_branch (&mut self, opcode: &[u8], kind: RelocationKind, inst: &Instruction) {
+        self.expect_operands(inst, 1);
+        let label = match &inst.operands[0] {
+            Operand::Label(label) => label,
+            other => panic!("Expected a label, got {}", other)
+        };
+        self.emit_bytes(opcode);
+        let symbol = self.symbol_index(label);
+        // Relative to the end of the instruction, which is the end of the offset
+        self.add_fixup(self.offset(), kind, symbol, -4);
+        self.emit_bytes(&[0; 4]);
+    }
+
+    // Whether this instruction uses spl, bpl, sil or dil, which only
+    // exist when there's a REX prefix, or ah-dh, which only exist without
+    fn byte_register_needs (&self, inst: &Instruction) -> (bool, bool) {
+        let names = inst.operands.iter().filter_map(|op| op.register());
+        let mut needs_rex = false;
+        let mut forbids_rex = false;
+        for name in names {
+            needs_rex |= ["spl", "bpl", "sil", "dil"].contains(&name);
+            forbids_rex |= ["ah", "bh", "ch", "dh"].contains(&name);
+        }
+        (needs_rex, forbids_rex)
+    }
+
+    fn emit_prefixes (&mut self, inst: &Instruction, size: usize, rex_bits: u8) {
+        if size == 2 {
+            self.emit_bytes(&[0x66]);
+        }
+        let (needs_rex, forbids_rex) = self.byte_register_needs(inst);
+        let w = if size == 8 { 0x08 } else { 0 };
+        if w | rex_bits != 0 || needs_rex {
+            if forbids_rex {
+                panic!("\"{}\" can't use %ah, %bh, %ch or %dh", inst)
+            }
+            self.emit_bytes(&[0x40 | w | rex_bits]);
+        }
+    }
+
+    // For instructions that add the register number to the opcode,
+    // like push, pop and mov $imm, %reg
+    fn emit_register_in_opcode (&mut self, inst: &Instruction, size: usize, opcode: u8, number: u8, imm: Option<(i64, usize)>) {
+        self.emit_prefixes(inst, size, number >> 3);
+        self.emit_bytes(&[opcode + (number & 7)]);
+        if let Some((value, width)) = imm {
+            check_immediate(value, width, size);
+            self.emit_bytes(&value.to_le_bytes()[..width]);
+        }
+    }
+
+    // Emits an instruction with a ModRM byte. reg goes in its reg field
+    // (either a register number or an opcode extension), and rm is the
+    // register or memory operand.
+    fn emit_modrm (&mut self, inst: &Instruction, size: usize, opcode: &[u8], reg: u8, rm: &Operand, imm: Option<(i64, usize)>) {
+        let mut rex_bits = (reg >> 3) << 2;
+        let mut modrm_sib = vec![];
+        let mut displacement = None;
+        let mut displacement_bytes = vec![];
+
+        let absolute;
+        let mem = match rm {
+            Operand::Register(_) => None,
+            Operand::Memory(mem) => Some(mem),
+            // A bare symbol is an absolute address
+            Operand::Label(label) => {
+                absolute = absolute_address(label);
+                Some(&absolute)
+            },
+            other => panic!("\"{}\" can't use {} as an operand", inst, other)
+        };
+
+        match mem {
+            Some(mem) => {
+                let (bits, bytes, disp_bytes, symbol) = self.memory_operand(inst, reg, mem);
+                rex_bits |= bits;
+                modrm_sib = bytes;
+                displacement_bytes = disp_bytes;
+                displacement = symbol;
+            },
+            None => {
+                let number = expect_register(rm);
+                rex_bits |= number >> 3;
+                modrm_sib.push(0xc0 | ((reg & 7) << 3) | (number & 7));
+            }
+        }
+
+        self.emit_prefixes(inst, size, rex_bits);
+        self.emit_bytes(opcode);
+        self.emit_bytes(&modrm_sib);
+        let displacement_offset = self.offset();
+        self.emit_bytes(&displacement_bytes);
+        if let Some((value, width)) = imm {
+            check_immediate(value, width, size);
+            self.emit_bytes(&value.to_le_bytes()[..width]);
+        }
+
+        if let Some(disp) = displacement {
+            let symbol = disp.symbol.as_str();
+            let (symbol, kind) = match symbol.strip_suffix("@GOTPCREL") {
+                Some(name) => (name, RelocationKind::GotPcRelative32),
+                None if disp.rip_relative => (symbol, RelocationKind::PcRelative32),
+                None => (symbol, RelocationKind::Absolute32Signed)
+            };
+            if kind != RelocationKind::Absolute32Signed && !disp.rip_relative {
+                panic!("\"{}\" needs to be relative to %rip", inst)
+            }
+            // PC-relative offsets count from the end of the instruction,
+            // which might have an immediate after the displacement
+            let addend = if disp.rip_relative {
+                disp.addend - (self.offset() - displacement_offset) as i64
+            } else {
+                disp.addend
+            };
+            let index = self.symbol_index(symbol);
+            self.add_fixup(displacement_offset, kind, index, addend);
+        }
+    }
+
+    // The REX bits, ModRM and SIB bytes and displacement for a memory operand
+    fn memory_operand (&self, inst: &Instruction, reg: u8, mem: &MemoryOperand) -> (u8, Vec<u8>, Vec<u8>, Option<Displacement>) {
+        let reg_field = (reg & 7) << 3;
+        let displacement = mem.displacement as i64;
+        let address_register = |name: &str| match register_number(name) {
+            Some((number, 8)) => number,
+            _ => panic!("\"{}\" needs 64-bit address registers", inst)
+        };
+        let symbol_displacement = |rip_relative| mem.symbol.clone().map(|symbol| Displacement {
+            symbol,
+            addend: displacement,
+            rip_relative
+        });
+
+        if mem.base.as_deref() == Some("rip") {
+            let bytes = if mem.symbol.is_some() { [0; 4] } else { (displacement as i32).to_le_bytes() };
+            return (0, vec![reg_field | 0b101], bytes.to_vec(), symbol_displacement(true))
+        }
+
+        let index = mem.index.as_deref().map(address_register);
+        if index == Some(4) {
+            panic!("%rsp can't be an index register")
+        }
+        let scale_bits = match mem.scale {
+            1 => 0, 2 => 1, 4 => 2, 8 => 3,
+            other => panic!("Scale factor must be 1, 2, 4 or 8, not {}", other)
+        };
+        let rex_bits = index.map_or(0, |index| (index >> 3) << 1);
+        let sib_index = index.map_or(0b100, |index| index & 7) << 3;
+        let disp32 = |symbolic: bool| if symbolic { vec![0; 4] } else { (displacement as i32).to_le_bytes().to_vec() };
+
+        let base = match &mem.base {
+            Some(base) => address_register(base),
+            // An absolute address, maybe plus an index
+            None => {
+                let sib = (scale_bits << 6) | sib_index | 0b101;
+                let bytes = vec![reg_field | 0b100, sib];
+                return (rex_bits, bytes, disp32(mem.symbol.is_some()), symbol_displacement(false))
+            }
+        };
+        let rex_bits = rex_bits | (base >> 3);
+
+        // %rbp and %r13 as a base always need a displacement
+        let (mode, disp_bytes) = if mem.symbol.is_some() || !fits_i8(displacement) {
+            (0b10, disp32(mem.symbol.is_some()))
+        } else if displacement == 0 && base & 7 != 0b101 {
+            (0b00, vec![])
+        } else {
+            (0b01, vec![displacement as u8])
+        };
+
+        // %rsp and %r12 as a base, or any index, need a SIB byte
+        let bytes = if index.is_some() || base & 7 == 0b100 {
+            vec![(mode << 6) | reg_field | 0b100, (scale_bits << 6) | sib_index | (base & 7)]
+        } else {
+            vec![(mode << 6) | reg_field | (base & 7)]
+        };
+        (rex_bits, bytes, disp_bytes, symbol_displacement(false))
+    }
+}
+
+// cmovs come with a size suffix sometimes, eg. cmovgeq
+fn split_size_suffix_for_cmov (cc: &str) -> &str {
+    if condition_code(cc).is_some() {
+        return cc
+    }
+    match cc.strip_suffix(['w', 'l', 'q']) {
+        Some(stripped) if condition_code(stripped).is_some() => stripped,
+        _ => cc
+    }
+}
@@ -0,0 +1,5 @@
+#[allow(clippy::module_inception)]
+pub mod assembler;
+pub mod encoder;
+pub mod object;
+pub mod elf;
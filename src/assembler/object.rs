@@ -0,0 +1,76 @@
+// What the assembler produces: machine code and data split into sections,
+// the symbols defined in them, and the places that can only be filled in
+// once we know where everything ends up (relocations).
+// This is independent of the file format it's eventually written as.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SectionKind {
+    Text,
+    Data,
+    ReadOnly,
+    // Zero-filled, so it takes up no room in the file
+    Bss,
+    // Eg. .note.GNU-stack, which isn't loaded into memory at all
+    NotLoaded
+}
+
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    // For .bss, zeroes standing in for the room it takes up
+    pub data: Vec<u8>,
+    pub align: usize,
+    pub relocations: Vec<Relocation>
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RelocationKind {
+    // The symbol's address, eg. ".quad label"
+    Absolute64,
+    // Sign-extended from 32 bits, for non-RIP-relative addressing
+    Absolute32Signed,
+    // Relative to the address of the relocated field, eg. "label(%rip)"
+    PcRelative32,
+    // A call to a function that might be in a shared library
+    Plt32,
+    // The address of a GOT entry holding the symbol's address
+    GotPcRelative32
+}
+
+pub struct Relocation {
+    pub offset: usize,
+    pub kind: RelocationKind,
+    // An index into ObjectFile::symbols
+    pub symbol: usize,
+    pub addend: i64
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymbolDefinition {
+    // Only referenced here, so some other object has to define it
+    Undefined,
+    // An index into ObjectFile::sections and an offset into that section
+    Defined { section: usize, value: usize },
+    // "int x;" at file scope. The linker allocates these, merging
+    // any that share a name.
+    Common { size: usize, align: usize }
+}
+
+pub struct Symbol {
+    pub name: String,
+    pub global: bool,
+    pub definition: SymbolDefinition
+}
+
+impl Symbol {
+    // Labels like .L3, which only exist while assembling
+    pub fn is_temporary (&self) -> bool {
+        self.name.starts_with(".L")
+    }
+}
+
+#[derive(Default)]
+pub struct ObjectFile {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>
+}
@@ -0,0 +1,303 @@
+// AArch64 code, for Linux's flavour of the AAPCS64 calling convention.
+// Arguments go in x0-x7 and then on the stack, structs of up to 16 bytes
+// in consecutive registers and bigger ones by reference to a copy the
+// caller makes. Big structs are returned to memory the caller passes in x8.
+// x16 and x17 are scratch registers nothing expects to survive a branch,
+// so they're used to build addresses and immediates that don't fit.
+use super::abi::CallingConvention;
+use super::codegen::Codegen;
+use super::machine::Location;
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+
+pub static AAPCS64: CallingConvention = CallingConvention {
+    argument_registers: &["x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7"],
+    return_registers: &["x0", "x1"],
+    indirect_result_register: "x8",
+    indirect_result_is_argument: false,
+    big_structs_by_reference: true,
+    stack_ends_registers: true,
+    splits_structs: false,
+    contiguous_variadic_arguments: false
+};
+
+// struct { void *__stack; void *__gr_top; void *__vr_top;
+//          int __gr_offs; int __vr_offs; }
+// __gr_offs counts up from minus the size of the unused part of the
+// register save area, which ends at __gr_top, to 0
+pub static VA_LIST_SIZE: isize = 32;
+static VA_STACK: isize = 0;
+static VA_GR_TOP: isize = 8;
+static VA_VR_TOP: isize = 16;
+static VA_GR_OFFS: isize = 24;
+static VA_VR_OFFS: isize = 28;
+
+// The 32-bit half of a register, eg. w3 for x3
+fn w_register (reg: &str) -> String {
+    match reg.strip_prefix('x') {
+        Some(number) => format!("w{}", number),
+        None => unreachable!("\"{}\" isn't a 64-bit general purpose register", reg)
+    }
+}
+
+// The register a store or load of width bytes uses
+fn sized_register (reg: &str, width: usize) -> String {
+    if width == 8 { reg.to_string() } else { w_register(reg) }
+}
+
+impl Codegen {
+    pub fn a64_load_immediate (&mut self, reg: &str, value: isize) {
+        if (-65536..65536).contains(&value) {
+            self.emit(format!("mov {}, #{}", reg, value));
+            return
+        }
+        // Built up 16 bits at a time, skipping the ones that are zero
+        let bits = value as u64;
+        let mut first = true;
+        for shift in (0..64).step_by(16) {
+            let chunk = (bits >> shift) & 0xffff;
+            if chunk == 0 {
+                continue
+            }
+            let op = if first { "movz" } else { "movk" };
+            self.emit(format!("{} {}, #{}, lsl #{}", op, reg, chunk, shift));
+            first = false;
+        }
+    }
+
+    // dest = src + value, where src and dest can be sp
+    pub fn a64_add_immediate (&mut self, dest: &str, src: &str, value: isize) {
+        if (0..4096).contains(&value) {
+            self.emit(format!("add {}, {}, #{}", dest, src, value));
+        } else if (-4095..0).contains(&value) {
+            self.emit(format!("sub {}, {}, #{}", dest, src, -value));
+        } else {
+            self.a64_load_immediate("x16", value);
+            self.emit(format!("add {}, {}, x16", dest, src));
+        }
+    }
+
+    pub fn a64_load_address (&mut self, dest: &str, location: &Location) {
+        match location {
+            Location::Frame(offset) => self.a64_add_immediate(dest, "x29", *offset),
+            Location::Stack(offset) => self.a64_add_immediate(dest, "sp", *offset),
+            Location::Symbol(label) => {
+                // The 4KB page, then where it is in the page
+                self.emit(format!("adrp {}, {}", dest, label));
+                self.emit(format!("add {}, {}, :lo12:{}", dest, dest, label));
+            },
+            Location::Pointer(register) => {
+                let src = self.register_name(*register);
+                if src != dest {
+                    self.emit(format!("mov {}, {}", dest, src));
+                }
+            }
+        }
+    }
+
+    // A memory operand for an access of width bytes, emitting whatever
+    // it takes to reach the location first. Also says whether the offset
+    // needs the unscaled form of the instruction (ldur and stur).
+    fn a64_memory_operand (&mut self, location: &Location, width: usize) -> (String, bool) {
+        let (base, offset) = match location {
+            Location::Frame(offset) => ("x29", *offset),
+            Location::Stack(offset) => ("sp", *offset),
+            Location::Symbol(_) => {
+                self.a64_load_address("x16", location);
+                ("x16", 0)
+            },
+            Location::Pointer(register) => (self.register_name(*register), 0)
+        };
+        self.a64_base_offset_operand(base, offset, width)
+    }
+
+    fn a64_base_offset_operand (&mut self, base: &str, offset: isize, width: usize) -> (String, bool) {
+        let width = width as isize;
+        if offset >= 0 && offset % width == 0 && offset / width < 4096 {
+            // Scaled and unsigned
+            (format!("[{}, #{}]", base, offset), false)
+        } else if (-256..256).contains(&offset) {
+            (format!("[{}, #{}]", base, offset), true)
+        } else {
+            self.a64_load_immediate("x16", offset);
+            (format!("[{}, x16]", base), false)
+        }
+    }
+
+    // Loads width bytes in to a register, sign or zero extended to 64 bits
+    pub fn a64_load (&mut self, dest: &str, width: usize, signed: bool, location: &Location) {
+        let (operand, unscaled) = self.a64_memory_operand(location, width);
+        self.a64_load_operand(dest, width, signed, &operand, unscaled);
+    }
+
+    fn a64_load_operand (&mut self, dest: &str, width: usize, signed: bool, operand: &str, unscaled: bool) {
+        let form = if unscaled { "ur" } else { "r" };
+        let w = w_register(dest);
+        match (width, signed) {
+            (1, true) => self.emit(format!("ld{}sb {}, {}", form, dest, operand)),
+            (1, false) => self.emit(format!("ld{}b {}, {}", form, w, operand)),
+            (2, true) => self.emit(format!("ld{}sh {}, {}", form, dest, operand)),
+            (2, false) => self.emit(format!("ld{}h {}, {}", form, w, operand)),
+            (4, true) => self.emit(format!("ld{}sw {}, {}", form, dest, operand)),
+            (4, false) => self.emit(format!("ld{} {}, {}", form, w, operand)),
+            _ => self.emit(format!("ld{} {}, {}", form, dest, operand))
+        }
+    }
+
+    // Stores the low width bytes of a register
+    pub fn a64_store (&mut self, src: &str, width: usize, location: &Location) {
+        let (operand, unscaled) = self.a64_memory_operand(location, width);
+        self.a64_store_operand(src, width, &operand, unscaled);
+    }
+
+    fn a64_store_operand (&mut self, src: &str, width: usize, operand: &str, unscaled: bool) {
+        let form = if unscaled { "ur" } else { "r" };
+        let reg = sized_register(src, width);
+        match width {
+            1 => self.emit(format!("st{}b {}, {}", form, reg, operand)),
+            2 => self.emit(format!("st{}h {}, {}", form, reg, operand)),
+            _ => self.emit(format!("st{} {}, {}", form, reg, operand))
+        }
+    }
+
+    // Copies size bytes from the address in x0 to the address in x1
+    pub fn a64_memory_copy (&mut self, size: isize) {
+        let mut copied = 0;
+        for width in [8, 4, 2, 1] {
+            while size - copied >= width {
+                let (from, unscaled) = self.a64_base_offset_operand("x0", copied, width as usize);
+                self.a64_load_operand("x2", width as usize, false, &from, unscaled);
+                let (to, unscaled) = self.a64_base_offset_operand("x1", copied, width as usize);
+                self.a64_store_operand("x2", width as usize, &to, unscaled);
+                copied += width;
+            }
+        }
+    }
+
+    // Loads part of a struct, without reading past its end
+    pub fn a64_load_eightbyte (&mut self, base: &str, offset: isize, size: isize, dest: &str) {
+        match size {
+            8 | 4 | 2 | 1 => {
+                let (operand, unscaled) = self.a64_base_offset_operand(base, offset, size as usize);
+                self.a64_load_operand(dest, size as usize, false, &operand, unscaled);
+            },
+            _ => {
+                // Odd sizes are built up a byte at a time, high bytes first
+                self.emit(format!("mov {}, #0", dest));
+                for byte in (0..size).rev() {
+                    self.emit(format!("lsl {}, {}, #8", dest, dest));
+                    let (operand, unscaled) = self.a64_base_offset_operand(base, offset + byte, 1);
+                    self.a64_load_operand("x17", 1, false, &operand, unscaled);
+                    self.emit(format!("orr {}, {}, x17", dest, dest));
+                }
+            }
+        }
+    }
+
+    pub fn a64_store_eightbyte (&mut self, src: &str, base: &str, offset: isize, size: isize) {
+        match size {
+            8 | 4 | 2 | 1 => {
+                let (operand, unscaled) = self.a64_base_offset_operand(base, offset, size as usize);
+                self.a64_store_operand(src, size as usize, &operand, unscaled);
+            },
+            _ => {
+                self.emit(format!("mov x17, {}", src));
+                for byte in 0..size {
+                    let (operand, unscaled) = self.a64_base_offset_operand(base, offset + byte, 1);
+                    self.a64_store_operand("x17", 1, &operand, unscaled);
+                    self.emit_str("lsr x17, x17, #8");
+                }
+            }
+        }
+    }
+
+    // x1 OP x0, in to x0
+    pub fn a64_arithmetic (&mut self, operator: &str, signed: bool) {
+        let divide = if signed { "sdiv" } else { "udiv" };
+        match operator {
+            "+" => self.emit_str("add x0, x1, x0"),
+            "-" => self.emit_str("sub x0, x1, x0"),
+            "*" => self.emit_str("mul x0, x1, x0"),
+            "/" => self.emit(format!("{} x0, x1, x0", divide)),
+            "%" => {
+                // x1 - (x1 / x0) * x0
+                self.emit(format!("{} x2, x1, x0", divide));
+                self.emit_str("msub x0, x2, x0, x1");
+            },
+            _ => {
+                let condition = match operator {
+                    "==" => "eq",
+                    "!=" => "ne",
+                    ">" => if signed { "gt" } else { "hi" },
+                    "<" => if signed { "lt" } else { "lo" },
+                    ">=" => if signed { "ge" } else { "hs" },
+                    "<=" => if signed { "le" } else { "ls" },
+                    _ => unimplemented!("\"{}\" stack operator", operator)
+                };
+                self.emit_str("cmp x1, x0");
+                self.emit(format!("cset x0, {}", condition));
+            }
+        }
+    }
+
+    pub fn a64_zero_fill (&mut self, offset: isize, size: isize) {
+        let mut filled = 0;
+        for width in [8, 4, 2, 1] {
+            while size - filled >= width {
+                self.a64_store("xzr", width as usize, &Location::Frame(offset + filled));
+                filled += width;
+            }
+        }
+    }
+
+    pub fn a64_va_start (&mut self, save_area: isize, named_registers: usize, named_stack_bytes: isize) {
+        // The list's address is in x0
+        self.a64_load_address("x1", &Location::Frame(16 + named_stack_bytes));
+        self.emit(format!("str x1, [x0, #{}]", VA_STACK));
+        self.a64_load_address("x1", &Location::Frame(save_area + AAPCS64.argument_registers.len() as isize * 8));
+        self.emit(format!("str x1, [x0, #{}]", VA_GR_TOP));
+        // There's no floating point, so no vector registers are saved.
+        // An offset of 0 says they're all used up.
+        self.emit(format!("str x1, [x0, #{}]", VA_VR_TOP));
+        let unused = AAPCS64.argument_registers.len() - named_registers;
+        self.a64_load_immediate("x1", -(unused as isize) * 8);
+        self.emit(format!("str w1, [x0, #{}]", VA_GR_OFFS));
+        self.emit(format!("str wzr, [x0, #{}]", VA_VR_OFFS));
+    }
+
+    // Leaves the argument's address in x0
+    pub fn a64_va_arg (&mut self, va_arg: &ASTVaArg) {
+        let arg_type = &va_arg.arg_type;
+        let by_reference = size_in_bytes(arg_type) > 16;
+        let slot_size = if by_reference { 8 } else { align_to(size_in_bytes(arg_type), 8) };
+
+        self.emit_for_node(&va_arg.list);
+        self.emit_str("mov x1, x0");
+
+        let stack_label = self.get_unique_label("va_arg_stack");
+        let end_label = self.get_unique_label("va_arg_end");
+
+        // Once an argument hasn't fit in the registers that were left,
+        // every one after it is on the stack too
+        self.emit(format!("ldrsw x2, [x1, #{}]", VA_GR_OFFS));
+        self.emit_str("cmp x2, #0");
+        self.emit(format!("b.ge {}", stack_label));
+        self.emit(format!("add x3, x2, #{}", slot_size));
+        self.emit(format!("str w3, [x1, #{}]", VA_GR_OFFS));
+        self.emit_str("cmp x3, #0");
+        self.emit(format!("b.gt {}", stack_label));
+        self.emit(format!("ldr x3, [x1, #{}]", VA_GR_TOP));
+        self.emit_str("add x0, x3, x2");
+        self.emit(format!("b {}", end_label));
+
+        self.emit(format!("{}:", stack_label));
+        self.emit(format!("ldr x0, [x1, #{}]", VA_STACK));
+        self.emit(format!("add x3, x0, #{}", slot_size));
+        self.emit(format!("str x3, [x1, #{}]", VA_STACK));
+
+        self.emit(format!("{}:", end_label));
+        if by_reference {
+            self.emit_str("ldr x0, [x0]");
+        }
+    }
+}
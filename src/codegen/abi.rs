@@ -0,0 +1,225 @@
+// Calling conventions: which arguments go in registers, which go on the
+// stack, and how structs are passed and returned. System V AMD64 is the
+// model, and the other targets describe how they differ from it.
+// There's no floating point in ass, so every eightbyte is INTEGER class.
+use super::codegen::Codegen;
+use super::instruction::register_with_width;
+use super::machine::Register;
+use super::target::Architecture;
+use super::{aarch64, riscv};
+use crate::parser::ast_utils::ASTFunctionDefinition;
+use crate::parser::types::*;
+
+pub fn signature_of (func: &ASTFunctionDefinition) -> FunctionTypeMetadata {
+    FunctionTypeMetadata {
+        return_type: Box::new(func.return_type.clone()),
+        params: func.params.iter().map(|p| p.param_type.clone()).collect(),
+        variadic: func.variadic
+    }
+}
+
+impl FunctionTypeMetadata {
+    // Big structs are returned through memory the caller passes a pointer to
+    pub fn returns_via_hidden_pointer (&self) -> bool {
+        matches!(classify(&self.return_type), ArgClass::Memory)
+    }
+}
+
+pub enum ArgClass {
+    // Passed in this many general purpose registers
+    Integer(usize),
+    // Passed by copying on to the stack
+    Memory
+}
+
+#[derive(Clone)]
+pub enum ArgLocation {
+    // One register per eightbyte, without the % prefix
+    Registers(Vec<&'static str>),
+    // Offset into the argument area at the top of the caller's stack
+    Stack(isize),
+    // The first eightbyte of a struct in the last register, and the
+    // second in the argument area (RISC-V)
+    Split(&'static str, isize),
+    // The address of a copy the caller made, passed like a pointer
+    Reference(Box<ArgLocation>)
+}
+
+pub fn classify (the_type: &Type) -> ArgClass {
+    match the_type {
+        Type::Struct(_) => {
+            let size = size_in_bytes(the_type);
+            if size > 16 {
+                ArgClass::Memory
+            } else {
+                ArgClass::Integer(((size + 7) / 8) as usize)
+            }
+        },
+        // Arrays decay to pointers
+        _ => ArgClass::Integer(1)
+    }
+}
+
+// What the calling conventions we follow disagree on. There's no floating
+// point, so this is everything about passing integers and structs.
+pub struct CallingConvention {
+    pub argument_registers: &'static [&'static str],
+    // Where small structs come back, an eightbyte in each
+    pub return_registers: &'static [&'static str],
+    // Where the caller's buffer for a big returned struct is passed
+    pub indirect_result_register: &'static str,
+    // Whether that takes up the first argument register
+    pub indirect_result_is_argument: bool,
+    // Whether structs too big for registers are passed as the address of
+    // a copy, instead of being copied whole on to the stack
+    pub big_structs_by_reference: bool,
+    // Whether anything after an argument that didn't fit in the registers
+    // goes on the stack too, even if it would fit
+    pub stack_ends_registers: bool,
+    // Whether a struct with only one register left for it is split
+    // between that register and the stack
+    pub splits_structs: bool,
+    // Whether va_list is just a pointer, so variadic functions have to
+    // save the argument registers right below the stack arguments for
+    // it to walk through them all in one go
+    pub contiguous_variadic_arguments: bool
+}
+
+pub static SYSTEM_V: CallingConvention = CallingConvention {
+    argument_registers: &["rdi", "rsi", "rdx", "rcx", "r8", "r9"],
+    return_registers: &["rax", "rdx"],
+    indirect_result_register: "rdi",
+    indirect_result_is_argument: true,
+    big_structs_by_reference: false,
+    stack_ends_registers: false,
+    splits_structs: false,
+    contiguous_variadic_arguments: false
+};
+
+pub fn calling_convention (arch: Architecture) -> &'static CallingConvention {
+    match arch {
+        Architecture::X86_64 => &SYSTEM_V,
+        Architecture::AArch64 => &aarch64::AAPCS64,
+        Architecture::RiscV64 => &riscv::LP64
+    }
+}
+
+// Decides where each argument goes, after the hidden return pointer if
+// there is one. Also returns the size of the stack area.
+pub fn locate_arguments (convention: &CallingConvention, types: &[Type], hidden_pointer: bool) -> (Vec<ArgLocation>, isize) {
+    let registers = convention.argument_registers;
+    let mut next_register = (hidden_pointer && convention.indirect_result_is_argument) as usize;
+    let mut stack_bytes = 0;
+    let mut locations = vec![];
+
+    for arg_type in types {
+        let by_reference = convention.big_structs_by_reference && matches!(classify(arg_type), ArgClass::Memory);
+        let count = match classify(arg_type) {
+            _ if by_reference => Some(1),
+            ArgClass::Integer(count) => Some(count),
+            ArgClass::Memory => None
+        };
+
+        let location = match count {
+            Some(count) if next_register + count <= registers.len() => {
+                let regs = registers[next_register..next_register + count].to_vec();
+                next_register += count;
+                ArgLocation::Registers(regs)
+            },
+            Some(2) if convention.splits_structs && next_register + 1 == registers.len() => {
+                let location = ArgLocation::Split(registers[next_register], stack_bytes);
+                next_register += 1;
+                stack_bytes += 8;
+                location
+            },
+            _ => {
+                if convention.stack_ends_registers {
+                    next_register = registers.len();
+                }
+                // Structs on the stack are copied whole, everything takes 8-byte slots
+                let size = if is_scalar(arg_type) || by_reference { 8 } else { size_in_bytes(arg_type) };
+                let location = ArgLocation::Stack(stack_bytes);
+                stack_bytes += align_to(size, 8);
+                location
+            }
+        };
+
+        if by_reference {
+            locations.push(ArgLocation::Reference(Box::new(location)));
+        } else {
+            locations.push(location);
+        }
+    }
+
+    (locations, stack_bytes)
+}
+
+impl ArgLocation {
+    // The registers an argument arrives in, if any
+    pub fn registers (&self) -> Vec<&'static str> {
+        match self {
+            ArgLocation::Registers(regs) => regs.clone(),
+            ArgLocation::Split(reg, _) => vec![reg],
+            ArgLocation::Reference(inner) => inner.registers(),
+            ArgLocation::Stack(_) => vec![]
+        }
+    }
+}
+
+impl Codegen {
+    // Loads part of a struct (at offset from the address in base) into a
+    // register, without reading past the end of the struct
+    pub fn emit_load_eightbyte (&mut self, base: Register, offset: isize, size: isize, dest: Register) {
+        let (base, dest) = (self.register_name(base), self.register_name(dest));
+        match self.target.arch {
+            Architecture::X86_64 => {},
+            Architecture::AArch64 => return self.a64_load_eightbyte(base, offset, size, dest),
+            Architecture::RiscV64 => return self.rv_load_eightbyte(base, offset, size, dest)
+        }
+        let dest32 = register_with_width(dest, 4);
+        match size {
+            8 => self.emit(format!("movq {}(%{}), %{}", offset, base, dest)),
+            4 => self.emit(format!("movl {}(%{}), %{}", offset, base, dest32)),
+            2 => self.emit(format!("movzwl {}(%{}), %{}", offset, base, dest32)),
+            1 => self.emit(format!("movzbl {}(%{}), %{}", offset, base, dest32)),
+            _ => {
+                // Odd sizes are built up a byte at a time, high bytes first
+                self.emit(format!("mov $0, %{}", dest));
+                for byte in (0..size).rev() {
+                    self.emit(format!("shl $8, %{}", dest));
+                    self.emit(format!("movzbl {}(%{}), %r11d", offset + byte, base));
+                    self.emit(format!("or %r11, %{}", dest));
+                }
+            }
+        }
+    }
+
+    // The opposite of emit_load_eightbyte
+    pub fn emit_store_eightbyte (&mut self, src: Register, base: Register, offset: isize, size: isize) {
+        let (src, base) = (self.register_name(src), self.register_name(base));
+        match self.target.arch {
+            Architecture::X86_64 => {},
+            Architecture::AArch64 => return self.a64_store_eightbyte(src, base, offset, size),
+            Architecture::RiscV64 => return self.rv_store_eightbyte(src, base, offset, size)
+        }
+        match size {
+            8 | 4 | 2 | 1 => {
+                let reg = register_with_width(src, size as usize);
+                self.emit(format!("mov %{}, {}(%{})", reg, offset, base));
+            },
+            _ => {
+                self.emit(format!("mov %{}, %r11", src));
+                for byte in 0..size {
+                    self.emit(format!("movb %r11b, {}(%{})", offset + byte, base));
+                    self.emit_str("shr $8, %r11");
+                }
+            }
+        }
+    }
+}
+
+// Size of each eightbyte of a struct, eg. [8, 4] for a 12 byte struct
+pub fn eightbyte_sizes (the_type: &Type) -> Vec<isize> {
+    let size = size_in_bytes(the_type);
+    (0..size).step_by(8).map(|offset| (size - offset).min(8)).collect()
+}
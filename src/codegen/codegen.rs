@@ -1,35 +1,166 @@
-use std::cmp::min;
 use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
 use crate::parser::ast_utils::*;
 use crate::parser::tokens::*;
 use crate::parser::ast_printer::print_ast_node;
-use super::stored_value::StoredValue;
-use super::constexpr::get_constant_value_from_node;
-
-// AMD64 assembly codegen
-
-// In the x86-64 System V calling convention macOS uses,
-// args are passed in certain registers and then on the stack
-static ARGUMENT_LOCATIONS: &[&str] = &[
-    "%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"
-];
-static MAX_ARGS: usize = ARGUMENT_LOCATIONS.len();
+use crate::parser::types::*;
+use super::stored_value::{StoredValue, ValueBackingStorage};
+use super::instruction::AsmLine;
+use super::frame::FrameLayout;
+use super::abi::*;
+use super::machine::{Location, Register};
+use super::typing::Typing;
+use super::peephole;
+use super::constexpr::try_get_constant_value_from_node;
+use super::target::{Target, Architecture, OperatingSystem};
+
+// Assembly codegen for x86-64, AArch64 and RISC-V
 
 pub struct Codegen {
     pub ast: Vec<ASTNode>,
     pub generated: String,
+    // Everything emitted so far, before optimisation
+    pub lines: Vec<AsmLine>,
     // Used to generate unique assembly jump labels and aligner names
     pub counter: usize,
     // A stack of hashmaps of local var names to stack offsets
     pub var_context: Vec<HashMap<String, StoredValue>>,
-    pub stack_offset: isize,
-    pub block_depth: usize,
+    // Where the current function's locals live
+    pub frame: Option<FrameLayout>,
+    // The type of the function being emitted
+    pub current_function: Option<FunctionTypeMetadata>,
+    // Bytes pushed on top of the frame by expression temporaries,
+    // so we know how to realign the stack pointer for calls
+    pub stack_depth: isize,
     // Indicates whether we're emitting inside code that will
     // not necessarily execute (if, for, while, etc.) For example, we
     // can detect whether a function is guaranteed to return or not.
     pub conditional_code_depth: usize,
     // Detects whether a function can end without returning
-    pub func_has_unconditional_return: bool
+    pub func_has_unconditional_return: bool,
+    // For each scope, where the stack pointer was saved before its first variable
+    // length array was allocated, so leaving the scope can free them
+    pub stack_marks: Vec<Option<isize>>,
+    // Where break and continue jump to, for the innermost loop or
+    // switch. (Switches can be broken out of but not continued)
+    // Along with how many scopes were open outside of the body.
+    pub break_labels: Vec<(String, usize)>,
+    pub continue_labels: Vec<(String, usize)>,
+    // For each switch we're inside, the jump labels of its cases by their ids
+    pub case_labels: Vec<HashMap<usize, String>>,
+    // File-scope names declared static, which aren't exported
+    pub internal_symbols: HashSet<String>,
+    // Globals that have had storage emitted with an initial value
+    pub defined_globals: HashSet<String>,
+    // Globals declared without a value, like "int x;", in the order they
+    // appeared. They get zeroed storage at the end if nothing defined them.
+    pub tentative_globals: Vec<String>,
+    // Globals this file defines, rather than only declaring them extern.
+    // Any others might be in a shared library, which can only be reached
+    // through the global offset table.
+    pub file_definitions: HashSet<String>,
+    pub target: Target,
+    // Programs that run without libc, so we provide their entry point
+    pub freestanding: bool
+}
+
+// The case labels that belong to a switch statement with this body,
+// which doesn't include ones in switches nested inside it
+pub fn collect_case_labels<'a> (node: &'a ASTNode, cases: &mut Vec<&'a ASTCaseLabel>) {
+    match node {
+        ASTNode::CaseLabel(label) => cases.push(label),
+        ASTNode::BlockStatement(stmts) => {
+            for stmt in stmts {
+                collect_case_labels(stmt, cases)
+            }
+        },
+        ASTNode::IfStatement(if_stmt) => {
+            collect_case_labels(&if_stmt.body, cases);
+            if let Some(else_stmt) = &if_stmt.else_stmt {
+                collect_case_labels(else_stmt, cases);
+            }
+        },
+        ASTNode::WhileLoop(while_loop) => collect_case_labels(&while_loop.body, cases),
+        ASTNode::ForLoop(for_loop) => collect_case_labels(&for_loop.body, cases),
+        _ => {}
+    }
+}
+
+// Whether control can get past the end of a statement, instead of every
+// path through it returning or looping forever
+fn can_complete (node: &ASTNode) -> bool {
+    match node {
+        ASTNode::SwitchStatement(switch) => {
+            let mut cases = vec![];
+            collect_case_labels(&switch.body, &mut cases);
+            let has_default = cases.iter().any(|case| case.value.is_none());
+            !has_default || breaks_out(&switch.body) || switch_body_can_complete(&switch.body)
+        },
+        ASTNode::ReturnStatement(_) => false,
+        ASTNode::BlockStatement(stmts) => stmts.iter().all(can_complete),
+        ASTNode::IfStatement(if_stmt) => match &if_stmt.else_stmt {
+            Some(else_stmt) => can_complete(&if_stmt.body) || can_complete(else_stmt),
+            None => true
+        },
+        ASTNode::WhileLoop(while_loop) => {
+            !is_always_true(Some(&while_loop.condition)) || breaks_out(&while_loop.body)
+        },
+        ASTNode::ForLoop(for_loop) => {
+            !is_always_true(for_loop.condition.as_deref()) || breaks_out(&for_loop.body)
+        },
+        _ => true
+    }
+}
+
+// Whether the end of a switch's body can be reached. Control jumps in
+// at a case label, so code after a return isn't necessarily dead.
+fn switch_body_can_complete (body: &ASTNode) -> bool {
+    let stmts = match body {
+        ASTNode::BlockStatement(stmts) => stmts,
+        _ => return true
+    };
+
+    let mut reachable = false;
+    for stmt in stmts {
+        let mut cases = vec![];
+        collect_case_labels(stmt, &mut cases);
+        if !cases.is_empty() {
+            // Eg. "case 1:", or a block with labels inside it, which is
+            // assumed to complete once it's been jumped into
+            reachable = true;
+        } else if reachable {
+            reachable = can_complete(stmt);
+        }
+    }
+    reachable
+}
+
+// Whether a loop body can break out of that loop (rather than one nested inside it)
+fn breaks_out (node: &ASTNode) -> bool {
+    match node {
+        ASTNode::BreakStatement => true,
+        ASTNode::BlockStatement(stmts) => stmts.iter().any(breaks_out),
+        ASTNode::IfStatement(if_stmt) => {
+            breaks_out(&if_stmt.body) || if_stmt.else_stmt.as_deref().is_some_and(breaks_out)
+        },
+        _ => false
+    }
+}
+
+// What pointer arithmetic counts in
+pub fn element_size (elem: &Type) -> isize {
+    if is_void(elem) {
+        panic!("Pointer arithmetic on a void* pointer")
+    }
+    size_in_bytes(elem)
+}
+
+// Loop conditions like "while (1)" or the missing one in "for (;;)"
+fn is_always_true (condition: Option<&ASTNode>) -> bool {
+    match condition {
+        Some(condition) => try_get_constant_value_from_node(condition).is_some_and(|value| value != 0),
+        None => true
+    }
 }
 
 impl Codegen {
@@ -39,9 +170,49 @@ impl Codegen {
         // This cannot have stack vars in it and does not get cleaned up.
         self.begin_var_scope();
 
+        // Functions can be called before they're defined, and
+        // globals can be declared extern before they're defined
+        for node in self.ast.clone() {
+            let decls = match node {
+                ASTNode::DeclarationList(decls) => decls,
+                other => vec![other]
+            };
+            for decl in decls {
+                match decl {
+                    ASTNode::FunctionDefinition(func) => {
+                        if func.storage_class == StorageClass::Static {
+                            self.internal_symbols.insert(func.name.clone());
+                        }
+                        self.declare_function(&func);
+                    },
+                    ASTNode::VariableDeclaration(var) if var.storage_class != StorageClass::Extern => {
+                        self.file_definitions.insert(var.identifier);
+                    },
+                    _ => {}
+                }
+            }
+        }
+
         for node in self.ast.clone() {
             self.emit_for_node(&node)
         }
+        self.emit_tentative_definitions();
+        if self.freestanding && self.defines_main() {
+            self.emit_entry_point();
+        }
+        if self.target.os == OperatingSystem::Linux {
+            // Our code doesn't need an executable stack
+            self.emit_str(".section .note.GNU-stack,\"\",@progbits");
+        }
+
+        // It only knows x86 instructions
+        if self.target.arch == Architecture::X86_64 {
+            peephole::optimise(&mut self.lines);
+        }
+
+        for line in &self.lines {
+            self.generated += &format!("{}\n", line);
+        }
     }
 
     fn emit_for_block (&mut self, block: &Vec<ASTNode>, is_function_body_scope: bool) {
@@ -57,21 +228,19 @@ impl Codegen {
 
             self.emit_for_node(node)
         }
-        self.end_runtime_var_scope(true);
         self.end_compiletime_var_scope();
     }
 
-    fn emit_for_node (&mut self, node: &ASTNode) {
+    pub fn emit_for_node (&mut self, node: &ASTNode) {
         match node {
             ASTNode::IntegerLiteral(int) => {
-                self.emit(format!("mov ${}, %rax", int))
+                self.emit_load_immediate(*int)
             },
             ASTNode::ReturnStatement(ret) => {
                 if self.conditional_code_depth == 0 {
                     self.func_has_unconditional_return = true;
                 }
-                self.emit_for_node(&ret);
-                self.end_runtime_var_scope(false);
+                self.emit_for_return_value(ret.as_deref());
                 self.emit_function_epilogue(false);
             },
             ASTNode::UnaryOperation(unar) => {
@@ -105,98 +274,419 @@ impl Codegen {
             ASTNode::ForLoop(for_loop) => {
                 self.emit_for_for_loop(for_loop)
             },
+            ASTNode::SwitchStatement(switch) => {
+                self.emit_for_switch_statement(switch)
+            },
+            ASTNode::CaseLabel(case) => {
+                let label = self.case_labels.last()
+                    .and_then(|labels| labels.get(&case.id));
+                match label {
+                    Some(label) => self.emit(format!("{}:", label)),
+                    None => panic!("Case label outside of a switch statement")
+                }
+            },
+            ASTNode::BreakStatement => {
+                match self.break_labels.last().cloned() {
+                    Some((label, depth)) => self.emit_jump_out_of_scopes(&label, depth),
+                    None => panic!("Break outside of a loop or switch statement")
+                }
+            },
+            ASTNode::ContinueStatement => {
+                match self.continue_labels.last().cloned() {
+                    Some((label, depth)) => self.emit_jump_out_of_scopes(&label, depth),
+                    None => panic!("Continue outside of a loop")
+                }
+            },
             ASTNode::StringLiteral(st) => {
-                self.emit_for_string_literal(&st)
-            }
+                self.emit_for_string_literal(st)
+            },
+            ASTNode::MemberAccess(_) => {
+                let member_type = self.type_of_node(node);
+                self.emit_address_of(node);
+                self.emit_load(&member_type, &Location::Pointer(Register::Accumulator));
+            },
+            ASTNode::VaArg(va_arg) => {
+                self.emit_for_va_arg(va_arg)
+            },
+            ASTNode::Cast(cast) => {
+                self.emit_for_cast(cast)
+            },
+            ASTNode::CompoundLiteral(lit) => {
+                let offset = self.emit_for_compound_literal(lit);
+                self.emit_load(&lit.literal_type, &Location::Frame(offset));
+            },
+            ASTNode::VariableArraySize(array) => {
+                self.emit_for_variable_array_size(array)
+            },
+            ASTNode::InlineAsm(asm) => {
+                self.emit_for_inline_asm(asm)
+            },
+            ASTNode::InitialiserList(_) => {
+                panic!("Initialiser list used outside of a declaration")
+            },
+            ASTNode::DeclarationList(decls) => {
+                for decl in decls {
+                    self.emit_for_node(decl)
+                }
+            },
+            ASTNode::EmptyStatement => {}
         }
     }
 
-    fn emit_for_string_literal (&mut self, st: &String) {
+    fn emit_for_string_literal (&mut self, st: &str) {
+        let label = self.emit_string_data(st);
+        self.emit_load_address(Register::Accumulator, &Location::Symbol(label));
+    }
+
+    // Puts the string's characters in the data section, returning their label
+    pub fn emit_string_data (&mut self, st: &str) -> String {
         self.emit_str(".data");
         let label = self.get_unique_label("string");
         self.emit(format!("{}:", label));
         self.emit(format!(".string \"{}\"", st));
         self.emit_str(".text");
-        self.emit(format!("lea {}(%rip), %rax", label));
+        label
     }
 
     fn emit_for_function_call (&mut self, func_call: &ASTFunctionCall) {
-        // println!("Call to {}", func_call.name);
-        // First 6 args are put into registers
-        let reg_args = min(func_call.args.len(), MAX_ARGS);
-        for i in 0..reg_args {
-            let arg = &func_call.args[i];
-            let arg_loc = ARGUMENT_LOCATIONS[i];
-            self.emit_for_node(arg);
-            self.emit(format!("mov %rax, {}", arg_loc));
+        if self.emit_for_stdarg_call(func_call) || self.emit_for_builtin_syscall(func_call) {
+            return
         }
 
-        let arg_allocs = (MAX_ARGS..func_call.args.len()).len() as isize;
-        self.align_stack(arg_allocs * -8);
+        let signature = self.type_of_callee(func_call);
+        // Calls to a function by name don't need to go through a pointer.
+        // Functions that haven't been declared are assumed to exist somewhere.
+        let direct_label = match &*func_call.callee {
+            ASTNode::Identifier(name) => match self.try_find_var(name).map(|v| &v.backing_store) {
+                Some(ValueBackingStorage::Function(_)) | None => Some(self.target.symbol(name)),
+                _ => None
+            },
+            _ => None
+        };
+
+        // Arguments without a declared parameter keep their own type
+        let arg_types: Vec<Type> = func_call.args.iter().enumerate().map(|(i, arg)| {
+            let own_type = self.type_of_node(arg);
+            check_not_void(&own_type);
+            match signature.as_ref().and_then(|sig| sig.params.get(i)) {
+                Some(param_type) => param_type.clone(),
+                None => decayed(own_type)
+            }
+        }).collect();
+
+        let arch = self.target.arch;
+        let convention = calling_convention(arch);
+        let hidden_pointer = signature.as_ref().is_some_and(|sig| sig.returns_via_hidden_pointer());
+        let (locations, stack_bytes) = locate_arguments(convention, &arg_types, hidden_pointer);
+
+        // Reserve the stack argument area up front, padded so that
+        // the stack pointer is 16-byte aligned at the call
+        let reserved = align_to(self.stack_depth + stack_bytes, 16) - self.stack_depth;
+        if reserved != 0 {
+            self.emit_reserve_stack(reserved);
+            self.stack_depth += reserved;
+        }
+        let base_depth = self.stack_depth;
+
+        if direct_label.is_none() {
+            self.emit_for_node(&func_call.callee);
+            self.emit_push(Register::Accumulator);
+        }
 
-        // Additional args are pushed on to the stack in reverse order
-        for i in (MAX_ARGS..func_call.args.len()).rev() {
-            let arg = &func_call.args[i];
+        // Every argument is evaluated before any registers are filled,
+        // since evaluating one could clobber another (eg. with a call)
+        for ((arg, arg_type), location) in func_call.args.iter().zip(&arg_types).zip(&locations) {
+            check_qualifiers_kept(&self.type_of_node(arg), arg_type);
             self.emit_for_node(arg);
-            self.emit_str("push %rax");
+            if is_scalar(arg_type) {
+                self.emit_normalise(arg_type);
+            }
+
+            // Big structs passed by reference are copied first, so the
+            // callee can't change the caller's
+            let (location, is_pointer) = match location {
+                ArgLocation::Reference(inner) => {
+                    let copy = self.allocate_temporary(arg_type);
+                    self.emit_load_address(Register::Secondary, &Location::Frame(copy));
+                    self.emit_memory_copy(size_in_bytes(arg_type));
+                    self.emit_load_address(Register::Accumulator, &Location::Frame(copy));
+                    (&**inner, true)
+                },
+                _ => (location, is_scalar(arg_type))
+            };
+
+            match location {
+                ArgLocation::Stack(offset) => {
+                    let loc = Location::Stack(offset + self.stack_depth - base_depth);
+                    if is_pointer {
+                        self.emit_store_register(Register::Accumulator, 8, &loc);
+                    } else {
+                        self.emit_load_address(Register::Secondary, &loc);
+                        self.emit_memory_copy(size_in_bytes(arg_type));
+                    }
+                },
+                ArgLocation::Registers(_) => {
+                    if is_pointer {
+                        self.emit_push(Register::Accumulator);
+                    } else {
+                        // Structs are split in to eightbytes
+                        for (i, size) in eightbyte_sizes(arg_type).into_iter().enumerate() {
+                            self.emit_load_eightbyte(Register::Accumulator, i as isize * 8, size, Register::Scratch);
+                            self.emit_push(Register::Scratch);
+                        }
+                    }
+                },
+                // The first eightbyte waits to go in its register with
+                // the others, the second goes straight on to the stack
+                ArgLocation::Split(_, offset) => {
+                    let sizes = eightbyte_sizes(arg_type);
+                    self.emit_load_eightbyte(Register::Accumulator, 0, sizes[0], Register::Scratch);
+                    self.emit_push(Register::Scratch);
+                    self.emit_load_eightbyte(Register::Accumulator, 8, sizes[1], Register::Scratch);
+                    let loc = Location::Stack(offset + self.stack_depth - base_depth);
+                    self.emit_store_register(Register::Scratch, 8, &loc);
+                },
+                ArgLocation::Reference(_) => unreachable!("References are passed like pointers")
+            }
+        }
+
+        for location in locations.iter().rev() {
+            for reg in location.registers().into_iter().rev() {
+                self.emit_pop(Register::Named(reg));
+            }
+        }
+
+        if direct_label.is_none() {
+            self.emit_pop(Register::Callee);
         }
 
-        self.emit(format!("call _{}", func_call.name));
+        // Returned structs need somewhere to live
+        let return_slot = match &signature {
+            Some(sig) if matches!(*sig.return_type, Type::Struct(_)) => {
+                Some(self.allocate_temporary(&sig.return_type))
+            },
+            _ => None
+        };
+        if hidden_pointer {
+            let register = Register::Named(convention.indirect_result_register);
+            self.emit_load_address(register, &Location::Frame(return_slot.unwrap()));
+        }
+
+        let may_be_variadic = signature.as_ref().is_none_or(|sig| sig.variadic);
+        self.emit_call(direct_label.as_deref(), may_be_variadic);
+
+        if reserved != 0 {
+            self.emit_release_stack(reserved);
+            self.stack_depth -= reserved;
+        }
+
+        // Only as much of the register as the return type needs is set, eg.
+        // %al for a char, so it's extended to 64 bits like any other value
+        match &signature {
+            Some(sig) if is_scalar(&sig.return_type) => self.emit_normalise(&sig.return_type),
+            Some(_) => {},
+            // Undeclared functions are assumed to return int
+            None => self.emit_normalise(&int_type())
+        }
+
+        // Small structs come back in two registers. Put them in memory so
+        // that the accumulator can hold their address like any other struct.
+        // Big ones are already there, and x86-64 returns their address.
+        if let (Some(slot), Some(sig)) = (return_slot, &signature) {
+            if !hidden_pointer {
+                let sizes = eightbyte_sizes(&sig.return_type);
+                for (i, (size, reg)) in sizes.into_iter().zip(convention.return_registers).enumerate() {
+                    self.emit_store_eightbyte(Register::Named(reg), Register::FramePointer, slot + i as isize * 8, size);
+                }
+            }
+            if !hidden_pointer || arch != Architecture::X86_64 {
+                self.emit_load_address(Register::Accumulator, &Location::Frame(slot));
+            }
+        }
+    }
+
+    fn allocate_temporary (&mut self, the_type: &Type) -> isize {
+        match &mut self.frame {
+            Some(frame) => frame.allocate_temporary(the_type),
+            None => unreachable!("Temporary needed outside of a function")
+        }
+    }
+
+    fn emit_for_return_value (&mut self, value: Option<&ASTNode>) {
+        let return_type = match &self.current_function {
+            Some(sig) => *sig.return_type.clone(),
+            None => panic!("Return statement outside of a function")
+        };
+        match value {
+            // "return f();" is fine in a void function if f returns void too
+            Some(value) if is_void(&return_type) => {
+                if !is_void(&self.type_of_node(value)) {
+                    panic!("Void function returns a value")
+                }
+                self.emit_for_node(value);
+            },
+            Some(value) => {
+                let value_type = self.type_of_node(value);
+                check_not_void(&value_type);
+                check_qualifiers_kept(&value_type, &return_type);
+                self.emit_for_node(value);
+                // Converted to the declared type, eg. "return 300;" from a char function
+                if is_scalar(&return_type) {
+                    self.emit_normalise(&return_type);
+                }
+                self.emit_for_struct_return();
+            },
+            None if is_void(&return_type) => {},
+            None => panic!("Non-void function returns without a value")
+        }
+    }
+
+    // Struct values are returned by their address in the accumulator, which
+    // has to be turned in to what the ABI expects before the epilogue
+    fn emit_for_struct_return (&mut self) {
+        let return_type = match &self.current_function {
+            Some(sig) => *sig.return_type.clone(),
+            None => return
+        };
+        if let Type::Struct(_) = return_type {
+            let return_pointer = self.frame.as_ref().and_then(|frame| frame.return_pointer);
+            match return_pointer {
+                Some(offset) => {
+                    // Copy in to the caller's buffer and return its address
+                    self.emit_load_register(Register::Secondary, &Location::Frame(offset));
+                    self.emit_memory_copy(size_in_bytes(&return_type));
+                    self.emit_move(Register::Accumulator, Register::Secondary);
+                },
+                None => {
+                    self.emit_move(Register::Secondary, Register::Accumulator);
+                    let sizes = eightbyte_sizes(&return_type);
+                    let return_registers = calling_convention(self.target.arch).return_registers;
+                    for (i, (size, reg)) in sizes.into_iter().zip(return_registers).enumerate() {
+                        self.emit_load_eightbyte(Register::Secondary, i as isize * 8, size, Register::Named(reg));
+                    }
+                }
+            }
+        }
     }
 
     fn emit_for_function_definition (&mut self, func: &ASTFunctionDefinition) {
-        // For now, we don't care about function definitions without a body.
-        // They would be important if we didn't know a func's return type, but this compiler
-        // doesn't deal with types atm
+        // Function declarations without a body only matter for their signature
         if let Some(body) = &func.body {
-            self.emit(format!(".globl _{}", func.name));
+            // Prototypes inside functions are only in scope there
+            self.declare_function(func);
+
+            if !self.internal_symbols.contains(&func.name) {
+                let symbol = self.target.symbol(&func.name);
+                self.emit(format!(".globl {}", symbol));
+            }
+
+            self.emit(format!("{}:", self.target.symbol(&func.name)));
 
-            self.emit(format!("_{}:", func.name));
-            self.emit_function_prologue();
+            let frame = FrameLayout::new(func, body, self.target.arch);
+            let frame_size_line = self.emit_function_prologue(&frame);
 
             // Alloc arguments
             self.begin_var_scope();
 
-            let reg_args = min(func.params.len(), MAX_ARGS);
-            for i in 0..reg_args {
-                let arg = &func.params[i];
-                let arg_loc = ARGUMENT_LOCATIONS[i];
-                self.emit_stack_alloc_from_location(&arg, arg_loc);
+            if let Some(offset) = frame.return_pointer {
+                let register = Register::Named(calling_convention(self.target.arch).indirect_result_register);
+                self.emit_store_register(register, 8, &Location::Frame(offset));
+            }
+            if let Some(offset) = frame.register_save_area {
+                self.emit_register_save_area(offset);
             }
 
-            // Additional args are in the stack in reverse order
-            let mut offset: isize = 16;
-            for i in (MAX_ARGS..func.params.len()).rev() {
-                let arg = &func.params[i];
-                self.stack_alloc_from_arbitrary_offset(&arg, offset);
-                offset += 8;
+            for (arg, (location, offset)) in func.params.iter().zip(&frame.params) {
+                // Register args are spilled to the frame, stack args
+                // can be used where the caller put them
+                match location {
+                    ArgLocation::Registers(regs) if is_scalar(&arg.param_type) => {
+                        // Only store as many bytes as the parameter's type has
+                        let width = size_in_bytes(&arg.param_type) as usize;
+                        self.emit_store_register(Register::Named(regs[0]), width, &Location::Frame(*offset));
+                    },
+                    ArgLocation::Registers(regs) => {
+                        let sizes = eightbyte_sizes(&arg.param_type);
+                        for (i, (size, reg)) in sizes.into_iter().zip(regs).enumerate() {
+                            self.emit_store_eightbyte(Register::Named(reg), Register::FramePointer, offset + i as isize * 8, size);
+                        }
+                    },
+                    // The pointer waits in the param's slot until every
+                    // register is safely spilled, then it's copied over
+                    ArgLocation::Reference(inner) => {
+                        if let ArgLocation::Registers(regs) = &**inner {
+                            self.emit_store_register(Register::Named(regs[0]), 8, &Location::Frame(*offset));
+                        }
+                    },
+                    // The stack half of a split struct is copied in afterwards too
+                    ArgLocation::Split(reg, _) => {
+                        let size = eightbyte_sizes(&arg.param_type)[0];
+                        self.emit_store_eightbyte(Register::Named(reg), Register::FramePointer, *offset, size);
+                    },
+                    ArgLocation::Stack(_) => {}
+                }
+                // Unnamed params can't be used
+                if !arg.name.is_empty() {
+                    self.declare_var(arg, ValueBackingStorage::Stack(*offset));
+                }
             }
+            for (arg, (location, offset)) in func.params.iter().zip(&frame.params) {
+                match location {
+                    ArgLocation::Reference(inner) => {
+                        let pointer = match &**inner {
+                            ArgLocation::Stack(stack_offset) => Location::Frame(stack_offset + frame.stack_arguments),
+                            _ => Location::Frame(*offset)
+                        };
+                        self.emit_load_register(Register::Accumulator, &pointer);
+                        self.emit_load_address(Register::Secondary, &Location::Frame(*offset));
+                        self.emit_memory_copy(size_in_bytes(&arg.param_type));
+                    },
+                    ArgLocation::Split(_, stack_offset) => {
+                        let size = eightbyte_sizes(&arg.param_type)[1];
+                        let base = Register::FramePointer;
+                        self.emit_load_eightbyte(base, stack_offset + frame.stack_arguments, size, Register::Accumulator);
+                        self.emit_store_eightbyte(Register::Accumulator, base, offset + 8, size);
+                    },
+                    _ => {}
+                }
+            }
+            self.frame = Some(frame);
+            self.current_function = Some(signature_of(func));
 
             self.emit_for_block(body, true);
 
-            // Dealloc args
-            // self.end_var_scope_without_dealloc();
-
             if !self.func_has_unconditional_return {
-                // We couldn't guarantee that the function will return a value
-                self.emit_function_epilogue(true);
+                // main returns 0 if it gets to the end, but other functions
+                // have to return their values themselves
+                let is_main = func.name == "main";
+                if !is_main && !is_void(&func.return_type) && body.iter().all(can_complete) {
+                    panic!("Function \"{}\" can reach its end without returning a value", func.name)
+                }
+                self.emit_function_epilogue(is_main);
             }
             self.func_has_unconditional_return = false;
+
+            // Temporaries may have made the frame bigger
+            if let Some(frame) = &self.frame {
+                for (i, line) in self.frame_reservation(frame.size).into_iter().enumerate() {
+                    self.lines[frame_size_line + i] = self.asm_line(&line);
+                }
+            }
         }
     }
 
     fn emit_for_if_statement (&mut self, if_stmt: &ASTIfStatement) {
         self.conditional_code_depth += 1;
 
+        check_not_void(&self.type_of_node(&if_stmt.condition));
         self.emit_for_node(&if_stmt.condition);
-        self.emit_str("cmp $0, %rax");
 
         let skip_label = self.get_unique_label("if_skip");
         let else_label = self.get_unique_label("else");
-        self.emit(format!("je {}", else_label));
+        self.emit_jump_if_zero(&else_label);
 
         self.emit_for_node(&if_stmt.body);
-        self.emit(format!("jmp {}", skip_label));
+        self.emit_jump(&skip_label);
 
         self.emit(format!("{}:", else_label));
 
@@ -217,15 +707,15 @@ impl Codegen {
         self.emit(format!("{}:", start_label));
 
         // Eval condition
+        check_not_void(&self.type_of_node(&while_loop.condition));
         self.emit_for_node(&while_loop.condition);
-        self.emit_str("cmp $0, %rax");
-        self.emit(format!("je {}", end_label));
+        self.emit_jump_if_zero(&end_label);
 
         // Run body
-        self.emit_for_node(&while_loop.body);
+        self.emit_for_loop_body(&while_loop.body, &end_label, &start_label);
 
         // Unconditionally jump to top
-        self.emit(format!("jmp {}", start_label));
+        self.emit_jump(&start_label);
 
         self.emit(format!("{}:", end_label));
 
@@ -245,123 +735,228 @@ impl Codegen {
         }
 
         let start_label = self.get_unique_label("for_start");
+        let continue_label = self.get_unique_label("for_continue");
         let end_label = self.get_unique_label("for_end");
 
         self.emit(format!("{}:", start_label));
 
         if let Some(condition) = &for_loop.condition {
+            check_not_void(&self.type_of_node(condition));
             self.emit_for_node(condition);
         } else {
             // If condition is empty, it's truthy
-            self.emit_str("mov $1, %rax");
+            self.emit_load_immediate(1);
         }
 
-        self.emit_str("cmp $0, %rax");
-        self.emit(format!("je {}", end_label));
+        self.emit_jump_if_zero(&end_label);
 
-        self.emit_for_node(&for_loop.body);
+        self.emit_for_loop_body(&for_loop.body, &end_label, &continue_label);
 
+        self.emit(format!("{}:", continue_label));
         if let Some(modification) = &for_loop.modification {
             self.emit_for_node(modification);
         }
 
-        self.emit(format!("jmp {}", start_label));
+        self.emit_jump(&start_label);
 
         self.emit(format!("{}:", end_label));
 
-        self.end_runtime_var_scope(true);
         self.end_compiletime_var_scope();
 
         self.conditional_code_depth -= 1;
     }
 
+    fn emit_for_loop_body (&mut self, body: &ASTNode, break_label: &str, continue_label: &str) {
+        let depth = self.var_context.len();
+        self.break_labels.push((break_label.to_string(), depth));
+        self.continue_labels.push((continue_label.to_string(), depth));
+        self.emit_for_node(body);
+        self.continue_labels.pop();
+        self.break_labels.pop();
+    }
+
+    // Compares the value against every case up front, then jumps in to the
+    // body at the matching case label and falls through from there
+    fn emit_for_switch_statement (&mut self, switch: &ASTSwitchStatement) {
+        self.conditional_code_depth += 1;
+
+        let end_label = self.get_unique_label("switch_end");
+        let mut cases = vec![];
+        collect_case_labels(&switch.body, &mut cases);
+
+        check_not_void(&self.type_of_node(&switch.value));
+        self.emit_for_node(&switch.value);
+
+        let mut labels = HashMap::new();
+        let mut values = vec![];
+        let mut default_label = None;
+        for case in cases {
+            let label = self.get_unique_label("case");
+            match case.value {
+                Some(value) => {
+                    if values.contains(&value) {
+                        panic!("Duplicate case value {} in switch statement", value)
+                    }
+                    values.push(value);
+                    self.emit_jump_if_equal(value, &label);
+                },
+                None => {
+                    if default_label.is_some() {
+                        panic!("Multiple default labels in one switch statement")
+                    }
+                    default_label = Some(label.clone());
+                }
+            }
+            labels.insert(case.id, label);
+        }
+        self.emit_jump(default_label.as_ref().unwrap_or(&end_label));
+
+        self.case_labels.push(labels);
+        self.break_labels.push((end_label.clone(), self.var_context.len()));
+        self.emit_for_node(&switch.body);
+        self.break_labels.pop();
+        self.case_labels.pop();
+
+        self.emit(format!("{}:", end_label));
+
+        self.conditional_code_depth -= 1;
+    }
+
     fn emit_for_variable_declaration (&mut self, var: &ASTVariableDeclaration) {
-        // If there's an initial value, we'll put it in eax, if not we'll
-        // shove whatever random vallue we were last using in there (it's UB)
+        let name_and_type = ASTNameAndType {
+            name: var.identifier.clone(),
+            param_type: var.var_type.clone()
+        };
+
+        let is_local = self.var_context.len() > 1;
+        if let Type::VariableArray(_) = var.var_type {
+            // They're allocated on the stack when the declaration runs
+            if !is_local || var.storage_class != StorageClass::Unspecified {
+                panic!("Variable length array \"{}\" can't have static storage", var.identifier)
+            }
+        }
 
-        if self.var_context.len() == 1 {
+        if !is_local {
             // If only the global context exists, this is a global variable
-            let constant_value = match &var.initial_value {
-                Some(init) => get_constant_value_from_node(init),
-                None => 0,
-            };
+            self.emit_for_global_variable_declaration(var, name_and_type);
+            return
+        }
 
-            self.emit_global_alloc_from_constant(
-                &ASTNameAndType {
-                    name: var.identifier.clone(),
-                    param_type: var.var_type.clone()
-                },
-                constant_value
-            );
-        } else {
-            // Else it is a local (stack) variable
-            if let Some(init) = &var.initial_value {
-                self.emit_for_node(init);
+        match var.storage_class {
+            StorageClass::Static => {
+                // Static locals are globals only this scope can see. They're
+                // initialised once, before the program starts, so the value
+                // has to be a constant (or a constant address).
+                // The dot keeps it from clashing with any C name
+                self.counter += 1;
+                let symbol = format!("{}.{}", var.identifier, self.counter);
+                self.file_definitions.insert(symbol.clone());
+                self.emit_static_storage(&symbol, &var.var_type, var.initial_value.as_deref(), false);
+                self.declare_var(&name_and_type, ValueBackingStorage::Global(symbol));
+            },
+            StorageClass::Extern => {
+                self.declare_var(&name_and_type, ValueBackingStorage::Global(var.identifier.clone()));
+            },
+            StorageClass::Unspecified => {
+                // Else it is a local (stack) variable
+                let offset = match &self.frame {
+                    Some(frame) => frame.slot_for(var),
+                    None => unreachable!("Local variable outside of a function")
+                };
+
+                if let Type::VariableArray(arr) = &var.var_type {
+                    if var.initial_value.is_some() {
+                        panic!("Variable length arrays can't be initialised")
+                    }
+                    self.emit_for_variable_array_allocation(offset, arr);
+                    self.declare_var(&name_and_type, ValueBackingStorage::Stack(offset));
+                    return
+                }
+
+                // If there's no initial value, whatever was in the slot
+                // before is left there (it's UB to read it)
+                match var.initial_value.as_deref() {
+                    Some(init @ ASTNode::InitialiserList(_)) => {
+                        self.emit_for_local_initialiser(offset, &var.var_type, init)
+                    },
+                    Some(init @ ASTNode::StringLiteral(_)) if matches!(var.var_type, Type::Array(_)) => {
+                        self.emit_for_local_initialiser(offset, &var.var_type, init)
+                    },
+                    Some(init) => {
+                        let init_type = self.type_of_node(init);
+                        check_not_void(&init_type);
+                        check_qualifiers_kept(&init_type, &var.var_type);
+                        self.emit_for_node(init);
+                        self.emit_store(&var.var_type, &Location::Frame(offset));
+                    },
+                    None => {}
+                }
+
+                self.declare_var(&name_and_type, ValueBackingStorage::Stack(offset));
             }
+        }
+    }
 
-            self.emit_stack_alloc_from_rax(&ASTNameAndType {
-                name: var.identifier.clone(),
-                param_type: var.var_type.clone()
-            });
+    fn emit_for_global_variable_declaration (&mut self, var: &ASTVariableDeclaration, name_and_type: ASTNameAndType) {
+        let name = &var.identifier;
+        if var.storage_class == StorageClass::Static {
+            self.internal_symbols.insert(name.clone());
+        }
+        self.declare_global_var(&name_and_type);
+
+        match (&var.initial_value, var.storage_class) {
+            (Some(init), _) => {
+                if !self.defined_globals.insert(name.clone()) {
+                    panic!("Redefinition of global variable \"{}\"", name)
+                }
+                let exported = !self.internal_symbols.contains(name);
+                // The type might have been completed by an earlier declaration
+                let var_type = self.find_var(name).value_type.clone();
+                self.emit_static_storage(name, &var_type, Some(init), exported);
+            },
+            // Defined somewhere else, maybe in another file
+            (None, StorageClass::Extern) => {},
+            // A tentative definition
+            (None, _) => {
+                if !self.tentative_globals.contains(name) {
+                    self.tentative_globals.push(name.clone());
+                }
+            }
         }
     }
 
     fn emit_for_binary_operation (&mut self, bin: &ASTBinaryOperation) {
         if is_binary_stack_operator(&bin.operator) {
+            let left_type = self.type_of_node(&bin.left_side);
+            let right_type = self.type_of_node(&bin.right_side);
+            let result_type = self.type_of_node(&ASTNode::BinaryOperation(bin.clone()));
+            // Comparisons of pointers and unsigned values are unsigned
+            let operand_type = arithmetic_result_type(&left_type, &right_type);
+            let signed = is_signed(&operand_type);
+
             // Emit stack precursor
             self.emit_for_node(&bin.left_side);
-            self.emit_str("push %rax");
+            self.emit_push(Register::Accumulator);
             self.emit_for_node(&bin.right_side);
-            self.emit_str("pop %rcx");
+            self.emit_pop(Register::Secondary);
+            // Now the left side is in the secondary register and the right side is in the accumulator
 
-            match &bin.operator[..] {
-                "+" => self.emit_str("addl %ecx, %eax"),
-                "-" => {
-                    self.emit_str("subl %eax, %ecx");
-                    self.emit_str("movl %ecx, %eax")
-                },
-                "*" => self.emit_str("imul %ecx, %eax"),
-                "/" => {
-                    self.emit_str("movl %eax, %r8d");
-                    self.emit_str("movl %ecx, %eax");
-                    self.emit_str("cdq");
-                    self.emit_str("idivl %r8d");
-                },
-                "%" => {
-                    // TODO: Share more code between / and %
-                    self.emit_str("movl %eax, %r8d");
-                    self.emit_str("movl %ecx, %eax");
-                    self.emit_str("cdq");
-                    self.emit_str("idivl %r8d");
-                    self.emit_str("mov %rdx, %rax");
-                },
-                "==" => {
-                    self.emit_for_comparison_precursor();
-                    self.emit_str("sete %al");
-                },
-                "!=" => {
-                    self.emit_for_comparison_precursor();
-                    self.emit_str("setne %al");
-                },
-                ">" => {
-                    self.emit_for_comparison_precursor();
-                    self.emit_str("setg %al");
-                },
-                "<" => {
-                    self.emit_for_comparison_precursor();
-                    self.emit_str("setl %al");
-                },
-                ">=" => {
-                    self.emit_for_comparison_precursor();
-                    self.emit_str("setge %al");
-                },
-                "<=" => {
-                    self.emit_for_comparison_precursor();
-                    self.emit_str("setle %al");
-                },
-                _ => unimplemented!("\"{}\" stack operator", bin.operator)
+            if bin.operator == "+" || bin.operator == "-" {
+                self.emit_for_pointer_arithmetic_scaling(&left_type, &right_type);
+            }
+            self.emit_arithmetic(&bin.operator, signed);
+            if bin.operator == "-" {
+                if let (Some(elem), Some(_)) = (pointed_to_type(&left_type), pointed_to_type(&right_type)) {
+                    // Pointer difference counts elements, not bytes
+                    self.emit_exact_division(element_size(&elem));
+                }
             }
 
+            // The maths is done in 64 bits, so bring it back in to range.
+            // (Comparisons always give 0 or 1 which needs no fixing)
+            if !is_comparison_operator(&bin.operator) {
+                self.emit_normalise(&result_type);
+            }
             return;
         }
 
@@ -373,21 +968,18 @@ impl Codegen {
                 let skip_label = self.get_unique_label("skip");
                 let end_label = self.get_unique_label("end");
 
-                self.emit_str("cmpl $0, %eax");
                 // If exp1 was false, we need to jump to evaluating exp2
-                self.emit(format!("je {}", skip_label));
+                self.emit_jump_if_zero(&skip_label);
 
-                // Otherwise, we set eax to true and skip to the end
-                self.emit_str("movl $1, %eax");
-                self.emit(format!("jmp {}", end_label));
+                // Otherwise, we set it to true and skip to the end
+                self.emit_load_immediate(1);
+                self.emit_jump(&end_label);
 
                 self.emit(format!("{}:", skip_label));
                 self.emit_for_node(&bin.right_side);
 
-                // Now we compare eax to 0 and set eax to the opposite of that comparison
-                self.emit_str("cmpl $0, %eax");
-                self.emit_str("movl $0, %eax");
-                self.emit_str("setne %al");
+                // Now it's 1 if exp2 was true, or 0
+                self.emit_truth_value();
 
                 self.emit(format!("{}:", end_label));
             },
@@ -398,99 +990,136 @@ impl Codegen {
                 let skip_label = self.get_unique_label("skip");
                 let end_label = self.get_unique_label("end");
 
-                self.emit_str("cmpl $0, %eax");
-                self.emit(format!("jne {}", skip_label));
-                self.emit(format!("jmp {}", end_label));
+                self.emit_jump_if_not_zero(&skip_label);
+                self.emit_jump(&end_label);
 
                 self.emit(format!("{}:", skip_label));
                 self.emit_for_node(&bin.right_side);
 
-                self.emit_str("cmp $0, %rax");
-                self.emit_str("mov $0, %rax");
-                self.emit_str("setne %al");
+                self.emit_truth_value();
 
                 self.emit(format!("{}:", end_label))
             },
             // Assignemnts (remember these are expressions with a value!)
             "=" => {
-                let loc = self.get_stored_value_location(self.find_node(&bin.left_side));
-                self.emit_for_node(&bin.right_side);
-                self.emit(format!("mov %rax, {}", loc));
+                let target_type = self.type_of_node(&bin.left_side);
+                if qualifiers_of(&target_type).is_const {
+                    panic!("Assignment to a read-only (const) location")
+                }
+                check_qualifiers_kept(&self.type_of_node(&bin.right_side), &target_type);
+
+                let direct = match &*bin.left_side {
+                    ASTNode::Identifier(ident) => self.get_stored_value_location(self.find_var(ident)),
+                    _ => None
+                };
+                if let Some(loc) = direct {
+                    // Variables can be stored to directly
+                    self.emit_for_node(&bin.right_side);
+                    self.emit_store(&target_type, &loc);
+                } else {
+                    self.emit_address_of(&bin.left_side);
+                    self.emit_push(Register::Accumulator);
+                    self.emit_for_node(&bin.right_side);
+                    self.emit_pop(Register::Secondary);
+                    self.emit_store(&target_type, &Location::Pointer(Register::Secondary));
+                }
             },
             _ => unimplemented!("\"{}\" non-stack operator", bin.operator)
         }
     }
 
-    fn find_node (&self, node: &ASTNode) -> &StoredValue {
-        match &node {
-            ASTNode::Identifier(ident) => self.find_var(ident),
-            _ => panic!("Cannot resolve non-identifier assignable")
+    // When adding an integer to a pointer, the integer counts elements
+    fn emit_for_pointer_arithmetic_scaling (&mut self, left_type: &Type, right_type: &Type) {
+        match (pointed_to_type(left_type), pointed_to_type(right_type)) {
+            (Some(elem), None) => self.emit_scale(Register::Accumulator, element_size(&elem)),
+            (None, Some(elem)) => self.emit_scale(Register::Secondary, element_size(&elem)),
+            _ => {}
         }
     }
 
-    fn emit_for_comparison_precursor (&mut self) {
-        self.emit_str("cmp %rax, %rcx");
-        self.emit_str("mov $0, %rax");
+    // Emits code that puts the address of an lvalue into the accumulator
+    pub fn emit_address_of (&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::Identifier(ident) => {
+                let value = self.find_var(ident).clone();
+                self.emit_load_address_of_stored_value(&value);
+            },
+            ASTNode::UnaryOperation(unar) if unar.operator == "*" => {
+                // The address is the pointer's value
+                self.emit_for_node(&unar.operand);
+            },
+            ASTNode::MemberAccess(mem) => {
+                let member = match self.type_of_node(&mem.object) {
+                    Type::Struct(st) => st.find_member(&mem.member),
+                    _ => panic!("Member access \".{}\" on a non-struct value", mem.member)
+                };
+                // Struct values are already represented by their address
+                self.emit_for_node(&mem.object);
+                if member.offset != 0 {
+                    self.emit_add_immediate(member.offset);
+                }
+            },
+            ASTNode::CompoundLiteral(lit) => {
+                let offset = self.emit_for_compound_literal(lit);
+                self.emit_load_address(Register::Accumulator, &Location::Frame(offset));
+            },
+            _ => panic!("Cannot take the address of a non-lvalue expression")
+        }
+    }
+
+    // Every scalar is held sign or zero extended to 64 bits in a register,
+    // so a cast only has to re-extend it from the target type's width.
+    // Pointers are 64 bits, so int <-> pointer conversions are free.
+    fn emit_for_cast (&mut self, cast: &ASTCast) {
+        if is_void(&cast.target_type) {
+            // The value is thrown away, eg. "(void)unused;"
+            self.emit_for_node(&cast.operand);
+            return
+        }
+        let operand_type = self.type_of_node(&cast.operand);
+        check_not_void(&operand_type);
+        if !is_scalar(&cast.target_type) {
+            panic!("Cast to a non-scalar type")
+        }
+        if let Type::Struct(_) = operand_type {
+            panic!("Cast of a struct value")
+        }
+
+        self.emit_for_node(&cast.operand);
+        self.emit_normalise(&cast.target_type);
     }
 
     fn emit_for_unary_operation (&mut self, unar: &ASTUnaryOperation) {
         if is_pointer_operator(&unar.operator) {
-            let value = self.find_node(&unar.operand).clone();
-
             match &unar.operator[..] {
                 "&" => {
-                    self.emit_load_address_of_stored_value(&value);
+                    self.emit_address_of(&unar.operand);
                 },
                 "*" => {
-                    self.emit_for_stored_value_access(&value);
-                    self.emit_str("movq (%rax), %rax");
+                    let pointee = self.type_of_node(&ASTNode::UnaryOperation(unar.clone()));
+                    self.emit_for_node(&unar.operand);
+                    self.emit_load(&pointee, &Location::Pointer(Register::Accumulator));
                 },
                 _ => unimplemented!("Pointer operator {}", unar.operator)
             }
         } else {
+            let result_type = self.type_of_node(&ASTNode::UnaryOperation(unar.clone()));
             self.emit_for_node(&unar.operand);
 
             match &unar.operator[..] {
-                "-" => {
-                    self.emit_str("neg %eax")
-                },
-                "~" => {
-                    self.emit_str("not %eax")
-                },
-                "!" => {
-                    self.emit_str("cmp $0, %rax");
-                    self.emit_str("mov $0, %rax");
-                    self.emit_str("setz %al");
+                "-" | "~" => {
+                    self.emit_unary_arithmetic(&unar.operator);
+                    self.emit_normalise(&result_type);
                 },
+                "!" => self.emit_logical_not(),
                 _ => panic!("Codegen unimplemented for unary operator \"{}\"", unar.operator)
             }
         }
     }
 
-    fn emit_function_prologue (&mut self) {
-        // Save the old base pointer
-        self.emit_str("push %rbp");
-        // The stack head is the new base
-        self.emit_str("mov %rsp, %rbp");
-    }
-
-    fn emit_function_epilogue (&mut self, gen_return_value: bool) {
-        if gen_return_value {
-            // Functions without a return statement return 0
-            self.emit_str("mov $0, %rax");
-        }
-
-        // Stack head is the base
-        self.emit_str("mov %rbp, %rsp");
-        // Restore the old base
-        self.emit_str("pop %rbp");
-        // Jump out of func
-        self.emit_str("ret");
-    }
-
-    fn get_unique_label (&mut self, comment: &str) -> String {
+    pub fn get_unique_label (&mut self, comment: &str) -> String {
         self.counter += 1;
-        format!("_{}_{}", comment, self.counter)
+        self.target.local_label(&format!("{}_{}", comment, self.counter))
     }
 
     pub fn emit_str (&mut self, st: &str) {
@@ -498,19 +1127,50 @@ impl Codegen {
     }
 
     pub fn emit (&mut self, st: String) {
-        self.generated = format!("{}{}\n", self.generated, st)
+        let line = self.asm_line(&st);
+        self.lines.push(line)
+    }
+
+    // Only x86 instructions are picked apart, for the peephole optimiser
+    pub fn asm_line (&self, st: &str) -> AsmLine {
+        match self.target.arch {
+            Architecture::X86_64 => AsmLine::parse(st),
+            _ => match AsmLine::parse(st) {
+                AsmLine::Instruction(_) => AsmLine::Raw(st.trim().to_string()),
+                other => other
+            }
+        }
+    }
+
+    // The last instruction emitted reads or writes a volatile object
+    pub fn mark_volatile_access (&mut self) {
+        if let Some(AsmLine::Instruction(inst)) = self.lines.last_mut() {
+            inst.volatile = true;
+        }
     }
 
-    pub fn new (ast: Vec<ASTNode>) -> Codegen {
+    pub fn new (ast: Vec<ASTNode>, target: Target, freestanding: bool) -> Codegen {
         Codegen {
             ast,
             generated: String::from(""),
+            lines: vec![],
             counter: 0,
             var_context: vec![],
-            stack_offset: 0,
-            block_depth: 0,
+            stack_marks: vec![],
+            frame: None,
+            current_function: None,
+            stack_depth: 0,
             conditional_code_depth: 0,
-            func_has_unconditional_return: false
+            func_has_unconditional_return: false,
+            break_labels: vec![],
+            continue_labels: vec![],
+            case_labels: vec![],
+            internal_symbols: HashSet::new(),
+            defined_globals: HashSet::new(),
+            tentative_globals: vec![],
+            file_definitions: HashSet::new(),
+            target,
+            freestanding
         }
     }
 }
@@ -1,25 +1,67 @@
 // This allows us to try and get constants from sets of ASTNodes
 // Useful, for example, for emmitting compile-time constant globals.
-use crate::parser::ast_utils::{ASTNode, ASTBinaryOperation};
+use crate::parser::ast_utils::{ASTNode, ASTBinaryOperation, ASTUnaryOperation, ASTCast};
+use crate::parser::types::*;
 
 pub fn get_constant_value_from_node(node: &ASTNode) -> isize {
+    match try_get_constant_value_from_node(node) {
+        Some(value) => value,
+        None => panic!("Constant propagation was not sophisticated enough to determine a value.
+OR you attempted to declare a constant value with a non-constant expression.")
+    }
+}
+
+// None if the node isn't an integer constant expression (it might
+// still be a constant address, eg. &x, see initialiser.rs)
+pub fn try_get_constant_value_from_node(node: &ASTNode) -> Option<isize> {
     match node {
-        ASTNode::IntegerLiteral(int) => *int,
+        ASTNode::IntegerLiteral(int) => Some(*int),
         ASTNode::BinaryOperation(bin) => resolve_binary_operation(bin),
-        _ => panic!("Constant propagation was not sophisticated enough to determine a value.
-OR you attempted to declare a constant value with a non-constant expression.")
+        ASTNode::UnaryOperation(unar) => resolve_unary_operation(unar),
+        ASTNode::Cast(cast) => resolve_cast(cast),
+        _ => None
+    }
+}
+
+// Truncates and re-extends the value like the cast would at runtime
+fn resolve_cast (cast: &ASTCast) -> Option<isize> {
+    let value = try_get_constant_value_from_node(&cast.operand)?;
+    let bits = match &cast.target_type {
+        Type::Char(_) => 8,
+        Type::Short(_) => 16,
+        Type::Int(_) => 32,
+        Type::LongLongInt(_) | Type::Pointer(_) => return Some(value),
+        _ => panic!("Constant cast to a non-scalar type")
+    };
+
+    let shift = 64 - bits;
+    if is_signed(&cast.target_type) {
+        Some((value << shift) >> shift)
+    } else {
+        Some(((value as usize) << shift >> shift) as isize)
+    }
+}
+
+fn resolve_unary_operation (unar: &ASTUnaryOperation) -> Option<isize> {
+    let operand = try_get_constant_value_from_node(&unar.operand)?;
+    match &unar.operator[..] {
+        "+" => Some(operand),
+        "-" => Some(-operand),
+        "~" => Some(!operand),
+        "!" => Some((operand == 0) as isize),
+        _ => None
     }
 }
 
-fn resolve_binary_operation (bin: &ASTBinaryOperation) -> isize {
-    let left = get_constant_value_from_node(&bin.left_side);
-    let right = get_constant_value_from_node(&bin.right_side);
+fn resolve_binary_operation (bin: &ASTBinaryOperation) -> Option<isize> {
+    let left = try_get_constant_value_from_node(&bin.left_side)?;
+    let right = try_get_constant_value_from_node(&bin.right_side)?;
     match &bin.operator[..] {
-        "+" => left + right,
-        "-" => left - right,
-        "*" => left * right,
-        "/" => left / right,
-        "%" => left % right,
+        "+" => Some(left + right),
+        "-" => Some(left - right),
+        "*" => Some(left * right),
+        "/" => Some(left / right),
+        "%" => Some(left % right),
         _ => panic!("Binary operator {} unknown to constant propagation", bin.operator)
     }
 }
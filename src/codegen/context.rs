@@ -3,90 +3,125 @@ use std::collections::hash_map::HashMap;
 use super::codegen::Codegen;
 use super::stored_value::*;
 use crate::parser::types::*;
-use crate::parser::ast_utils::ASTNameAndType;
+use crate::parser::ast_utils::{ASTNode, ASTNameAndType, ASTFunctionDefinition};
+use super::abi::signature_of;
 
 impl Codegen {
     pub fn find_var (&self, name: &String) -> &StoredValue {
-        for map in self.var_context.iter().rev() {
-            if map.contains_key(name) {
-                return &map[name];
-            }
+        match self.try_find_var(name) {
+            Some(value) => value,
+            None => panic!("Unresolved variable reference \"{}\"", name)
         }
+    }
 
-        panic!("Unresolved variable reference \"{}\"", name)
+    pub fn try_find_var (&self, name: &String) -> Option<&StoredValue> {
+        self.var_context.iter().rev().find_map(|map| map.get(name))
     }
 
     pub fn begin_var_scope (&mut self) {
         // println!(" = SCOPE BEGAN = ");
         self.var_context.push(HashMap::new());
+        self.stack_marks.push(None);
     }
 
     // This is emitted when curly braces end
     pub fn end_compiletime_var_scope (&mut self) {
         // println!(" = COMPILETIME SCOPE ENDED = ");
         self.var_context.pop();
-    }
-    // This is emitted when a function returns at any time
-    // Depth is how many compound statements deep we are at the time of returning.
-    // Eg. in an if statement within a function is depth: 2
-    pub fn end_runtime_var_scope (&mut self, mutate_stack_offset: bool) {
-        let scope = &self.var_context[self.var_context.len() - 1];
-        // Each stack item is 64 bits (8 bytes)
-        // We need to dealloc this scope by moving up
-        // the stack pointer so future vars are alloced higher
-        let dealloc_bytes = scope.len() * 8;
-        // println!("In this scope (stack_offset is {}):", self.stack_offset);
-        // for varname in scope {
-        //     println!(" - {} ({})", varname.0, varname.1);
-        // }
-
-        self.emit(format!("add ${}, %rsp", dealloc_bytes));
-        if mutate_stack_offset {
-            self.stack_offset += dealloc_bytes as isize;
-            // println!("runtime var stack deflation deallocced {} bytes ({})", dealloc_bytes, self.stack_offset);
+        // Variable length arrays are freed when their scope ends
+        if let Some(mark) = self.stack_marks.pop().flatten() {
+            self.emit_restore_stack_pointer(mark);
         }
-
-        // println!(" = RUNTIME SCOPE ENDED = (mutate: {})", mutate_stack_offset);
     }
 
-    pub fn emit_global_alloc_from_constant (&mut self, var: &ASTNameAndType, value: isize) {
-        let label = self.get_global_var_label(&var.name);
-        let align_pot = power_of_two_alignment(&var.param_type);
-        let literal_name = global_literal_name(&var.param_type);
-
-        self.emit(format!(".globl {}", label));
-        self.emit_str(".data");
-        self.emit(format!(".align {}", align_pot));
+    // Storage for a variable that lives as long as the program does.
+    // Only exported symbols can be seen from other files, and const
+    // ones go in a read-only section so writing to them faults, or
+    // one the loader protects once it has filled in their addresses.
+    // Without an initialiser it's zero-filled, in .bss.
+    pub fn emit_static_storage (&mut self, symbol: &str, var_type: &Type, init: Option<&ASTNode>, exported: bool) {
+        let label = self.get_global_var_label(symbol);
+        let align_pot = power_of_two_alignment(var_type);
+
+        // Worked out first, since it can emit string literals of its own
+        let (section, directives) = match init {
+            Some(init) => {
+                let (directives, has_addresses) = self.static_initialiser_directives(var_type, init);
+                let section = match (qualifiers_of(var_type).is_const, has_addresses) {
+                    (true, false) => self.target.read_only_section(),
+                    (true, true) => self.target.relocated_read_only_section(),
+                    (false, _) => ".data"
+                };
+                (section, directives)
+            },
+            None => (".bss", vec![format!(".zero {}", size_in_bytes(var_type))])
+        };
+
+        if exported {
+            self.emit(format!(".globl {}", label));
+        }
+        self.emit_str(section);
+        self.emit(self.target.align_directive(align_pot));
         self.emit(format!("{}:", label));
-        self.emit(format!("{} {}", literal_name, value));
+        for directive in directives {
+            self.emit(directive);
+        }
         self.emit_str(".text");
+    }
 
-        let latest = self.var_context.len() - 1;
-        let map = &mut self.var_context[latest];
+    // File-scope variables can be declared any number of times,
+    // eg. "extern int x; int x = 3;" but only defined once
+    pub fn declare_global_var (&mut self, var: &ASTNameAndType) {
+        if let Some(existing) = self.var_context[0].get_mut(&var.name) {
+            if let ValueBackingStorage::Global(_) = existing.backing_store {
+                // Later declarations can complete the type, eg. "int a[]; int a[3];"
+                let incomplete = matches!(&var.param_type, Type::Array(arr) if arr.length == 0);
+                if !incomplete {
+                    existing.value_type = var.param_type.clone();
+                }
+                return
+            }
+        }
 
-        map.insert(var.name.clone(), StoredValue {
-            backing_store: ValueBackingStorage::Global(var.name.clone()),
-            value_type: Type::Int(IntegerTypeMetadata {
-                signed: true
-            })
-        });
+        self.declare_var(var, ValueBackingStorage::Global(var.name.clone()));
     }
 
-    pub fn stack_alloc_from_arbitrary_offset (&mut self, var: &ASTNameAndType, offset: isize) {
-        let latest = self.var_context.len() - 1;
-        let map = &mut self.var_context[latest];
+    // Globals that were only ever declared like "int x;" are zero-initialised.
+    // Exported ones are common symbols, so other files can declare them the
+    // same way and still end up sharing one variable.
+    pub fn emit_tentative_definitions (&mut self) {
+        for name in self.tentative_globals.clone() {
+            if self.defined_globals.contains(&name) {
+                continue
+            }
 
-        map.insert(var.name.clone(), StoredValue {
-            backing_store: ValueBackingStorage::Stack(offset),
-            value_type: var.param_type.clone()
-        });
+            let var_type = self.var_context[0][&name].value_type.clone();
+            if self.internal_symbols.contains(&name) {
+                self.emit_static_storage(&name, &var_type, None, false);
+            } else {
+                let label = self.get_global_var_label(&name);
+                let align_pot = power_of_two_alignment(&var_type);
+                self.emit(self.target.common_directive(&label, size_in_bytes(&var_type), align_pot));
+            }
+        }
     }
 
-    pub fn emit_stack_alloc_from_location(&mut self, var: &ASTNameAndType, location: &str) {
-        self.emit(format!("push {}", location));
-        self.stack_offset -= 8;
-        // println!("alloc from {} as \"{}\" ({})", location, name, self.stack_offset);
+    // Symbols this file defines for other files to use. Tentative definitions
+    // are left out since they're common symbols, which the linker merges.
+    pub fn exported_definitions (&self) -> Vec<String> {
+        let functions = self.ast.iter().filter_map(|node| match node {
+            ASTNode::FunctionDefinition(func) if func.body.is_some() => Some(&func.name),
+            _ => None
+        });
+        let mut names: Vec<String> = functions.chain(self.defined_globals.iter())
+            .filter(|name| !self.internal_symbols.contains(*name))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
 
+    pub fn declare_var (&mut self, var: &ASTNameAndType, backing_store: ValueBackingStorage) {
         let latest = self.var_context.len() - 1;
         let map = &mut self.var_context[latest];
 
@@ -95,33 +130,39 @@ impl Codegen {
         }
 
         map.insert(var.name.clone(), StoredValue {
-            backing_store: ValueBackingStorage::Stack(self.stack_offset),
+            backing_store,
             value_type: var.param_type.clone()
         });
     }
 
-    pub fn emit_stack_alloc_from_rax (&mut self, var: &ASTNameAndType) {
-        self.emit_stack_alloc_from_location(var, "%rax")
-    }
-
-    // NOTE: If you want to align when you're just about to push
-    // some new stuff, but that stuff needs to be on top, provide
-    // the future bytes eg. -8
-    pub fn align_stack (&mut self, future_bytes: isize) {
-        let total = self.stack_offset + future_bytes;
-        if total % 16 != 0 {
-            self.counter += 1;
-            self.emit_stack_alloc_from_location(&ASTNameAndType {
-                name: format!("__ASS_ALIGN_{}", self.counter),
-                param_type: Type::LongLongInt(IntegerTypeMetadata { signed: false })
-            }, "$0");
+    // Functions can be declared any number of times, unlike variables, as
+    // long as they agree. "int f();" doesn't say what the parameters are,
+    // so a later prototype like "int f(char c);" can fill them in.
+    pub fn declare_function (&mut self, func: &ASTFunctionDefinition) {
+        let latest = self.var_context.len() - 1;
+        let signature = signature_of(func);
+        if let Some(existing) = self.var_context[latest].get_mut(&func.name) {
+            if let (ValueBackingStorage::Function(_), Type::Function(known)) = (&existing.backing_store, &existing.value_type) {
+                if known.return_type != signature.return_type {
+                    panic!("Conflicting return types for function \"{}\"", func.name)
+                }
+                let unspecified = known.params.is_empty() && !known.variadic;
+                if unspecified {
+                    existing.value_type = Type::Function(signature);
+                }
+                return
+            }
         }
+
+        self.declare_var(&ASTNameAndType {
+            name: func.name.clone(),
+            param_type: Type::Function(signature_of(func))
+        }, ValueBackingStorage::Function(func.name.clone()));
     }
 
-    pub fn get_global_var_label (&self, name: &String) -> String {
-        // This isn't technically requried, we're just making sure you don't
-        // accidentally name your variable after a jump label ass happens to
-        // be using, as unlikely as that is.
-        format!("_GLOBAL_VAR_{}", name)
+    // Globals have their C names, so that other files (including ones
+    // from other compilers) can refer to them
+    pub fn get_global_var_label (&self, name: &str) -> String {
+        self.target.symbol(name)
     }
 }
@@ -0,0 +1,192 @@
+// Works out where every local variable of a function lives before any of
+// its code is emitted, so the whole frame can be reserved with one sub.
+// Variables in scopes that can't be alive at the same time (eg. the bodies
+// of two different if statements) share the same stack slots.
+// Temporaries that are only discovered while emitting (like structs returned
+// from calls) are added on the end, and the sub is fixed up afterwards.
+use std::collections::hash_map::HashMap;
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+use super::abi::*;
+use super::target::Architecture;
+use super::variable_array::variable_array_slot_type;
+
+pub struct FrameLayout {
+    // Bytes reserved below the frame pointer. Always a multiple of 16
+    // so that the stack pointer stays aligned for calls
+    pub size: isize,
+    // Where each parameter arrived and the frame pointer offset it lives at
+    pub params: Vec<(ArgLocation, isize)>,
+    // Where the caller's return buffer address is saved, for
+    // functions that return big structs
+    pub return_pointer: Option<isize>,
+    // Variadic functions save every argument register here, so va_arg
+    // can walk through the ones the named params didn't use
+    pub register_save_area: Option<isize>,
+    // Frame pointer offset of the caller's stack arguments, which are
+    // above the return address and saved frame pointer (and on RISC-V,
+    // the register save area)
+    pub stack_arguments: isize,
+    // How many argument registers and how much of the caller's stack
+    // area the named params take up
+    pub named_registers: usize,
+    pub named_stack_bytes: isize,
+    // Frame pointer offsets of local variables, keyed by their declaration's id
+    slots: HashMap<usize, isize>,
+    // Bytes below the frame pointer given out so far, including temporaries
+    used: isize
+}
+
+struct LayoutBuilder {
+    // Bytes currently in use below the frame pointer
+    offset: isize,
+    // The most that was ever in use at once
+    max_offset: isize,
+    slots: HashMap<usize, isize>
+}
+
+impl FrameLayout {
+    pub fn new (func: &ASTFunctionDefinition, body: &[ASTNode], arch: Architecture) -> FrameLayout {
+        let mut builder = LayoutBuilder {
+            offset: 0,
+            max_offset: 0,
+            slots: HashMap::new()
+        };
+
+        let signature = signature_of(func);
+        let return_pointer = if signature.returns_via_hidden_pointer() {
+            Some(builder.allocate(&pointer_to(*signature.return_type.clone())))
+        } else {
+            None
+        };
+
+        let convention = calling_convention(arch);
+        let hidden_pointer = return_pointer.is_some();
+        let (locations, named_stack_bytes) = locate_arguments(convention, &signature.params, hidden_pointer);
+        let hidden_register = (hidden_pointer && convention.indirect_result_is_argument) as usize;
+        let named_registers = if convention.stack_ends_registers && named_stack_bytes > 0 {
+            // Any registers that were left over can't be used any more
+            convention.argument_registers.len()
+        } else {
+            hidden_register + locations.iter().map(|location| location.registers().len()).sum::<usize>()
+        };
+
+        let save_area_size = convention.argument_registers.len() as isize * 8;
+        let mut stack_arguments = 16;
+        let register_save_area = if !signature.variadic {
+            None
+        } else if convention.contiguous_variadic_arguments {
+            // Between the saved frame pointer and the stack arguments
+            stack_arguments += save_area_size;
+            Some(16)
+        } else {
+            let area_type = Type::Array(ArrayTypeMetadata {
+                element: Box::new(long_type()),
+                length: convention.argument_registers.len()
+            });
+            Some(builder.allocate(&area_type))
+        };
+
+        let params = locations.into_iter().zip(&signature.params).map(|(location, param_type)| {
+            let offset = match location {
+                // Register params are spilled in to the frame, and ones
+                // passed by reference or split are copied in to it
+                ArgLocation::Registers(_) | ArgLocation::Reference(_) | ArgLocation::Split(..) => builder.allocate(param_type),
+                ArgLocation::Stack(offset) => offset + stack_arguments
+            };
+            (location, offset)
+        }).collect();
+
+        builder.walk_nodes(body);
+
+        FrameLayout {
+            size: align_to(builder.max_offset, 16),
+            params,
+            return_pointer,
+            register_save_area,
+            stack_arguments,
+            named_registers,
+            named_stack_bytes,
+            slots: builder.slots,
+            used: builder.max_offset
+        }
+    }
+
+    pub fn slot_for (&self, var: &ASTVariableDeclaration) -> isize {
+        match self.slots.get(&var.id) {
+            Some(offset) => *offset,
+            None => panic!("No stack slot was laid out for \"{}\"", var.identifier)
+        }
+    }
+
+    // Space that lasts for the rest of the function. Returns its frame pointer offset
+    pub fn allocate_temporary (&mut self, the_type: &Type) -> isize {
+        self.used = align_to(self.used + size_in_bytes(the_type), alignment_in_bytes(the_type));
+        self.size = align_to(self.used, 16);
+        -self.used
+    }
+}
+
+impl LayoutBuilder {
+    // Returns the new variable's offset from the frame pointer
+    fn allocate (&mut self, var_type: &Type) -> isize {
+        let size = size_in_bytes(var_type);
+        let align = alignment_in_bytes(var_type);
+
+        self.offset = align_to(self.offset + size, align);
+        self.max_offset = self.max_offset.max(self.offset);
+        -self.offset
+    }
+
+    // Anything declared inside is freed up again afterwards
+    fn scoped<F: FnOnce(&mut LayoutBuilder)> (&mut self, f: F) {
+        let before = self.offset;
+        f(self);
+        self.offset = before;
+    }
+
+    fn walk_nodes (&mut self, nodes: &[ASTNode]) {
+        for node in nodes {
+            self.walk_node(node);
+        }
+    }
+
+    fn walk_node (&mut self, node: &ASTNode) {
+        match node {
+            // Static and extern locals don't live on the stack
+            ASTNode::VariableDeclaration(var) if var.storage_class == StorageClass::Unspecified => {
+                let offset = match var.var_type {
+                    // The elements themselves are allocated at run time
+                    Type::VariableArray(_) => self.allocate(&variable_array_slot_type()),
+                    _ => self.allocate(&var.var_type)
+                };
+                self.slots.insert(var.id, offset);
+            },
+            ASTNode::DeclarationList(decls) => self.walk_nodes(decls),
+            ASTNode::BlockStatement(stmts) => {
+                self.scoped(|b| b.walk_nodes(stmts))
+            },
+            ASTNode::IfStatement(if_stmt) => {
+                self.scoped(|b| b.walk_node(&if_stmt.body));
+                if let Some(else_stmt) = &if_stmt.else_stmt {
+                    self.scoped(|b| b.walk_node(else_stmt));
+                }
+            },
+            ASTNode::WhileLoop(while_loop) => {
+                self.scoped(|b| b.walk_node(&while_loop.body))
+            },
+            ASTNode::SwitchStatement(switch) => {
+                self.scoped(|b| b.walk_node(&switch.body))
+            },
+            ASTNode::ForLoop(for_loop) => {
+                self.scoped(|b| {
+                    if let Some(declaration) = &for_loop.declaration {
+                        b.walk_node(declaration);
+                    }
+                    b.walk_node(&for_loop.body);
+                })
+            },
+            _ => {}
+        }
+    }
+}
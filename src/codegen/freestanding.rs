@@ -0,0 +1,89 @@
+// Support for programs that run without libc (-ffreestanding/-nostdlib).
+// They need an entry point of their own, and a way to make system calls
+// since there's no library to wrap them.
+use super::codegen::Codegen;
+use super::instruction::AsmLine;
+use super::machine::Register;
+use super::target::Target;
+use crate::parser::ast_utils::*;
+
+// Where the x86-64 Linux kernel looks for a system call's arguments.
+// The number goes in %rax, which is also where the result comes back.
+static SYSCALL_ARGUMENT_LOCATIONS: &[&str] = &[
+    "rdi", "rsi", "rdx", "r10", "r8", "r9"
+];
+// exit_group, which ends every thread like _exit does
+static SYS_EXIT_GROUP: usize = 231;
+
+// __builtin_syscall(number, args...) returns a long
+pub fn is_builtin_syscall (func_call: &ASTFunctionCall) -> bool {
+    matches!(&*func_call.callee, ASTNode::Identifier(name) if name == "__builtin_syscall")
+}
+
+// The kernel starts us at _start with argc on top of the stack,
+// followed by the argv and envp arrays. %rsp is 16-byte aligned.
+pub fn entry_point_assembly (target: Target) -> Vec<String> {
+    let main = target.symbol("main");
+    vec![
+        ".globl _start".to_string(),
+        "_start:".to_string(),
+        // Marks the outermost frame for debuggers
+        "xor %ebp, %ebp".to_string(),
+        "mov (%rsp), %rdi".to_string(),
+        "lea 8(%rsp), %rsi".to_string(),
+        "lea 16(%rsp,%rdi,8), %rdx".to_string(),
+        format!("call {}", main),
+        "mov %eax, %edi".to_string(),
+        format!("mov ${}, %eax", SYS_EXIT_GROUP),
+        "syscall".to_string()
+    ]
+}
+
+impl Codegen {
+    // Returns false if the call isn't to __builtin_syscall
+    pub fn emit_for_builtin_syscall (&mut self, func_call: &ASTFunctionCall) -> bool {
+        if !is_builtin_syscall(func_call) {
+            return false
+        }
+        if self.target != Target::X86_64_LINUX {
+            panic!("__builtin_syscall is only supported when targeting x86-64 Linux")
+        }
+        if func_call.args.is_empty() || func_call.args.len() > SYSCALL_ARGUMENT_LOCATIONS.len() + 1 {
+            panic!("__builtin_syscall takes a system call number and up to {} arguments", SYSCALL_ARGUMENT_LOCATIONS.len())
+        }
+
+        // Everything is evaluated before any registers are filled,
+        // since evaluating one argument could clobber another
+        for arg in &func_call.args {
+            self.emit_for_node(arg);
+            self.emit_push(Register::Accumulator);
+        }
+        for i in (1..func_call.args.len()).rev() {
+            self.emit_pop(Register::Named(SYSCALL_ARGUMENT_LOCATIONS[i - 1]));
+        }
+        self.emit_pop(Register::Accumulator);
+        // Clobbers %rcx and %r11, which are caller-saved anyway
+        self.emit_str("syscall");
+        true
+    }
+
+    // Other files could be linked in to provide main instead
+    pub fn defines_main (&self) -> bool {
+        self.ast.iter().any(|node| match node {
+            ASTNode::FunctionDefinition(func) => func.name == "main" && func.body.is_some(),
+            _ => false
+        })
+    }
+
+    pub fn emit_entry_point (&mut self) {
+        if self.target != Target::X86_64_LINUX {
+            panic!("Freestanding programs are only supported when targeting x86-64 Linux")
+        }
+
+        let lines = entry_point_assembly(self.target);
+        // Nothing here should be touched by the peephole optimiser
+        for line in lines {
+            self.lines.push(AsmLine::Raw(line));
+        }
+    }
+}
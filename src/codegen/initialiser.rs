@@ -0,0 +1,378 @@
+// Initialisers for variables, which can be (nested) braced lists for
+// aggregates, eg. "struct point ps[] = { {1, 2}, {3, 4} };"
+// Globals and static locals are initialised by data directives in the
+// assembly, so their initialisers have to be constants or addresses of
+// things with a fixed location (eg. "int *p = &x;" or "char *s = "hi";")
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+use super::codegen::Codegen;
+use super::constexpr::try_get_constant_value_from_node;
+use super::machine::{Location, Register};
+use super::stored_value::ValueBackingStorage;
+use super::target::Architecture;
+use super::typing::Typing;
+
+// A scalar, or a char array initialised from a string, somewhere in
+// the object being initialised
+pub struct InitialiserLeaf<'a> {
+    pub offset: isize,
+    pub leaf_type: Type,
+    pub value: &'a ASTNode
+}
+
+// Works out which value goes where, following the braces where there are
+// any and filling sub-objects in order where there aren't (brace elision),
+// eg. "int a[2][2] = {1, 2, 3, 4};"
+struct Flattener<'a> {
+    leaves: Vec<InitialiserLeaf<'a>>
+}
+
+impl<'a> Flattener<'a> {
+    // The whole initialiser for one object
+    fn object (&mut self, the_type: &Type, offset: isize, init: &'a ASTNode) {
+        match init {
+            ASTNode::InitialiserList(values) if is_scalar(the_type) => {
+                // Eg. "int x = {3};"
+                if values.len() != 1 {
+                    panic!("Scalar initialisers can only have one value in braces")
+                }
+                self.object(the_type, offset, &values[0])
+            },
+            ASTNode::InitialiserList(values) => {
+                let mut position = 0;
+                self.fill_from_list(the_type, offset, values, &mut position);
+                if position < values.len() {
+                    panic!("Too many values in initialiser")
+                }
+            },
+            _ => {
+                if let Type::Array(_) = the_type {
+                    if !is_string_for_char_array(the_type, init) {
+                        panic!("Arrays can only be initialised with a braced list or a string")
+                    }
+                }
+                self.leaves.push(InitialiserLeaf {
+                    offset,
+                    leaf_type: the_type.clone(),
+                    value: init
+                })
+            }
+        }
+    }
+
+    // Fills an aggregate's members or elements from values[position..],
+    // stopping when it's full. Returns how many it filled.
+    fn fill_from_list (&mut self, the_type: &Type, offset: isize, values: &'a [ASTNode], position: &mut usize) -> usize {
+        let mut filled = 0;
+        match the_type {
+            Type::Array(arr) => {
+                let element_size = size_in_bytes(&arr.element);
+                // Arrays declared with [] take as many as there are
+                while *position < values.len() && (arr.length == 0 || filled < arr.length) {
+                    self.sub_object(&arr.element, offset + filled as isize * element_size, values, position);
+                    filled += 1;
+                }
+            },
+            Type::Struct(st) => {
+                let members = match &st.definition.borrow().members {
+                    Some(members) => members.clone(),
+                    None => panic!("Initialiser for an incomplete struct type")
+                };
+                for member in members {
+                    if *position >= values.len() { break }
+                    self.sub_object(&member.member_type, offset + member.offset, values, position);
+                    filled += 1;
+                }
+            },
+            _ => unreachable!("Only aggregates are filled from lists")
+        }
+        filled
+    }
+
+    fn sub_object (&mut self, the_type: &Type, offset: isize, values: &'a [ASTNode], position: &mut usize) {
+        let value = &values[*position];
+        let whole_value = is_scalar(the_type) ||
+            matches!(value, ASTNode::InitialiserList(_)) ||
+            is_string_for_char_array(the_type, value);
+
+        if whole_value {
+            self.object(the_type, offset, value);
+            *position += 1;
+        } else {
+            // The braces were left out, so it takes as many values as it needs
+            self.fill_from_list(the_type, offset, values, position);
+        }
+    }
+}
+
+fn is_string_for_char_array (the_type: &Type, value: &ASTNode) -> bool {
+    match (the_type, value) {
+        (Type::Array(arr), ASTNode::StringLiteral(_)) => matches!(*arr.element, Type::Char(_)),
+        _ => false
+    }
+}
+
+// The scalars (and strings) to store, in order of offset
+pub fn flatten_initialiser<'a> (the_type: &Type, init: &'a ASTNode) -> Vec<InitialiserLeaf<'a>> {
+    let mut flattener = Flattener { leaves: vec![] };
+    flattener.object(the_type, 0, init);
+    flattener.leaves
+}
+
+// Arrays declared with [] get their length from their initialiser,
+// eg. "int a[] = {1, 2, 3};" or "char s[] = "hi";"
+pub fn completed_type (the_type: Type, init: &ASTNode) -> Type {
+    let arr = match &the_type {
+        Type::Array(arr) if arr.length == 0 => arr,
+        _ => return the_type
+    };
+
+    let length = match init {
+        ASTNode::StringLiteral(st) if is_string_for_char_array(&the_type, init) => {
+            decoded_string_literal(st).len() + 1
+        },
+        ASTNode::InitialiserList(values) => {
+            let mut flattener = Flattener { leaves: vec![] };
+            flattener.fill_from_list(&the_type, 0, values, &mut 0)
+        },
+        _ => return the_type
+    };
+
+    Type::Array(ArrayTypeMetadata {
+        element: arr.element.clone(),
+        length
+    })
+}
+
+// The bytes a string literal stands for. Literals keep their escape
+// sequences from the source, since the assembler understands them too.
+pub fn decoded_string_literal (st: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut chars = st.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some(other) => other as u8,
+            None => b'\\'
+        });
+    }
+    bytes
+}
+
+impl Codegen {
+    // The data directives for a global or static variable's initial value,
+    // and whether any of them are addresses the linker has to fill in
+    pub fn static_initialiser_directives (&mut self, the_type: &Type, init: &ASTNode) -> (Vec<String>, bool) {
+        let mut directives = vec![];
+        let mut position = 0;
+        let mut has_addresses = false;
+
+        for leaf in flatten_initialiser(the_type, init) {
+            if leaf.offset > position {
+                directives.push(format!(".zero {}", leaf.offset - position));
+            }
+            has_addresses |= is_scalar(&leaf.leaf_type) && try_get_constant_value_from_node(leaf.value).is_none();
+            directives.extend(self.static_leaf_directives(&leaf));
+            position = leaf.offset + size_in_bytes(&leaf.leaf_type);
+        }
+
+        let size = size_in_bytes(the_type);
+        if size > position {
+            directives.push(format!(".zero {}", size - position));
+        }
+        (directives, has_addresses)
+    }
+
+    fn static_leaf_directives (&mut self, leaf: &InitialiserLeaf) -> Vec<String> {
+        let size = size_in_bytes(&leaf.leaf_type);
+
+        if let ASTNode::StringLiteral(st) = leaf.value {
+            if let Type::Array(_) = leaf.leaf_type {
+                let bytes = decoded_string_literal(st);
+                if (bytes.len() as isize) < size {
+                    let mut directives = vec![format!(".string \"{}\"", st)];
+                    if bytes.len() as isize + 1 < size {
+                        directives.push(format!(".zero {}", size - bytes.len() as isize - 1));
+                    }
+                    return directives
+                }
+                // Eg. char s[2] = "hi"; which has no room for the terminator
+                let truncated: Vec<String> = bytes[..size as usize].iter().map(|b| b.to_string()).collect();
+                return vec![format!(".byte {}", truncated.join(", "))]
+            }
+        }
+
+        if !is_scalar(&leaf.leaf_type) {
+            panic!("Initialiser for a global or static variable isn't constant")
+        }
+        if let Some(value) = try_get_constant_value_from_node(leaf.value) {
+            return vec![format!("{} {}", global_literal_name(&leaf.leaf_type), value)]
+        }
+
+        // Addresses are only known once the program is linked, so they're
+        // left for the assembler and linker to fill in
+        match self.constant_address(leaf.value) {
+            Some((label, offset)) if size == 8 => {
+                check_qualifiers_kept(&self.type_of_node(leaf.value), &leaf.leaf_type);
+                if offset == 0 {
+                    vec![format!(".quad {}", label)]
+                } else {
+                    vec![format!(".quad {}{:+}", label, offset)]
+                }
+            },
+            Some(_) => panic!("Addresses don't fit in types smaller than a pointer"),
+            None => panic!("Initialiser for a global or static variable isn't constant")
+        }
+    }
+
+    // A label and offset from it, if the expression is an address that's
+    // fixed when the program is linked
+    fn constant_address (&mut self, node: &ASTNode) -> Option<(String, isize)> {
+        match node {
+            // Arrays and functions decay to their address
+            ASTNode::Identifier(_) if !is_scalar(&self.type_of_node(node)) => {
+                if let Type::Struct(_) = self.type_of_node(node) {
+                    return None
+                }
+                self.constant_lvalue_address(node)
+            },
+            ASTNode::StringLiteral(st) => Some((self.emit_string_data(st), 0)),
+            ASTNode::CompoundLiteral(lit) if matches!(lit.literal_type, Type::Array(_)) => {
+                self.constant_lvalue_address(node)
+            },
+            ASTNode::UnaryOperation(unar) if unar.operator == "&" => {
+                self.constant_lvalue_address(&unar.operand)
+            },
+            ASTNode::Cast(cast) => self.constant_address(&cast.operand),
+            ASTNode::BinaryOperation(bin) if bin.operator == "+" || bin.operator == "-" => {
+                let (label, offset) = self.constant_address(&bin.left_side)?;
+                let count = try_get_constant_value_from_node(&bin.right_side)?;
+                let element = pointed_to_type(&self.type_of_node(&bin.left_side))?;
+                let moved = count * size_in_bytes(&element);
+                Some((label, if bin.operator == "+" { offset + moved } else { offset - moved }))
+            },
+            // So do arrays inside structs or other arrays, eg. s.points or grid[1]
+            _ if matches!(self.type_of_node(node), Type::Array(_)) => self.constant_lvalue_address(node),
+            _ => None
+        }
+    }
+
+    // Where an lvalue with a fixed address lives, eg. x, a[3] or s.member
+    fn constant_lvalue_address (&mut self, node: &ASTNode) -> Option<(String, isize)> {
+        match node {
+            ASTNode::Identifier(name) => match &self.try_find_var(name)?.backing_store {
+                ValueBackingStorage::Global(symbol) => Some((self.get_global_var_label(symbol), 0)),
+                ValueBackingStorage::Function(name) => Some((self.target.symbol(name), 0)),
+                ValueBackingStorage::Stack(_) => None
+            },
+            ASTNode::MemberAccess(mem) => {
+                let member = match self.type_of_node(&mem.object) {
+                    Type::Struct(st) => st.find_member(&mem.member),
+                    _ => return None
+                };
+                let (label, offset) = self.constant_lvalue_address(&mem.object)?;
+                Some((label, offset + member.offset))
+            },
+            ASTNode::UnaryOperation(unar) if unar.operator == "*" => self.constant_address(&unar.operand),
+            // Outside of functions they have static storage, like an unnamed global
+            ASTNode::CompoundLiteral(lit) => {
+                self.counter += 1;
+                let symbol = format!("compound_literal.{}", self.counter);
+                self.emit_static_storage(&symbol, &lit.literal_type, Some(&lit.initial_value), false);
+                Some((self.get_global_var_label(&symbol), 0))
+            },
+            _ => None
+        }
+    }
+
+    // Local variables initialised with a list (or a char array with a
+    // string) are zeroed, then have each value stored in to place
+    pub fn emit_for_local_initialiser (&mut self, offset: isize, the_type: &Type, init: &ASTNode) {
+        self.emit_zero_fill(offset, size_in_bytes(the_type));
+
+        for leaf in flatten_initialiser(the_type, init) {
+            let location = offset + leaf.offset;
+            match (leaf.value, &leaf.leaf_type) {
+                (ASTNode::StringLiteral(st), Type::Array(_)) => {
+                    let length = decoded_string_literal(st).len() as isize + 1;
+                    self.emit_for_node(leaf.value);
+                    self.emit_load_address(Register::Secondary, &Location::Frame(location));
+                    self.emit_memory_copy(length.min(size_in_bytes(&leaf.leaf_type)));
+                },
+                _ => {
+                    check_qualifiers_kept(&self.type_of_node(leaf.value), &leaf.leaf_type);
+                    self.emit_for_node(leaf.value);
+                    self.emit_store(&leaf.leaf_type, &Location::Frame(location));
+                }
+            }
+        }
+    }
+
+    // Inside functions, compound literals live in the frame like an
+    // unnamed local variable. Returns its frame pointer offset.
+    pub fn emit_for_compound_literal (&mut self, lit: &ASTCompoundLiteral) -> isize {
+        let offset = match &mut self.frame {
+            Some(frame) => frame.allocate_temporary(&lit.literal_type),
+            None => unreachable!("Compound literal outside of a function that isn't constant")
+        };
+        self.emit_for_local_initialiser(offset, &lit.literal_type, &lit.initial_value);
+        offset
+    }
+
+    fn emit_zero_fill (&mut self, offset: isize, size: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => {},
+            Architecture::AArch64 => return self.a64_zero_fill(offset, size),
+            Architecture::RiscV64 => return self.rv_zero_fill(offset, size)
+        }
+        let mut filled = 0;
+        for (width, suffix) in &[(8, "q"), (4, "l"), (2, "w"), (1, "b")] {
+            while size - filled >= *width {
+                self.emit(format!("mov{} $0, {}(%rbp)", suffix, offset + filled));
+                filled += width;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::char_stream::CharStream;
+    use crate::parser::parser::Parser;
+    use crate::parser::tokeniser::Tokeniser;
+    use crate::codegen::target::Target;
+
+    // The addresses the program's global initialisers are made of
+    fn initialiser_quads (code: &str) -> Vec<String> {
+        let ast = Parser::new(Tokeniser::new(CharStream::new(code.to_string())), Target::X86_64_LINUX).generate_ast();
+        let mut codegen = Codegen::new(ast, Target::X86_64_LINUX, false);
+        codegen.generate();
+        codegen.generated.lines()
+            .filter_map(|line| line.strip_prefix(".quad "))
+            .map(|quad| quad.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn arrays_inside_aggregates_decay () {
+        let quads = initialiser_quads("\
+            struct s { int a; int b[4]; } v;
+            int *p = v.b;
+            int *q = &v.b[2];
+            int grid[2][3];
+            int *row = grid[1];");
+        assert_eq!(quads.len(), 3);
+        assert!(quads[0].ends_with("v+4"), "{}", quads[0]);
+        assert!(quads[1].ends_with("v+12"), "{}", quads[1]);
+        assert!(quads[2].ends_with("grid+12"), "{}", quads[2]);
+    }
+}
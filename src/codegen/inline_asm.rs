@@ -0,0 +1,334 @@
+// Inline assembly. Operands are evaluated in to frame temporaries first
+// (since evaluating them can use any register), then loaded in to the
+// registers their constraints ask for just before the template. Register
+// outputs are saved straight afterwards, then stored to their lvalues.
+use std::collections::HashSet;
+use super::codegen::Codegen;
+use super::constexpr::try_get_constant_value_from_node;
+use super::initialiser::decoded_string_literal;
+use super::instruction::{AsmLine, register_family, register_with_width};
+use super::machine::{Location, Register};
+use super::stored_value::ValueBackingStorage;
+use super::target::Architecture;
+use super::typing::Typing;
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+
+// What "r" operands can be given, caller-saved ones first
+static GENERAL_REGISTERS: &[&str] = &[
+    "rax", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11",
+    "rbx", "r12", "r13", "r14", "r15"
+];
+// Our caller expects these to be the same when we return
+static CALLEE_SAVED: &[&str] = &["rbx", "r12", "r13", "r14", "r15"];
+
+enum Placement {
+    // The register family, eg. "rax"
+    Register(String),
+    // The operand as it appears in the template, eg. "-8(%rbp)". Memory
+    // that isn't at a fixed place is reached through an address register.
+    Memory(String, Option<String>),
+    Immediate(isize)
+}
+
+struct PlacedOperand<'a> {
+    operand: &'a ASTAsmOperand,
+    placement: Placement,
+    // Bytes of register that %0 refers to, eg. %eax for an int
+    width: usize,
+    is_output: bool
+}
+
+// Eg. "=r" or "+m", with the = and + taken off the letters
+fn constraint_letters (constraint: &str) -> &str {
+    constraint.trim_start_matches(['=', '+', '&'])
+}
+
+// The registers constraints like "a" ask for
+fn fixed_register (letter: char) -> Option<&'static str> {
+    match letter {
+        'a' => Some("rax"),
+        'b' => Some("rbx"),
+        'c' => Some("rcx"),
+        'd' => Some("rdx"),
+        'S' => Some("rsi"),
+        'D' => Some("rdi"),
+        _ => None
+    }
+}
+
+fn clobbered_register (clobber: &str) -> Option<String> {
+    let name = clobber.trim_start_matches('%');
+    if name == "memory" || name == "cc" {
+        // We don't keep anything in registers or flags between
+        // statements, so there's nothing to reload
+        return None
+    }
+    match register_family(name) {
+        Some((family, _)) => Some(family),
+        None => panic!("Unknown register \"{}\" in asm clobbers", clobber)
+    }
+}
+
+impl Codegen {
+    pub fn emit_for_inline_asm (&mut self, asm: &ASTInlineAsm) {
+        // Templates are passed through to an x86-64 assembler, and
+        // constraints are in terms of x86 registers
+        if self.target.arch != Architecture::X86_64 {
+            panic!("Inline assembly is only supported when targeting x86-64")
+        }
+        if !asm.is_extended {
+            let template = String::from_utf8_lossy(&decoded_string_literal(&asm.template)).to_string();
+            self.emit_asm_template(&template);
+            return
+        }
+        if self.frame.is_none() {
+            panic!("Asm statements with operands must be inside a function")
+        }
+
+        let operands = self.place_asm_operands(asm);
+        let template = self.substitute_asm_operands(asm, &operands);
+
+        let mut used: Vec<String> = asm.clobbers.iter().filter_map(|c| clobbered_register(c)).collect();
+        for placed in &operands {
+            match &placed.placement {
+                Placement::Register(family) | Placement::Memory(_, Some(family)) => used.push(family.clone()),
+                _ => {}
+            }
+        }
+        if used.iter().any(|family| family == "rsp" || family == "rbp") {
+            panic!("Asm statements can't change %rsp or %rbp")
+        }
+        let saved: Vec<&str> = CALLEE_SAVED.iter().copied()
+            .filter(|reg| used.iter().any(|family| family == reg)).collect();
+        for reg in &saved {
+            self.emit_push(Register::Named(reg));
+        }
+
+        // Everything the template reads, worked out before any of it is in place
+        let mut loads = vec![];
+        for placed in &operands {
+            let value = &placed.operand.value;
+            match &placed.placement {
+                Placement::Register(family) if !placed.is_output || placed.operand.constraint.starts_with('+') => {
+                    if let Type::Struct(_) = self.type_of_node(value) {
+                        panic!("Register operands of asm statements can't be structs")
+                    }
+                    self.emit_for_node(value);
+                    loads.push((self.save_asm_temporary(), family.clone()));
+                },
+                Placement::Memory(_, Some(family)) => {
+                    self.emit_address_of(value);
+                    loads.push((self.save_asm_temporary(), family.clone()));
+                },
+                _ => {}
+            }
+        }
+        for (temporary, family) in loads {
+            self.emit(format!("mov {}(%rbp), %{}", temporary, family));
+        }
+
+        self.emit_asm_template(&template);
+
+        let mut results = vec![];
+        for placed in operands.iter().filter(|placed| placed.is_output) {
+            if let Placement::Register(family) = &placed.placement {
+                let temporary = self.allocate_asm_temporary();
+                self.emit(format!("mov %{}, {}(%rbp)", family, temporary));
+                results.push((&placed.operand.value, temporary));
+            }
+        }
+        for reg in saved.iter().rev() {
+            self.emit_pop(Register::Named(reg));
+        }
+        for (lvalue, temporary) in results {
+            self.emit_store_asm_output(lvalue, temporary);
+        }
+    }
+
+    fn place_asm_operands<'a> (&mut self, asm: &'a ASTInlineAsm) -> Vec<PlacedOperand<'a>> {
+        // Registers asked for by name can't be handed out for "r". An input
+        // and an output can ask for the same one (the output overwrites it),
+        // but two inputs or two outputs can't.
+        let mut taken: HashSet<String> = asm.clobbers.iter().filter_map(|c| clobbered_register(c)).collect();
+        for operands in &[&asm.outputs, &asm.inputs] {
+            let mut named = HashSet::new();
+            for operand in operands.iter() {
+                if let Some(reg) = constraint_letters(&operand.constraint).chars().find_map(fixed_register) {
+                    if !named.insert(reg) {
+                        panic!("Register \"{}\" is used by more than one asm operand", reg)
+                    }
+                    taken.insert(reg.to_string());
+                }
+            }
+        }
+
+        let all = asm.outputs.iter().map(|o| (o, true)).chain(asm.inputs.iter().map(|i| (i, false)));
+        let mut placed: Vec<PlacedOperand> = vec![];
+        for (operand, is_output) in all {
+            let letters = constraint_letters(&operand.constraint);
+            if is_output && !operand.constraint.starts_with(['=', '+']) {
+                panic!("Asm output constraint \"{}\" must start with = or +", operand.constraint)
+            }
+
+            let placement = if let Some(number) = letters.parse::<usize>().ok().filter(|_| !is_output) {
+                // Eg. "0" (x), which goes in the same place as output 0
+                match placed.get(number).filter(|p| p.is_output) {
+                    Some(PlacedOperand { placement: Placement::Register(family), .. }) => Placement::Register(family.clone()),
+                    _ => panic!("Asm operand \"{}\" doesn't match a register output", letters)
+                }
+            } else if let Some(reg) = letters.chars().find_map(fixed_register) {
+                Placement::Register(reg.to_string())
+            } else if letters.contains(['r', 'q', 'g']) {
+                Placement::Register(take_free_register(&mut taken))
+            } else if letters.contains('m') {
+                match self.fixed_location(&operand.value) {
+                    Some(location) => Placement::Memory(self.x86_operand(&location), None),
+                    None => {
+                        let family = take_free_register(&mut taken);
+                        Placement::Memory(format!("(%{})", family), Some(family))
+                    }
+                }
+            } else if letters.contains(['i', 'n']) && !is_output {
+                match try_get_constant_value_from_node(&operand.value) {
+                    Some(value) => Placement::Immediate(value),
+                    None => panic!("Asm operand with constraint \"{}\" must be a constant", operand.constraint)
+                }
+            } else {
+                panic!("Unsupported asm constraint \"{}\"", operand.constraint)
+            };
+
+            let width = match decayed(self.type_of_node(&operand.value)) {
+                the_type if is_scalar(&the_type) => size_in_bytes(&the_type) as usize,
+                _ => 8
+            };
+            placed.push(PlacedOperand { operand, placement, width, is_output });
+        }
+        placed
+    }
+
+    // Replaces %0, %[name] and %% in the template. Operands can have a
+    // modifier for the width of register they refer to, eg. %k0 for %eax.
+    fn substitute_asm_operands (&self, asm: &ASTInlineAsm, operands: &[PlacedOperand]) -> String {
+        let template = String::from_utf8_lossy(&decoded_string_literal(&asm.template)).to_string();
+        let mut result = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                result.push('%');
+                continue
+            }
+
+            let modifier = match chars.peek() {
+                Some(m) if "bwkqc".contains(*m) => chars.next(),
+                _ => None
+            };
+
+            let placed = if chars.peek() == Some(&'[') {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                match operands.iter().find(|p| p.operand.name.as_deref() == Some(&name[..])) {
+                    Some(placed) => placed,
+                    None => panic!("Asm template refers to unknown operand \"{}\"", name)
+                }
+            } else {
+                let mut digits = String::new();
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    chars.next();
+                }
+                match digits.parse::<usize>().ok().and_then(|n| operands.get(n)) {
+                    Some(placed) => placed,
+                    None => panic!("Invalid operand reference in asm template \"{}\" (use %% for a %)", asm.template)
+                }
+            };
+
+            result += &match (&placed.placement, modifier) {
+                (Placement::Register(family), _) => {
+                    let width = match modifier {
+                        Some('b') => 1,
+                        Some('w') => 2,
+                        Some('k') => 4,
+                        Some('q') => 8,
+                        _ => placed.width
+                    };
+                    format!("%{}", register_with_width(family, width))
+                },
+                (Placement::Memory(location, _), _) => location.clone(),
+                (Placement::Immediate(value), Some('c')) => value.to_string(),
+                (Placement::Immediate(value), _) => format!("${}", value)
+            };
+        }
+        result
+    }
+
+    // The template's lines are copied out as they are, and the
+    // peephole optimiser doesn't look inside them
+    fn emit_asm_template (&mut self, template: &str) {
+        for line in template.lines().map(|line| line.trim()).filter(|line| !line.is_empty()) {
+            self.lines.push(AsmLine::Raw(line.to_string()));
+        }
+    }
+
+    // Variables can be used in place as memory operands
+    fn fixed_location (&self, node: &ASTNode) -> Option<Location> {
+        let stored = match node {
+            ASTNode::Identifier(name) => self.try_find_var(name)?,
+            _ => return None
+        };
+        match (&stored.backing_store, &stored.value_type) {
+            (ValueBackingStorage::Function(_), _) | (_, Type::VariableArray(_)) => None,
+            _ => self.get_stored_value_location(stored)
+        }
+    }
+
+    fn allocate_asm_temporary (&mut self) -> isize {
+        match &mut self.frame {
+            Some(frame) => frame.allocate_temporary(&long_type()),
+            None => unreachable!("Asm operands outside of a function")
+        }
+    }
+
+    fn save_asm_temporary (&mut self) -> isize {
+        let temporary = self.allocate_asm_temporary();
+        self.emit(format!("mov %rax, {}(%rbp)", temporary));
+        temporary
+    }
+
+    fn emit_store_asm_output (&mut self, lvalue: &ASTNode, temporary: isize) {
+        let target_type = self.type_of_node(lvalue);
+        if !is_scalar(&target_type) {
+            panic!("Register outputs of asm statements must be scalars")
+        }
+        if qualifiers_of(&target_type).is_const {
+            panic!("Assignment to a read-only (const) location")
+        }
+
+        let location = match self.fixed_location(lvalue) {
+            Some(location) => location,
+            None => {
+                self.emit_address_of(lvalue);
+                self.emit_move(Register::Secondary, Register::Accumulator);
+                Location::Pointer(Register::Secondary)
+            }
+        };
+        self.emit(format!("mov {}(%rbp), %rax", temporary));
+        self.emit_store(&target_type, &location);
+    }
+}
+
+fn take_free_register (taken: &mut HashSet<String>) -> String {
+    match GENERAL_REGISTERS.iter().find(|reg| !taken.contains(**reg)) {
+        Some(reg) => {
+            taken.insert(reg.to_string());
+            reg.to_string()
+        },
+        None => panic!("Asm statement needs more registers than there are")
+    }
+}
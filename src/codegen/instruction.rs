@@ -0,0 +1,326 @@
+// A structured view of the AT&T assembly Codegen emits, so that later
+// passes (eg. the peephole optimiser) can reason about it without
+// string-matching every line.
+use std::fmt;
+
+#[derive(Clone, PartialEq)]
+pub enum AsmLine {
+    Label(String),
+    // Anything starting with a '.', eg. .globl, .data, .string
+    Directive(String),
+    Instruction(Instruction),
+    // Lines we couldn't make sense of. Passes must treat these as opaque.
+    Raw(String)
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Instruction {
+    pub mnemonic: String,
+    // In AT&T order, so the destination (if any) is last
+    pub operands: Vec<Operand>,
+    // Accesses memory through a volatile lvalue. Passes must not remove,
+    // merge or reorder these, since the access itself might matter
+    // (eg. memory-mapped IO)
+    pub volatile: bool
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Operand {
+    // Stored without the % prefix, eg. "rax"
+    Register(String),
+    Immediate(isize),
+    Memory(MemoryOperand),
+    // Jump and call targets
+    Label(String),
+    // Targets of indirect jumps and calls, eg. *%r10
+    Indirect(Box<Operand>)
+}
+
+// disp(base, index, scale) or symbol+disp(%rip)
+#[derive(Clone, PartialEq)]
+pub struct MemoryOperand {
+    pub symbol: Option<String>,
+    pub displacement: isize,
+    pub base: Option<String>,
+    pub index: Option<String>,
+    pub scale: usize
+}
+
+impl AsmLine {
+    pub fn parse (line: &str) -> AsmLine {
+        let line = line.trim();
+
+        // Labels can start with a '.' too, eg. .L3 on Linux
+        if line.ends_with(':') && !line.contains(char::is_whitespace) {
+            return AsmLine::Label(line[..line.len() - 1].to_string())
+        }
+        if line.starts_with('.') {
+            return AsmLine::Directive(line.to_string())
+        }
+
+        match Instruction::parse(line) {
+            Some(inst) => AsmLine::Instruction(inst),
+            None => AsmLine::Raw(line.to_string())
+        }
+    }
+
+    pub fn instruction (&self) -> Option<&Instruction> {
+        match self {
+            AsmLine::Instruction(inst) => Some(inst),
+            _ => None
+        }
+    }
+
+    pub fn is_volatile (&self) -> bool {
+        self.instruction().is_some_and(|inst| inst.volatile)
+    }
+}
+
+impl Instruction {
+    pub fn new (mnemonic: &str, operands: Vec<Operand>) -> Instruction {
+        Instruction {
+            mnemonic: mnemonic.to_string(),
+            operands,
+            volatile: false
+        }
+    }
+
+    fn parse (line: &str) -> Option<Instruction> {
+        let (mnemonic, rest) = match line.find(char::is_whitespace) {
+            Some(idx) => (&line[..idx], line[idx..].trim()),
+            None => (line, "")
+        };
+
+        let mut operands = vec![];
+        for op in split_operands(rest) {
+            operands.push(Operand::parse(op)?);
+        }
+
+        Some(Instruction::new(mnemonic, operands))
+    }
+
+    pub fn is (&self, mnemonic: &str) -> bool {
+        self.mnemonic == mnemonic
+    }
+}
+
+// Maps any name for a general-purpose register to its 64-bit name and
+// the width (in bytes) that name refers to. Eg. "eax" -> ("rax", 4)
+pub fn register_family (reg: &str) -> Option<(String, usize)> {
+    let legacy = ["ax", "bx", "cx", "dx", "si", "di", "bp", "sp"];
+    for name in &legacy {
+        let full = format!("r{}", name);
+        if reg == full { return Some((full, 8)) }
+        if reg == format!("e{}", name) { return Some((full, 4)) }
+        if reg == *name { return Some((full, 2)) }
+    }
+    for name in &["a", "b", "c", "d"] {
+        if reg == format!("{}l", name) || reg == format!("{}h", name) {
+            return Some((format!("r{}x", name), 1))
+        }
+    }
+    for name in &["si", "di", "bp", "sp"] {
+        if reg == format!("{}l", name) {
+            return Some((format!("r{}", name), 1))
+        }
+    }
+    if let Some(num) = reg.strip_prefix('r') {
+        let digits: String = num.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if !digits.is_empty() {
+            let full = format!("r{}", digits);
+            return match &num[digits.len()..] {
+                "" => Some((full, 8)),
+                "d" => Some((full, 4)),
+                "w" => Some((full, 2)),
+                "b" => Some((full, 1)),
+                _ => None
+            }
+        }
+    }
+    None
+}
+
+// The inverse of register_family, eg. ("rax", 4) -> "eax"
+pub fn register_with_width (family: &str, width: usize) -> String {
+    if let Some(num) = family.strip_prefix('r') {
+        if num.starts_with(|c: char| c.is_ascii_digit()) {
+            return match width {
+                8 => family.to_string(),
+                4 => format!("{}d", family),
+                2 => format!("{}w", family),
+                _ => format!("{}b", family)
+            }
+        }
+        let is_letter_reg = num.len() == 2 && num.ends_with('x');
+        return match width {
+            8 => family.to_string(),
+            4 => format!("e{}", num),
+            2 => num.to_string(),
+            _ if is_letter_reg => format!("{}l", &num[..1]),
+            _ => format!("{}l", num)
+        }
+    }
+    family.to_string()
+}
+
+// Splits on commas that aren't inside a memory operand's brackets
+fn split_operands (s: &str) -> Vec<&str> {
+    let mut ops = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                ops.push(s[start..i].trim());
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    if !s[start..].trim().is_empty() {
+        ops.push(s[start..].trim());
+    }
+    ops
+}
+
+impl Operand {
+    pub fn reg (name: &str) -> Operand {
+        Operand::Register(name.to_string())
+    }
+
+    fn parse (s: &str) -> Option<Operand> {
+        if let Some(target) = s.strip_prefix('*') {
+            return Operand::parse(target).map(|op| Operand::Indirect(Box::new(op)))
+        }
+        if let Some(reg) = s.strip_prefix('%') {
+            return Some(Operand::reg(reg))
+        }
+        if let Some(imm) = s.strip_prefix('$') {
+            return imm.parse().ok().map(Operand::Immediate)
+        }
+        if s.contains('(') {
+            return MemoryOperand::parse(s).map(Operand::Memory)
+        }
+        if s.starts_with(|c: char| c == '_' || c == '.' || c.is_alphabetic()) {
+            return Some(Operand::Label(s.to_string()))
+        }
+        None
+    }
+
+    pub fn register (&self) -> Option<&str> {
+        match self {
+            Operand::Register(reg) => Some(reg),
+            _ => None
+        }
+    }
+
+    pub fn immediate (&self) -> Option<isize> {
+        match self {
+            Operand::Immediate(imm) => Some(*imm),
+            _ => None
+        }
+    }
+}
+
+impl MemoryOperand {
+    fn parse (s: &str) -> Option<MemoryOperand> {
+        let open = s.find('(')?;
+        let close = s.rfind(')')?;
+
+        let (symbol, displacement) = parse_displacement(&s[..open])?;
+
+        let inner: Vec<&str> = s[open + 1..close].split(',').map(|x| x.trim()).collect();
+        let reg = |x: &str| -> Option<Option<String>> {
+            if x.is_empty() { return Some(None) }
+            x.strip_prefix('%').map(|r| Some(r.to_string()))
+        };
+
+        let base = reg(inner[0])?;
+        let index = match inner.get(1) { Some(x) => reg(x)?, None => None };
+        let scale = match inner.get(2) { Some(x) => x.parse().ok()?, None => 1 };
+
+        Some(MemoryOperand { symbol, displacement, base, index, scale })
+    }
+
+    pub fn registers (&self) -> Vec<&str> {
+        let mut regs = vec![];
+        if let Some(base) = &self.base { regs.push(&base[..]) }
+        if let Some(index) = &self.index { regs.push(&index[..]) }
+        regs
+    }
+}
+
+// Eg. "-8", "_label", "_label+16"
+fn parse_displacement (s: &str) -> Option<(Option<String>, isize)> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Some((None, 0))
+    }
+    if let Ok(disp) = s.parse() {
+        return Some((None, disp))
+    }
+
+    // The offset sign is the first + or - after the symbol starts
+    match s[1..].find(['+', '-']) {
+        Some(idx) => {
+            let (sym, off) = s.split_at(idx + 1);
+            let off = off.strip_prefix('+').unwrap_or(off);
+            Some((Some(sym.to_string()), off.parse().ok()?))
+        },
+        None => Some((Some(s.to_string()), 0))
+    }
+}
+
+impl fmt::Display for AsmLine {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmLine::Label(label) => write!(f, "{}:", label),
+            AsmLine::Directive(dir) => write!(f, "{}", dir),
+            AsmLine::Instruction(inst) => write!(f, "{}", inst),
+            AsmLine::Raw(raw) => write!(f, "{}", raw)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, op) in self.operands.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", sep, op)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(reg) => write!(f, "%{}", reg),
+            Operand::Immediate(imm) => write!(f, "${}", imm),
+            Operand::Memory(mem) => write!(f, "{}", mem),
+            Operand::Label(label) => write!(f, "{}", label),
+            Operand::Indirect(target) => write!(f, "*{}", target)
+        }
+    }
+}
+
+impl fmt::Display for MemoryOperand {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.symbol, self.displacement) {
+            (Some(sym), 0) => write!(f, "{}", sym)?,
+            (Some(sym), disp) if disp > 0 => write!(f, "{}+{}", sym, disp)?,
+            (Some(sym), disp) => write!(f, "{}{}", sym, disp)?,
+            (None, 0) => {},
+            (None, disp) => write!(f, "{}", disp)?
+        }
+        write!(f, "(")?;
+        if let Some(base) = &self.base { write!(f, "%{}", base)? }
+        if let Some(index) = &self.index {
+            write!(f, ", %{}, {}", index, self.scale)?;
+        }
+        write!(f, ")")
+    }
+}
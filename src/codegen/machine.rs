@@ -0,0 +1,598 @@
+// The operations the rest of the code generator is built from, for each
+// architecture. Every value is worked out in an accumulator register, with
+// the left side of binary operators popped in to a secondary one, so most
+// of the walker doesn't need to know which instruction set it's emitting.
+use super::codegen::Codegen;
+use super::frame::FrameLayout;
+use super::instruction::register_with_width;
+use super::peephole;
+use super::target::Architecture;
+
+// Registers by what they're used for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Register {
+    // Where every expression leaves its value
+    Accumulator,
+    // The left side of binary operators, and where copies go to
+    Secondary,
+    // For moving parts of structs around
+    Scratch,
+    // The function pointer of an indirect call
+    Callee,
+    FramePointer,
+    // A particular register, eg. for an argument
+    Named(&'static str)
+}
+
+// Somewhere in memory a value can be loaded from or stored to
+#[derive(Clone, PartialEq, Debug)]
+pub enum Location {
+    // Locals and params, at an offset from the frame pointer
+    Frame(isize),
+    // Outgoing arguments, at an offset from the stack pointer
+    Stack(isize),
+    // A label defined in this file
+    Symbol(String),
+    // Wherever the address in a register points
+    Pointer(Register)
+}
+
+impl Codegen {
+    pub fn register_name (&self, register: Register) -> &'static str {
+        match (self.target.arch, register) {
+            (_, Register::Named(name)) => name,
+            (Architecture::X86_64, Register::Accumulator) => "rax",
+            (Architecture::X86_64, Register::Secondary) => "rcx",
+            (Architecture::X86_64, Register::Scratch) => "rdx",
+            (Architecture::X86_64, Register::Callee) => "r10",
+            (Architecture::X86_64, Register::FramePointer) => "rbp",
+            (Architecture::AArch64, Register::Accumulator) => "x0",
+            (Architecture::AArch64, Register::Secondary) => "x1",
+            (Architecture::AArch64, Register::Scratch) => "x2",
+            (Architecture::AArch64, Register::Callee) => "x9",
+            (Architecture::AArch64, Register::FramePointer) => "x29",
+            (Architecture::RiscV64, Register::Accumulator) => "a0",
+            (Architecture::RiscV64, Register::Secondary) => "a1",
+            (Architecture::RiscV64, Register::Scratch) => "a2",
+            (Architecture::RiscV64, Register::Callee) => "t1",
+            (Architecture::RiscV64, Register::FramePointer) => "s0"
+        }
+    }
+
+    // How a location is written as an x86 memory operand, eg. -8(%rbp)
+    pub fn x86_operand (&self, location: &Location) -> String {
+        match location {
+            Location::Frame(offset) => format!("{}(%rbp)", offset),
+            Location::Stack(offset) => format!("{}(%rsp)", offset),
+            Location::Symbol(label) => format!("{}(%rip)", label),
+            Location::Pointer(register) => format!("(%{})", self.register_name(*register))
+        }
+    }
+
+    pub fn emit_load_immediate (&mut self, value: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("mov ${}, %rax", value)),
+            Architecture::AArch64 => self.a64_load_immediate("x0", value),
+            Architecture::RiscV64 => self.emit(format!("li a0, {}", value))
+        }
+    }
+
+    pub fn emit_move (&mut self, dest: Register, src: Register) {
+        let (dest, src) = (self.register_name(dest), self.register_name(src));
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("mov %{}, %{}", src, dest)),
+            Architecture::AArch64 => self.emit(format!("mov {}, {}", dest, src)),
+            Architecture::RiscV64 => self.emit(format!("mv {}, {}", dest, src))
+        }
+    }
+
+    pub fn emit_load_address (&mut self, dest: Register, location: &Location) {
+        let dest = self.register_name(dest);
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("lea {}, %{}", self.x86_operand(location), dest)),
+            Architecture::AArch64 => self.a64_load_address(dest, location),
+            Architecture::RiscV64 => self.rv_load_address(dest, location)
+        }
+    }
+
+    // Globals from other files (or shared libraries) are reached through
+    // the global offset table, which the linker fills in
+    pub fn emit_load_address_from_got (&mut self, label: &str) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("movq {}@GOTPCREL(%rip), %rax", label)),
+            Architecture::AArch64 => {
+                self.emit(format!("adrp x0, :got:{}", label));
+                self.emit(format!("ldr x0, [x0, :got_lo12:{}]", label));
+            },
+            Architecture::RiscV64 => {
+                // The low half is relative to where the high half was worked out
+                let anchor = self.get_unique_label("got");
+                self.emit(format!("{}:", anchor));
+                self.emit(format!("auipc a0, %got_pcrel_hi({})", label));
+                self.emit(format!("ld a0, %pcrel_lo({})(a0)", anchor));
+            }
+        }
+    }
+
+    // A whole register's worth, eg. a saved pointer
+    pub fn emit_load_register (&mut self, dest: Register, location: &Location) {
+        let dest = self.register_name(dest);
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("mov {}, %{}", self.x86_operand(location), dest)),
+            Architecture::AArch64 => self.a64_load(dest, 8, false, location),
+            Architecture::RiscV64 => self.rv_load(dest, 8, false, location)
+        }
+    }
+
+    // Stores the low width bytes of a register
+    pub fn emit_store_register (&mut self, src: Register, width: usize, location: &Location) {
+        let src = self.register_name(src);
+        match self.target.arch {
+            Architecture::X86_64 => {
+                let reg = register_with_width(src, width);
+                self.emit(format!("mov %{}, {}", reg, self.x86_operand(location)));
+            },
+            Architecture::AArch64 => self.a64_store(src, width, location),
+            Architecture::RiscV64 => self.rv_store(src, width, location)
+        }
+    }
+
+    // Loads width bytes in to the accumulator, sign or zero extended to 64 bits
+    pub fn emit_load_scalar (&mut self, width: usize, signed: bool, location: &Location) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                let loc = self.x86_operand(location);
+                match (width, signed) {
+                    (1, true) => self.emit(format!("movsbq {}, %rax", loc)),
+                    (1, false) => self.emit(format!("movzbq {}, %rax", loc)),
+                    (2, true) => self.emit(format!("movswq {}, %rax", loc)),
+                    (2, false) => self.emit(format!("movzwq {}, %rax", loc)),
+                    (4, true) => self.emit(format!("movslq {}, %rax", loc)),
+                    // 32-bit moves clear the top half of the register
+                    (4, false) => self.emit(format!("movl {}, %eax", loc)),
+                    _ => self.emit(format!("movq {}, %rax", loc))
+                }
+            },
+            Architecture::AArch64 => self.a64_load("x0", width, signed, location),
+            Architecture::RiscV64 => self.rv_load("a0", width, signed, location)
+        }
+    }
+
+    // Re-extends the low width bytes of the accumulator to 64 bits
+    pub fn emit_extend (&mut self, width: usize, signed: bool) {
+        match self.target.arch {
+            Architecture::X86_64 => match (width, signed) {
+                (1, true) => self.emit_str("movsbq %al, %rax"),
+                (1, false) => self.emit_str("movzbq %al, %rax"),
+                (2, true) => self.emit_str("movswq %ax, %rax"),
+                (2, false) => self.emit_str("movzwq %ax, %rax"),
+                (4, true) => self.emit_str("movslq %eax, %rax"),
+                (4, false) => self.emit_str("movl %eax, %eax"),
+                _ => {}
+            },
+            Architecture::AArch64 => match (width, signed) {
+                (1, true) => self.emit_str("sxtb x0, w0"),
+                (1, false) => self.emit_str("and x0, x0, #0xff"),
+                (2, true) => self.emit_str("sxth x0, w0"),
+                (2, false) => self.emit_str("and x0, x0, #0xffff"),
+                (4, true) => self.emit_str("sxtw x0, w0"),
+                // Writing a w register clears the top half
+                (4, false) => self.emit_str("mov w0, w0"),
+                _ => {}
+            },
+            Architecture::RiscV64 => self.rv_extend(width, signed)
+        }
+    }
+
+    // Temporaries pushed and popped while evaluating expressions.
+    // These keep track of the stack depth for aligning calls.
+    pub fn emit_push (&mut self, register: Register) {
+        let reg = self.register_name(register);
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("push %{}", reg)),
+            // The stack pointer always has to stay 16-byte aligned
+            Architecture::AArch64 => self.emit(format!("str {}, [sp, #-16]!", reg)),
+            // RISC-V only needs it to be at calls, but it's simpler to keep it that way
+            Architecture::RiscV64 => {
+                self.emit_str("addi sp, sp, -16");
+                self.emit(format!("sd {}, 0(sp)", reg));
+            }
+        }
+        self.stack_depth += self.push_size();
+    }
+
+    pub fn emit_pop (&mut self, register: Register) {
+        let reg = self.register_name(register);
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("pop %{}", reg)),
+            Architecture::AArch64 => self.emit(format!("ldr {}, [sp], #16", reg)),
+            Architecture::RiscV64 => {
+                self.emit(format!("ld {}, 0(sp)", reg));
+                self.emit_str("addi sp, sp, 16");
+            }
+        }
+        self.stack_depth -= self.push_size();
+    }
+
+    fn push_size (&self) -> isize {
+        match self.target.arch {
+            Architecture::X86_64 => 8,
+            Architecture::AArch64 | Architecture::RiscV64 => 16
+        }
+    }
+
+    // Moves the stack pointer down to make room, or back up again
+    pub fn emit_reserve_stack (&mut self, bytes: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("sub ${}, %rsp", bytes)),
+            Architecture::AArch64 => self.a64_add_immediate("sp", "sp", -bytes),
+            Architecture::RiscV64 => self.rv_add_immediate("sp", "sp", -bytes)
+        }
+    }
+
+    pub fn emit_release_stack (&mut self, bytes: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("add ${}, %rsp", bytes)),
+            Architecture::AArch64 => self.a64_add_immediate("sp", "sp", bytes),
+            Architecture::RiscV64 => self.rv_add_immediate("sp", "sp", bytes)
+        }
+    }
+
+    // Takes the number of bytes in the accumulator off the stack
+    // pointer, rounded up to keep it aligned for calls
+    pub fn emit_allocate_stack (&mut self) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                self.emit_str("add $15, %rax");
+                self.emit_str("and $-16, %rax");
+                self.emit_str("sub %rax, %rsp");
+            },
+            Architecture::AArch64 => {
+                self.emit_str("add x0, x0, #15");
+                self.emit_str("and x0, x0, #0xfffffffffffffff0");
+                self.emit_str("sub sp, sp, x0");
+            },
+            Architecture::RiscV64 => {
+                self.emit_str("addi a0, a0, 15");
+                self.emit_str("andi a0, a0, -16");
+                self.emit_str("sub sp, sp, a0");
+            }
+        }
+    }
+
+    // The stack pointer can't be stored directly on AArch64. It goes
+    // through x17, since a far slot's offset may need x16.
+    pub fn emit_save_stack_pointer (&mut self, offset: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("mov %rsp, {}(%rbp)", offset)),
+            Architecture::AArch64 => {
+                self.emit_str("mov x17, sp");
+                self.a64_store("x17", 8, &Location::Frame(offset));
+            },
+            Architecture::RiscV64 => self.rv_store("sp", 8, &Location::Frame(offset))
+        }
+    }
+
+    pub fn emit_restore_stack_pointer (&mut self, offset: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("mov {}(%rbp), %rsp", offset)),
+            Architecture::AArch64 => {
+                self.a64_load("x16", 8, false, &Location::Frame(offset));
+                self.emit_str("mov sp, x16");
+            },
+            Architecture::RiscV64 => self.rv_load("sp", 8, false, &Location::Frame(offset))
+        }
+    }
+
+    pub fn emit_jump (&mut self, label: &str) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("jmp {}", label)),
+            Architecture::AArch64 => self.emit(format!("b {}", label)),
+            Architecture::RiscV64 => self.emit(format!("j {}", label))
+        }
+    }
+
+    pub fn emit_jump_if_zero (&mut self, label: &str) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                self.emit_str("cmp $0, %rax");
+                self.emit(format!("je {}", label));
+            },
+            Architecture::AArch64 => self.emit(format!("cbz x0, {}", label)),
+            Architecture::RiscV64 => self.emit(format!("beqz a0, {}", label))
+        }
+    }
+
+    pub fn emit_jump_if_not_zero (&mut self, label: &str) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                self.emit_str("cmp $0, %rax");
+                self.emit(format!("jne {}", label));
+            },
+            Architecture::AArch64 => self.emit(format!("cbnz x0, {}", label)),
+            Architecture::RiscV64 => self.emit(format!("bnez a0, {}", label))
+        }
+    }
+
+    // For switch cases
+    pub fn emit_jump_if_equal (&mut self, value: isize, label: &str) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                if peephole::fits_in_imm32(value) {
+                    self.emit(format!("cmp ${}, %rax", value));
+                } else {
+                    // Immediates for cmp are only 32 bits
+                    self.emit(format!("mov ${}, %rcx", value));
+                    self.emit_str("cmp %rcx, %rax");
+                }
+                self.emit(format!("je {}", label));
+            },
+            Architecture::AArch64 => {
+                if (0..4096).contains(&value) {
+                    self.emit(format!("cmp x0, #{}", value));
+                } else {
+                    self.a64_load_immediate("x16", value);
+                    self.emit_str("cmp x0, x16");
+                }
+                self.emit(format!("b.eq {}", label));
+            },
+            Architecture::RiscV64 => {
+                self.emit(format!("li t0, {}", value));
+                self.emit(format!("beq a0, t0, {}", label));
+            }
+        }
+    }
+
+    // Turns the accumulator in to 1 if it's non-zero, for || and &&
+    pub fn emit_truth_value (&mut self) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                self.emit_str("cmp $0, %rax");
+                self.emit_str("mov $0, %rax");
+                self.emit_str("setne %al");
+            },
+            Architecture::AArch64 => {
+                self.emit_str("cmp x0, #0");
+                self.emit_str("cset x0, ne");
+            },
+            Architecture::RiscV64 => self.emit_str("snez a0, a0")
+        }
+    }
+
+    pub fn emit_logical_not (&mut self) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                self.emit_str("cmp $0, %rax");
+                self.emit_str("mov $0, %rax");
+                self.emit_str("setz %al");
+            },
+            Architecture::AArch64 => {
+                self.emit_str("cmp x0, #0");
+                self.emit_str("cset x0, eq");
+            },
+            Architecture::RiscV64 => self.emit_str("seqz a0, a0")
+        }
+    }
+
+    // - and ~ on the accumulator
+    pub fn emit_unary_arithmetic (&mut self, operator: &str) {
+        let instruction = match (self.target.arch, operator) {
+            (Architecture::X86_64, "-") => "neg %rax",
+            (Architecture::X86_64, "~") => "not %rax",
+            (Architecture::AArch64, "-") => "neg x0, x0",
+            (Architecture::AArch64, "~") => "mvn x0, x0",
+            (Architecture::RiscV64, "-") => "neg a0, a0",
+            (Architecture::RiscV64, "~") => "not a0, a0",
+            _ => unreachable!("\"{}\" isn't an arithmetic unary operator", operator)
+        };
+        self.emit_str(instruction);
+    }
+
+    // Works out the secondary register OP the accumulator, in to the accumulator
+    pub fn emit_arithmetic (&mut self, operator: &str, signed: bool) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit_x86_arithmetic(operator, signed),
+            Architecture::AArch64 => self.a64_arithmetic(operator, signed),
+            Architecture::RiscV64 => self.rv_arithmetic(operator, signed)
+        }
+    }
+
+    fn emit_x86_arithmetic (&mut self, operator: &str, signed: bool) {
+        match operator {
+            "+" => self.emit_str("add %rcx, %rax"),
+            "-" => {
+                self.emit_str("sub %rax, %rcx");
+                self.emit_str("mov %rcx, %rax");
+            },
+            "*" => self.emit_str("imul %rcx, %rax"),
+            "/" => self.emit_x86_division(signed),
+            "%" => {
+                self.emit_x86_division(signed);
+                self.emit_str("mov %rdx, %rax");
+            },
+            _ => {
+                let set = match operator {
+                    "==" => "sete",
+                    "!=" => "setne",
+                    ">" => if signed { "setg" } else { "seta" },
+                    "<" => if signed { "setl" } else { "setb" },
+                    ">=" => if signed { "setge" } else { "setae" },
+                    "<=" => if signed { "setle" } else { "setbe" },
+                    _ => unimplemented!("\"{}\" stack operator", operator)
+                };
+                self.emit_str("cmp %rax, %rcx");
+                self.emit_str("mov $0, %rax");
+                self.emit(format!("{} %al", set));
+            }
+        }
+    }
+
+    // Divides %rcx by %rax, leaving the quotient in %rax and remainder in %rdx
+    fn emit_x86_division (&mut self, signed: bool) {
+        self.emit_str("mov %rax, %r8");
+        self.emit_str("mov %rcx, %rax");
+        if signed {
+            self.emit_str("cqo");
+            self.emit_str("idiv %r8");
+        } else {
+            self.emit_str("mov $0, %rdx");
+            self.emit_str("div %r8");
+        }
+    }
+
+    // Multiplies a register by a constant, eg. for pointer arithmetic
+    pub fn emit_scale (&mut self, register: Register, factor: isize) {
+        let reg = self.register_name(register);
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("imul ${}, %{}", factor, reg)),
+            Architecture::AArch64 => {
+                self.a64_load_immediate("x16", factor);
+                self.emit(format!("mul {}, {}, x16", reg, reg));
+            },
+            Architecture::RiscV64 => {
+                self.emit(format!("li t0, {}", factor));
+                self.emit(format!("mul {}, {}, t0", reg, reg));
+            }
+        }
+    }
+
+    // Divides the accumulator by a constant it's known to be a multiple
+    // of, eg. to count the elements between two pointers
+    pub fn emit_exact_division (&mut self, divisor: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                self.emit(format!("mov ${}, %r8", divisor));
+                self.emit_str("cqo");
+                self.emit_str("idiv %r8");
+            },
+            Architecture::AArch64 => {
+                self.a64_load_immediate("x16", divisor);
+                self.emit_str("sdiv x0, x0, x16");
+            },
+            Architecture::RiscV64 => {
+                self.emit(format!("li t0, {}", divisor));
+                self.emit_str("div a0, a0, t0");
+            }
+        }
+    }
+
+    pub fn emit_add_immediate (&mut self, value: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => self.emit(format!("add ${}, %rax", value)),
+            Architecture::AArch64 => self.a64_add_immediate("x0", "x0", value),
+            Architecture::RiscV64 => self.rv_add_immediate("a0", "a0", value)
+        }
+    }
+
+    // Calls a function by name, or through the callee register. Variadic
+    // functions on x86-64 are told how many vector registers are used in
+    // %al. We never use any, and we have to assume functions we don't know
+    // about could be variadic.
+    pub fn emit_call (&mut self, label: Option<&str>, may_be_variadic: bool) {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                if may_be_variadic {
+                    self.emit_str("mov $0, %eax");
+                }
+                match label {
+                    Some(label) => self.emit(format!("call {}", label)),
+                    None => self.emit_str("call *%r10")
+                }
+            },
+            Architecture::AArch64 => match label {
+                Some(label) => self.emit(format!("bl {}", label)),
+                None => self.emit_str("blr x9")
+            },
+            Architecture::RiscV64 => match label {
+                Some(label) => self.emit(format!("call {}", label)),
+                None => self.emit_str("jalr t1")
+            }
+        }
+    }
+
+    // Returns the index of the first line that reserves the frame
+    pub fn emit_function_prologue (&mut self, frame: &FrameLayout) -> usize {
+        match self.target.arch {
+            Architecture::X86_64 => {
+                // Save the old base pointer
+                self.emit_str("push %rbp");
+                // The stack head is the new base
+                self.emit_str("mov %rsp, %rbp");
+            },
+            Architecture::AArch64 => {
+                // Save the old frame pointer and the return address
+                self.emit_str("stp x29, x30, [sp, #-16]!");
+                self.emit_str("mov x29, sp");
+            },
+            Architecture::RiscV64 => {
+                // Variadic functions' register save area is above these
+                self.rv_add_immediate("sp", "sp", -frame.stack_arguments);
+                self.emit_str("sd ra, 8(sp)");
+                self.emit_str("sd s0, 0(sp)");
+                self.emit_str("mv s0, sp");
+            }
+        }
+        // Reserve space for all the locals at once
+        let index = self.lines.len();
+        for line in self.frame_reservation(frame.size) {
+            self.emit(line);
+        }
+        index
+    }
+
+    // Always the same number of lines for a target, so that they can be
+    // replaced once temporaries have made the frame bigger
+    pub fn frame_reservation (&self, frame_size: isize) -> Vec<String> {
+        match self.target.arch {
+            Architecture::X86_64 => vec![format!("sub ${}, %rsp", frame_size)],
+            Architecture::AArch64 => {
+                // Immediates are 12 bits, optionally shifted up by 12
+                if frame_size >= 1 << 24 {
+                    panic!("Stack frame of {} bytes is too big", frame_size)
+                }
+                vec![
+                    format!("sub sp, sp, #{}, lsl #12", frame_size >> 12),
+                    format!("sub sp, sp, #{}", frame_size & 0xfff)
+                ]
+            },
+            Architecture::RiscV64 => vec![
+                format!("li t0, {}", frame_size),
+                "sub sp, sp, t0".to_string()
+            ]
+        }
+    }
+
+    pub fn emit_function_epilogue (&mut self, gen_return_value: bool) {
+        if gen_return_value {
+            // main without a return statement returns 0
+            self.emit_load_immediate(0);
+        }
+
+        match self.target.arch {
+            Architecture::X86_64 => {
+                // Stack head is the base
+                self.emit_str("mov %rbp, %rsp");
+                // Restore the old base
+                self.emit_str("pop %rbp");
+                // Jump out of func
+                self.emit_str("ret");
+            },
+            Architecture::AArch64 => {
+                self.emit_str("mov sp, x29");
+                self.emit_str("ldp x29, x30, [sp], #16");
+                self.emit_str("ret");
+            },
+            Architecture::RiscV64 => {
+                let stack_arguments = match &self.frame {
+                    Some(frame) => frame.stack_arguments,
+                    None => unreachable!("Epilogue outside of a function")
+                };
+                self.emit_str("mv sp, s0");
+                self.emit_str("ld ra, 8(sp)");
+                self.emit_str("ld s0, 0(sp)");
+                self.rv_add_immediate("sp", "sp", stack_arguments);
+                self.emit_str("ret");
+            }
+        }
+    }
+}
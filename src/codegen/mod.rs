@@ -1,4 +1,19 @@
+#[allow(clippy::module_inception)]
 pub mod codegen;
 pub mod context;
 pub mod stored_value;
 pub mod constexpr;
+pub mod instruction;
+pub mod peephole;
+pub mod frame;
+pub mod typing;
+pub mod abi;
+pub mod stdarg;
+pub mod initialiser;
+pub mod variable_array;
+pub mod inline_asm;
+pub mod target;
+pub mod freestanding;
+pub mod machine;
+pub mod aarch64;
+pub mod riscv;
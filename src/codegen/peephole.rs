@@ -0,0 +1,1110 @@
+// Peephole optimisation over the structured instruction list.
+// Codegen is a simple stack machine, so it produces lots of obviously
+// redundant sequences (push/pop pairs, zero-sized stack adjustments, etc.)
+// These passes clean them up without changing what the program does.
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+use super::instruction::*;
+
+// How many instructions liveness checking will look at before
+// giving up and assuming a register is still live
+static LIVENESS_BUDGET: usize = 256;
+
+static FLAGS: &str = "flags";
+
+static ARGUMENT_REGISTERS: &[&str] = &["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+static CALLER_SAVED: &[&str] = &[
+    "rax", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11"
+];
+// Registers whose values are observable after a ret
+static LIVE_ON_RETURN: &[&str] = &[
+    "rax", "rdx", "rbx", "rsp", "rbp", "r12", "r13", "r14", "r15"
+];
+
+pub fn optimise (lines: &mut Vec<AsmLine>) {
+    loop {
+        let mut changed = false;
+        changed |= remove_zero_adjustments(lines);
+        changed |= fold_push_pop(lines);
+        changed |= zero_extend_setcc(lines);
+        changed |= fuse_compare_and_branch(lines);
+        changed |= simplify_jumps(lines);
+        changed |= fold_immediates(lines);
+        changed |= remove_redundant_moves(lines);
+        changed |= zero_with_xor(lines);
+        if !changed { break }
+    }
+}
+
+// What an instruction does to registers, so we can tell whether
+// it's safe to delete or move things around it
+struct Effects {
+    reads: Vec<String>,
+    writes: Vec<String>,
+    // We don't understand this instruction, assume the worst
+    opaque: bool
+}
+
+impl Effects {
+    fn reads (&self, family: &str) -> bool {
+        self.opaque || self.reads.iter().any(|r| r == family)
+    }
+    fn writes (&self, family: &str) -> bool {
+        self.opaque || self.writes.iter().any(|r| r == family)
+    }
+    fn touches (&self, family: &str) -> bool {
+        self.reads(family) || self.writes(family)
+    }
+}
+
+fn family_of (op: &Operand) -> Option<String> {
+    op.register().and_then(register_family).map(|(fam, _)| fam)
+}
+
+// The register or memory an indirect jump or call goes through
+fn through_indirect (op: &Operand) -> &Operand {
+    match op {
+        Operand::Indirect(target) => target,
+        _ => op
+    }
+}
+
+fn width_of (op: &Operand) -> Option<usize> {
+    op.register().and_then(register_family).map(|(_, width)| width)
+}
+
+// Strips an AT&T size suffix if the mnemonic is otherwise one we know
+fn base_mnemonic (mnemonic: &str) -> &str {
+    let known = [
+        "mov", "add", "sub", "and", "or", "xor", "adc", "sbb", "cmp",
+        "test", "neg", "not", "inc", "dec", "shl", "shr", "sar", "sal",
+        "imul", "mul", "idiv", "div", "lea", "push", "pop", "call", "ret"
+    ];
+    if known.contains(&mnemonic) {
+        return mnemonic
+    }
+    for suffix in &["b", "w", "l", "q"] {
+        if let Some(base) = mnemonic.strip_suffix(suffix) {
+            if known.contains(&base) { return base }
+        }
+    }
+    mnemonic
+}
+
+fn is_conditional_jump (mnemonic: &str) -> bool {
+    mnemonic.starts_with('j') && mnemonic != "jmp"
+}
+
+fn is_control_transfer (mnemonic: &str) -> bool {
+    mnemonic.starts_with('j') || mnemonic == "call" || mnemonic == "ret"
+}
+
+fn effects_of (inst: &Instruction) -> Effects {
+    let mut fx = Effects { reads: vec![], writes: vec![], opaque: false };
+    let ops = &inst.operands;
+
+    // Every register used in an address is read, whatever the instruction
+    for op in ops.iter().map(through_indirect) {
+        if let Operand::Memory(mem) = op {
+            for reg in mem.registers() {
+                if let Some((fam, _)) = register_family(reg) {
+                    fx.reads.push(fam);
+                }
+            }
+        }
+    }
+
+    let read = |fx: &mut Effects, op: &Operand| {
+        if let Some(fam) = family_of(through_indirect(op)) { fx.reads.push(fam) }
+    };
+    // Writes of less than 32 bits keep the rest of the register
+    let write = |fx: &mut Effects, op: &Operand| {
+        if let Some((fam, width)) = op.register().and_then(register_family) {
+            if width < 4 { fx.reads.push(fam.clone()) }
+            fx.writes.push(fam);
+        }
+    };
+    let flags = |fx: &mut Effects| fx.writes.push(FLAGS.to_string());
+
+    let mnemonic = &inst.mnemonic[..];
+    let base = base_mnemonic(mnemonic);
+
+    match (base, ops.len()) {
+        ("mov", 2) | ("lea", 2) => {
+            read(&mut fx, &ops[0]);
+            write(&mut fx, &ops[1]);
+        },
+        _ if (mnemonic.starts_with("movz") || mnemonic.starts_with("movs"))
+            && ops.len() == 2 => {
+            read(&mut fx, &ops[0]);
+            write(&mut fx, &ops[1]);
+        },
+        ("xor", 2) if ops[0] == ops[1] && ops[0].register().is_some() => {
+            // Zeroing idiom, doesn't depend on the old value
+            write(&mut fx, &ops[1]);
+            flags(&mut fx);
+        },
+        ("add", 2) | ("sub", 2) | ("and", 2) | ("or", 2) | ("xor", 2) |
+        ("imul", 2) | ("shl", 2) | ("shr", 2) | ("sar", 2) | ("sal", 2) => {
+            read(&mut fx, &ops[0]);
+            read(&mut fx, &ops[1]);
+            write(&mut fx, &ops[1]);
+            flags(&mut fx);
+        },
+        ("adc", 2) | ("sbb", 2) => {
+            read(&mut fx, &ops[0]);
+            read(&mut fx, &ops[1]);
+            write(&mut fx, &ops[1]);
+            fx.reads.push(FLAGS.to_string());
+            flags(&mut fx);
+        },
+        ("cmp", 2) | ("test", 2) => {
+            read(&mut fx, &ops[0]);
+            read(&mut fx, &ops[1]);
+            flags(&mut fx);
+        },
+        ("neg", 1) => {
+            read(&mut fx, &ops[0]);
+            write(&mut fx, &ops[0]);
+            flags(&mut fx);
+        },
+        ("not", 1) => {
+            read(&mut fx, &ops[0]);
+            write(&mut fx, &ops[0]);
+        },
+        ("inc", 1) | ("dec", 1) => {
+            // These preserve the carry flag, so partially read flags
+            read(&mut fx, &ops[0]);
+            write(&mut fx, &ops[0]);
+            fx.reads.push(FLAGS.to_string());
+            flags(&mut fx);
+        },
+        ("idiv", 1) | ("div", 1) | ("imul", 1) | ("mul", 1) => {
+            read(&mut fx, &ops[0]);
+            fx.reads.push("rax".to_string());
+            fx.reads.push("rdx".to_string());
+            fx.writes.push("rax".to_string());
+            fx.writes.push("rdx".to_string());
+            flags(&mut fx);
+        },
+        ("cdq", 0) | ("cltd", 0) | ("cqo", 0) | ("cqto", 0) => {
+            fx.reads.push("rax".to_string());
+            fx.writes.push("rdx".to_string());
+        },
+        ("cltq", 0) | ("cdqe", 0) => {
+            fx.reads.push("rax".to_string());
+            fx.writes.push("rax".to_string());
+        },
+        ("push", 1) => {
+            read(&mut fx, &ops[0]);
+            fx.reads.push("rsp".to_string());
+            fx.writes.push("rsp".to_string());
+        },
+        ("pop", 1) => {
+            fx.reads.push("rsp".to_string());
+            fx.writes.push("rsp".to_string());
+            write(&mut fx, &ops[0]);
+        },
+        ("call", 1) => {
+            read(&mut fx, &ops[0]);
+            for reg in ARGUMENT_REGISTERS {
+                fx.reads.push(reg.to_string());
+            }
+            // %al holds the vector register count for variadic calls
+            fx.reads.push("rax".to_string());
+            fx.reads.push("rsp".to_string());
+            for reg in CALLER_SAVED {
+                fx.writes.push(reg.to_string());
+            }
+            flags(&mut fx);
+        },
+        ("ret", 0) => {
+            for reg in LIVE_ON_RETURN {
+                fx.reads.push(reg.to_string());
+            }
+        },
+        ("leave", 0) => {
+            fx.reads.push("rbp".to_string());
+            fx.writes.push("rsp".to_string());
+            fx.writes.push("rbp".to_string());
+        },
+        ("nop", 0) => {},
+        _ if mnemonic.starts_with("set") && ops.len() == 1 => {
+            fx.reads.push(FLAGS.to_string());
+            write(&mut fx, &ops[0]);
+        },
+        _ if mnemonic.starts_with("cmov") && ops.len() == 2 => {
+            fx.reads.push(FLAGS.to_string());
+            read(&mut fx, &ops[0]);
+            read(&mut fx, &ops[1]);
+            write(&mut fx, &ops[1]);
+        },
+        _ if mnemonic == "jmp" && ops.len() == 1 => {
+            read(&mut fx, &ops[0]);
+        },
+        _ if is_conditional_jump(mnemonic) && ops.len() == 1 => {
+            fx.reads.push(FLAGS.to_string());
+        },
+        _ => fx.opaque = true
+    }
+
+    fx
+}
+
+fn label_indices (lines: &[AsmLine]) -> HashMap<String, usize> {
+    let mut labels = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let AsmLine::Label(label) = line {
+            labels.insert(label.clone(), i);
+        }
+    }
+    labels
+}
+
+// Where a jump goes, if it's to a label in this file
+fn jump_target (inst: &Instruction, labels: &HashMap<String, usize>) -> Option<usize> {
+    match inst.operands.first() {
+        Some(Operand::Label(label)) => labels.get(label).copied(),
+        _ => None
+    }
+}
+
+// Could the value of a register family (or "flags") at the end of
+// instruction idx be read by anything that runs afterwards?
+// Errs on the side of "yes".
+fn is_live_after (lines: &[AsmLine], labels: &HashMap<String, usize>, idx: usize, family: &str) -> bool {
+    // If idx is itself a jump, whatever's at its target runs next too
+    let mut to_visit = vec![];
+    match instruction_at(lines, idx) {
+        Some(inst) if inst.mnemonic.starts_with('j') => {
+            match jump_target(inst, labels) {
+                Some(target) => to_visit.push(target),
+                None => return true
+            }
+            if !inst.is("jmp") { to_visit.push(idx + 1) }
+        },
+        _ => to_visit.push(idx + 1)
+    }
+    let mut visited = HashSet::new();
+    let mut budget = LIVENESS_BUDGET;
+
+    while let Some(start) = to_visit.pop() {
+        let mut i = start;
+        loop {
+            if budget == 0 { return true }
+            budget -= 1;
+
+            if !visited.insert(i) { break }
+            if i >= lines.len() { return true }
+
+            let inst = match &lines[i] {
+                AsmLine::Label(_) | AsmLine::Directive(_) => {
+                    i += 1;
+                    continue
+                },
+                AsmLine::Raw(_) => return true,
+                AsmLine::Instruction(inst) => inst
+            };
+
+            let fx = effects_of(inst);
+            if fx.reads(family) { return true }
+            if fx.writes(family) { break }
+
+            if inst.is("ret") { break }
+            if inst.mnemonic.starts_with('j') {
+                match jump_target(inst, labels) {
+                    Some(target) => to_visit.push(target),
+                    None => return true
+                }
+                if inst.is("jmp") { break }
+            }
+            i += 1;
+        }
+    }
+
+    false
+}
+
+fn instruction_at (lines: &[AsmLine], idx: usize) -> Option<&Instruction> {
+    lines.get(idx).and_then(|line| line.instruction())
+}
+
+fn retain_indices (lines: &mut Vec<AsmLine>, remove: &HashSet<usize>) {
+    let mut i = 0;
+    lines.retain(|_| {
+        i += 1;
+        !remove.contains(&(i - 1))
+    });
+}
+
+// end_runtime_var_scope on an empty scope emits "add $0, %rsp"
+fn remove_zero_adjustments (lines: &mut Vec<AsmLine>) -> bool {
+    let labels = label_indices(lines);
+    let mut remove = HashSet::new();
+
+    for i in 0..lines.len() {
+        if let Some(inst) = instruction_at(lines, i) {
+            let base = base_mnemonic(&inst.mnemonic);
+            let is_zero_adjust = (base == "add" || base == "sub")
+                && inst.operands.len() == 2
+                && inst.operands[0].immediate() == Some(0)
+                && inst.operands[1].register().is_some();
+
+            if is_zero_adjust && !is_live_after(lines, &labels, i, FLAGS) {
+                remove.insert(i);
+            }
+        }
+    }
+
+    retain_indices(lines, &remove);
+    !remove.is_empty()
+}
+
+// push X; ...; pop Y  =>  mov X, Y; ...
+// As long as nothing in between touches the stack or Y
+fn fold_push_pop (lines: &mut Vec<AsmLine>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let pushed = match instruction_at(lines, i) {
+            Some(inst) if inst.is("push") || inst.is("pushq") => {
+                match &inst.operands[0] {
+                    op @ Operand::Register(_) | op @ Operand::Immediate(_) => op.clone(),
+                    _ => { i += 1; continue }
+                }
+            },
+            _ => { i += 1; continue }
+        };
+
+        let mut j = i + 1;
+        let mut folded = false;
+        while let Some(inst) = instruction_at(lines, j) {
+            if (inst.is("pop") || inst.is("popq")) && inst.operands[0].register().is_some() {
+                let popped = inst.operands[0].clone();
+
+                let popped_fam = family_of(&popped).unwrap();
+                let clobbered = (i + 1..j).any(|k| {
+                    effects_of(instruction_at(lines, k).unwrap()).touches(&popped_fam)
+                });
+                if clobbered { break }
+
+                if pushed == popped {
+                    lines.remove(j);
+                    lines.remove(i);
+                } else {
+                    lines.remove(j);
+                    lines[i] = AsmLine::Instruction(Instruction::new("mov", vec![pushed, popped]));
+                }
+                folded = true;
+                break
+            }
+
+            let fx = effects_of(inst);
+            if fx.touches("rsp") || is_control_transfer(&inst.mnemonic) { break }
+            j += 1;
+        }
+
+        if folded { changed = true } else { i += 1 }
+    }
+
+    changed
+}
+
+// mov $0, %rax; setX %al  =>  setX %al; movzbl %al, %eax
+// The mov can't be an xor because that would clobber the flags setX reads
+fn zero_extend_setcc (lines: &mut [AsmLine]) -> bool {
+    let mut changed = false;
+
+    for i in 0..lines.len().saturating_sub(1) {
+        let (zeroed, set) = match (instruction_at(lines, i), instruction_at(lines, i + 1)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue
+        };
+        if !(base_mnemonic(&zeroed.mnemonic) == "mov"
+            && zeroed.operands.len() == 2
+            && zeroed.operands[0].immediate() == Some(0)
+            && width_of(&zeroed.operands[1]).is_some_and(|w| w >= 4)) { continue }
+        if !(set.mnemonic.starts_with("set") && set.operands.len() == 1
+            && width_of(&set.operands[0]) == Some(1)) { continue }
+
+        let fam = family_of(&zeroed.operands[1]).unwrap();
+        if family_of(&set.operands[0]) != Some(fam.clone()) { continue }
+
+        let byte_reg = set.operands[0].clone();
+        lines[i] = lines[i + 1].clone();
+        lines[i + 1] = AsmLine::Instruction(Instruction::new("movzbl", vec![
+            byte_reg,
+            Operand::Register(register_with_width(&fam, 4))
+        ]));
+        changed = true;
+    }
+
+    changed
+}
+
+fn negate_condition (cond: &str) -> Option<&'static str> {
+    Some(match cond {
+        "e" | "z" => "ne",
+        "ne" | "nz" => "e",
+        "l" => "ge",
+        "ge" => "l",
+        "g" => "le",
+        "le" => "g",
+        "b" => "ae",
+        "ae" => "b",
+        "a" => "be",
+        "be" => "a",
+        _ => return None
+    })
+}
+
+fn is_compare_with_zero (inst: &Instruction, family: &str) -> bool {
+    let base = base_mnemonic(&inst.mnemonic);
+    if inst.operands.len() != 2 { return false }
+    let (a, b) = (&inst.operands[0], &inst.operands[1]);
+    match base {
+        "cmp" => a.immediate() == Some(0) && family_of(b).as_deref() == Some(family),
+        "test" => a == b && family_of(a).as_deref() == Some(family),
+        _ => false
+    }
+}
+
+// setX %al; movzbl %al, %eax; cmp $0, %rax; je L  =>  jnX L
+fn fuse_compare_and_branch (lines: &mut Vec<AsmLine>) -> bool {
+    let mut labels = label_indices(lines);
+    let mut changed = false;
+    let mut i = 0;
+
+    while i + 3 < lines.len() {
+        let window: Vec<Option<&Instruction>> = (i..i + 4).map(|k| instruction_at(lines, k)).collect();
+        let (set, ext, cmp, jump) = match (window[0], window[1], window[2], window[3]) {
+            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+            _ => { i += 1; continue }
+        };
+
+        let cond = match set.mnemonic.strip_prefix("set") {
+            Some(cond) if set.operands.len() == 1 => cond,
+            _ => { i += 1; continue }
+        };
+        let fam = match family_of(&set.operands[0]) {
+            Some(fam) => fam,
+            None => { i += 1; continue }
+        };
+
+        let fits = ext.mnemonic.starts_with("movzb")
+            && ext.operands[0] == set.operands[0]
+            && family_of(&ext.operands[1]).as_deref() == Some(&fam[..])
+            && is_compare_with_zero(cmp, &fam)
+            && (jump.is("je") || jump.is("jne") || jump.is("jz") || jump.is("jnz"));
+        if !fits { i += 1; continue }
+
+        let branch_if_true = jump.is("jne") || jump.is("jnz");
+        let new_cond = if branch_if_true { Some(cond) } else { negate_condition(cond) };
+        let new_cond = match new_cond {
+            Some(c) => c.to_string(),
+            None => { i += 1; continue }
+        };
+
+        if is_live_after(lines, &labels, i + 3, &fam) || is_live_after(lines, &labels, i + 3, FLAGS) {
+            i += 1;
+            continue
+        }
+
+        let target = jump.operands.clone();
+        lines.splice(i..i + 4, vec![
+            AsmLine::Instruction(Instruction::new(&format!("j{}", new_cond), target))
+        ]);
+        // Everything after the splice has moved
+        labels = label_indices(lines);
+        changed = true;
+        i += 1;
+    }
+
+    changed
+}
+
+// jX L1; jmp L2; L1:  =>  jnX L2; L1:
+// jmp L; L:  =>  L:
+fn simplify_jumps (lines: &mut Vec<AsmLine>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        if let Some(inst) = instruction_at(lines, i) {
+            if inst.is("jmp") {
+                if let (Some(Operand::Label(target)), Some(AsmLine::Label(next))) =
+                    (inst.operands.first(), lines.get(i + 1)) {
+                    if target == next {
+                        lines.remove(i);
+                        changed = true;
+                        continue
+                    }
+                }
+            }
+
+            if is_conditional_jump(&inst.mnemonic) && i + 2 < lines.len() {
+                let over = match (inst.operands.first(), instruction_at(lines, i + 1), &lines[i + 2]) {
+                    (Some(Operand::Label(skip)), Some(jmp), AsmLine::Label(next))
+                        if jmp.is("jmp") && skip == next => Some(jmp.operands.clone()),
+                    _ => None
+                };
+                let negated = negate_condition(&inst.mnemonic[1..]);
+
+                if let (Some(target), Some(negated)) = (over, negated) {
+                    if matches!(target.first(), Some(Operand::Label(_))) {
+                        lines[i] = AsmLine::Instruction(
+                            Instruction::new(&format!("j{}", negated), target)
+                        );
+                        lines.remove(i + 1);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+
+    changed
+}
+
+pub fn fits_in_imm32 (imm: isize) -> bool {
+    imm >= i32::MIN as isize && imm <= i32::MAX as isize
+}
+
+// The immediate an instruction reading a register at one width sees, when
+// it was moved into the register at another. Narrower reads only see its
+// low bytes. Writing a 32-bit register zeroes the upper half, but an
+// immediate operand is sign extended, so negative ones can't be widened.
+fn immediate_as_read (imm: isize, written: usize, read: usize) -> Option<isize> {
+    match read {
+        _ if read > written && imm < 0 => None,
+        1 => Some(imm as i8 as isize),
+        2 => Some(imm as i16 as isize),
+        4 => Some(imm as i32 as isize),
+        _ => Some(imm)
+    }
+}
+
+fn size_suffix (width: usize) -> &'static str {
+    match width {
+        1 => "b",
+        2 => "w",
+        4 => "l",
+        _ => "q"
+    }
+}
+
+// Is this operand exactly a register of the given family?
+fn is_family (op: &Operand, family: &str) -> bool {
+    family_of(op).as_deref() == Some(family)
+}
+
+fn fold_immediates (lines: &mut Vec<AsmLine>) -> bool {
+    let mut labels = label_indices(lines);
+    let mut changed = false;
+    let mut i = 0;
+
+    while i + 1 < lines.len() {
+        if let Some(folded) = fold_immediate_source(lines, &labels, i)
+            .or_else(|| fold_immediate_operation(lines, &labels, i)) {
+            let (len, replacement) = folded;
+            // The replacements are new instructions, so they'd lose the mark
+            if lines[i..i + len].iter().any(AsmLine::is_volatile) {
+                i += 1;
+                continue
+            }
+            lines.splice(i..i + len, replacement.into_iter().map(AsmLine::Instruction));
+            labels = label_indices(lines);
+            changed = true;
+        }
+        i += 1;
+    }
+
+    changed
+}
+
+// mov $imm, %R; OP %R, X  =>  OP $imm, X
+// When R isn't needed afterwards
+fn fold_immediate_source (lines: &[AsmLine], labels: &HashMap<String, usize>, i: usize) -> Option<(usize, Vec<Instruction>)> {
+    let mov = instruction_at(lines, i)?;
+    let user = instruction_at(lines, i + 1)?;
+
+    if base_mnemonic(&mov.mnemonic) != "mov" || mov.operands.len() != 2 { return None }
+    let imm = mov.operands[0].immediate()?;
+    let fam = family_of(&mov.operands[1])?;
+    if width_of(&mov.operands[1])? < 4 || !fits_in_imm32(imm) { return None }
+
+    let base = base_mnemonic(&user.mnemonic);
+    let foldable = ["mov", "add", "sub", "and", "or", "xor", "cmp", "push"];
+    if !foldable.contains(&base) { return None }
+
+    let src = &user.operands[0];
+    if !is_family(src, &fam) { return None }
+    // The register must not be part of the rest of the instruction
+    let rest_uses_it = user.operands[1..].iter().any(|op| match op {
+        Operand::Memory(mem) => mem.registers().iter().any(|r| {
+            register_family(r).map(|(f, _)| f) == Some(fam.clone())
+        }),
+        other => is_family(other, &fam)
+    });
+    if rest_uses_it { return None }
+    if is_live_after(lines, labels, i + 1, &fam) { return None }
+
+    let width = width_of(src)?;
+    let imm = immediate_as_read(imm, width_of(&mov.operands[1])?, width)?;
+    // Without a register operand, an immediate needs an explicit size
+    let sized_by_register = user.operands[1..].iter().any(|op| op.register().is_some());
+    let mnemonic = if user.mnemonic == base && base != "push" && !sized_by_register {
+        format!("{}{}", base, size_suffix(width))
+    } else {
+        user.mnemonic.clone()
+    };
+
+    let mut operands = user.operands.clone();
+    operands[0] = Operand::Immediate(imm);
+    Some((2, vec![Instruction::new(&mnemonic, operands)]))
+}
+
+// The stack machine computes "a OP imm" as:
+//   mov %rax, %rcx; mov $imm, %rax; OP %ecx, %eax
+// when it could be OP $imm, %eax
+fn fold_immediate_operation (lines: &[AsmLine], labels: &HashMap<String, usize>, i: usize) -> Option<(usize, Vec<Instruction>)> {
+    let save = instruction_at(lines, i)?;
+    let load = instruction_at(lines, i + 1)?;
+    let op = instruction_at(lines, i + 2)?;
+
+    // mov %A, %B
+    if base_mnemonic(&save.mnemonic) != "mov" || save.operands.len() != 2 { return None }
+    if width_of(&save.operands[0])? != 8 || width_of(&save.operands[1])? != 8 { return None }
+    let a = family_of(&save.operands[0])?;
+    let b = family_of(&save.operands[1])?;
+
+    // mov $imm, %A
+    if base_mnemonic(&load.mnemonic) != "mov" || load.operands.len() != 2 { return None }
+    let imm = load.operands[0].immediate()?;
+    if !is_family(&load.operands[1], &a) || width_of(&load.operands[1])? < 4 { return None }
+    if !fits_in_imm32(imm) { return None }
+
+    if op.operands.len() != 2 { return None }
+    let base = base_mnemonic(&op.mnemonic);
+    let (x, y) = (&op.operands[0], &op.operands[1]);
+    let imm = immediate_as_read(imm, width_of(&load.operands[1])?, width_of(y)?)?;
+
+    let commutative = ["add", "imul", "and", "or", "xor"];
+    if commutative.contains(&base) && is_family(x, &b) && is_family(y, &a) && width_of(x) == width_of(y) {
+        // OP %B, %A  =>  OP $imm, %A
+        if is_live_after(lines, labels, i + 2, &b) { return None }
+        return Some((3, vec![Instruction::new(&op.mnemonic, vec![Operand::Immediate(imm), y.clone()])]))
+    }
+
+    if base == "cmp" && is_family(x, &a) && is_family(y, &b) && width_of(x) == width_of(y) {
+        // cmp %A, %B  =>  cmp $imm, %A
+        // Only valid if A's new value (imm) isn't needed afterwards, bar the
+        // setX/movzbl pair the comparison codegen always produces
+        if is_live_after(lines, labels, i + 2, &b) { return None }
+        let set = instruction_at(lines, i + 3);
+        let ext = instruction_at(lines, i + 4);
+        let a_overwritten_by_setcc = match (set, ext) {
+            (Some(set), Some(ext)) => set.mnemonic.starts_with("set")
+                && is_family(&set.operands[0], &a)
+                && ext.mnemonic.starts_with("movzb")
+                && ext.operands[0] == set.operands[0]
+                && is_family(&ext.operands[1], &a)
+                && width_of(&ext.operands[1]).is_some_and(|w| w >= 4),
+            _ => false
+        };
+        if !a_overwritten_by_setcc && is_live_after(lines, labels, i + 2, &a) { return None }
+
+        let compared = Operand::Register(register_with_width(&a, width_of(y)?));
+        return Some((3, vec![Instruction::new(&op.mnemonic, vec![Operand::Immediate(imm), compared])]))
+    }
+
+    if base == "sub" && is_family(x, &a) && is_family(y, &b) && width_of(x) == width_of(y) {
+        // sub %A, %B; mov %B, %A  =>  sub $imm, %A
+        let mov_back = instruction_at(lines, i + 3)?;
+        if base_mnemonic(&mov_back.mnemonic) != "mov" || mov_back.operands.len() != 2 { return None }
+        if mov_back.operands[0] != *y || !is_family(&mov_back.operands[1], &a) { return None }
+        if width_of(&mov_back.operands[1]) != width_of(y) { return None }
+        if is_live_after(lines, labels, i + 3, &b) { return None }
+
+        return Some((4, vec![Instruction::new(&op.mnemonic, vec![
+            Operand::Immediate(imm),
+            mov_back.operands[1].clone()
+        ])]))
+    }
+
+    None
+}
+
+fn is_plain_move (inst: &Instruction) -> bool {
+    let base = base_mnemonic(&inst.mnemonic);
+    inst.operands.len() == 2
+        && (base == "mov" || base == "lea" || inst.mnemonic.starts_with("movz") || inst.mnemonic.starts_with("movs"))
+}
+
+fn remove_redundant_moves (lines: &mut Vec<AsmLine>) -> bool {
+    let labels = label_indices(lines);
+    let mut remove = HashSet::new();
+
+    for i in 0..lines.len() {
+        let inst = match instruction_at(lines, i) {
+            Some(inst) if is_plain_move(inst) && !inst.volatile => inst,
+            _ => continue
+        };
+        let (src, dst) = (&inst.operands[0], &inst.operands[1]);
+
+        // mov %rax, %rax (but not movl %eax, %eax, which zero-extends)
+        if base_mnemonic(&inst.mnemonic) == "mov" && src == dst && width_of(dst) == Some(8) {
+            remove.insert(i);
+            continue
+        }
+
+        // Moves into registers nobody reads
+        if let Some(fam) = family_of(dst) {
+            if width_of(dst).is_some_and(|w| w >= 4) && !is_live_after(lines, &labels, i, &fam) {
+                remove.insert(i);
+                continue
+            }
+        }
+
+        // mov A, B; mov B, A  =>  mov A, B
+        // (Only for full 64-bit moves, so nothing is truncated)
+        if let Some(next) = instruction_at(lines, i + 1) {
+            let both_full = [src, dst].iter().all(|op| match op {
+                Operand::Register(_) => width_of(op) == Some(8),
+                Operand::Memory(_) => true,
+                _ => false
+            });
+            let same_kind = next.mnemonic == inst.mnemonic
+                && (inst.mnemonic == "movq" || (inst.mnemonic == "mov" && both_full));
+            let writes_address_reg = match (src, dst) {
+                (Operand::Memory(mem), Operand::Register(_)) | (Operand::Register(_), Operand::Memory(mem)) => {
+                    let fams: Vec<Option<String>> = mem.registers().iter()
+                        .map(|r| register_family(r).map(|(f, _)| f)).collect();
+                    fams.contains(&family_of(dst))
+                },
+                _ => false
+            };
+            if both_full && same_kind && !writes_address_reg && !next.volatile
+                && next.operands.len() == 2 && next.operands[0] == *dst && next.operands[1] == *src {
+                remove.insert(i + 1);
+            }
+        }
+    }
+
+    retain_indices(lines, &remove);
+    !remove.is_empty()
+}
+
+// mov $0, %R  =>  xor %R32, %R32
+// Only when nothing reads the flags the xor would clobber
+fn zero_with_xor (lines: &mut [AsmLine]) -> bool {
+    let labels = label_indices(lines);
+    let mut changed = false;
+
+    for i in 0..lines.len() {
+        let fam = match instruction_at(lines, i) {
+            Some(inst) if base_mnemonic(&inst.mnemonic) == "mov"
+                && inst.operands.len() == 2
+                && inst.operands[0].immediate() == Some(0)
+                && width_of(&inst.operands[1]).is_some_and(|w| w >= 4) => {
+                family_of(&inst.operands[1]).unwrap()
+            },
+            _ => continue
+        };
+
+        if is_live_after(lines, &labels, i, FLAGS) { continue }
+
+        let reg = Operand::Register(register_with_width(&fam, 4));
+        lines[i] = AsmLine::Instruction(Instruction::new("xor", vec![reg.clone(), reg]));
+        changed = true;
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs one pass over some assembly, and returns what it became
+    fn apply<F: FnOnce(&mut Vec<AsmLine>) -> bool> (pass: F, asm: &str) -> String {
+        let mut lines: Vec<AsmLine> = asm.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(AsmLine::parse)
+            .collect();
+        pass(&mut lines);
+        lines.iter().map(|line| line.to_string()).collect::<Vec<_>>().join("\n")
+    }
+
+    fn unchanged<F: FnOnce(&mut Vec<AsmLine>) -> bool> (pass: F, asm: &str) {
+        let expected = apply(|_| false, asm);
+        assert_eq!(apply(pass, asm), expected);
+    }
+
+    #[test]
+    fn zero_adjustments_are_removed () {
+        assert_eq!(apply(remove_zero_adjustments, "
+            add $0, %rsp
+            ret
+        "), "ret");
+    }
+
+    #[test]
+    fn zero_adjustments_whose_flags_are_read_stay () {
+        unchanged(remove_zero_adjustments, "
+            add $0, %rsp
+            jmp .L1
+            .L1:
+            sete %al
+            ret
+        ");
+    }
+
+    #[test]
+    fn push_pop_pairs_become_moves () {
+        assert_eq!(apply(fold_push_pop, "
+            push %rax
+            mov -8(%rbp), %rax
+            pop %rcx
+        "), "mov %rax, %rcx\nmov -8(%rbp), %rax");
+        assert_eq!(apply(fold_push_pop, "
+            push %rax
+            pop %rax
+            ret
+        "), "ret");
+    }
+
+    #[test]
+    fn push_pop_pairs_around_jumps_stay () {
+        unchanged(fold_push_pop, "
+            push %rax
+            jne .L1
+            pop %rcx
+            .L1:
+            pop %rdx
+        ");
+    }
+
+    #[test]
+    fn setcc_is_zero_extended_instead_of_pre_zeroed () {
+        assert_eq!(apply(|lines| zero_extend_setcc(lines), "
+            mov $0, %rax
+            setl %al
+        "), "setl %al\nmovzbl %al, %eax");
+    }
+
+    #[test]
+    fn setcc_into_another_register_stays () {
+        unchanged(|lines| zero_extend_setcc(lines), "
+            mov $0, %rcx
+            setl %al
+        ");
+    }
+
+    #[test]
+    fn compare_and_branch_is_fused () {
+        assert_eq!(apply(fuse_compare_and_branch, "
+            cmp %rax, %rcx
+            setl %al
+            movzbl %al, %eax
+            cmp $0, %rax
+            je .L1
+            mov $2, %rax
+            .L1:
+            mov $1, %rax
+            ret
+        "), "cmp %rax, %rcx\njge .L1\nmov $2, %rax\n.L1:\nmov $1, %rax\nret");
+    }
+
+    // What && and || do, where the 0/1 is the result if the jump is taken
+    #[test]
+    fn compare_and_branch_whose_value_is_read_at_the_target_stays () {
+        unchanged(fuse_compare_and_branch, "
+            cmp %rax, %rcx
+            setg %al
+            movzbl %al, %eax
+            cmp $0, %rax
+            je .L1
+            mov $3, %rax
+            .L1:
+            mov %eax, -4(%rbp)
+            ret
+        ");
+    }
+
+    #[test]
+    fn compare_and_branch_liveness_follows_earlier_fusions () {
+        // Once the first sequence is fused, .L2 moves up. If the second's
+        // liveness check still used its old position it would start at
+        // the mov $3 and miss the read of %rax.
+        assert_eq!(apply(fuse_compare_and_branch, "
+            setl %al
+            movzbl %al, %eax
+            cmp $0, %rax
+            je .L1
+            .L1:
+            mov $1, %rax
+            cmp %rcx, %rdx
+            setg %al
+            movzbl %al, %eax
+            cmp $0, %rax
+            je .L2
+            mov $2, %rax
+            ret
+            .L2:
+            mov %rax, %rdi
+            nop
+            mov $3, %rax
+            ret
+        "), "jge .L1\n.L1:\nmov $1, %rax\ncmp %rcx, %rdx\nsetg %al\nmovzbl %al, %eax\ncmp $0, %rax\nje .L2\n\
+             mov $2, %rax\nret\n.L2:\nmov %rax, %rdi\nnop\nmov $3, %rax\nret");
+    }
+
+    #[test]
+    fn jumps_over_jumps_are_inverted () {
+        assert_eq!(apply(simplify_jumps, "
+            jl .L1
+            jmp .L2
+            .L1:
+            jmp .L3
+            .L3:
+            ret
+        "), "jge .L2\n.L1:\n.L3:\nret");
+    }
+
+    #[test]
+    fn jumps_elsewhere_stay () {
+        unchanged(simplify_jumps, "
+            jl .L1
+            jmp .L2
+            .L3:
+            ret
+            .L1:
+            ret
+        ");
+    }
+
+    #[test]
+    fn immediates_are_folded_into_their_users () {
+        assert_eq!(apply(fold_immediates, "
+            mov $5, %rax
+            add %rax, %rcx
+            mov $1, %rax
+            ret
+        "), "add $5, %rcx\nmov $1, %rax\nret");
+        assert_eq!(apply(fold_immediates, "
+            mov %rax, %rcx
+            mov $3, %rax
+            add %ecx, %eax
+            ret
+        "), "add $3, %eax\nret");
+    }
+
+    #[test]
+    fn negative_immediates_arent_widened () {
+        // movl zero extends, so %rsi is 4294967295 rather than -1
+        unchanged(fold_immediates, "
+            movl $-1, %esi
+            addq %rsi, %rcx
+            ret
+        ");
+        unchanged(fold_immediates, "
+            movl $-1, %esi
+            push %rsi
+            ret
+        ");
+        unchanged(fold_immediates, "
+            mov %rax, %rcx
+            movl $-2, %eax
+            add %rcx, %rax
+            ret
+        ");
+        // Positive ones read back the same at any width
+        assert_eq!(apply(fold_immediates, "
+            movl $7, %esi
+            addq %rsi, %rcx
+            ret
+        "), "addq $7, %rcx\nret");
+        assert_eq!(apply(fold_immediates, "
+            movl $-1, %esi
+            addl %esi, %ecx
+            ret
+        "), "addl $-1, %ecx\nret");
+        // Narrower ones only see the low bytes, eg. char c = 321;
+        assert_eq!(apply(fold_immediates, "
+            movl $321, %esi
+            movb %sil, -1(%rbp)
+            ret
+        "), "movb $65, -1(%rbp)\nret");
+    }
+
+    #[test]
+    fn immediates_still_needed_on_a_branch_stay () {
+        unchanged(fold_immediates, "
+            mov $5, %rax
+            add %rax, %rcx
+            jne .L1
+            mov $1, %rax
+            ret
+            .L1:
+            mov %rax, %rdx
+            ret
+        ");
+        unchanged(fold_immediates, "
+            mov %rax, %rcx
+            mov $3, %rax
+            add %ecx, %eax
+            jne .L1
+            ret
+            .L1:
+            mov %rcx, %rdx
+            ret
+        ");
+    }
+
+    #[test]
+    fn redundant_moves_are_removed () {
+        assert_eq!(apply(remove_redundant_moves, "
+            mov %rax, %rax
+            mov %rax, %rcx
+            mov %rax, -8(%rbp)
+            mov -8(%rbp), %rax
+            ret
+        "), "mov %rax, -8(%rbp)\nret");
+    }
+
+    #[test]
+    fn moves_read_on_a_branch_stay () {
+        unchanged(remove_redundant_moves, "
+            mov %rax, %rcx
+            je .L1
+            ret
+            .L1:
+            mov %rcx, %rax
+            ret
+        ");
+    }
+
+    #[test]
+    fn zeroing_uses_xor () {
+        assert_eq!(apply(|lines| zero_with_xor(lines), "
+            mov $0, %rax
+            ret
+        "), "xor %eax, %eax\nret");
+    }
+
+    #[test]
+    fn zeroing_before_a_flags_read_on_a_branch_stays () {
+        unchanged(|lines| zero_with_xor(lines), "
+            cmp %rcx, %rdx
+            mov $0, %rax
+            jmp .L1
+            .L1:
+            sete %al
+            ret
+        ");
+    }
+}
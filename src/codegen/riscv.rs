@@ -0,0 +1,250 @@
+// RISC-V code for RV64IM, following the LP64 calling convention.
+// Arguments go in a0-a7 and then on the stack. Structs of up to 16 bytes
+// take two registers, or are split between a7 and the stack if that's
+// all that's left, and bigger ones are passed by reference to a copy the
+// caller makes. Big structs are returned to memory the caller passes in a0.
+// Instructions only have room for 12-bit immediates, so t0 is used to
+// build bigger ones and the addresses they'd have been offsets in to.
+use super::abi::CallingConvention;
+use super::codegen::Codegen;
+use super::machine::Location;
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+
+pub static LP64: CallingConvention = CallingConvention {
+    argument_registers: &["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"],
+    return_registers: &["a0", "a1"],
+    indirect_result_register: "a0",
+    indirect_result_is_argument: true,
+    big_structs_by_reference: true,
+    stack_ends_registers: false,
+    splits_structs: true,
+    contiguous_variadic_arguments: true
+};
+
+// va_list is a pointer to the next argument
+pub static VA_LIST_SIZE: isize = 8;
+
+fn fits_in_imm12 (value: isize) -> bool {
+    (-2048..2048).contains(&value)
+}
+
+impl Codegen {
+    // dest = src + value, where src and dest can be sp
+    pub fn rv_add_immediate (&mut self, dest: &str, src: &str, value: isize) {
+        if fits_in_imm12(value) {
+            self.emit(format!("addi {}, {}, {}", dest, src, value));
+        } else {
+            self.emit(format!("li t0, {}", value));
+            self.emit(format!("add {}, {}, t0", dest, src));
+        }
+    }
+
+    pub fn rv_load_address (&mut self, dest: &str, location: &Location) {
+        match location {
+            Location::Frame(offset) => self.rv_add_immediate(dest, "s0", *offset),
+            Location::Stack(offset) => self.rv_add_immediate(dest, "sp", *offset),
+            Location::Symbol(label) => self.emit(format!("lla {}, {}", dest, label)),
+            Location::Pointer(register) => {
+                let src = self.register_name(*register);
+                if src != dest {
+                    self.emit(format!("mv {}, {}", dest, src));
+                }
+            }
+        }
+    }
+
+    // A memory operand for the location, emitting whatever it takes to
+    // reach it first
+    fn rv_memory_operand (&mut self, location: &Location) -> String {
+        match location {
+            Location::Frame(offset) => self.rv_base_offset_operand("s0", *offset),
+            Location::Stack(offset) => self.rv_base_offset_operand("sp", *offset),
+            Location::Symbol(_) => {
+                self.rv_load_address("t0", location);
+                "0(t0)".to_string()
+            },
+            Location::Pointer(register) => format!("0({})", self.register_name(*register))
+        }
+    }
+
+    fn rv_base_offset_operand (&mut self, base: &str, offset: isize) -> String {
+        if fits_in_imm12(offset) {
+            format!("{}({})", offset, base)
+        } else {
+            self.rv_add_immediate("t0", base, offset);
+            "0(t0)".to_string()
+        }
+    }
+
+    // Loads width bytes in to a register, sign or zero extended to 64 bits
+    pub fn rv_load (&mut self, dest: &str, width: usize, signed: bool, location: &Location) {
+        let operand = self.rv_memory_operand(location);
+        self.rv_load_operand(dest, width, signed, &operand);
+    }
+
+    fn rv_load_operand (&mut self, dest: &str, width: usize, signed: bool, operand: &str) {
+        let instruction = match (width, signed) {
+            (1, true) => "lb",
+            (1, false) => "lbu",
+            (2, true) => "lh",
+            (2, false) => "lhu",
+            (4, true) => "lw",
+            (4, false) => "lwu",
+            _ => "ld"
+        };
+        self.emit(format!("{} {}, {}", instruction, dest, operand));
+    }
+
+    // Stores the low width bytes of a register
+    pub fn rv_store (&mut self, src: &str, width: usize, location: &Location) {
+        let operand = self.rv_memory_operand(location);
+        self.rv_store_operand(src, width, &operand);
+    }
+
+    fn rv_store_operand (&mut self, src: &str, width: usize, operand: &str) {
+        let instruction = match width {
+            1 => "sb",
+            2 => "sh",
+            4 => "sw",
+            _ => "sd"
+        };
+        self.emit(format!("{} {}, {}", instruction, src, operand));
+    }
+
+    // Re-extends the low width bytes of a0 to 64 bits. There are no
+    // extension instructions in RV64IM, so the value is shifted to the
+    // top of the register and back down again.
+    pub fn rv_extend (&mut self, width: usize, signed: bool) {
+        match (width, signed) {
+            (1, false) => self.emit_str("andi a0, a0, 255"),
+            (4, true) => self.emit_str("sext.w a0, a0"),
+            (1 | 2 | 4, _) => {
+                let shift = 64 - width * 8;
+                let shift_right = if signed { "srai" } else { "srli" };
+                self.emit(format!("slli a0, a0, {}", shift));
+                self.emit(format!("{} a0, a0, {}", shift_right, shift));
+            },
+            _ => {}
+        }
+    }
+
+    // Copies size bytes from the address in a0 to the address in a1
+    pub fn rv_memory_copy (&mut self, size: isize) {
+        let mut copied = 0;
+        for width in [8, 4, 2, 1] {
+            while size - copied >= width {
+                let from = self.rv_base_offset_operand("a0", copied);
+                self.rv_load_operand("a2", width as usize, false, &from);
+                let to = self.rv_base_offset_operand("a1", copied);
+                self.rv_store_operand("a2", width as usize, &to);
+                copied += width;
+            }
+        }
+    }
+
+    // Loads part of a struct, without reading past its end
+    pub fn rv_load_eightbyte (&mut self, base: &str, offset: isize, size: isize, dest: &str) {
+        match size {
+            8 | 4 | 2 | 1 => {
+                let operand = self.rv_base_offset_operand(base, offset);
+                self.rv_load_operand(dest, size as usize, false, &operand);
+            },
+            _ => {
+                // Odd sizes are built up a byte at a time, high bytes first
+                self.emit(format!("li {}, 0", dest));
+                for byte in (0..size).rev() {
+                    self.emit(format!("slli {}, {}, 8", dest, dest));
+                    let operand = self.rv_base_offset_operand(base, offset + byte);
+                    self.rv_load_operand("t2", 1, false, &operand);
+                    self.emit(format!("or {}, {}, t2", dest, dest));
+                }
+            }
+        }
+    }
+
+    pub fn rv_store_eightbyte (&mut self, src: &str, base: &str, offset: isize, size: isize) {
+        match size {
+            8 | 4 | 2 | 1 => {
+                let operand = self.rv_base_offset_operand(base, offset);
+                self.rv_store_operand(src, size as usize, &operand);
+            },
+            _ => {
+                self.emit(format!("mv t2, {}", src));
+                for byte in 0..size {
+                    let operand = self.rv_base_offset_operand(base, offset + byte);
+                    self.rv_store_operand("t2", 1, &operand);
+                    self.emit_str("srli t2, t2, 8");
+                }
+            }
+        }
+    }
+
+    // a1 OP a0, in to a0
+    pub fn rv_arithmetic (&mut self, operator: &str, signed: bool) {
+        let suffix = if signed { "" } else { "u" };
+        match operator {
+            "+" => self.emit_str("add a0, a1, a0"),
+            "-" => self.emit_str("sub a0, a1, a0"),
+            "*" => self.emit_str("mul a0, a1, a0"),
+            "/" => self.emit(format!("div{} a0, a1, a0", suffix)),
+            "%" => self.emit(format!("rem{} a0, a1, a0", suffix)),
+            "==" => {
+                self.emit_str("sub a0, a1, a0");
+                self.emit_str("seqz a0, a0");
+            },
+            "!=" => {
+                self.emit_str("sub a0, a1, a0");
+                self.emit_str("snez a0, a0");
+            },
+            // There's only set-if-less-than, so the others swap its
+            // operands and/or flip its result
+            "<" => self.emit(format!("slt{} a0, a1, a0", suffix)),
+            ">" => self.emit(format!("slt{} a0, a0, a1", suffix)),
+            ">=" => {
+                self.emit(format!("slt{} a0, a1, a0", suffix));
+                self.emit_str("xori a0, a0, 1");
+            },
+            "<=" => {
+                self.emit(format!("slt{} a0, a0, a1", suffix));
+                self.emit_str("xori a0, a0, 1");
+            },
+            _ => unimplemented!("\"{}\" stack operator", operator)
+        }
+    }
+
+    pub fn rv_zero_fill (&mut self, offset: isize, size: isize) {
+        let mut filled = 0;
+        for width in [8, 4, 2, 1] {
+            while size - filled >= width {
+                self.rv_store("zero", width as usize, &Location::Frame(offset + filled));
+                filled += width;
+            }
+        }
+    }
+
+    // The save area is right below the stack arguments, so the unnamed
+    // arguments are one after another, wherever they were passed
+    pub fn rv_va_start (&mut self, save_area: isize, named_registers: usize, named_stack_bytes: isize) {
+        // The list's address is in a0
+        let first_unnamed = save_area + named_registers as isize * 8 + named_stack_bytes;
+        self.rv_load_address("a1", &Location::Frame(first_unnamed));
+        self.emit_str("sd a1, 0(a0)");
+    }
+
+    // Leaves the argument's address in a0
+    pub fn rv_va_arg (&mut self, va_arg: &ASTVaArg) {
+        let arg_type = &va_arg.arg_type;
+        let by_reference = size_in_bytes(arg_type) > 16;
+        let slot_size = if by_reference { 8 } else { align_to(size_in_bytes(arg_type), 8) };
+
+        self.emit_for_node(&va_arg.list);
+        self.emit_str("ld a1, 0(a0)");
+        self.emit(format!("addi a2, a1, {}", slot_size));
+        self.emit_str("sd a2, 0(a0)");
+        self.emit_str("mv a0, a1");
+        if by_reference {
+            self.emit_str("ld a0, 0(a0)");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// The builtins behind <stdarg.h>, following the System V va_list layout:
+// struct { unsigned gp_offset; unsigned fp_offset;
+//          void* overflow_arg_area; void* reg_save_area; }
+// gp_offset is how far through the register save area va_arg has got.
+// AArch64's and RISC-V's va_lists are different, see aarch64.rs and riscv.rs.
+use super::codegen::Codegen;
+use super::abi::*;
+use super::{aarch64, riscv};
+use super::machine::{Location, Register};
+use super::target::Architecture;
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+
+// Bytes of general purpose registers in the save area
+static GP_AREA_SIZE: usize = SYSTEM_V.argument_registers.len() * 8;
+// We never save the vector registers (there's no floating point), so
+// va_lists we make always say they've all been used up
+static FP_OFFSET_EXHAUSTED: usize = GP_AREA_SIZE + 8 * 16;
+static VA_LIST_SIZE: isize = 24;
+
+impl Codegen {
+    // Stores every argument register after the prologue of a variadic function
+    pub fn emit_register_save_area (&mut self, offset: isize) {
+        for (i, reg) in calling_convention(self.target.arch).argument_registers.iter().copied().enumerate() {
+            self.emit_store_register(Register::Named(reg), 8, &Location::Frame(offset + i as isize * 8));
+        }
+    }
+
+    // Returns false if the call isn't to one of the va_ builtins
+    pub fn emit_for_stdarg_call (&mut self, func_call: &ASTFunctionCall) -> bool {
+        let name = match &*func_call.callee {
+            ASTNode::Identifier(name) => name.clone(),
+            _ => return false
+        };
+        let expected_args = match &name[..] {
+            "va_start" | "va_copy" => 2,
+            "va_end" => 1,
+            _ => return false
+        };
+        if func_call.args.len() != expected_args {
+            panic!("{} takes {} arguments", name, expected_args)
+        }
+
+        match &name[..] {
+            "va_start" => self.emit_for_va_start(&func_call.args[0]),
+            "va_copy" => {
+                let size = match self.target.arch {
+                    Architecture::X86_64 => VA_LIST_SIZE,
+                    Architecture::AArch64 => aarch64::VA_LIST_SIZE,
+                    Architecture::RiscV64 => riscv::VA_LIST_SIZE
+                };
+                self.emit_for_node(&func_call.args[0]);
+                self.emit_push(Register::Accumulator);
+                self.emit_for_node(&func_call.args[1]);
+                self.emit_pop(Register::Secondary);
+                self.emit_memory_copy(size);
+            },
+            // There's nothing to clean up
+            _ => self.emit_for_node(&func_call.args[0])
+        }
+        true
+    }
+
+    // The second argument of va_start is only there for old compilers,
+    // we know where the named params end from the frame
+    fn emit_for_va_start (&mut self, list: &ASTNode) {
+        let (save_area, named_registers, named_stack_bytes) = match &self.frame {
+            Some(frame) => match frame.register_save_area {
+                Some(offset) => (offset, frame.named_registers, frame.named_stack_bytes),
+                None => panic!("va_start used in a function that isn't variadic")
+            },
+            None => unreachable!("va_start outside of a function")
+        };
+
+        self.emit_for_node(list);
+        match self.target.arch {
+            Architecture::X86_64 => {},
+            Architecture::AArch64 => return self.a64_va_start(save_area, named_registers, named_stack_bytes),
+            Architecture::RiscV64 => return self.rv_va_start(save_area, named_registers, named_stack_bytes)
+        }
+        self.emit(format!("movl ${}, 0(%rax)", named_registers * 8));
+        self.emit(format!("movl ${}, 4(%rax)", FP_OFFSET_EXHAUSTED));
+        // Stack args start above the return address and saved %rbp
+        self.emit(format!("lea {}(%rbp), %rcx", 16 + named_stack_bytes));
+        self.emit_str("mov %rcx, 8(%rax)");
+        self.emit(format!("lea {}(%rbp), %rcx", save_area));
+        self.emit_str("mov %rcx, 16(%rax)");
+    }
+
+    pub fn emit_for_va_arg (&mut self, va_arg: &ASTVaArg) {
+        let arg_type = &va_arg.arg_type;
+        if self.target.arch != Architecture::X86_64 {
+            match self.target.arch {
+                Architecture::AArch64 => self.a64_va_arg(va_arg),
+                _ => self.rv_va_arg(va_arg)
+            }
+            self.emit_load(arg_type, &Location::Pointer(Register::Accumulator));
+            return
+        }
+        let size = align_to(size_in_bytes(arg_type), 8);
+
+        self.emit_for_node(&va_arg.list);
+        self.emit_str("mov %rax, %rcx");
+
+        let overflow_label = self.get_unique_label("va_arg_overflow");
+        let end_label = self.get_unique_label("va_arg_end");
+
+        if let ArgClass::Integer(count) = classify(arg_type) {
+            // If there are enough registers left, the arg is in the save area.
+            // Eightbytes of structs are in consecutive registers, so are
+            // laid out just like the struct itself.
+            self.emit_str("movl 0(%rcx), %eax");
+            self.emit(format!("cmp ${}, %rax", GP_AREA_SIZE - count * 8));
+            self.emit(format!("ja {}", overflow_label));
+            self.emit_str("mov 16(%rcx), %rdx");
+            self.emit_str("add %rax, %rdx");
+            self.emit(format!("addl ${}, 0(%rcx)", count * 8));
+            self.emit(format!("jmp {}", end_label));
+        }
+
+        // Otherwise it was passed on the stack
+        self.emit(format!("{}:", overflow_label));
+        self.emit_str("mov 8(%rcx), %rdx");
+        self.emit(format!("lea {}(%rdx), %rax", size));
+        self.emit_str("mov %rax, 8(%rcx)");
+
+        self.emit(format!("{}:", end_label));
+        // Now %rdx has the arg's address
+        self.emit_str("mov %rdx, %rax");
+        self.emit_load(arg_type, &Location::Pointer(Register::Accumulator));
+    }
+}
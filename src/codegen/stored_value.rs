@@ -1,5 +1,8 @@
 use super::codegen::Codegen;
-use crate::parser::types::Type;
+use super::instruction::register_with_width;
+use super::machine::{Location, Register};
+use super::target::Architecture;
+use crate::parser::types::*;
 
 // These are values that can be referred to by identifiers in the scope_context
 #[derive(Clone)]
@@ -9,66 +12,125 @@ pub struct StoredValue {
 }
 #[derive(Clone)]
 pub enum ValueBackingStorage {
-    Stack(isize), // Local vars as offsets from the stack ptr
+    Stack(isize), // Local vars as offsets from the base ptr
     Global(String), // Global vars as assembly identifiers
+    Function(String), // Functions by name, which are code labels
 }
 
 impl Codegen {
-    pub fn get_stored_value_location (&self, value: &StoredValue) -> String {
+    // Where the value can be used in place as a memory operand. Globals
+    // defined in other files don't have one, since their address has
+    // to be loaded from the global offset table.
+    pub fn get_stored_value_location (&self, value: &StoredValue) -> Option<Location> {
         match &value.backing_store {
-            ValueBackingStorage::Stack(offset) => {
-                format!("{}(%rbp)", offset)
-            },
-            ValueBackingStorage::Global(ident) => {
-                let label = self.get_global_var_label(ident);
-                format!("{}(%rip)", label)
-            }
+            ValueBackingStorage::Stack(offset) => Some(Location::Frame(*offset)),
+            ValueBackingStorage::Global(ident) if !self.file_definitions.contains(ident) => None,
+            ValueBackingStorage::Global(ident) => Some(Location::Symbol(self.get_global_var_label(ident))),
+            ValueBackingStorage::Function(name) => Some(Location::Symbol(self.target.symbol(name)))
         }
     }
 
-    // Will emit code that puts the address of the value into %rax
-    // (used for &/* operators)
+    // Will emit code that puts the address of the value into the
+    // accumulator (used for &/* operators)
     pub fn emit_load_address_of_stored_value (&mut self, value: &StoredValue) {
-        match &value.backing_store {
-            ValueBackingStorage::Stack(offset) => {
-                self.emit_str("movq %rbp, %rax");
-                if *offset > 0 {
-                    self.emit(format!("addq ${}, %rax", offset));
-                } else {
-                    self.emit(format!("subq ${}, %rax", offset.abs()));
+        let loc = match self.get_stored_value_location(value) {
+            Some(loc) => loc,
+            None => {
+                if let ValueBackingStorage::Global(ident) = &value.backing_store {
+                    let label = self.get_global_var_label(ident);
+                    self.emit_load_address_from_got(&label);
                 }
-            },
-            ValueBackingStorage::Global(ident) => {
-                let label = self.get_global_var_label(ident);
-                self.emit(format!("lea {}(%rip), %rax", label));
+                return
             }
+        };
+        if let Type::VariableArray(_) = value.value_type {
+            // Its elements are allocated at run time, and the slot holds where
+            self.emit_load_scalar(8, false, &loc);
+        } else {
+            self.emit_load_address(Register::Accumulator, &loc);
         }
     }
 
     pub fn emit_for_stored_value_access (&mut self, value: &StoredValue) {
-        let loc = self.get_stored_value_location(value);
-        match &value.value_type {
-            Type::Char(_) => {
-                // NOTE: Little-endianness on x86 means that even though we're
-                // 64-bit pushing chars, we can retrieve just the top byte of the stack
-                // Byte moves don't auto-clear rax's high bits
-                self.emit_str("mov $0, %rax");
-                self.emit(format!("movb {}, %al", loc));
-            },
-            Type::Short(_) => {
-                self.emit_str("mov $0, %rax");
-                self.emit(format!("mov {}, %ax", loc));
+        if is_scalar(&value.value_type) {
+            match self.get_stored_value_location(value) {
+                Some(loc) => self.emit_load(&value.value_type, &loc),
+                None => {
+                    self.emit_load_address_of_stored_value(value);
+                    self.emit_load(&value.value_type, &Location::Pointer(Register::Accumulator));
+                }
+            }
+        } else {
+            // Arrays and functions decay to a pointer, and structs are passed around by address
+            self.emit_load_address_of_stored_value(value);
+        }
+    }
+
+    // Loads a value of the given type from loc into the accumulator, sign
+    // or zero extending it so the whole register holds the value
+    pub fn emit_load (&mut self, the_type: &Type, loc: &Location) {
+        match the_type {
+            Type::Char(_) | Type::Short(_) | Type::Int(_) | Type::LongLongInt(_) | Type::Pointer(_) => {
+                self.emit_load_scalar(size_in_bytes(the_type) as usize, is_signed(the_type), loc);
             },
-            Type::Int(_) => {
-                self.emit(format!("movl {}, %eax", loc));
+            Type::Array(_) | Type::Struct(_) | Type::Function(_) => {
+                self.emit_load_address(Register::Accumulator, loc);
+                return
             },
-            Type::LongLongInt(_) => {
-                self.emit(format!("movq {}, %rax", loc));
+            Type::VariableArray(_) => unreachable!("Variable length arrays are only used through their variable"),
+            // Nothing can have type void, so this is *p for a void* p
+            Type::Void(_) => panic!("Dereference of a void* pointer")
+        }
+
+        if qualifiers_of(the_type).is_volatile {
+            self.mark_volatile_access();
+        }
+    }
+
+    // Stores the accumulator into loc as the given type. Structs are copied
+    // from the address in the accumulator. Either way, it's left as it was.
+    pub fn emit_store (&mut self, the_type: &Type, loc: &Location) {
+        match the_type {
+            Type::Struct(_) | Type::Array(_) => {
+                self.emit_load_address(Register::Secondary, loc);
+                self.emit_memory_copy(size_in_bytes(the_type));
             },
-            Type::Pointer(_) => {
-                self.emit(format!("movq {}, %rax", loc));
+            _ => {
+                let width = size_in_bytes(the_type) as usize;
+                self.emit_store_register(Register::Accumulator, width, loc);
+                if qualifiers_of(the_type).is_volatile {
+                    self.mark_volatile_access();
+                }
             }
-        };
-        // self.emit(format!(format_string, self.get_stored_value_location(value)))
+        }
+    }
+
+    // Copies size bytes from the address in the accumulator to the
+    // address in the secondary register, using the scratch register
+    pub fn emit_memory_copy (&mut self, size: isize) {
+        match self.target.arch {
+            Architecture::X86_64 => {},
+            Architecture::AArch64 => return self.a64_memory_copy(size),
+            Architecture::RiscV64 => return self.rv_memory_copy(size)
+        }
+        let mut copied = 0;
+        for width in &[8, 4, 2, 1] {
+            while size - copied >= *width {
+                let scratch = register_with_width("rdx", *width as usize);
+                self.emit(format!("mov {}(%rax), %{}", copied, scratch));
+                self.emit(format!("mov %{}, {}(%rcx)", scratch, copied));
+                copied += width;
+            }
+        }
+    }
+
+    // Re-extends the accumulator after arithmetic so it holds a valid value of the type
+    pub fn emit_normalise (&mut self, the_type: &Type) {
+        match the_type {
+            Type::Char(_) | Type::Short(_) | Type::Int(_) => {
+                self.emit_extend(size_in_bytes(the_type) as usize, is_signed(the_type))
+            },
+            _ => {}
+        }
     }
 }
@@ -0,0 +1,133 @@
+// What we're generating assembly for: an instruction set and an OS.
+// x86-64 macOS and Linux share the System V calling convention, but
+// their assemblers and linkers disagree on how symbols are named and
+// how some directives are spelled. AArch64 follows AAPCS64 on Linux,
+// and 64-bit RISC-V (RV64IM) the LP64 convention.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Architecture {
+    X86_64,
+    AArch64,
+    RiscV64
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OperatingSystem {
+    // Mach-O
+    Darwin,
+    // ELF
+    Linux
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Target {
+    pub arch: Architecture,
+    pub os: OperatingSystem
+}
+
+impl Target {
+    // Where our own assembler, linker and JIT work
+    pub const X86_64_LINUX: Target = Target { arch: Architecture::X86_64, os: OperatingSystem::Linux };
+
+    pub fn new (arch: Architecture, os: OperatingSystem) -> Target {
+        Target { arch, os }
+    }
+
+    // What the compiler itself is running on, which is what
+    // we build for unless we're asked otherwise. Macs always get
+    // x86-64, which Apple silicon can run too.
+    pub fn host () -> Target {
+        if cfg!(target_os = "linux") {
+            let arch = if cfg!(target_arch = "aarch64") {
+                Architecture::AArch64
+            } else if cfg!(target_arch = "riscv64") {
+                Architecture::RiscV64
+            } else {
+                Architecture::X86_64
+            };
+            Target::new(arch, OperatingSystem::Linux)
+        } else {
+            Target::new(Architecture::X86_64, OperatingSystem::Darwin)
+        }
+    }
+
+    // "darwin" and "linux" on their own mean x86-64, otherwise it's a
+    // triple like aarch64-linux-gnu, riscv64-unknown-linux-gnu or x86_64-apple-darwin
+    pub fn from_name (name: &str) -> Option<Target> {
+        match name {
+            "darwin" | "macos" => return Some(Target::new(Architecture::X86_64, OperatingSystem::Darwin)),
+            "linux" => return Some(Target::X86_64_LINUX),
+            _ => {}
+        }
+
+        let mut parts = name.split('-');
+        let arch = match parts.next()? {
+            "x86_64" | "amd64" => Architecture::X86_64,
+            "aarch64" | "arm64" => Architecture::AArch64,
+            "riscv64" => Architecture::RiscV64,
+            _ => return None
+        };
+        let os = parts.find_map(|part| match part {
+            "linux" => Some(OperatingSystem::Linux),
+            _ if part.starts_with("darwin") || part.starts_with("macos") => Some(OperatingSystem::Darwin),
+            _ => None
+        })?;
+        Some(Target::new(arch, os))
+    }
+
+    // Whether char without "signed" or "unsigned" is signed. AAPCS64 and
+    // the RISC-V psABI make it unsigned.
+    pub fn char_is_signed (&self) -> bool {
+        self.arch == Architecture::X86_64
+    }
+
+    // The assembly name of a C function or global, eg. _main on macOS
+    pub fn symbol (&self, name: &str) -> String {
+        match self.os {
+            OperatingSystem::Darwin => format!("_{}", name),
+            OperatingSystem::Linux => name.to_string()
+        }
+    }
+
+    // Labels that only mean something inside this file, like jump targets,
+    // which the assembler leaves out of the object's symbol table. Since
+    // they can't look like C names, they can't clash with them either.
+    pub fn local_label (&self, name: &str) -> String {
+        match self.os {
+            OperatingSystem::Darwin => format!("L{}", name),
+            OperatingSystem::Linux => format!(".L{}", name)
+        }
+    }
+
+    pub fn read_only_section (&self) -> &'static str {
+        match self.os {
+            OperatingSystem::Darwin => ".const",
+            OperatingSystem::Linux => ".section .rodata"
+        }
+    }
+
+    // Read-only data holding addresses, which the dynamic loader has to
+    // fill in before it can be made read-only, eg. const char *const s = "x";
+    pub fn relocated_read_only_section (&self) -> &'static str {
+        match self.os {
+            OperatingSystem::Darwin => ".const_data",
+            OperatingSystem::Linux => ".section .data.rel.ro,\"aw\""
+        }
+    }
+
+    // Mach-O's .align takes a power of two, but ELF's takes bytes.
+    // .p2align means the same thing on both.
+    pub fn align_directive (&self, align_pot: usize) -> String {
+        match self.os {
+            OperatingSystem::Darwin => format!(".align {}", align_pot),
+            OperatingSystem::Linux => format!(".p2align {}", align_pot)
+        }
+    }
+
+    // The same goes for the alignment of common symbols
+    pub fn common_directive (&self, label: &str, size: isize, align_pot: usize) -> String {
+        match self.os {
+            OperatingSystem::Darwin => format!(".comm {}, {}, {}", label, size, align_pot),
+            OperatingSystem::Linux => format!(".comm {}, {}, {}", label, size, 1 << align_pot)
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// Works out the C type of expressions, which decides things like
+// how wide loads and stores are and how pointer arithmetic is scaled.
+// The parser needs this too, for sizeof.
+use super::codegen::Codegen;
+use super::freestanding::is_builtin_syscall;
+use crate::parser::ast_utils::*;
+use crate::parser::tokens::*;
+use crate::parser::types::*;
+
+pub trait Typing {
+    // The declared type of a variable or function in scope, or None
+    // if it was never declared (eg. printf without a prototype)
+    fn type_of_name (&self, name: &str) -> Option<Type>;
+
+    // Whether plain char is signed, which string literals are made of
+    fn char_is_signed (&self) -> bool;
+
+    fn type_of_node (&self, node: &ASTNode) -> Type {
+        match node {
+            ASTNode::IntegerLiteral(int) => integer_literal_type(*int),
+            ASTNode::StringLiteral(_) => {
+                pointer_to(plain_char_type(self.char_is_signed()))
+            },
+            ASTNode::Identifier(ident) => match self.type_of_name(ident) {
+                Some(the_type) => the_type,
+                None => panic!("Unresolved variable reference \"{}\"", ident)
+            },
+            ASTNode::UnaryOperation(unar) => {
+                let operand = self.type_of_node(&unar.operand);
+                match &unar.operator[..] {
+                    "&" => pointer_to(operand),
+                    "*" => match (pointed_to_type(&operand), &operand) {
+                        (Some(t), _) => t,
+                        // *func is the same as func
+                        (None, Type::Function(_)) => operand,
+                        (None, _) => panic!("Dereference of a non-pointer value")
+                    },
+                    "!" => {
+                        check_not_void(&operand);
+                        int_type()
+                    },
+                    _ => {
+                        check_not_void(&operand);
+                        arithmetic_result_type(&operand, &operand)
+                    }
+                }
+            },
+            ASTNode::BinaryOperation(bin) => self.type_of_binary_operation(bin),
+            ASTNode::MemberAccess(mem) => {
+                match self.type_of_node(&mem.object) {
+                    // Members of a const struct are const too
+                    Type::Struct(st) => qualified(st.find_member(&mem.member).member_type, st.qualifiers),
+                    _ => panic!("Member access \".{}\" on a non-struct value", mem.member)
+                }
+            },
+            ASTNode::VariableDeclaration(var) => var.var_type.clone(),
+            ASTNode::VaArg(va_arg) => va_arg.arg_type.clone(),
+            ASTNode::Cast(cast) => cast.target_type.clone(),
+            ASTNode::CompoundLiteral(lit) => lit.literal_type.clone(),
+            ASTNode::VariableArraySize(_) => size_type(),
+            ASTNode::FunctionCall(call) if is_builtin_syscall(call) => long_type(),
+            ASTNode::FunctionCall(call) => match self.type_of_callee(call) {
+                Some(signature) => *signature.return_type,
+                // Undeclared functions are assumed to return int
+                None => int_type()
+            },
+            _ => int_type()
+        }
+    }
+
+    // None for calls to functions that were never declared (eg. printf)
+    fn type_of_callee (&self, call: &ASTFunctionCall) -> Option<FunctionTypeMetadata> {
+        if let ASTNode::Identifier(name) = &*call.callee {
+            self.type_of_name(name)?;
+        }
+
+        match called_function_type(&self.type_of_node(&call.callee)) {
+            Some(signature) => Some(signature),
+            None => panic!("Called object is not a function or function pointer")
+        }
+    }
+
+    fn type_of_binary_operation (&self, bin: &ASTBinaryOperation) -> Type {
+        let left = self.type_of_node(&bin.left_side);
+        let right = self.type_of_node(&bin.right_side);
+        check_not_void(&left);
+        check_not_void(&right);
+
+        if is_assignment_operator(&bin.operator) {
+            return left
+        }
+
+        match &bin.operator[..] {
+            "==" | "!=" | "<" | ">" | "<=" | ">=" | "&&" | "||" => int_type(),
+            "+" | "-" => {
+                match (pointed_to_type(&left), pointed_to_type(&right)) {
+                    // Pointer difference is a count of elements
+                    (Some(_), Some(_)) => long_type(),
+                    (Some(elem), None) => pointer_to(elem),
+                    (None, Some(elem)) if bin.operator == "+" => pointer_to(elem),
+                    _ => arithmetic_result_type(&left, &right)
+                }
+            },
+            _ => arithmetic_result_type(&left, &right)
+        }
+    }
+}
+
+impl Typing for Codegen {
+    fn type_of_name (&self, name: &str) -> Option<Type> {
+        self.try_find_var(&name.to_string()).map(|value| value.value_type.clone())
+    }
+
+    fn char_is_signed (&self) -> bool {
+        self.target.char_is_signed()
+    }
+}
@@ -0,0 +1,59 @@
+// Variable length arrays, eg. "int a[n];" which are allocated on the
+// stack below the frame when their declaration runs. Their stack slot
+// holds where the elements are, their size in bytes (for sizeof) and
+// where the stack pointer was before, so they can be freed when their
+// scope ends.
+use super::codegen::Codegen;
+use super::machine::{Location, Register};
+use super::stored_value::ValueBackingStorage;
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+
+// How much of the frame each one takes up: the three values above
+pub fn variable_array_slot_type () -> Type {
+    Type::Array(ArrayTypeMetadata {
+        element: Box::new(long_type()),
+        length: 3
+    })
+}
+
+impl Codegen {
+    pub fn emit_for_variable_array_allocation (&mut self, offset: isize, arr: &VariableArrayTypeMetadata) {
+        self.emit_for_node(&arr.length);
+        self.emit_scale(Register::Accumulator, size_in_bytes(&arr.element));
+        self.emit_store_register(Register::Accumulator, 8, &Location::Frame(offset + 8));
+
+        // The first one in a scope remembers where the stack was beforehand
+        if let Some(mark @ None) = self.stack_marks.last_mut() {
+            *mark = Some(offset + 16);
+            self.emit_save_stack_pointer(offset + 16);
+        }
+
+        self.emit_allocate_stack();
+        self.emit_save_stack_pointer(offset);
+    }
+
+    pub fn emit_for_variable_array_size (&mut self, array: &ASTNode) {
+        let stored = match array {
+            ASTNode::Identifier(name) => self.find_var(name).clone(),
+            _ => panic!("sizeof a variable length array only works on the array's name")
+        };
+        match stored.backing_store {
+            ValueBackingStorage::Stack(offset) => {
+                self.emit_load_register(Register::Accumulator, &Location::Frame(offset + 8))
+            },
+            _ => unreachable!("Variable length arrays are always on the stack")
+        }
+    }
+
+    // break and continue free any variable length arrays in the
+    // scopes they jump out of (deeper than depth)
+    pub fn emit_jump_out_of_scopes (&mut self, label: &str, depth: usize) {
+        let mark = self.stack_marks.get(depth..)
+            .and_then(|marks| marks.iter().find_map(|mark| *mark));
+        if let Some(mark) = mark {
+            self.emit_restore_stack_pointer(mark);
+        }
+        self.emit_jump(label);
+    }
+}
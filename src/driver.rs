@@ -0,0 +1,345 @@
+// Turns the command line into work: compiling each C file on its own,
+// assembling the results, then linking them with the system's linker (cc)
+// or our own
+use std::collections::hash_map::HashMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::parser::char_stream::CharStream;
+use crate::parser::tokeniser::Tokeniser;
+use crate::parser::parser::Parser;
+use crate::codegen::codegen::Codegen;
+use crate::codegen::target::Target;
+use crate::assembler::assembler::assemble_source;
+use crate::assembler::elf::{write_object, read_object};
+use crate::linker::linker::{link, LinkInput};
+use crate::linker::runtime::*;
+use crate::jit::jit::run_object;
+use crate::interpreter::interpreter::Interpreter;
+use crate::parser::ast_utils::ASTNode;
+use crate::options::{Options, OutputKind};
+
+#[allow(unused_imports)]
+use crate::parser::ast_printer::print_ast_node;
+
+// The interpreter recurses once per nested expression and call in the
+// program, so it gets a thread with a much bigger stack than main's
+static INTERPRETER_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+fn read_code (path: &str) -> String {
+    fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Failed to open code file \"{}\" for reading", path))
+}
+
+fn compile (path: &str, options: &Options) -> Codegen {
+    compile_code(read_code(path), options.target, options.freestanding)
+}
+
+fn parse (code: String, target: Target) -> Vec<ASTNode> {
+    let stream = CharStream::new(code);
+    let tokeniser = Tokeniser::new(stream);
+    let mut parser = Parser::new(tokeniser, target);
+
+    let ast = parser.generate_ast();
+
+    // for node in &ast {
+    //     print_ast_node(node, 0);
+    // }
+
+    ast
+}
+
+// Each translation unit gets a fresh parser and codegen, so nothing
+// leaks from one file to the next besides what the linker connects
+fn compile_code (code: String, target: Target, freestanding: bool) -> Codegen {
+    let ast = parse(code, target);
+    let mut codegen = Codegen::new(ast, target, freestanding);
+    codegen.generate();
+    codegen
+}
+
+fn is_c_file (path: &str) -> bool {
+    path.ends_with(".c")
+}
+
+fn is_assembly_file (path: &str) -> bool {
+    path.ends_with(".s") || path.ends_with(".S")
+}
+
+// "src/a.c" -> "a.o", written to the working directory like other compilers do
+fn replace_extension (path: &str, extension: &str) -> PathBuf {
+    let stem = Path::new(path).file_stem().expect("Input file has no name");
+    PathBuf::from(stem).with_extension(extension)
+}
+
+fn run_tool (command: &mut Command) {
+    let status = match command.status() {
+        Ok(status) => status,
+        Err(err) => panic!("Failed to run {:?} ({})", command.get_program(), err)
+    };
+    if !status.success() {
+        panic!("{:?} failed", command)
+    }
+}
+
+// x86-64 ELF objects come from our own assembler. There's no Mach-O
+// writer or AArch64/RISC-V encoder, so anything else needs the system's.
+fn assemble (assembly: &str, object_path: &Path, target: Target, work_dir: &WorkDir) {
+    if target == Target::X86_64_LINUX {
+        let object = assemble_source(assembly);
+        fs::write(object_path, write_object(&object)).expect("Failed to write object file");
+        return
+    }
+
+    let assembly_path = work_dir.path.join(object_path.file_name().unwrap()).with_extension("s");
+    fs::write(&assembly_path, assembly).expect("Failed to write assembly file");
+    run_tool(Command::new("cc")
+        .arg("-c").arg(&assembly_path)
+        .arg("-o").arg(object_path));
+}
+
+fn read_assembly (path: &str) -> String {
+    fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Failed to open assembly file \"{}\" for reading", path))
+}
+
+// Two files defining the same non-static name would be rejected by the
+// linker anyway, but we can say which files they are
+fn check_for_duplicate_definitions (units: &[(String, Codegen)]) {
+    let mut definers: HashMap<String, &str> = HashMap::new();
+    for (path, codegen) in units {
+        for name in codegen.exported_definitions() {
+            if let Some(first) = definers.insert(name.clone(), path) {
+                panic!("Multiple definitions of \"{}\" (in {} and {})", name, first, path)
+            }
+        }
+    }
+}
+
+pub fn run (options: &Options) {
+    let needs_host = !matches!(options.output_kind, OutputKind::Assembly | OutputKind::Interpret);
+    if needs_host && options.target != Target::host() {
+        panic!("Objects and executables can only be built for the host, use -S to generate assembly")
+    }
+
+    if options.output_kind != OutputKind::Executable {
+        if let Some(path) = options.input_files.iter().find(|path| !is_c_file(path) && !is_assembly_file(path)) {
+            panic!("\"{}\" can only be used when linking", path)
+        }
+    }
+
+    match options.output_kind {
+        OutputKind::Assembly => emit_assembly(options),
+        OutputKind::Object => emit_objects(options),
+        OutputKind::Executable => link_executable(options),
+        OutputKind::Run => run_in_memory(options),
+        OutputKind::Interpret => interpret(options)
+    }
+}
+
+fn emit_assembly (options: &Options) {
+    for path in &options.input_files {
+        if !is_c_file(path) {
+            panic!("\"{}\" isn't a C file", path)
+        }
+        let codegen = compile(path, options);
+
+        match (&options.output_file, options.input_files.len()) {
+            (Some(output), _) => fs::write(output, &codegen.generated),
+            (None, 1) => {
+                print!("{}", codegen.generated);
+                Ok(())
+            },
+            (None, _) => fs::write(replace_extension(path, "s"), &codegen.generated)
+        }.expect("Failed to write assembly file")
+    }
+}
+
+fn emit_objects (options: &Options) {
+    let work_dir = WorkDir::new();
+    for path in &options.input_files {
+        let object_path = match &options.output_file {
+            Some(output) => PathBuf::from(output),
+            None => replace_extension(path, "o")
+        };
+
+        let assembly = if is_c_file(path) {
+            compile(path, options).generated
+        } else {
+            read_assembly(path)
+        };
+        assemble(&assembly, &object_path, options.target, &work_dir);
+    }
+}
+
+fn link_executable (options: &Options) {
+    let work_dir = WorkDir::new();
+
+    // Everything is compiled before anything is assembled, so that
+    // mistakes across files are reported before we run other tools
+    let mut units = vec![];
+    for path in options.input_files.iter().filter(|path| is_c_file(path)) {
+        units.push((path.clone(), compile(path, options)));
+    }
+    check_for_duplicate_definitions(&units);
+
+    if options.own_linker {
+        return link_with_own_linker(options, units)
+    }
+
+    let mut units = units.into_iter();
+    let mut link_inputs = vec![];
+    for (index, path) in options.input_files.iter().enumerate() {
+        if is_c_file(path) || is_assembly_file(path) {
+            let assembly = if is_c_file(path) {
+                units.next().unwrap().1.generated
+            } else {
+                read_assembly(path)
+            };
+            let object_path = work_dir.path_for(index, path, "o");
+            assemble(&assembly, &object_path, options.target, &work_dir);
+            link_inputs.push(object_path);
+        } else {
+            // Objects and libraries from elsewhere
+            link_inputs.push(PathBuf::from(path));
+        }
+    }
+
+    let mut linker = Command::new("cc");
+    if options.freestanding {
+        linker.arg("-nostdlib").arg("-static");
+    }
+    run_tool(linker.args(&link_inputs).arg("-o").arg(output_path(options)));
+}
+
+fn output_path (options: &Options) -> String {
+    options.output_file.clone().unwrap_or_else(|| "a.out".to_string())
+}
+
+// Everything stays in memory, and the result is a static executable.
+// Unless the program is freestanding, our runtime stands in for libc.
+fn link_with_own_linker (options: &Options, units: Vec<(String, Codegen)>) {
+    let mut units = units.into_iter();
+    let mut inputs = vec![];
+    for path in &options.input_files {
+        let object = if is_c_file(path) {
+            assemble_source(&units.next().unwrap().1.generated)
+        } else if is_assembly_file(path) {
+            assemble_source(&read_assembly(path))
+        } else if path.ends_with(".o") {
+            let bytes = fs::read(path).unwrap_or_else(|_| panic!("Failed to open object file \"{}\" for reading", path));
+            read_object(&bytes)
+        } else {
+            panic!("ass's linker can't use \"{}\", only C, assembly and object files", path)
+        };
+        inputs.push(LinkInput { name: path.clone(), object, library: false });
+    }
+
+    if !options.freestanding {
+        let runtime = compile_code(RUNTIME_SOURCE.to_string(), Target::X86_64_LINUX, true).generated + &runtime_entry_point();
+        inputs.push(LinkInput { name: "ass's runtime".to_string(), object: assemble_source(&runtime), library: true });
+    }
+
+    let output = output_path(options);
+    fs::write(&output, link(&inputs)).expect("Failed to write executable");
+    make_executable(&output);
+}
+
+#[cfg(unix)]
+fn make_executable (path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).expect("Failed to make the output executable")
+}
+
+#[cfg(not(unix))]
+fn make_executable (_path: &str) {}
+
+// No files and no other tools, so it's quick to try out changes. The
+// program shares our process, and its exit code becomes ours.
+fn run_in_memory (options: &Options) {
+    if !cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        panic!("--run only works on x86-64 Linux")
+    }
+    let path = &options.input_files[0];
+    if !is_c_file(path) {
+        panic!("\"{}\" isn't a C file", path)
+    }
+
+    let object = assemble_source(&compile(path, options).generated);
+    let mut args = vec![path.clone()];
+    args.extend(options.program_args.iter().cloned());
+    let status = run_object(&object, &args);
+    // Exiting through libc flushes anything the program printed with stdio
+    process::exit(status)
+}
+
+// Works anywhere, since no machine code is involved. Only the C library
+// functions the interpreter provides itself can be called.
+fn interpret (options: &Options) {
+    let path = &options.input_files[0];
+    if !is_c_file(path) {
+        panic!("\"{}\" isn't a C file", path)
+    }
+
+    let code = read_code(path);
+    let char_is_signed = options.target.char_is_signed();
+    let mut args = vec![path.clone()];
+    args.extend(options.program_args.iter().cloned());
+    // The AST can't be sent between threads, so it's parsed on this one too
+    let interpreter = thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || {
+            // Its va_list is laid out like x86-64's, whatever the target,
+            // but plain char is signed or not like it is on the target
+            let mut parser = Parser::new(Tokeniser::new(CharStream::new(code)), Target::X86_64_LINUX);
+            parser.char_is_signed = char_is_signed;
+            let ast = parser.generate_ast();
+            Interpreter::new(&ast, char_is_signed).run(&args)
+        })
+        .expect("Failed to start the interpreter");
+    match interpreter.join() {
+        Ok(status) => process::exit(status),
+        Err(panic) => std::panic::resume_unwind(panic)
+    }
+}
+
+// Somewhere to keep intermediate files, deleted when we're done
+struct WorkDir {
+    path: PathBuf
+}
+
+impl WorkDir {
+    // The temporary directory is shared, so the name mustn't be guessable
+    // ahead of time, and the directory has to be a new one rather than
+    // something already there
+    fn new () -> WorkDir {
+        for attempt in 0..100 {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos());
+            let path = env::temp_dir().join(format!("ass-{}-{:08x}{:02}", process::id(), nanos, attempt));
+            match fs::create_dir(&path) {
+                Ok(()) => return WorkDir { path },
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => panic!("Failed to create a temporary directory: {}", error)
+            }
+        }
+        panic!("Failed to create a temporary directory: too many already exist")
+    }
+
+    // Input files from different directories can share a name,
+    // so their position on the command line keeps them apart
+    fn path_for (&self, index: usize, input: &str, extension: &str) -> PathBuf {
+        let file_name = replace_extension(input, extension);
+        self.path.join(format!("{}-{}", index, file_name.display()))
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop (&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,903 @@
+// Runs programs by walking their AST instead of generating code. Values
+// follow the same rules as the codegen (the same types, sizes and struct
+// layouts, and scalars held as 64-bit numbers) so the two can be checked
+// against each other, and it works on machines that can't assemble or
+// run x86-64 code.
+use std::collections::hash_map::HashMap;
+use std::io::{self, Write};
+use crate::parser::ast_utils::*;
+use crate::parser::types::*;
+use crate::codegen::abi::signature_of;
+use crate::codegen::codegen::{collect_case_labels, element_size};
+use crate::codegen::freestanding::is_builtin_syscall;
+use crate::codegen::initialiser::{flatten_initialiser, completed_type, decoded_string_literal};
+use crate::codegen::typing::Typing;
+use super::memory::*;
+
+// Where the value behind a name is
+#[derive(Clone)]
+enum Storage {
+    Memory(u64),
+    Function(String),
+    // Declared extern but never defined, eg. libc's stdout, which we don't have
+    External(String)
+}
+
+#[derive(Clone)]
+struct Binding {
+    storage: Storage,
+    value_type: Type
+}
+
+// What running a statement leads to
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(i64)
+}
+
+// A function call that's running
+struct Frame {
+    // Every argument after the named ones is copied in to memory here,
+    // in 8-byte slots, for va_start to point at
+    variadic_args: Option<u64>
+}
+
+// va_list's overflow_arg_area, the only part of it we use
+pub static VA_LIST_NEXT_ARG: u64 = 8;
+static VA_LIST_SIZE: usize = 24;
+// How far apart function addresses are
+static FUNCTION_ALIGNMENT: u64 = 16;
+
+pub struct Interpreter<'a> {
+    pub memory: Memory,
+    // File scope names, which are visible everywhere
+    globals: HashMap<String, Binding>,
+    // Block scopes in the function that's running
+    scopes: Vec<HashMap<String, Binding>>,
+    frames: Vec<Frame>,
+    functions: HashMap<String, &'a ASTFunctionDefinition>,
+    // Functions that have had their address taken, in order. The address
+    // is worked out from the position.
+    function_table: Vec<String>,
+    // Initialised the first time their declaration runs, which is as good
+    // as before the program starts since nothing can see them before that
+    static_locals: HashMap<*const ASTVariableDeclaration, u64>,
+    string_literals: HashMap<*const ASTNode, u64>,
+    // In bytes, by the address of the array's elements
+    variable_array_sizes: HashMap<u64, u64>,
+    // The case label a switch is jumping to, while statements before it are skipped
+    seeking: Option<usize>,
+    // Whether plain char is signed on the target being imitated
+    char_is_signed: bool,
+    pub output: io::BufWriter<io::Stdout>
+}
+
+// Re-extends a 64-bit value so it holds a valid value of the type,
+// like the codegen does after arithmetic
+pub fn normalised (the_type: &Type, value: i64) -> i64 {
+    let signed = is_signed(the_type);
+    match the_type {
+        Type::Char(_) => if signed { value as i8 as i64 } else { value as u8 as i64 },
+        Type::Short(_) => if signed { value as i16 as i64 } else { value as u16 as i64 },
+        Type::Int(_) => if signed { value as i32 as i64 } else { value as u32 as i64 },
+        _ => value
+    }
+}
+
+fn size_of (the_type: &Type) -> usize {
+    size_in_bytes(the_type) as usize
+}
+
+fn align_of (the_type: &Type) -> usize {
+    alignment_in_bytes(the_type) as usize
+}
+
+// Functions can be declared any number of times, and a prototype with
+// parameters fills in one without, like in the codegen
+fn declare_function (scope: &mut HashMap<String, Binding>, func: &ASTFunctionDefinition) {
+    let signature = signature_of(func);
+    if let Some(Binding { storage: Storage::Function(_), value_type: Type::Function(known) }) = scope.get(&func.name) {
+        if known.return_type != signature.return_type {
+            panic!("Conflicting return types for function \"{}\"", func.name)
+        }
+        if !known.params.is_empty() || known.variadic {
+            return
+        }
+    }
+    scope.insert(func.name.clone(), Binding {
+        storage: Storage::Function(func.name.clone()),
+        value_type: Type::Function(signature)
+    });
+}
+
+// "int a, *b;" is one node with several declarations in it
+fn declarations (node: &ASTNode) -> Vec<&ASTNode> {
+    match node {
+        ASTNode::DeclarationList(decls) => decls.iter().collect(),
+        other => vec![other]
+    }
+}
+
+// Whether a switch's jump to this case label goes in to the statement
+fn contains_case (node: &ASTNode, target: usize) -> bool {
+    let mut cases = vec![];
+    collect_case_labels(node, &mut cases);
+    cases.into_iter().any(|case| case.id == target)
+}
+
+impl Typing for Interpreter<'_> {
+    fn type_of_name (&self, name: &str) -> Option<Type> {
+        self.find_binding(name).map(|binding| binding.value_type.clone())
+    }
+
+    fn char_is_signed (&self) -> bool {
+        self.char_is_signed
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new (ast: &'a [ASTNode], char_is_signed: bool) -> Interpreter<'a> {
+        let mut interpreter = Interpreter {
+            memory: Memory::new(),
+            globals: HashMap::new(),
+            scopes: vec![],
+            frames: vec![],
+            functions: HashMap::new(),
+            function_table: vec![],
+            static_locals: HashMap::new(),
+            string_literals: HashMap::new(),
+            variable_array_sizes: HashMap::new(),
+            seeking: None,
+            char_is_signed,
+            output: io::BufWriter::new(io::stdout())
+        };
+        interpreter.define_globals(ast);
+        interpreter
+    }
+
+    // Calls main with the arguments, returning its exit code
+    pub fn run (&mut self, args: &[String]) -> i32 {
+        let main = match self.functions.get("main") {
+            Some(main) => *main,
+            None => panic!("There's no main function to run")
+        };
+
+        // argv and its strings live with the globals. envp is empty.
+        let mut pointers = vec![];
+        for arg in args {
+            let mut bytes = arg.as_bytes().to_vec();
+            bytes.push(0);
+            let address = self.memory.allocate_static(bytes.len(), 1);
+            self.memory.write(address, &bytes);
+            pointers.push(address);
+        }
+        pointers.extend([0, 0]);
+        let argv = self.memory.allocate_static(pointers.len() * 8, 8);
+        for (i, pointer) in pointers.iter().enumerate() {
+            self.memory.write_integer(argv + i as u64 * 8, 8, *pointer);
+        }
+        let envp = argv + args.len() as u64 * 8 + 8;
+
+        let values = [args.len() as i64, argv as i64, envp as i64];
+        let main_args = main.params.iter().zip(values)
+            .map(|(param, value)| (param.param_type.clone(), value))
+            .collect();
+        let status = self.call_function(main, main_args);
+        self.flush_output();
+        status as i32
+    }
+
+    pub fn flush_output (&mut self) {
+        let _ = self.output.flush();
+    }
+
+    fn find_binding (&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    fn binding (&self, name: &str) -> Binding {
+        match self.find_binding(name) {
+            Some(binding) => binding.clone(),
+            None => panic!("Unresolved variable reference \"{}\"", name)
+        }
+    }
+
+    // Globals can be declared many times (eg. "extern int x; int x = 3;")
+    // and later declarations can complete their type, so storage is only
+    // allocated once every one has been seen
+    fn define_globals (&mut self, ast: &'a [ASTNode]) {
+        let mut defined = vec![];
+        let mut initialised = vec![];
+        for node in ast {
+            for decl in declarations(node) {
+                match decl {
+                    ASTNode::FunctionDefinition(func) => {
+                        declare_function(&mut self.globals, func);
+                        if func.body.is_some() && self.functions.insert(func.name.clone(), func).is_some() {
+                            panic!("Redefinition of function \"{}\"", func.name)
+                        }
+                    },
+                    ASTNode::VariableDeclaration(var) => {
+                        let var_type = match &var.initial_value {
+                            Some(init) => completed_type(var.var_type.clone(), init),
+                            None => var.var_type.clone()
+                        };
+                        let incomplete = matches!(&var_type, Type::Array(arr) if arr.length == 0);
+                        match self.globals.get_mut(&var.identifier) {
+                            Some(_) if incomplete => {},
+                            Some(existing) => existing.value_type = var_type,
+                            None => {
+                                self.globals.insert(var.identifier.clone(), Binding {
+                                    storage: Storage::External(var.identifier.clone()),
+                                    value_type: var_type
+                                });
+                            }
+                        }
+
+                        let is_definition = var.storage_class != StorageClass::Extern || var.initial_value.is_some();
+                        if is_definition && !defined.contains(&var.identifier) {
+                            defined.push(var.identifier.clone());
+                        }
+                        if let Some(init) = &var.initial_value {
+                            initialised.push((var.identifier.clone(), &**init));
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        for name in defined {
+            let global = self.globals.get_mut(&name).unwrap();
+            let address = self.memory.allocate_static(size_of(&global.value_type), align_of(&global.value_type));
+            global.storage = Storage::Memory(address);
+        }
+        for (name, init) in initialised {
+            let global = self.globals[&name].clone();
+            if let Storage::Memory(address) = global.storage {
+                self.initialise(address, &global.value_type, init);
+            }
+        }
+    }
+
+    // Zeroes the object, then stores each value from the initialiser in to place
+    fn initialise (&mut self, address: u64, the_type: &Type, init: &ASTNode) {
+        self.memory.fill(address, size_of(the_type), 0);
+        for leaf in flatten_initialiser(the_type, init) {
+            let location = address + leaf.offset as u64;
+            match (leaf.value, &leaf.leaf_type) {
+                (ASTNode::StringLiteral(st), Type::Array(_)) => {
+                    let mut bytes = decoded_string_literal(st);
+                    bytes.push(0);
+                    bytes.truncate(size_of(&leaf.leaf_type));
+                    self.memory.write(location, &bytes);
+                },
+                _ => {
+                    let value = self.eval(leaf.value);
+                    self.store(&leaf.leaf_type, location, value);
+                }
+            }
+        }
+    }
+
+    fn load (&self, the_type: &Type, address: u64) -> i64 {
+        match the_type {
+            Type::Char(_) | Type::Short(_) | Type::Int(_) | Type::LongLongInt(_) | Type::Pointer(_) => {
+                normalised(the_type, self.memory.read_integer(address, size_of(the_type)) as i64)
+            },
+            // Aggregates (and functions) are represented by their address
+            Type::Array(_) | Type::Struct(_) | Type::Function(_) => address as i64,
+            Type::VariableArray(_) => unreachable!("Variable length arrays are only used through their variable"),
+            Type::Void(_) => panic!("Dereference of a void* pointer")
+        }
+    }
+
+    // Structs and arrays are copied from the address in value
+    fn store (&mut self, the_type: &Type, address: u64, value: i64) {
+        match the_type {
+            Type::Struct(_) | Type::Array(_) => self.memory.copy(address, value as u64, size_of(the_type)),
+            _ => self.memory.write_integer(address, size_of(the_type), value as u64)
+        }
+    }
+
+    fn function_address (&mut self, name: &str) -> i64 {
+        let index = match self.function_table.iter().position(|known| known == name) {
+            Some(index) => index,
+            None => {
+                self.function_table.push(name.to_string());
+                self.function_table.len() - 1
+            }
+        };
+        (FUNCTION_BASE + index as u64 * FUNCTION_ALIGNMENT) as i64
+    }
+
+    fn function_at (&self, address: u64) -> String {
+        let index = address.wrapping_sub(FUNCTION_BASE) / FUNCTION_ALIGNMENT;
+        match self.function_table.get(index as usize) {
+            Some(name) if address.is_multiple_of(FUNCTION_ALIGNMENT) => name.clone(),
+            _ => panic!("Call through a pointer that isn't a function ({:#x})", address)
+        }
+    }
+
+    fn exec_block (&mut self, stmts: &[ASTNode]) -> Flow {
+        self.scopes.push(HashMap::new());
+        let mark = self.memory.stack_mark();
+
+        let mut flow = Flow::Normal;
+        for stmt in stmts {
+            flow = self.exec(stmt);
+            if !matches!(flow, Flow::Normal) {
+                break
+            }
+        }
+
+        self.scopes.pop();
+        // A returned struct can still be in this block's memory, so
+        // the function call frees it once it's been copied out
+        if !matches!(flow, Flow::Return(_)) {
+            self.memory.free_stack(mark);
+        }
+        flow
+    }
+
+    fn exec (&mut self, node: &ASTNode) -> Flow {
+        if let Some(target) = self.seeking {
+            return self.seek(node, target)
+        }
+
+        match node {
+            ASTNode::ReturnStatement(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value),
+                    None => 0
+                };
+                Flow::Return(value)
+            },
+            ASTNode::BlockStatement(stmts) => self.exec_block(stmts),
+            ASTNode::IfStatement(if_stmt) => {
+                if self.eval(&if_stmt.condition) != 0 {
+                    self.exec(&if_stmt.body)
+                } else if let Some(else_stmt) = &if_stmt.else_stmt {
+                    self.exec(else_stmt)
+                } else {
+                    Flow::Normal
+                }
+            },
+            ASTNode::WhileLoop(while_loop) => self.exec_while_loop(while_loop, false),
+            ASTNode::ForLoop(for_loop) => self.exec_for_loop(for_loop, false),
+            ASTNode::SwitchStatement(switch) => self.exec_switch_statement(switch),
+            ASTNode::BreakStatement => Flow::Break,
+            ASTNode::ContinueStatement => Flow::Continue,
+            ASTNode::VariableDeclaration(var) => {
+                self.declare_local(var, true);
+                Flow::Normal
+            },
+            ASTNode::DeclarationList(decls) => {
+                for decl in decls {
+                    self.exec(decl);
+                }
+                Flow::Normal
+            },
+            // Prototypes inside functions are only in scope there
+            ASTNode::FunctionDefinition(func) => {
+                declare_function(self.scopes.last_mut().unwrap(), func);
+                Flow::Normal
+            },
+            ASTNode::CaseLabel(_) | ASTNode::EmptyStatement => Flow::Normal,
+            expression => {
+                self.eval(expression);
+                Flow::Normal
+            }
+        }
+    }
+
+    // Skips statements on the way to a switch's case label. Jumping past a
+    // declaration still puts its name in scope, just without its value.
+    fn seek (&mut self, node: &ASTNode, target: usize) -> Flow {
+        if !contains_case(node, target) {
+            for decl in declarations(node) {
+                if let ASTNode::VariableDeclaration(var) = decl {
+                    self.declare_local(var, false);
+                }
+            }
+            return Flow::Normal
+        }
+
+        match node {
+            ASTNode::CaseLabel(_) => {
+                self.seeking = None;
+                Flow::Normal
+            },
+            ASTNode::BlockStatement(stmts) => self.exec_block(stmts),
+            ASTNode::IfStatement(if_stmt) => {
+                if contains_case(&if_stmt.body, target) {
+                    self.exec(&if_stmt.body)
+                } else {
+                    self.exec(if_stmt.else_stmt.as_ref().unwrap())
+                }
+            },
+            ASTNode::WhileLoop(while_loop) => self.exec_while_loop(while_loop, true),
+            ASTNode::ForLoop(for_loop) => self.exec_for_loop(for_loop, true),
+            _ => unreachable!("Case labels are only found in blocks, ifs and loops")
+        }
+    }
+
+    // entering is true when a switch jumps in to the body, past the condition
+    fn exec_while_loop (&mut self, while_loop: &ASTWhileLoop, entering: bool) -> Flow {
+        let mut skip_condition = entering;
+        loop {
+            if !skip_condition && self.eval(&while_loop.condition) == 0 {
+                return Flow::Normal
+            }
+            skip_condition = false;
+
+            let mark = self.memory.stack_mark();
+            match self.exec(&while_loop.body) {
+                Flow::Break => return Flow::Normal,
+                Flow::Return(value) => return Flow::Return(value),
+                _ => self.memory.free_stack(mark)
+            }
+        }
+    }
+
+    fn exec_for_loop (&mut self, for_loop: &ASTForLoop, entering: bool) -> Flow {
+        // The loop counter has a scope of its own
+        self.scopes.push(HashMap::new());
+        let mark = self.memory.stack_mark();
+        if let Some(declaration) = &for_loop.declaration {
+            self.exec(declaration);
+        }
+
+        let mut skip_condition = entering;
+        let flow = loop {
+            if !skip_condition {
+                if let Some(condition) = &for_loop.condition {
+                    if self.eval(condition) == 0 {
+                        break Flow::Normal
+                    }
+                }
+            }
+            skip_condition = false;
+
+            let body_mark = self.memory.stack_mark();
+            match self.exec(&for_loop.body) {
+                Flow::Break => break Flow::Normal,
+                Flow::Return(value) => break Flow::Return(value),
+                _ => self.memory.free_stack(body_mark)
+            }
+            if let Some(modification) = &for_loop.modification {
+                self.eval(modification);
+            }
+        };
+
+        self.scopes.pop();
+        if !matches!(flow, Flow::Return(_)) {
+            self.memory.free_stack(mark);
+        }
+        flow
+    }
+
+    fn exec_switch_statement (&mut self, switch: &ASTSwitchStatement) -> Flow {
+        let value = self.eval(&switch.value);
+        let mut cases = vec![];
+        collect_case_labels(&switch.body, &mut cases);
+
+        let matching = cases.iter().find(|case| case.value.is_some_and(|case_value| case_value as i64 == value));
+        let default = cases.iter().find(|case| case.value.is_none());
+        let target = match matching.or(default) {
+            Some(target) => target.id,
+            None => return Flow::Normal
+        };
+
+        self.seeking = Some(target);
+        let flow = self.exec(&switch.body);
+        self.seeking = None;
+        match flow {
+            Flow::Break => Flow::Normal,
+            other => other
+        }
+    }
+
+    // Locals get fresh stack memory each time their declaration runs
+    fn declare_local (&mut self, var: &ASTVariableDeclaration, initialise: bool) {
+        let var_type = match &var.initial_value {
+            Some(init) => completed_type(var.var_type.clone(), init),
+            None => var.var_type.clone()
+        };
+
+        let storage = match var.storage_class {
+            StorageClass::Static => {
+                let key = var as *const ASTVariableDeclaration;
+                let address = match self.static_locals.get(&key) {
+                    Some(address) => *address,
+                    None => {
+                        let address = self.memory.allocate_static(size_of(&var_type), align_of(&var_type));
+                        self.static_locals.insert(key, address);
+                        if let Some(init) = &var.initial_value {
+                            self.initialise(address, &var_type, init);
+                        }
+                        address
+                    }
+                };
+                Storage::Memory(address)
+            },
+            StorageClass::Extern => match self.globals.get(&var.identifier) {
+                Some(global) => global.storage.clone(),
+                None => Storage::External(var.identifier.clone())
+            },
+            StorageClass::Unspecified => {
+                if let Type::VariableArray(arr) = &var.var_type {
+                    let length = if initialise { self.eval(&arr.length) } else { 0 };
+                    let size = length as usize * size_of(&arr.element);
+                    let address = self.memory.allocate_stack(size, 16);
+                    self.variable_array_sizes.insert(address, size as u64);
+                    Storage::Memory(address)
+                } else {
+                    let address = self.memory.allocate_stack(size_of(&var_type), align_of(&var_type));
+                    if let (true, Some(init)) = (initialise, &var.initial_value) {
+                        self.initialise(address, &var_type, init);
+                    }
+                    Storage::Memory(address)
+                }
+            }
+        };
+
+        // Declared after the initialiser runs, so "int x = x;" means the outer x
+        self.scopes.last_mut().unwrap().insert(var.identifier.clone(), Binding {
+            storage,
+            value_type: var_type
+        });
+    }
+
+    fn eval (&mut self, node: &ASTNode) -> i64 {
+        match node {
+            ASTNode::IntegerLiteral(int) => *int as i64,
+            ASTNode::StringLiteral(st) => self.string_literal_address(node, st) as i64,
+            ASTNode::Identifier(name) => {
+                let binding = self.binding(name);
+                match binding.storage {
+                    Storage::Memory(address) => match binding.value_type {
+                        Type::VariableArray(_) => address as i64,
+                        value_type => self.load(&value_type, address)
+                    },
+                    Storage::Function(name) => self.function_address(&name),
+                    Storage::External(name) => panic!("\"{}\" is declared but never defined", name)
+                }
+            },
+            ASTNode::UnaryOperation(unar) => self.eval_unary_operation(node, unar),
+            ASTNode::BinaryOperation(bin) => self.eval_binary_operation(node, bin),
+            ASTNode::FunctionCall(call) => self.eval_function_call(call),
+            ASTNode::MemberAccess(_) => {
+                let member_type = self.type_of_node(node);
+                let address = self.address_of(node);
+                self.load(&member_type, address)
+            },
+            ASTNode::VaArg(va_arg) => {
+                let list = self.eval(&va_arg.list) as u64;
+                let next = self.memory.read_integer(list + VA_LIST_NEXT_ARG, 8);
+                let slot_size = align_to(size_in_bytes(&va_arg.arg_type), 8) as u64;
+                self.memory.write_integer(list + VA_LIST_NEXT_ARG, 8, next + slot_size);
+                self.load(&va_arg.arg_type, next)
+            },
+            ASTNode::Cast(cast) => {
+                let value = self.eval(&cast.operand);
+                if is_void(&cast.target_type) {
+                    // The value is thrown away, eg. "(void)unused;"
+                    return 0
+                }
+                normalised(&cast.target_type, value)
+            },
+            ASTNode::CompoundLiteral(lit) => {
+                let address = self.compound_literal(lit);
+                self.load(&lit.literal_type, address)
+            },
+            ASTNode::VariableArraySize(array) => {
+                let address = match &**array {
+                    ASTNode::Identifier(_) => self.address_of(array),
+                    _ => panic!("sizeof a variable length array only works on the array's name")
+                };
+                self.variable_array_sizes[&address] as i64
+            },
+            ASTNode::InlineAsm(_) => panic!("Inline assembly can't be interpreted"),
+            ASTNode::InitialiserList(_) => panic!("Initialiser list used outside of a declaration"),
+            _ => unreachable!("Statements don't have a value")
+        }
+    }
+
+    // Each string literal has one copy of its characters, made when it's first used
+    fn string_literal_address (&mut self, node: &ASTNode, st: &str) -> u64 {
+        let key = node as *const ASTNode;
+        if let Some(address) = self.string_literals.get(&key) {
+            return *address
+        }
+        let mut bytes = decoded_string_literal(st);
+        bytes.push(0);
+        let address = self.memory.allocate_static(bytes.len(), 1);
+        self.memory.write(address, &bytes);
+        self.string_literals.insert(key, address);
+        address
+    }
+
+    // Inside functions they're unnamed locals, outside they're unnamed globals
+    fn compound_literal (&mut self, lit: &ASTCompoundLiteral) -> u64 {
+        let (size, align) = (size_of(&lit.literal_type), align_of(&lit.literal_type));
+        let address = if self.frames.is_empty() {
+            self.memory.allocate_static(size, align)
+        } else {
+            self.memory.allocate_stack(size, align)
+        };
+        self.initialise(address, &lit.literal_type, &lit.initial_value);
+        address
+    }
+
+    fn address_of (&mut self, node: &ASTNode) -> u64 {
+        match node {
+            ASTNode::Identifier(name) => match self.binding(name).storage {
+                Storage::Memory(address) => address,
+                Storage::Function(name) => self.function_address(&name) as u64,
+                Storage::External(name) => panic!("\"{}\" is declared but never defined", name)
+            },
+            // The address is the pointer's value
+            ASTNode::UnaryOperation(unar) if unar.operator == "*" => self.eval(&unar.operand) as u64,
+            ASTNode::MemberAccess(mem) => {
+                let member = match self.type_of_node(&mem.object) {
+                    Type::Struct(st) => st.find_member(&mem.member),
+                    _ => panic!("Member access \".{}\" on a non-struct value", mem.member)
+                };
+                // Struct values are already represented by their address
+                self.eval(&mem.object) as u64 + member.offset as u64
+            },
+            ASTNode::CompoundLiteral(lit) => self.compound_literal(lit),
+            _ => panic!("Cannot take the address of a non-lvalue expression")
+        }
+    }
+
+    fn eval_unary_operation (&mut self, node: &ASTNode, unar: &ASTUnaryOperation) -> i64 {
+        match &unar.operator[..] {
+            "&" => self.address_of(&unar.operand) as i64,
+            "*" => {
+                let pointee = self.type_of_node(node);
+                let address = self.eval(&unar.operand);
+                self.load(&pointee, address as u64)
+            },
+            operator => {
+                let result_type = self.type_of_node(node);
+                let operand = self.eval(&unar.operand);
+                match operator {
+                    "-" => normalised(&result_type, operand.wrapping_neg()),
+                    "~" => normalised(&result_type, !operand),
+                    "+" => normalised(&result_type, operand),
+                    "!" => (operand == 0) as i64,
+                    _ => panic!("Unknown unary operator \"{}\"", operator)
+                }
+            }
+        }
+    }
+
+    fn eval_binary_operation (&mut self, node: &ASTNode, bin: &ASTBinaryOperation) -> i64 {
+        match &bin.operator[..] {
+            // Short-circuiting, so the right side might not run
+            "&&" => return (self.eval(&bin.left_side) != 0 && self.eval(&bin.right_side) != 0) as i64,
+            "||" => return (self.eval(&bin.left_side) != 0 || self.eval(&bin.right_side) != 0) as i64,
+            "=" => {
+                let target_type = self.type_of_node(&bin.left_side);
+                let address = self.address_of(&bin.left_side);
+                let value = self.eval(&bin.right_side);
+                self.store(&target_type, address, value);
+                return if is_scalar(&target_type) { normalised(&target_type, value) } else { value }
+            },
+            _ => {}
+        }
+
+        let left_type = self.type_of_node(&bin.left_side);
+        let right_type = self.type_of_node(&bin.right_side);
+        let result_type = self.type_of_node(node);
+        // Comparisons and division of pointers and unsigned values are unsigned
+        let operand_type = arithmetic_result_type(&left_type, &right_type);
+        let signed = is_signed(&operand_type);
+        let mut left = self.eval(&bin.left_side);
+        let mut right = self.eval(&bin.right_side);
+
+        let value = match &bin.operator[..] {
+            "+" | "-" => {
+                // When adding an integer to a pointer, the integer counts elements
+                match (pointed_to_type(&left_type), pointed_to_type(&right_type)) {
+                    (Some(elem), None) => right = right.wrapping_mul(element_size(&elem) as i64),
+                    (None, Some(elem)) => left = left.wrapping_mul(element_size(&elem) as i64),
+                    _ => {}
+                }
+                if bin.operator == "+" {
+                    left.wrapping_add(right)
+                } else if let (Some(elem), Some(_)) = (pointed_to_type(&left_type), pointed_to_type(&right_type)) {
+                    // Pointer difference counts elements, not bytes
+                    left.wrapping_sub(right) / element_size(&elem) as i64
+                } else {
+                    left.wrapping_sub(right)
+                }
+            },
+            "*" => left.wrapping_mul(right),
+            "/" | "%" => {
+                let (left, right) = (normalised(&operand_type, left), normalised(&operand_type, right));
+                if right == 0 {
+                    panic!("Division by zero in the interpreted program")
+                }
+                match (&bin.operator[..], signed) {
+                    ("/", true) => left.wrapping_div(right),
+                    ("/", false) => ((left as u64) / (right as u64)) as i64,
+                    (_, true) => left.wrapping_rem(right),
+                    (_, false) => ((left as u64) % (right as u64)) as i64
+                }
+            },
+            comparison => {
+                let (left, right) = (normalised(&operand_type, left), normalised(&operand_type, right));
+                let ordering = if signed { left.cmp(&right) } else { (left as u64).cmp(&(right as u64)) };
+                let result = match comparison {
+                    "==" => ordering.is_eq(),
+                    "!=" => ordering.is_ne(),
+                    "<" => ordering.is_lt(),
+                    ">" => ordering.is_gt(),
+                    "<=" => ordering.is_le(),
+                    ">=" => ordering.is_ge(),
+                    _ => panic!("Unknown binary operator \"{}\"", comparison)
+                };
+                return result as i64
+            }
+        };
+        normalised(&result_type, value)
+    }
+
+    fn eval_function_call (&mut self, call: &ASTFunctionCall) -> i64 {
+        if let ASTNode::Identifier(name) = &*call.callee {
+            if let Some(value) = self.eval_stdarg_call(name, &call.args) {
+                return value
+            }
+        }
+        if is_builtin_syscall(call) {
+            let args: Vec<i64> = call.args.iter().map(|arg| self.eval(arg)).collect();
+            return self.builtin_syscall(&args)
+        }
+
+        let signature = self.type_of_callee(call);
+        // Functions that haven't been declared are assumed to exist somewhere
+        let name = match &*call.callee {
+            ASTNode::Identifier(name) if matches!(self.find_binding(name).map(|b| &b.storage), Some(Storage::Function(_)) | None) => name.clone(),
+            callee => {
+                let address = self.eval(callee);
+                self.function_at(address as u64)
+            }
+        };
+
+        // Arguments without a declared parameter keep their own type
+        let mut args = vec![];
+        for (i, arg) in call.args.iter().enumerate() {
+            let arg_type = match signature.as_ref().and_then(|sig| sig.params.get(i)) {
+                Some(param_type) => param_type.clone(),
+                None => decayed(self.type_of_node(arg))
+            };
+            let value = self.eval(arg);
+            let value = if is_scalar(&arg_type) { normalised(&arg_type, value) } else { value };
+            args.push((arg_type, value));
+        }
+
+        let result = match self.functions.get(&name).copied() {
+            Some(func) => self.call_function(func, args),
+            None => self.call_library_function(&name, &args)
+        };
+        match &signature {
+            Some(sig) if is_scalar(&sig.return_type) => normalised(&sig.return_type, result),
+            Some(_) => result,
+            // Undeclared functions are assumed to return int
+            None => normalised(&int_type(), result)
+        }
+    }
+
+    // None if it isn't one of the va_ builtins
+    fn eval_stdarg_call (&mut self, name: &str, args: &[ASTNode]) -> Option<i64> {
+        let expected_args = match name {
+            "va_start" | "va_copy" => 2,
+            "va_end" => 1,
+            _ => return None
+        };
+        if args.len() != expected_args {
+            panic!("{} takes {} arguments", name, expected_args)
+        }
+
+        let list = self.eval(&args[0]) as u64;
+        match name {
+            "va_start" => {
+                let variadic_args = match self.frames.last().and_then(|frame| frame.variadic_args) {
+                    Some(address) => address,
+                    None => panic!("va_start used in a function that isn't variadic")
+                };
+                self.memory.write_integer(list + VA_LIST_NEXT_ARG, 8, variadic_args);
+            },
+            "va_copy" => {
+                let source = self.eval(&args[1]) as u64;
+                self.memory.copy(list, source, VA_LIST_SIZE);
+            },
+            // There's nothing to clean up
+            _ => {}
+        }
+        Some(0)
+    }
+
+    // Runs a function defined in the program, with arguments already
+    // converted to the parameters' types
+    fn call_function (&mut self, func: &'a ASTFunctionDefinition, args: Vec<(Type, i64)>) -> i64 {
+        if args.len() < func.params.len() {
+            panic!("Too few arguments in call to \"{}\"", func.name)
+        }
+        let return_type = &func.return_type;
+        // Returned structs are copied out to the caller before the callee's memory is freed
+        let return_slot = match return_type {
+            Type::Struct(_) => Some(self.memory.allocate_stack(size_of(return_type), align_of(return_type))),
+            _ => None
+        };
+
+        let mark = self.memory.stack_mark();
+        let caller_scopes = std::mem::replace(&mut self.scopes, vec![HashMap::new()]);
+
+        for (param, (_, value)) in func.params.iter().zip(&args) {
+            let address = self.memory.allocate_stack(size_of(&param.param_type), align_of(&param.param_type));
+            self.store(&param.param_type, address, *value);
+            // Unnamed params can't be used
+            if !param.name.is_empty() {
+                self.scopes[0].insert(param.name.clone(), Binding {
+                    storage: Storage::Memory(address),
+                    value_type: param.param_type.clone()
+                });
+            }
+        }
+
+        let variadic_args = if func.variadic {
+            let extra = &args[func.params.len()..];
+            let size: usize = extra.iter().map(|(arg_type, _)| align_to(size_in_bytes(arg_type), 8) as usize).sum();
+            let area = self.memory.allocate_stack(size, 8);
+            let mut offset = 0;
+            for (arg_type, value) in extra {
+                if is_scalar(arg_type) {
+                    // All 8 bytes, so it can be read back as any integer type
+                    self.memory.write_integer(area + offset, 8, *value as u64);
+                } else {
+                    self.store(arg_type, area + offset, *value);
+                }
+                offset += align_to(size_in_bytes(arg_type), 8) as u64;
+            }
+            Some(area)
+        } else {
+            None
+        };
+        self.frames.push(Frame { variadic_args });
+
+        // The body shares the parameters' scope
+        let mut flow = Flow::Normal;
+        for stmt in func.body.as_ref().unwrap() {
+            flow = self.exec(stmt);
+            if !matches!(flow, Flow::Normal) {
+                break
+            }
+        }
+        self.frames.pop();
+
+        // main returns 0 if it gets to the end
+        let value = match flow {
+            Flow::Return(value) => value,
+            _ => 0
+        };
+        let value = match return_slot {
+            Some(slot) => {
+                self.memory.copy(slot, value as u64, size_of(return_type));
+                slot as i64
+            },
+            None if is_scalar(return_type) => normalised(return_type, value),
+            None => value
+        };
+
+        self.scopes = caller_scopes;
+        self.memory.free_stack(mark);
+        value
+    }
+}
@@ -0,0 +1,284 @@
+// The C library functions interpreted programs can call, implemented
+// here on top of the simulated memory, and __builtin_syscall for the
+// few system calls freestanding programs need
+use std::io::{self, Write};
+use std::process;
+use crate::parser::types::Type;
+use super::interpreter::{Interpreter, VA_LIST_NEXT_ARG};
+
+static SYS_WRITE: i64 = 1;
+static SYS_EXIT: i64 = 60;
+static SYS_EXIT_GROUP: i64 = 231;
+
+// One % conversion in a printf format, eg. %-08.3ld
+#[derive(Default)]
+struct Conversion {
+    left_justify: bool,
+    plus_sign: bool,
+    space_sign: bool,
+    zero_pad: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+    // How many bytes the argument is, from hh, h, l, ll or z
+    length: usize
+}
+
+impl Conversion {
+    // Spaces to make up the width, on whichever side
+    fn pad (&self, body: Vec<u8>) -> Vec<u8> {
+        if body.len() >= self.width {
+            return body
+        }
+        let padding = vec![b' '; self.width - body.len()];
+        if self.left_justify {
+            [body, padding].concat()
+        } else {
+            [padding, body].concat()
+        }
+    }
+
+    // Zero padding goes between the sign (or 0x) and the digits
+    fn pad_number (&self, sign: &str, prefix: &str, mut digits: String) -> Vec<u8> {
+        if let Some(precision) = self.precision {
+            // Eg. printf("%.0d", 0) prints nothing
+            if precision == 0 && digits == "0" {
+                digits.clear();
+            }
+            while digits.len() < precision {
+                digits.insert(0, '0');
+            }
+        }
+        let length = sign.len() + prefix.len() + digits.len();
+        if self.zero_pad && !self.left_justify && self.precision.is_none() && length < self.width {
+            digits = "0".repeat(self.width - length) + &digits;
+        }
+        self.pad(format!("{}{}{}", sign, prefix, digits).into_bytes())
+    }
+
+    fn signed (&self, value: i64) -> i64 {
+        match self.length {
+            1 => value as i8 as i64,
+            2 => value as i16 as i64,
+            4 => value as i32 as i64,
+            _ => value
+        }
+    }
+
+    fn unsigned (&self, value: i64) -> u64 {
+        match self.length {
+            1 => value as u8 as u64,
+            2 => value as u16 as u64,
+            4 => value as u32 as u64,
+            _ => value as u64
+        }
+    }
+}
+
+fn argument (name: &str, args: &[(Type, i64)], index: usize) -> i64 {
+    match args.get(index) {
+        Some((_, value)) => *value,
+        None => panic!("Too few arguments in call to \"{}\"", name)
+    }
+}
+
+impl Interpreter<'_> {
+    pub fn call_library_function (&mut self, name: &str, args: &[(Type, i64)]) -> i64 {
+        match name {
+            "putchar" => {
+                let c = argument(name, args, 0) as u8;
+                if self.write_output(&[c]) { c as i64 } else { -1 }
+            },
+            "puts" => {
+                let mut bytes = self.memory.read_string(argument(name, args, 0) as u64);
+                bytes.push(b'\n');
+                if self.write_output(&bytes) { 0 } else { -1 }
+            },
+            "printf" => {
+                let format = self.memory.read_string(argument(name, args, 0) as u64);
+                let mut values = args[1..].iter().map(|(_, value)| *value);
+                let bytes = self.formatted(&format, || values.next());
+                if self.write_output(&bytes) { bytes.len() as i64 } else { -1 }
+            },
+            // Variadic arguments are all in 8 byte slots, see va_arg
+            "vprintf" => {
+                let format = self.memory.read_string(argument(name, args, 0) as u64);
+                let list = argument(name, args, 1) as u64;
+                let mut next = self.memory.read_integer(list + VA_LIST_NEXT_ARG, 8);
+                let memory = &self.memory;
+                let bytes = self.formatted(&format, || {
+                    let value = memory.read_integer(next, 8) as i64;
+                    next += 8;
+                    Some(value)
+                });
+                if self.write_output(&bytes) { bytes.len() as i64 } else { -1 }
+            },
+            "malloc" => self.memory.allocate_heap(argument(name, args, 0) as usize) as i64,
+            // The heap starts off zeroed and is never reused
+            "calloc" => {
+                let size = argument(name, args, 0) as usize * argument(name, args, 1) as usize;
+                self.memory.allocate_heap(size) as i64
+            },
+            "free" => 0,
+            "exit" => self.exit(argument(name, args, 0)),
+            _ => panic!("Undefined reference to \"{}\", which the interpreter doesn't provide", name)
+        }
+    }
+
+    pub fn builtin_syscall (&mut self, args: &[i64]) -> i64 {
+        let arg = |index: usize| match args.get(index) {
+            Some(value) => *value,
+            None => panic!("Too few arguments for system call {}", args[0])
+        };
+        match args.first() {
+            Some(number) if *number == SYS_WRITE => {
+                let bytes = self.memory.read(arg(2) as u64, arg(3) as usize).to_vec();
+                let written = match arg(1) {
+                    1 => self.write_output(&bytes),
+                    2 => {
+                        self.flush_output();
+                        io::stderr().write_all(&bytes).is_ok()
+                    },
+                    fd => panic!("The interpreter can't write to file descriptor {}", fd)
+                };
+                // -EIO
+                if written { bytes.len() as i64 } else { -5 }
+            },
+            Some(number) if *number == SYS_EXIT || *number == SYS_EXIT_GROUP => self.exit(arg(1)),
+            Some(number) => panic!("The interpreter can't make system call {}", number),
+            None => panic!("__builtin_syscall needs a system call number")
+        }
+    }
+
+    fn exit (&mut self, status: i64) -> ! {
+        self.flush_output();
+        process::exit(status as i32)
+    }
+
+    fn write_output (&mut self, bytes: &[u8]) -> bool {
+        self.output.write_all(bytes).is_ok()
+    }
+
+    fn formatted (&self, format: &[u8], mut args: impl FnMut() -> Option<i64>) -> Vec<u8> {
+        let mut next_arg = || match args() {
+            Some(value) => value,
+            None => panic!("printf's format needs more arguments than it was given")
+        };
+
+        let mut out = vec![];
+        let mut i = 0;
+        while i < format.len() {
+            let c = format[i];
+            i += 1;
+            if c != b'%' {
+                out.push(c);
+                continue
+            }
+
+            let mut conversion = Conversion { length: 4, ..Conversion::default() };
+            while i < format.len() {
+                match format[i] {
+                    b'-' => conversion.left_justify = true,
+                    b'+' => conversion.plus_sign = true,
+                    b' ' => conversion.space_sign = true,
+                    b'0' => conversion.zero_pad = true,
+                    b'#' => conversion.alternate = true,
+                    _ => break
+                }
+                i += 1;
+            }
+
+            if format.get(i) == Some(&b'*') {
+                i += 1;
+                let width = next_arg() as i32;
+                conversion.left_justify |= width < 0;
+                conversion.width = width.unsigned_abs() as usize;
+            } else {
+                while let Some(digit @ b'0'..=b'9') = format.get(i) {
+                    conversion.width = conversion.width * 10 + (digit - b'0') as usize;
+                    i += 1;
+                }
+            }
+
+            if format.get(i) == Some(&b'.') {
+                i += 1;
+                let mut precision = 0;
+                if format.get(i) == Some(&b'*') {
+                    i += 1;
+                    precision = (next_arg() as i32).max(0) as usize;
+                } else {
+                    while let Some(digit @ b'0'..=b'9') = format.get(i) {
+                        precision = precision * 10 + (digit - b'0') as usize;
+                        i += 1;
+                    }
+                }
+                conversion.precision = Some(precision);
+            }
+
+            while let Some(modifier) = format.get(i) {
+                conversion.length = match modifier {
+                    b'h' if conversion.length == 2 => 1,
+                    b'h' => 2,
+                    b'l' | b'z' | b'j' | b't' => 8,
+                    _ => break
+                };
+                i += 1;
+            }
+
+            let specifier = match format.get(i) {
+                Some(specifier) => *specifier,
+                None => panic!("printf format ends in the middle of a conversion")
+            };
+            i += 1;
+
+            let bytes = match specifier {
+                b'%' => vec![b'%'],
+                b'd' | b'i' => {
+                    let value = conversion.signed(next_arg());
+                    let sign = if value < 0 {
+                        "-"
+                    } else if conversion.plus_sign {
+                        "+"
+                    } else if conversion.space_sign {
+                        " "
+                    } else {
+                        ""
+                    };
+                    conversion.pad_number(sign, "", value.unsigned_abs().to_string())
+                },
+                b'u' => conversion.pad_number("", "", conversion.unsigned(next_arg()).to_string()),
+                b'x' | b'X' => {
+                    let value = conversion.unsigned(next_arg());
+                    let prefix = match (conversion.alternate && value != 0, specifier) {
+                        (true, b'x') => "0x",
+                        (true, _) => "0X",
+                        _ => ""
+                    };
+                    let digits = if specifier == b'x' { format!("{:x}", value) } else { format!("{:X}", value) };
+                    conversion.pad_number("", prefix, digits)
+                },
+                b'o' => {
+                    let value = conversion.unsigned(next_arg());
+                    let prefix = if conversion.alternate && value != 0 { "0" } else { "" };
+                    conversion.pad_number("", prefix, format!("{:o}", value))
+                },
+                b'c' => conversion.pad(vec![next_arg() as u8]),
+                b's' => {
+                    let mut string = self.memory.read_string(next_arg() as u64);
+                    if let Some(precision) = conversion.precision {
+                        string.truncate(precision);
+                    }
+                    conversion.pad(string)
+                },
+                // Like glibc
+                b'p' => match next_arg() {
+                    0 => conversion.pad(b"(nil)".to_vec()),
+                    pointer => conversion.pad(format!("{:#x}", pointer).into_bytes())
+                },
+                other => panic!("The interpreter's printf doesn't support %{}", other as char)
+            };
+            out.extend(bytes);
+        }
+        out
+    }
+}
@@ -0,0 +1,148 @@
+// The interpreter's simulated address space. Pointers are plain numbers,
+// like they are in compiled code, so pointer arithmetic and casts behave
+// the same. Each region lives at its own address range, and anything
+// outside of them (like null) faults instead of reading garbage.
+
+// Globals, static locals and string literals
+static STATIC_BASE: u64 = 0x10000;
+// Grows upwards, unlike a real stack, which C code can't tell apart
+static STACK_BASE: u64 = 0x1000_0000;
+static STACK_SIZE: usize = 8 * 1024 * 1024;
+// malloc's memory, which is never given back
+static HEAP_BASE: u64 = 0x4000_0000;
+// Functions get addresses here so they can be pointed to, but there's
+// no memory behind them
+pub static FUNCTION_BASE: u64 = 0x7000_0000;
+
+struct Region {
+    name: &'static str,
+    start: u64,
+    bytes: Vec<u8>,
+    // How big it can get before we run out
+    limit: usize
+}
+
+impl Region {
+    fn new (name: &'static str, start: u64, limit: usize) -> Region {
+        Region { name, start, bytes: vec![], limit }
+    }
+
+    fn contains (&self, address: u64, length: usize) -> bool {
+        address >= self.start && address.saturating_add(length as u64) <= self.start + self.bytes.len() as u64
+    }
+
+    // Returns the offset of the new memory
+    fn grow (&mut self, size: usize, align: usize) -> usize {
+        let offset = self.bytes.len().div_ceil(align.max(1)) * align.max(1);
+        if offset + size > self.limit {
+            panic!("The interpreted program ran out of {} memory", self.name)
+        }
+        self.bytes.resize(offset + size, 0);
+        offset
+    }
+}
+
+pub struct Memory {
+    statics: Region,
+    stack: Region,
+    heap: Region
+}
+
+impl Memory {
+    pub fn new () -> Memory {
+        Memory {
+            statics: Region::new("static", STATIC_BASE, (STACK_BASE - STATIC_BASE) as usize),
+            stack: Region::new("stack", STACK_BASE, STACK_SIZE),
+            heap: Region::new("heap", HEAP_BASE, (FUNCTION_BASE - HEAP_BASE) as usize)
+        }
+    }
+
+    pub fn allocate_static (&mut self, size: usize, align: usize) -> u64 {
+        self.statics.start + self.statics.grow(size, align) as u64
+    }
+
+    pub fn allocate_heap (&mut self, size: usize) -> u64 {
+        self.heap.start + self.heap.grow(size, 16) as u64
+    }
+
+    // Fresh stack memory is zeroed, and anything above the top of the
+    // stack can't be used, so pointers to dead locals fault
+    pub fn allocate_stack (&mut self, size: usize, align: usize) -> u64 {
+        if self.stack.bytes.len() + size + align > self.stack.limit {
+            panic!("Stack overflow in the interpreted program")
+        }
+        self.stack.start + self.stack.grow(size, align) as u64
+    }
+
+    // Where the top of the stack is, to free everything above it later
+    pub fn stack_mark (&self) -> usize {
+        self.stack.bytes.len()
+    }
+
+    pub fn free_stack (&mut self, mark: usize) {
+        self.stack.bytes.truncate(mark);
+    }
+
+    fn region (&self, address: u64, length: usize) -> Option<&Region> {
+        let regions: [&Region; 3] = [&self.statics, &self.stack, &self.heap];
+        IntoIterator::into_iter(regions).find(|region| region.contains(address, length))
+    }
+
+    fn region_mut (&mut self, address: u64, length: usize) -> Option<&mut Region> {
+        let regions: [&mut Region; 3] = [&mut self.statics, &mut self.stack, &mut self.heap];
+        IntoIterator::into_iter(regions).find(|region| region.contains(address, length))
+    }
+
+    pub fn read (&self, address: u64, length: usize) -> &[u8] {
+        match self.region(address, length) {
+            Some(region) => {
+                let offset = (address - region.start) as usize;
+                &region.bytes[offset..offset + length]
+            },
+            None => panic!("Segmentation fault: read of {} bytes at {:#x}", length, address)
+        }
+    }
+
+    pub fn write (&mut self, address: u64, bytes: &[u8]) {
+        match self.region_mut(address, bytes.len()) {
+            Some(region) => {
+                let offset = (address - region.start) as usize;
+                region.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+            },
+            None => panic!("Segmentation fault: write of {} bytes at {:#x}", bytes.len(), address)
+        }
+    }
+
+    // Little endian, and zero extended to 64 bits
+    pub fn read_integer (&self, address: u64, size: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(self.read(address, size));
+        u64::from_le_bytes(bytes)
+    }
+
+    // Only the low size bytes are stored
+    pub fn write_integer (&mut self, address: u64, size: usize, value: u64) {
+        self.write(address, &value.to_le_bytes()[..size]);
+    }
+
+    pub fn copy (&mut self, destination: u64, source: u64, length: usize) {
+        let bytes = self.read(source, length).to_vec();
+        self.write(destination, &bytes);
+    }
+
+    pub fn fill (&mut self, address: u64, length: usize, value: u8) {
+        self.write(address, &vec![value; length]);
+    }
+
+    // A null terminated string, without its terminator
+    pub fn read_string (&self, address: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let byte = self.read(address + bytes.len() as u64, 1)[0];
+            if byte == 0 {
+                return bytes
+            }
+            bytes.push(byte);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Loads an object from our assembler straight into memory and calls its
+// main, like a tiny dynamic loader. Functions from libc are found in our
+// own process, and reached through stubs and a GOT since they can be
+// much more than 2GB away from the program.
+use std::collections::hash_map::HashMap;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use crate::assembler::object::*;
+use crate::jit::memory::*;
+
+// "jmp *entry(%rip)", then padding
+static STUB_SIZE: usize = 8;
+
+type MainFunction = extern "C" fn (c_int, *const *const c_char) -> c_int;
+
+struct Loader<'a> {
+    object: &'a ObjectFile,
+    section_offsets: HashMap<usize, usize>,
+    common_offsets: HashMap<usize, usize>,
+    // Indexed by symbol, for undefined functions that are called
+    stub_offsets: HashMap<usize, usize>,
+    got_offsets: HashMap<usize, usize>,
+    library_addresses: HashMap<usize, u64>,
+    base: u64
+}
+
+// Returns main's exit code
+pub fn run_object (object: &ObjectFile, args: &[String]) -> i32 {
+    let mut loader = Loader {
+        object,
+        section_offsets: HashMap::new(),
+        common_offsets: HashMap::new(),
+        stub_offsets: HashMap::new(),
+        got_offsets: HashMap::new(),
+        library_addresses: HashMap::new(),
+        base: 0
+    };
+    loader.find_library_symbols();
+    let main = loader.load();
+
+    // argv is null terminated, like the kernel gives it to us
+    let args: Vec<CString> = args.iter()
+        .map(|arg| CString::new(&arg[..]).expect("Program arguments can't contain null bytes"))
+        .collect();
+    let mut argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+    argv.push(std::ptr::null());
+
+    let main: MainFunction = unsafe { std::mem::transmute(main as usize) };
+    main(args.len() as c_int, argv.as_ptr())
+}
+
+fn sections_of_kind (object: &ObjectFile, kind: SectionKind) -> impl Iterator<Item = (usize, &Section)> {
+    object.sections.iter().enumerate().filter(move |(_, section)| section.kind == kind)
+}
+
+fn align_up (value: usize, align: usize) -> usize {
+    value.div_ceil(align.max(1)) * align.max(1)
+}
+
+impl Loader<'_> {
+    fn find_library_symbols (&mut self) {
+        let mut missing = vec![];
+        for (index, symbol) in self.object.symbols.iter().enumerate() {
+            if symbol.definition != SymbolDefinition::Undefined {
+                continue
+            }
+            match find_library_symbol(&symbol.name) {
+                Some(address) => { self.library_addresses.insert(index, address); },
+                None => missing.push(&symbol.name[..])
+            }
+        }
+        if !missing.is_empty() {
+            missing.sort_unstable();
+            panic!("Undefined references to {}", missing.join(", "))
+        }
+    }
+
+    // Code, then read-only data, then writable data, each on their own pages
+    // so they can be given the right permissions. Returns where each starts.
+    fn lay_out (&mut self) -> (usize, usize, usize) {
+        let mut size = 0;
+        for (index, section) in sections_of_kind(self.object, SectionKind::Text) {
+            size = align_up(size, section.align);
+            self.section_offsets.insert(index, size);
+            size += section.data.len();
+        }
+
+        let mut got_targets = vec![];
+        let mut stub_targets = vec![];
+        for section in &self.object.sections {
+            for reloc in &section.relocations {
+                let is_library = self.library_addresses.contains_key(&reloc.symbol);
+                if reloc.kind == RelocationKind::GotPcRelative32 || (is_library && reloc.kind == RelocationKind::Plt32) {
+                    got_targets.push(reloc.symbol);
+                }
+                if is_library && reloc.kind == RelocationKind::Plt32 {
+                    stub_targets.push(reloc.symbol);
+                }
+            }
+        }
+        got_targets.sort_unstable();
+        got_targets.dedup();
+        stub_targets.sort_unstable();
+        stub_targets.dedup();
+        for symbol in stub_targets {
+            size = align_up(size, STUB_SIZE);
+            self.stub_offsets.insert(symbol, size);
+            size += STUB_SIZE;
+        }
+
+        let read_only_start = align_up(size, PAGE_SIZE);
+        size = read_only_start;
+        for (index, section) in sections_of_kind(self.object, SectionKind::ReadOnly) {
+            size = align_up(size, section.align);
+            self.section_offsets.insert(index, size);
+            size += section.data.len();
+        }
+
+        let writable_start = align_up(size, PAGE_SIZE);
+        size = writable_start;
+        for kind in [SectionKind::Data, SectionKind::Bss] {
+            for (index, section) in sections_of_kind(self.object, kind) {
+                size = align_up(size, section.align);
+                self.section_offsets.insert(index, size);
+                size += section.data.len();
+            }
+        }
+        for symbol in got_targets {
+            size = align_up(size, 8);
+            self.got_offsets.insert(symbol, size);
+            size += 8;
+        }
+        for (index, symbol) in self.object.symbols.iter().enumerate() {
+            if let SymbolDefinition::Common { size: common_size, align } = symbol.definition {
+                size = align_up(size, align);
+                self.common_offsets.insert(index, size);
+                size += common_size;
+            }
+        }
+        (read_only_start, writable_start, align_up(size, PAGE_SIZE))
+    }
+
+    fn address (&self, symbol: usize) -> u64 {
+        match self.object.symbols[symbol].definition {
+            SymbolDefinition::Defined { section, value } => self.base + (self.section_offsets[&section] + value) as u64,
+            SymbolDefinition::Common { .. } => self.base + self.common_offsets[&symbol] as u64,
+            SymbolDefinition::Undefined => self.library_addresses[&symbol]
+        }
+    }
+
+    // Copies everything into memory and links it there, returning main's address
+    fn load (&mut self) -> u64 {
+        let (read_only_start, writable_start, size) = self.lay_out();
+        let mut memory = Memory::map(size);
+        self.base = memory.address();
+        let bytes = memory.bytes();
+
+        for (index, section) in self.object.sections.iter().enumerate() {
+            let offset = match self.section_offsets.get(&index) {
+                Some(offset) => *offset,
+                None => continue
+            };
+            // mmap's memory is already zeroed, which suits .bss
+            if section.kind != SectionKind::Bss {
+                bytes[offset..offset + section.data.len()].copy_from_slice(&section.data);
+            }
+            for reloc in &section.relocations {
+                self.apply_relocation(bytes, offset, reloc);
+            }
+        }
+        for (symbol, offset) in &self.got_offsets {
+            bytes[*offset..*offset + 8].copy_from_slice(&self.address(*symbol).to_le_bytes());
+        }
+        for (symbol, offset) in &self.stub_offsets {
+            let entry = self.got_offsets[symbol] as i64 - (*offset as i64 + 6);
+            bytes[*offset..*offset + 2].copy_from_slice(&[0xff, 0x25]);
+            bytes[*offset + 2..*offset + 6].copy_from_slice(&(entry as i32).to_le_bytes());
+            bytes[*offset + 6..*offset + STUB_SIZE].copy_from_slice(&[0xcc, 0xcc]);
+        }
+
+        memory.protect(0, read_only_start, Protection::ReadExecute);
+        memory.protect(read_only_start, writable_start, Protection::ReadOnly);
+
+        let main = self.object.symbols.iter().position(|symbol| {
+            symbol.name == "main" && matches!(symbol.definition, SymbolDefinition::Defined { .. })
+        });
+        match main {
+            Some(main) => self.address(main),
+            None => panic!("There's no main function to run")
+        }
+    }
+
+    fn apply_relocation (&self, bytes: &mut [u8], section_offset: usize, reloc: &Relocation) {
+        let place = self.base as i64 + (section_offset + reloc.offset) as i64;
+        let symbol = match self.stub_offsets.get(&reloc.symbol) {
+            Some(stub) if reloc.kind == RelocationKind::Plt32 => self.base + *stub as u64,
+            _ => self.address(reloc.symbol)
+        } as i64;
+        let field = &mut bytes[section_offset + reloc.offset..];
+
+        let value = match reloc.kind {
+            RelocationKind::Absolute64 => {
+                field[..8].copy_from_slice(&(symbol + reloc.addend).to_le_bytes());
+                return
+            },
+            RelocationKind::Absolute32Signed => symbol + reloc.addend,
+            RelocationKind::PcRelative32 | RelocationKind::Plt32 => symbol + reloc.addend - place,
+            RelocationKind::GotPcRelative32 => self.base as i64 + self.got_offsets[&reloc.symbol] as i64 + reloc.addend - place
+        };
+        if value < i32::MIN as i64 || value > i32::MAX as i64 {
+            panic!("Can't reach \"{}\" from the program, it's too far away in memory", self.object.symbols[reloc.symbol].name)
+        }
+        field[..4].copy_from_slice(&(value as i32).to_le_bytes());
+    }
+}
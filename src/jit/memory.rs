@@ -0,0 +1,76 @@
+// The bits of libc the JIT needs to get memory it can run code from,
+// and to find functions like printf in the C library ass is linked with
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+
+static PROT_READ: c_int = 1;
+static PROT_WRITE: c_int = 2;
+static PROT_EXEC: c_int = 4;
+static MAP_PRIVATE: c_int = 0x02;
+static MAP_ANONYMOUS: c_int = 0x20;
+
+pub static PAGE_SIZE: usize = 0x1000;
+
+extern "C" {
+    fn mmap (addr: *mut c_void, length: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn mprotect (addr: *mut c_void, length: usize, prot: c_int) -> c_int;
+    fn dlsym (handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Protection {
+    ReadOnly,
+    ReadExecute
+}
+
+// Pages from mmap, which start off writable so we can fill them in.
+// They're never unmapped, since the process exits once the program's done.
+pub struct Memory {
+    address: *mut u8,
+    size: usize
+}
+
+impl Memory {
+    pub fn map (size: usize) -> Memory {
+        let size = size.max(1).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let address = unsafe {
+            mmap(std::ptr::null_mut(), size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        };
+        // MAP_FAILED
+        if address as isize == -1 {
+            panic!("Failed to map {} bytes of memory for the program", size)
+        }
+        Memory { address: address as *mut u8, size }
+    }
+
+    pub fn address (&self) -> u64 {
+        self.address as u64
+    }
+
+    pub fn bytes (&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.address, self.size) }
+    }
+
+    // Both ends have to be page aligned
+    pub fn protect (&mut self, start: usize, end: usize, protection: Protection) {
+        if start == end {
+            return
+        }
+        let prot = match protection {
+            Protection::ReadOnly => PROT_READ,
+            Protection::ReadExecute => PROT_READ | PROT_EXEC
+        };
+        let result = unsafe { mprotect(self.address.add(start) as *mut c_void, end - start, prot) };
+        if result != 0 {
+            panic!("Failed to make the program's memory {:?}", protection)
+        }
+    }
+}
+
+// Searches everything loaded into this process, which includes libc.
+// RTLD_DEFAULT is a null handle on Linux.
+pub fn find_library_symbol (name: &str) -> Option<u64> {
+    let name = CString::new(name).ok()?;
+    let address = unsafe { dlsym(std::ptr::null_mut(), name.as_ptr()) };
+    if address.is_null() { None } else { Some(address as u64) }
+}
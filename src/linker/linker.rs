@@ -0,0 +1,345 @@
+// Links x86-64 ELF objects into a static Linux executable without the
+// system's linker. With no dynamic loader, everything is resolved here:
+// calls through the PLT become direct calls, and the GOT is filled in
+// before the program runs.
+use std::collections::hash_map::HashMap;
+use crate::assembler::object::*;
+use crate::assembler::elf::*;
+
+// The traditional address for non-PIE x86-64 executables
+static BASE_ADDRESS: u64 = 0x400000;
+static PAGE_SIZE: u64 = 0x1000;
+static PROGRAM_HEADER_SIZE: usize = 56;
+// Room for one per segment, plus PT_GNU_STACK
+static MAX_PROGRAM_HEADERS: usize = 4;
+
+static ET_EXEC: u16 = 2;
+static PT_LOAD: u32 = 1;
+static PT_GNU_STACK: u32 = 0x6474e551;
+static PF_X: u32 = 1;
+static PF_W: u32 = 2;
+static PF_R: u32 = 4;
+
+pub struct LinkInput {
+    // For error messages, eg. a file name
+    pub name: String,
+    pub object: ObjectFile,
+    // Like an archive, a library's definitions give way to the program's own
+    pub library: bool
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SymbolRef {
+    input: usize,
+    symbol: usize
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SectionRef {
+    input: usize,
+    section: usize
+}
+
+// What a reference to a symbol ends up meaning
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Resolution {
+    Definition(SymbolRef),
+    // Only ever declared like "int x;", so the linker allocates it
+    Common(String)
+}
+
+// A loadable part of the executable, with the sections in it
+struct Segment {
+    flags: u32,
+    // Relative to the start of the segment
+    placements: Vec<(SectionRef, usize)>,
+    size: usize,
+    // Zero-filled memory at the end, which isn't in the file
+    bss_size: usize,
+    file_offset: u64,
+    address: u64
+}
+
+impl Segment {
+    fn new (flags: u32) -> Segment {
+        Segment { flags, placements: vec![], size: 0, bss_size: 0, file_offset: 0, address: 0 }
+    }
+
+    fn allocate (&mut self, size: usize, align: usize) -> usize {
+        let offset = align_up(self.size as u64, align as u64) as usize;
+        self.size = offset + size;
+        offset
+    }
+}
+
+fn align_up (value: u64, align: u64) -> u64 {
+    value.div_ceil(align.max(1)) * align.max(1)
+}
+
+struct Linker<'a> {
+    inputs: &'a [LinkInput],
+    // The definition each global name refers to
+    globals: HashMap<String, SymbolRef>,
+    // Sizes and alignments of common symbols with no real definition
+    commons: HashMap<String, (usize, usize)>,
+    section_addresses: HashMap<SectionRef, u64>,
+    common_addresses: HashMap<String, u64>,
+    got_addresses: HashMap<Resolution, u64>
+}
+
+pub fn link (inputs: &[LinkInput]) -> Vec<u8> {
+    let mut linker = Linker {
+        inputs,
+        globals: HashMap::new(),
+        commons: HashMap::new(),
+        section_addresses: HashMap::new(),
+        common_addresses: HashMap::new(),
+        got_addresses: HashMap::new()
+    };
+    linker.resolve_globals();
+    linker.check_for_undefined_symbols();
+    linker.write_executable()
+}
+
+impl Linker<'_> {
+    fn symbol (&self, reference: SymbolRef) -> &Symbol {
+        &self.inputs[reference.input].object.symbols[reference.symbol]
+    }
+
+    fn resolve_globals (&mut self) {
+        // The program's objects go first, so libraries can't override them
+        for library in [false, true] {
+            for (input_index, input) in self.inputs.iter().enumerate().filter(|(_, input)| input.library == library) {
+                for (symbol_index, symbol) in input.object.symbols.iter().enumerate().filter(|(_, sym)| sym.global) {
+                    let reference = SymbolRef { input: input_index, symbol: symbol_index };
+                    match symbol.definition {
+                        SymbolDefinition::Defined { .. } => {
+                            if let Some(existing) = self.globals.get(&symbol.name) {
+                                let first = &self.inputs[existing.input];
+                                if !library && !first.library {
+                                    panic!("Multiple definitions of \"{}\" (in {} and {})", symbol.name, first.name, input.name)
+                                }
+                                continue
+                            }
+                            self.globals.insert(symbol.name.clone(), reference);
+                        },
+                        SymbolDefinition::Common { size, align } => {
+                            let (old_size, old_align) = self.commons.get(&symbol.name).copied().unwrap_or((0, 1));
+                            self.commons.insert(symbol.name.clone(), (old_size.max(size), old_align.max(align)));
+                        },
+                        SymbolDefinition::Undefined => {}
+                    }
+                }
+            }
+        }
+        // A real definition wins over common ones
+        let globals = &self.globals;
+        self.commons.retain(|name, _| !globals.contains_key(name));
+    }
+
+    fn check_for_undefined_symbols (&self) {
+        let mut undefined: Vec<&str> = self.inputs.iter()
+            .flat_map(|input| &input.object.symbols)
+            .filter(|sym| sym.global && sym.definition == SymbolDefinition::Undefined)
+            .map(|sym| &sym.name[..])
+            .filter(|name| !self.globals.contains_key(*name) && !self.commons.contains_key(*name))
+            .collect();
+        undefined.sort_unstable();
+        undefined.dedup();
+        if !undefined.is_empty() {
+            panic!("Undefined references to {}", undefined.join(", "))
+        }
+    }
+
+    fn resolve (&self, reference: SymbolRef) -> Resolution {
+        let symbol = self.symbol(reference);
+        if !symbol.global {
+            return Resolution::Definition(reference)
+        }
+        match self.globals.get(&symbol.name) {
+            Some(definition) => Resolution::Definition(*definition),
+            None => Resolution::Common(symbol.name.clone())
+        }
+    }
+
+    fn address (&self, resolution: &Resolution) -> u64 {
+        match resolution {
+            Resolution::Definition(reference) => match self.symbol(*reference).definition {
+                SymbolDefinition::Defined { section, value } => {
+                    self.section_addresses[&SectionRef { input: reference.input, section }] + value as u64
+                },
+                _ => unreachable!("Globals are always resolved to definitions")
+            },
+            Resolution::Common(name) => self.common_addresses[name]
+        }
+    }
+
+    // Code, then read-only data, then everything writable. Each gets its own
+    // pages, so they can be mapped with the right permissions.
+    fn lay_out (&mut self) -> Vec<Segment> {
+        let mut text = Segment::new(PF_R | PF_X);
+        let mut read_only = Segment::new(PF_R);
+        let mut writable = Segment::new(PF_R | PF_W);
+
+        // The ELF header and program headers are loaded with the code
+        text.size = ELF_HEADER_SIZE + MAX_PROGRAM_HEADERS * PROGRAM_HEADER_SIZE;
+
+        let mut got_entries = vec![];
+        for kind in [SectionKind::Text, SectionKind::ReadOnly, SectionKind::Data] {
+            for (input_index, input) in self.inputs.iter().enumerate() {
+                for (section_index, section) in input.object.sections.iter().enumerate().filter(|(_, s)| s.kind == kind) {
+                    let segment = match kind {
+                        SectionKind::Text => &mut text,
+                        SectionKind::ReadOnly => &mut read_only,
+                        _ => &mut writable
+                    };
+                    let offset = segment.allocate(section.data.len(), section.align);
+                    segment.placements.push((SectionRef { input: input_index, section: section_index }, offset));
+
+                    for reloc in section.relocations.iter().filter(|r| r.kind == RelocationKind::GotPcRelative32) {
+                        let target = self.resolve(SymbolRef { input: input_index, symbol: reloc.symbol });
+                        if !got_entries.contains(&target) {
+                            got_entries.push(target);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The GOT, whose entries we fill in ourselves
+        let mut got_offsets = vec![];
+        for _ in &got_entries {
+            got_offsets.push(writable.allocate(8, 8));
+        }
+
+        // Zero-filled data goes at the end, where it doesn't need room in the file
+        let file_size = writable.size;
+        for (input_index, input) in self.inputs.iter().enumerate() {
+            for (section_index, section) in input.object.sections.iter().enumerate().filter(|(_, s)| s.kind == SectionKind::Bss) {
+                let offset = writable.allocate(section.data.len(), section.align);
+                writable.placements.push((SectionRef { input: input_index, section: section_index }, offset));
+            }
+        }
+        let mut commons: Vec<(&String, &(usize, usize))> = self.commons.iter().collect();
+        commons.sort();
+        let common_offsets: Vec<(String, usize)> = commons.into_iter()
+            .map(|(name, (size, align))| (name.clone(), writable.allocate(*size, *align)))
+            .collect();
+        writable.bss_size = writable.size - file_size;
+        writable.size = file_size;
+
+        let mut file_end = 0;
+        let mut segments = vec![text, read_only, writable];
+        for segment in &mut segments {
+            segment.file_offset = align_up(file_end, PAGE_SIZE);
+            segment.address = BASE_ADDRESS + segment.file_offset;
+            file_end = segment.file_offset + segment.size as u64;
+            for (section, offset) in &segment.placements {
+                self.section_addresses.insert(*section, segment.address + *offset as u64);
+            }
+        }
+
+        let writable = &segments[2];
+        for (target, offset) in got_entries.into_iter().zip(got_offsets) {
+            self.got_addresses.insert(target, writable.address + offset as u64);
+        }
+        for (name, offset) in common_offsets {
+            self.common_addresses.insert(name, writable.address + offset as u64);
+        }
+        segments
+    }
+
+    fn write_executable (&mut self) -> Vec<u8> {
+        let segments = self.lay_out();
+        let file_size = segments.iter().map(|segment| segment.file_offset + segment.size as u64).max().unwrap();
+        let mut out = vec![0; file_size as usize];
+
+        for segment in &segments {
+            for (section_ref, _) in &segment.placements {
+                let section = &self.inputs[section_ref.input].object.sections[section_ref.section];
+                if section.kind == SectionKind::Bss {
+                    continue
+                }
+                let address = self.section_addresses[section_ref];
+                let start = (address - BASE_ADDRESS) as usize;
+                out[start..start + section.data.len()].copy_from_slice(&section.data);
+
+                for reloc in &section.relocations {
+                    let target = self.resolve(SymbolRef { input: section_ref.input, symbol: reloc.symbol });
+                    self.apply_relocation(&mut out[start..], address, reloc, &target);
+                }
+            }
+        }
+        for (target, got_address) in &self.got_addresses {
+            let start = (got_address - BASE_ADDRESS) as usize;
+            out[start..start + 8].copy_from_slice(&self.address(target).to_le_bytes());
+        }
+
+        let entry = match self.globals.get("_start") {
+            Some(start) => self.address(&Resolution::Definition(*start)),
+            None => panic!("There's no _start to begin the program at")
+        };
+        self.write_headers(&mut out, &segments, entry);
+        out
+    }
+
+    fn apply_relocation (&self, section: &mut [u8], section_address: u64, reloc: &Relocation, target: &Resolution) {
+        let symbol = self.address(target) as i64;
+        let place = (section_address + reloc.offset as u64) as i64;
+        let field = &mut section[reloc.offset..];
+
+        let value = match reloc.kind {
+            RelocationKind::Absolute64 => {
+                field[..8].copy_from_slice(&(symbol + reloc.addend).to_le_bytes());
+                return
+            },
+            RelocationKind::Absolute32Signed => symbol + reloc.addend,
+            RelocationKind::PcRelative32 | RelocationKind::Plt32 => symbol + reloc.addend - place,
+            RelocationKind::GotPcRelative32 => self.got_addresses[target] as i64 + reloc.addend - place
+        };
+        if value < i32::MIN as i64 || value > i32::MAX as i64 {
+            panic!("Relocation at {:#x} doesn't fit in 32 bits", place)
+        }
+        field[..4].copy_from_slice(&(value as i32).to_le_bytes());
+    }
+
+    fn write_headers (&self, out: &mut [u8], segments: &[Segment], entry: u64) {
+        let segments: Vec<&Segment> = segments.iter().filter(|segment| segment.size + segment.bss_size > 0).collect();
+        let mut headers = vec![];
+        headers.extend_from_slice(ELF_MAGIC);
+        headers.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, EV_CURRENT]);
+        headers.resize(16, 0);
+        push_u16(&mut headers, ET_EXEC);
+        push_u16(&mut headers, EM_X86_64);
+        push_u32(&mut headers, EV_CURRENT as u32);
+        push_u64(&mut headers, entry);
+        // Program headers straight after this one, and no section headers
+        push_u64(&mut headers, ELF_HEADER_SIZE as u64);
+        push_u64(&mut headers, 0);
+        push_u32(&mut headers, 0);
+        push_u16(&mut headers, ELF_HEADER_SIZE as u16);
+        push_u16(&mut headers, PROGRAM_HEADER_SIZE as u16);
+        push_u16(&mut headers, segments.len() as u16 + 1);
+        push_u16(&mut headers, SECTION_HEADER_SIZE as u16);
+        push_u16(&mut headers, 0);
+        push_u16(&mut headers, 0);
+
+        for segment in segments {
+            push_u32(&mut headers, PT_LOAD);
+            push_u32(&mut headers, segment.flags);
+            push_u64(&mut headers, segment.file_offset);
+            push_u64(&mut headers, segment.address);
+            push_u64(&mut headers, segment.address);
+            push_u64(&mut headers, segment.size as u64);
+            push_u64(&mut headers, (segment.size + segment.bss_size) as u64);
+            push_u64(&mut headers, PAGE_SIZE);
+        }
+        // The stack doesn't need to be executable
+        push_u32(&mut headers, PT_GNU_STACK);
+        push_u32(&mut headers, PF_R | PF_W);
+        headers.resize(headers.len() + 5 * 8, 0);
+        push_u64(&mut headers, 16);
+
+        out[..headers.len()].copy_from_slice(&headers);
+    }
+}
@@ -0,0 +1,35 @@
+// The parts of the C library that programs linked by ass's own linker
+// can use, instead of libc. It's compiled by ass like any other file,
+// and talks to the Linux kernel directly.
+
+long write(int fd, const void *buf, long count) {
+    return __builtin_syscall(1, fd, buf, count);
+}
+
+void exit(int status) {
+    // exit_group, which never returns
+    __builtin_syscall(231, status);
+}
+
+int putchar(int c) {
+    unsigned char byte = c;
+    if (write(1, &byte, 1) != 1) {
+        return -1;
+    }
+    return byte;
+}
+
+int puts(const char *s) {
+    long length = 0;
+    while (s[length]) {
+        length = length + 1;
+    }
+    if (write(1, s, length) != length) {
+        return -1;
+    }
+    // With the newline puts adds
+    if (putchar(10) < 0) {
+        return -1;
+    }
+    return 0;
+}
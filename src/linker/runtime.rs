@@ -0,0 +1,13 @@
+// The stand-in for libc that's linked into programs when we use our own
+// linker. It's kept as C source and compiled along with the program.
+use crate::codegen::freestanding::entry_point_assembly;
+use crate::codegen::target::Target;
+
+pub static RUNTIME_SOURCE: &str = include_str!("runtime.c");
+
+// Assembly for the entry point, which calls main and exits with its result
+pub fn runtime_entry_point () -> String {
+    let mut lines = vec![".text".to_string()];
+    lines.extend(entry_point_assembly(Target::X86_64_LINUX));
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,118 @@
+// Command line arguments, eg. "ass -ffreestanding --target=linux a.c b.c -o prog"
+use crate::codegen::target::{Target, Architecture, OperatingSystem};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputKind {
+    // -S
+    Assembly,
+    // -c, one object per input file
+    Object,
+    // Everything is compiled separately and linked together
+    Executable,
+    // --run, compiled in memory and executed straight away
+    Run,
+    // --interpret, run by walking the AST without generating any code
+    Interpret
+}
+
+pub struct Options {
+    pub input_files: Vec<String>,
+    pub output_file: Option<String>,
+    pub output_kind: OutputKind,
+    pub target: Target,
+    // Don't rely on libc, and emit our own _start
+    pub freestanding: bool,
+    // Link with our own linker and runtime instead of cc and libc
+    pub own_linker: bool,
+    // With --run or --interpret, everything after the C file is passed on to the program
+    pub program_args: Vec<String>
+}
+
+impl Options {
+    pub fn from_args (args: impl Iterator<Item = String>) -> Options {
+        let mut input_files = vec![];
+        let mut output_file = None;
+        let mut output_kind = None;
+        let mut target = Target::host();
+        let mut freestanding = false;
+        let mut own_linker = false;
+        let mut program_args = vec![];
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let runs_program = matches!(output_kind, Some(OutputKind::Run | OutputKind::Interpret));
+            if runs_program && !input_files.is_empty() {
+                program_args.push(arg);
+                continue
+            }
+            match &arg[..] {
+                "-ffreestanding" | "-nostdlib" => freestanding = true,
+                "-S" => output_kind = Some(OutputKind::Assembly),
+                "-c" => output_kind = Some(OutputKind::Object),
+                "--run" => output_kind = Some(OutputKind::Run),
+                "--interpret" => output_kind = Some(OutputKind::Interpret),
+                "-fuse-ld=ass" => own_linker = true,
+                "-fuse-ld=cc" => own_linker = false,
+                "-o" => match args.next() {
+                    Some(path) => output_file = Some(path),
+                    None => panic!("Expected a file path after -o")
+                },
+                "--target" => match args.next() {
+                    Some(name) => target = parse_target(&name),
+                    None => panic!("Expected a target after --target")
+                },
+                _ if arg.starts_with("--target=") => target = parse_target(&arg["--target=".len()..]),
+                _ if arg.starts_with('-') => panic!("Unknown option \"{}\"", arg),
+                _ => input_files.push(arg)
+            }
+        }
+
+        if input_files.is_empty() {
+            panic!("Pass a C file path argument")
+        }
+        if freestanding && target != Target::X86_64_LINUX {
+            panic!("Freestanding programs are only supported when targeting x86-64 Linux")
+        }
+        if own_linker && target != Target::X86_64_LINUX {
+            panic!("ass's linker only makes x86-64 Linux executables")
+        }
+
+        // "ass input.c" on its own prints assembly, like it always has
+        let output_kind = output_kind.unwrap_or(
+            if input_files.len() == 1 && output_file.is_none() {
+                OutputKind::Assembly
+            } else {
+                OutputKind::Executable
+            }
+        );
+        if output_kind == OutputKind::Run && output_file.is_some() {
+            panic!("--run doesn't write any files, so it can't be used with -o")
+        }
+        if output_kind == OutputKind::Interpret && output_file.is_some() {
+            panic!("--interpret doesn't write any files, so it can't be used with -o")
+        }
+        if output_kind != OutputKind::Executable && input_files.len() > 1 && output_file.is_some() {
+            panic!("-o can't be used with -S or -c when there are several input files")
+        }
+
+        Options {
+            input_files,
+            output_file,
+            output_kind,
+            target,
+            freestanding,
+            own_linker,
+            program_args
+        }
+    }
+}
+
+fn parse_target (name: &str) -> Target {
+    match Target::from_name(name) {
+        Some(target) if target.arch != Architecture::X86_64 && target.os != OperatingSystem::Linux => {
+            panic!("AArch64 and RISC-V code can only be generated for Linux")
+        },
+        Some(target) => target,
+        None => panic!("Unknown target \"{}\" (expected darwin, linux or a triple like aarch64-linux-gnu or riscv64-linux-gnu)", name)
+    }
+}
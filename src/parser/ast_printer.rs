@@ -10,6 +10,14 @@ pub fn print_at_depth (s: String, depth: isize) {
     eprintln!("{}", str);
 }
 
+fn storage_class_note (storage_class: StorageClass) -> &'static str {
+    match storage_class {
+        StorageClass::Unspecified => "",
+        StorageClass::Static => " (static)",
+        StorageClass::Extern => " (extern)"
+    }
+}
+
 pub fn print_ast_node (node: &ASTNode, depth: isize) {
     match node {
         ASTNode::IntegerLiteral(int) => {
@@ -19,8 +27,10 @@ pub fn print_ast_node (node: &ASTNode, depth: isize) {
             print_at_depth(format!("Identifier: {}", ident), depth)
         },
         ASTNode::ReturnStatement(ret_stmt) => {
-            print_at_depth(format!("Return:"), depth);
-            print_ast_node(ret_stmt.as_ref(), depth + 1)
+            print_at_depth("Return:".to_string(), depth);
+            if let Some(value) = ret_stmt {
+                print_ast_node(value, depth + 1)
+            }
         },
         ASTNode::BlockStatement(block) => {
             print_at_depth("Block:".to_string(), depth);
@@ -29,9 +39,10 @@ pub fn print_ast_node (node: &ASTNode, depth: isize) {
             }
         },
         ASTNode::FunctionDefinition(func) => {
-            print_at_depth(format!("Function: {}", func.name), depth);
+            let variadic = if func.variadic { " (variadic)" } else { "" };
+            print_at_depth(format!("Function: {}{}{}", func.name, variadic, storage_class_note(func.storage_class)), depth);
 
-            if func.params.len() > 0 {
+            if !func.params.is_empty() {
                 print_at_depth("Parameters:".to_string(), depth + 1);
                 for param in &func.params {
                     print_at_depth(format!("- \"{}\"", param.name), depth + 2);
@@ -56,7 +67,7 @@ pub fn print_ast_node (node: &ASTNode, depth: isize) {
             print_ast_node(&bin.right_side, depth + 1)
         }
         ASTNode::VariableDeclaration(var) => {
-            print_at_depth(format!("Variable declaration: {}", var.identifier), depth);
+            print_at_depth(format!("Variable declaration: {}{}", var.identifier, storage_class_note(var.storage_class)), depth);
             print_type(&var.var_type, depth + 1);
             if let Some(val) = &var.initial_value {
                 print_at_depth("Initial value:".to_string(), depth + 1);
@@ -75,9 +86,14 @@ pub fn print_ast_node (node: &ASTNode, depth: isize) {
             }
         },
         ASTNode::FunctionCall(func_call) => {
-            print_at_depth(format!("Function call: {}", func_call.name), depth);
-            for arg in &func_call.args {
-                print_ast_node(arg, depth + 1);
+            print_at_depth("Function call:".to_string(), depth);
+            print_at_depth("Callee:".to_string(), depth + 1);
+            print_ast_node(&func_call.callee, depth + 2);
+            if !func_call.args.is_empty() {
+                print_at_depth("Arguments:".to_string(), depth + 1);
+                for arg in &func_call.args {
+                    print_ast_node(arg, depth + 2);
+                }
             }
         },
         ASTNode::WhileLoop(while_loop) => {
@@ -105,8 +121,77 @@ pub fn print_ast_node (node: &ASTNode, depth: isize) {
             print_at_depth("Body:".to_string(), depth + 1);
             print_ast_node(&for_loop.body, depth + 2);
         },
+        ASTNode::SwitchStatement(switch) => {
+            print_at_depth("Switch statement:".to_string(), depth);
+            print_at_depth("Value:".to_string(), depth + 1);
+            print_ast_node(&switch.value, depth + 2);
+            print_at_depth("Body:".to_string(), depth + 1);
+            print_ast_node(&switch.body, depth + 2);
+        },
+        ASTNode::CaseLabel(label) => match label.value {
+            Some(value) => print_at_depth(format!("Case: {}", value), depth),
+            None => print_at_depth("Default:".to_string(), depth)
+        },
+        ASTNode::BreakStatement => {
+            print_at_depth("Break".to_string(), depth);
+        },
+        ASTNode::ContinueStatement => {
+            print_at_depth("Continue".to_string(), depth);
+        },
         ASTNode::StringLiteral(st) => {
             print_at_depth(format!("String: \"{}\"", st), depth);
+        },
+        ASTNode::MemberAccess(mem) => {
+            print_at_depth(format!("Member access: .{}", mem.member), depth);
+            print_ast_node(&mem.object, depth + 1);
+        },
+        ASTNode::VaArg(va_arg) => {
+            print_at_depth("va_arg:".to_string(), depth);
+            print_ast_node(&va_arg.list, depth + 1);
+            print_type(&va_arg.arg_type, depth + 1);
+        },
+        ASTNode::Cast(cast) => {
+            print_at_depth("Cast to:".to_string(), depth);
+            print_type(&cast.target_type, depth + 1);
+            print_ast_node(&cast.operand, depth + 1);
+        },
+        ASTNode::InitialiserList(values) => {
+            print_at_depth("Initialiser list:".to_string(), depth);
+            for value in values {
+                print_ast_node(value, depth + 1)
+            }
+        },
+        ASTNode::CompoundLiteral(lit) => {
+            print_at_depth("Compound literal:".to_string(), depth);
+            print_type(&lit.literal_type, depth + 1);
+            print_ast_node(&lit.initial_value, depth + 1);
+        },
+        ASTNode::VariableArraySize(array) => {
+            print_at_depth("Size of variable length array:".to_string(), depth);
+            print_ast_node(array, depth + 1);
+        },
+        ASTNode::InlineAsm(asm) => {
+            print_at_depth(format!("Inline asm: \"{}\"", asm.template), depth);
+            for (heading, operands) in &[("Outputs:", &asm.outputs), ("Inputs:", &asm.inputs)] {
+                if operands.is_empty() { continue }
+                print_at_depth(heading.to_string(), depth + 1);
+                for operand in operands.iter() {
+                    print_at_depth(format!("\"{}\"", operand.constraint), depth + 2);
+                    print_ast_node(&operand.value, depth + 3);
+                }
+            }
+            if !asm.clobbers.is_empty() {
+                print_at_depth(format!("Clobbers: {}", asm.clobbers.join(", ")), depth + 1);
+            }
+        },
+        ASTNode::DeclarationList(decls) => {
+            print_at_depth("Declarations:".to_string(), depth);
+            for decl in decls {
+                print_ast_node(decl, depth + 1)
+            }
+        },
+        ASTNode::EmptyStatement => {
+            print_at_depth("Empty statement".to_string(), depth);
         }
     }
 }
@@ -4,7 +4,8 @@ use super::types::Type;
 pub enum ASTNode {
     IntegerLiteral(isize),
     Identifier(String),
-    ReturnStatement(Box<ASTNode>),
+    // "return;" (None) is only allowed in void functions
+    ReturnStatement(Option<Box<ASTNode>>),
     BlockStatement(Vec<ASTNode>),
     FunctionDefinition(ASTFunctionDefinition),
     UnaryOperation(ASTUnaryOperation),
@@ -14,7 +15,32 @@ pub enum ASTNode {
     FunctionCall(ASTFunctionCall),
     WhileLoop(ASTWhileLoop),
     ForLoop(ASTForLoop),
-    StringLiteral(String)
+    SwitchStatement(ASTSwitchStatement),
+    // "case 3:" or "default:", labelling whatever comes next in a switch
+    CaseLabel(ASTCaseLabel),
+    BreakStatement,
+    ContinueStatement,
+    StringLiteral(String),
+    // s.member (p->member is parsed as (*p).member)
+    MemberAccess(ASTMemberAccess),
+    // va_arg(list, type), which needs a type as an argument so isn't a call
+    VaArg(ASTVaArg),
+    // (type)value
+    Cast(ASTCast),
+    // The braces in "int a[] = {1, 2, 3};" which can be nested for
+    // arrays of arrays, structs, etc.
+    InitialiserList(Vec<ASTNode>),
+    // (type){...}, an unnamed object made from an initialiser list
+    CompoundLiteral(ASTCompoundLiteral),
+    // sizeof a variable length array, which is only known at run time.
+    // Every other sizeof becomes a constant while parsing.
+    VariableArraySize(Box<ASTNode>),
+    // Eg. "int a, *b;" which declares more than one thing
+    DeclarationList(Vec<ASTNode>),
+    // asm volatile ("..." : outputs : inputs : clobbers);
+    InlineAsm(ASTInlineAsm),
+    // A lone ; or a declaration that only declares a struct
+    EmptyStatement
 }
 
 #[derive(Clone, PartialEq)]
@@ -23,7 +49,10 @@ pub struct ASTFunctionDefinition {
     pub return_type: Type,
     // If this option is None, it's a function declaration without an implementation
     pub body: Option<Vec<ASTNode>>,
-    pub params: Vec<ASTNameAndType>
+    pub params: Vec<ASTNameAndType>,
+    // Ends in "..."
+    pub variadic: bool,
+    pub storage_class: StorageClass
 }
 #[derive(Clone, PartialEq)]
 pub struct ASTNameAndType {
@@ -48,7 +77,22 @@ pub struct ASTBinaryOperation {
 pub struct ASTVariableDeclaration {
     pub identifier: String,
     pub var_type: Type,
-    pub initial_value: Option<Box<ASTNode>>
+    pub initial_value: Option<Box<ASTNode>>,
+    pub storage_class: StorageClass,
+    // Unique in the file, so codegen can find a declaration's stack
+    // slot again wherever the AST has been copied or moved to
+    pub id: usize
+}
+
+// The "static" or "extern" at the start of a declaration
+#[derive(Clone, Copy, PartialEq)]
+pub enum StorageClass {
+    Unspecified,
+    // Globals and functions that aren't visible to other files,
+    // and locals that keep their value between calls
+    Static,
+    // Refers to something defined elsewhere (maybe in another file)
+    Extern
 }
 
 #[derive(Clone, PartialEq)]
@@ -60,7 +104,9 @@ pub struct ASTIfStatement {
 
 #[derive(Clone, PartialEq)]
 pub struct ASTFunctionCall {
-    pub name: String,
+    // Usually the function's name, but can be any expression
+    // with a function or function pointer type
+    pub callee: Box<ASTNode>,
     pub args: Vec<ASTNode>
 }
 
@@ -80,3 +126,75 @@ pub struct ASTForLoop {
     pub modification: Option<Box<ASTNode>>,
     pub body: Box<ASTNode>
 }
+
+#[derive(Clone, PartialEq)]
+pub struct ASTCaseLabel {
+    // None for "default:"
+    pub value: Option<isize>,
+    // Unique in the file, so a switch can find its labels again
+    pub id: usize
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ASTSwitchStatement {
+    pub value: Box<ASTNode>,
+    // The case labels are statements somewhere inside this
+    pub body: Box<ASTNode>
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ASTMemberAccess {
+    pub object: Box<ASTNode>,
+    pub member: String
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ASTVaArg {
+    pub list: Box<ASTNode>,
+    pub arg_type: Type
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ASTCast {
+    pub operand: Box<ASTNode>,
+    pub target_type: Type
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ASTInlineAsm {
+    // With escape sequences still in it, like other string literals
+    pub template: String,
+    // Basic asm (without any colons) is copied out as it is, so a % in
+    // it is just a %. Extended asm's are operand references like %0.
+    pub is_extended: bool,
+    pub outputs: Vec<ASTAsmOperand>,
+    pub inputs: Vec<ASTAsmOperand>,
+    // Registers (and "memory" or "cc") the instructions change
+    pub clobbers: Vec<String>
+}
+
+// Eg. [result] "=r" (x)
+#[derive(Clone, PartialEq)]
+pub struct ASTAsmOperand {
+    pub name: Option<String>,
+    pub constraint: String,
+    pub value: ASTNode
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ASTCompoundLiteral {
+    pub literal_type: Type,
+    // Always an InitialiserList
+    pub initial_value: Box<ASTNode>
+}
+
+// Nodes that can't be used as part of an expression
+pub fn is_statement (node: &ASTNode) -> bool {
+    matches!(node,
+        ASTNode::ReturnStatement(_) | ASTNode::BlockStatement(_) |
+        ASTNode::FunctionDefinition(_) | ASTNode::VariableDeclaration(_) |
+        ASTNode::IfStatement(_) | ASTNode::WhileLoop(_) | ASTNode::ForLoop(_) |
+        ASTNode::SwitchStatement(_) | ASTNode::CaseLabel(_) |
+        ASTNode::BreakStatement | ASTNode::ContinueStatement |
+        ASTNode::InlineAsm(_) | ASTNode::DeclarationList(_) | ASTNode::EmptyStatement)
+}
@@ -0,0 +1,207 @@
+// C declarators: the part of a declaration after the base type, which names
+// the thing being declared and says how its type is built from the base.
+// They read inside out, eg. in "int (*p)[10]", p is a pointer to an array
+// of 10 ints, but in "int *p[10]" it's an array of 10 pointers to int.
+use super::parser::Parser;
+use super::ast_utils::{ASTNode, ASTNameAndType};
+use super::tokens::*;
+use super::parser_helpers::apply_qualifiers;
+use super::types::*;
+use crate::codegen::constexpr::try_get_constant_value_from_node;
+
+// One step of building a declarator's type from the base type
+enum Derivation {
+    // With the pointer's own qualifiers, eg. "* const"
+    Pointer(Qualifiers),
+    // Arrays declared with [] have a length of 0
+    Array(usize),
+    // The length isn't a constant, eg. "int a[n];"
+    VariableArray(ASTNode),
+    Function(Vec<ASTNameAndType>, bool)
+}
+
+pub struct Declarator {
+    // None for abstract declarators, eg. the "(*)(int)" in "int (*)(int)"
+    pub name: Option<String>,
+    pub declared_type: Type,
+    // The parameters (with their names) and whether it's variadic,
+    // if this declares a function rather than eg. a function pointer
+    pub params: Option<(Vec<ASTNameAndType>, bool)>
+}
+
+impl Parser {
+    pub fn parse_declarator (&mut self, base: Type) -> Declarator {
+        let (name, derivations) = self.parse_derivations();
+
+        let mut declared_type = base;
+        let mut params = None;
+        for derivation in derivations {
+            params = None;
+            declared_type = match derivation {
+                Derivation::Pointer(qualifiers) => {
+                    if let Type::VariableArray(_) = declared_type {
+                        panic!("Pointers to variable length arrays, eg. int (*p)[n], aren't supported")
+                    }
+                    apply_qualifiers(pointer_to(declared_type), qualifiers)
+                },
+                Derivation::Array(length) => Type::Array(ArrayTypeMetadata {
+                    element: Box::new(fixed_size_element(declared_type)),
+                    length
+                }),
+                Derivation::VariableArray(length) => Type::VariableArray(VariableArrayTypeMetadata {
+                    element: Box::new(fixed_size_element(declared_type)),
+                    length: Box::new(length)
+                }),
+                Derivation::Function(function_params, variadic) => {
+                    let function_type = FunctionTypeMetadata {
+                        return_type: Box::new(declared_type),
+                        params: function_params.iter().map(|p| p.param_type.clone()).collect(),
+                        variadic
+                    };
+                    params = Some((function_params, variadic));
+                    Type::Function(function_type)
+                }
+            }
+        }
+
+        Declarator { name, declared_type, params }
+    }
+
+    // The type at the start of a declaration, without any declarator
+    pub fn parse_base_type (&mut self) -> Type {
+        match self.tokeniser.read() {
+            Token::Keyword(kw) => self.parse_type(&kw),
+            Token::Identifier(ident) if self.is_typedef_name(&ident) => self.parse_type(&ident),
+            _ => panic!("Expected a type")
+        }
+    }
+
+    // A type on its own, eg. "char *" or "int (*)[3]" (for va_arg, casts, etc.)
+    pub fn parse_type_name (&mut self) -> Type {
+        let base = self.parse_base_type();
+        let declarator = self.parse_declarator(base);
+        if let Some(name) = declarator.name {
+            panic!("Unexpected name \"{}\" in a type name", name)
+        }
+        declarator.declared_type
+    }
+
+    // After the opening bracket, up to and including the closing one
+    pub fn parse_parameter_list (&mut self) -> (Vec<ASTNameAndType>, bool) {
+        let mut params = vec![];
+        let mut variadic = false;
+        while self.is_next_type_name() || self.is_next_punctuation('.') {
+            if self.is_next_punctuation('.') {
+                // "..." has to come last
+                for _ in 0..3 { self.expect_punctuation('.') }
+                variadic = true;
+                break;
+            }
+
+            let base = self.parse_base_type();
+            let declarator = self.parse_declarator(base);
+            if is_void(&declarator.declared_type) {
+                // "(void)" means there aren't any parameters, unlike "()"
+                // which means they weren't specified
+                if !params.is_empty() || declarator.name.is_some() || !self.is_next_punctuation(')') {
+                    panic!("Parameters can't have type void")
+                }
+                break
+            }
+            // Array and function parameters are really pointers
+            params.push(ASTNameAndType {
+                name: declarator.name.unwrap_or_default(),
+                param_type: decayed(declarator.declared_type)
+            });
+
+            // NOTE: This doesn't quite match the standard
+            if self.is_next_punctuation(',') {
+                self.tokeniser.read();
+            }
+        }
+
+        self.expect_punctuation(')');
+        (params, variadic)
+    }
+
+    // Returns the declarator's name and the steps to build its type,
+    // in the order they apply to the base type
+    fn parse_derivations (&mut self) -> (Option<String>, Vec<Derivation>) {
+        let mut pointers = vec![];
+        while self.is_next_operator("*") {
+            self.tokeniser.read();
+            pointers.push(Derivation::Pointer(self.parse_qualifiers()));
+        }
+
+        let mut name = None;
+        let mut inner = vec![];
+        let mut suffixes = vec![];
+
+        if self.is_next_punctuation('(') {
+            self.tokeniser.read();
+            // Either a nested declarator like (*p), or the parameters of an
+            // abstract function declarator like the (int) in "int (int)"
+            if self.is_next_type_name() || self.is_next_punctuation(')') || self.is_next_punctuation('.') {
+                let (params, variadic) = self.parse_parameter_list();
+                suffixes.push(Derivation::Function(params, variadic));
+            } else {
+                let (inner_name, inner_derivations) = self.parse_derivations();
+                self.expect_punctuation(')');
+                name = inner_name;
+                inner = inner_derivations;
+            }
+        } else if let Token::Identifier(ident) = self.tokeniser.peek() {
+            self.tokeniser.read();
+            name = Some(ident);
+        }
+
+        loop {
+            if self.is_next_punctuation('[') {
+                self.tokeniser.read();
+                suffixes.push(self.parse_array_length());
+            } else if self.is_next_punctuation('(') {
+                self.tokeniser.read();
+                let (params, variadic) = self.parse_parameter_list();
+                suffixes.push(Derivation::Function(params, variadic));
+            } else {
+                break
+            }
+        }
+
+        // The pointers are closest to the base type, then the suffixes
+        // from right to left, then whatever was in brackets
+        let mut derivations = pointers;
+        derivations.extend(suffixes.into_iter().rev());
+        derivations.extend(inner);
+        (name, derivations)
+    }
+
+    // After the opening square bracket, up to and including the closing one
+    fn parse_array_length (&mut self) -> Derivation {
+        if self.is_next_punctuation(']') {
+            self.tokeniser.read();
+            return Derivation::Array(0)
+        }
+
+        let length_node = self.parse_component(0);
+        self.expect_punctuation(']');
+
+        let length = match try_get_constant_value_from_node(&length_node) {
+            Some(length) => length,
+            None => return Derivation::VariableArray(length_node)
+        };
+        if length <= 0 {
+            panic!("Array length must be positive")
+        }
+        Derivation::Array(length as usize)
+    }
+}
+
+// Pointer arithmetic and indexing only know sizes that are fixed when
+// they're compiled, so only the outermost length of an array can be variable
+fn fixed_size_element (element: Type) -> Type {
+    if let Type::VariableArray(_) = element {
+        panic!("Only the outermost length of a variable length array can be variable, eg. int a[n][4] but not int a[4][n]")
+    }
+    element
+}
@@ -0,0 +1,88 @@
+// GCC-style inline assembly statements, eg.
+// asm volatile ("rdtsc" : "=a" (low), "=d" (high));
+use super::parser::Parser;
+use super::ast_utils::*;
+use super::tokens::*;
+
+impl Parser {
+    // After the "asm" keyword
+    pub fn parse_inline_asm (&mut self) -> ASTNode {
+        // We never remove or move asm statements, so volatile
+        // doesn't change anything, but it's usually there
+        if self.is_next_keyword("volatile") || self.is_next_keyword("__volatile__") {
+            self.tokeniser.read();
+        }
+        self.expect_punctuation('(');
+
+        let template = self.parse_asm_string();
+        let mut asm = ASTInlineAsm {
+            template,
+            is_extended: false,
+            outputs: vec![],
+            inputs: vec![],
+            clobbers: vec![]
+        };
+
+        // Each section is optional, but they're always in this order
+        for section in 0..3 {
+            if !self.is_next_punctuation(':') { break }
+            self.tokeniser.read();
+            asm.is_extended = true;
+
+            match section {
+                0 => asm.outputs = self.parse_asm_operands(),
+                1 => asm.inputs = self.parse_asm_operands(),
+                _ => {
+                    while let Token::String(_) = self.tokeniser.peek() {
+                        asm.clobbers.push(self.parse_asm_string());
+                        if !self.is_next_punctuation(',') { break }
+                        self.tokeniser.read();
+                    }
+                }
+            }
+        }
+
+        self.expect_punctuation(')');
+        ASTNode::InlineAsm(asm)
+    }
+
+    fn parse_asm_operands (&mut self) -> Vec<ASTAsmOperand> {
+        let mut operands = vec![];
+        while self.is_next_punctuation('[') || matches!(self.tokeniser.peek(), Token::String(_)) {
+            let mut name = None;
+            if self.is_next_punctuation('[') {
+                self.tokeniser.read();
+                match self.tokeniser.read() {
+                    Token::Identifier(ident) => name = Some(ident),
+                    _ => panic!("Expected a name for the asm operand")
+                }
+                self.expect_punctuation(']');
+            }
+
+            let constraint = self.parse_asm_string();
+            self.expect_punctuation('(');
+            let value = self.parse_component(0);
+            self.expect_punctuation(')');
+            operands.push(ASTAsmOperand { name, constraint, value });
+
+            if !self.is_next_punctuation(',') { break }
+            self.tokeniser.read();
+        }
+        operands
+    }
+
+    // Adjacent string literals are joined, so long templates can be split
+    // over lines like "mov %1, %0\n\t" "add $1, %0"
+    fn parse_asm_string (&mut self) -> String {
+        let mut joined = match self.tokeniser.read() {
+            Token::String(st) => st,
+            _ => panic!("Expected a string in asm statement")
+        };
+        while let Token::String(_) = self.tokeniser.peek() {
+            if let Token::String(st) = self.tokeniser.read() {
+                joined += &st;
+            }
+        }
+        joined
+    }
+}
@@ -2,8 +2,11 @@ pub mod char_stream;
 pub mod tokens;
 pub mod tokeniser;
 pub mod token_printer;
+#[allow(clippy::module_inception)]
 pub mod parser;
 pub mod parser_helpers;
+pub mod declarator;
+pub mod inline_asm;
 pub mod ast_utils;
 pub mod ast_printer;
 pub mod types;
@@ -1,12 +1,37 @@
 use super::ast_utils::*;
 use super::tokeniser::Tokeniser;
 use super::tokens::*;
+use super::parser_helpers::{add_qualifier, apply_qualifiers};
 use super::token_printer::print_token;
-use super::types::Type;
-use crate::parser::types::{IntegerTypeMetadata, PointerTypeMetadata};
+use super::types::*;
+use crate::codegen::typing::Typing;
+use crate::codegen::initialiser::completed_type;
+use crate::codegen::constexpr::get_constant_value_from_node;
+use crate::codegen::target::{Target, Architecture};
+use std::cell::RefCell;
+use std::collections::hash_map::HashMap;
+use std::rc::Rc;
 
 pub struct Parser {
-    pub tokeniser: Tokeniser
+    pub tokeniser: Tokeniser,
+    // Struct tags that have been declared so far
+    pub structs: HashMap<String, Rc<RefCell<StructDefinition>>>,
+    // A stack of scopes of the names declared in them. Typedef names and
+    // enumerators share a namespace with variables and functions.
+    pub names: Vec<HashMap<String, OrdinaryName>>,
+    // The last id given to a declaration or case label
+    next_id: usize,
+    // Whether plain char is signed, which depends on the target
+    pub char_is_signed: bool
+}
+
+pub enum OrdinaryName {
+    Typedef(Type),
+    // Enumerators are constants, so they're replaced by their value
+    Enumerator(isize),
+    // A variable or function (with its type), which can hide an outer
+    // typedef or enumerator
+    Object(Type)
 }
 
 impl Parser {
@@ -21,6 +46,7 @@ impl Parser {
 
     fn parse_block_statement (&mut self, expect_first: bool, expect_last: bool) -> ASTNode {
         if expect_first { self.expect_punctuation('{') }
+        self.begin_scope();
 
         let mut statements = vec![];
         while !self.tokeniser.eof {
@@ -32,18 +58,19 @@ impl Parser {
         }
 
         if expect_last { self.expect_punctuation('}') }
+        self.end_scope();
         ASTNode::BlockStatement(statements)
     }
 
-    fn parse_component (&mut self, precedence: usize) -> ASTNode {
-        let mut node = self.parse_atom();
-
-        while !self.tokeniser.eof {
-            let (was_call, call_node) = self.maybe_call(node);
-            node = call_node;
-
-            if !was_call { break; }
+    pub fn parse_component (&mut self, precedence: usize) -> ASTNode {
+        let atom = self.parse_atom();
+        // Statements can't be operands, so whatever comes after one is the
+        // next statement, eg. the "*p = 1;" in "if (c) return 0; *p = 1;"
+        if is_statement(&atom) {
+            self.allow_expression_statement();
+            return atom
         }
+        let node = self.parse_postfix_operations(atom);
 
         let bin = self.maybe_binary_operation(node, precedence);
         self.allow_expression_statement();
@@ -52,23 +79,77 @@ impl Parser {
 
     // It might be something we didn't expect to be a statement.
     // eg. 3 + 3; or a = 1;
+    // A statement only ends with one ;, which an expression inside it may
+    // have already eaten (eg. "int i = 0;"). If so, the next ; is a separate
+    // empty statement, like the missing condition in "for (int i = 0;;)"
     fn allow_expression_statement (&mut self) {
-        if self.is_next_punctuation(';') {
+        if self.is_next_punctuation(';') && !matches!(self.tokeniser.previous, Some(Token::Punctuation(';'))) {
             self.tokeniser.read();
         }
     }
 
+    // Calls, subscripts and member accesses, which bind tighter than anything
+    fn parse_postfix_operations (&mut self, mut node: ASTNode) -> ASTNode {
+        while !self.tokeniser.eof {
+            let (was_call, call_node) = self.maybe_call(node);
+            node = call_node;
+            if was_call { continue }
+
+            if self.is_next_punctuation('[') {
+                // a[i] is *(a + i)
+                self.tokeniser.read();
+                let index = self.parse_component(0);
+                self.expect_punctuation(']');
+
+                node = ASTNode::UnaryOperation(ASTUnaryOperation {
+                    operator: "*".to_string(),
+                    operand: Box::new(ASTNode::BinaryOperation(ASTBinaryOperation {
+                        left_side: Box::new(node),
+                        operator: "+".to_string(),
+                        right_side: Box::new(index)
+                    }))
+                });
+            } else if self.is_next_punctuation('.') {
+                self.tokeniser.read();
+                node = ASTNode::MemberAccess(ASTMemberAccess {
+                    object: Box::new(node),
+                    member: self.parse_member_name()
+                });
+            } else if self.is_next_operator("->") {
+                // p->x is (*p).x
+                self.tokeniser.read();
+                let object = ASTNode::UnaryOperation(ASTUnaryOperation {
+                    operator: "*".to_string(),
+                    operand: Box::new(node)
+                });
+                node = ASTNode::MemberAccess(ASTMemberAccess {
+                    object: Box::new(object),
+                    member: self.parse_member_name()
+                });
+            } else {
+                break
+            }
+        }
+
+        node
+    }
+
+    fn parse_member_name (&mut self) -> String {
+        match self.tokeniser.read() {
+            Token::Identifier(ident) => ident,
+            tk => {
+                print_token(&tk);
+                panic!("Expected a member name")
+            }
+        }
+    }
+
     fn maybe_call (&mut self, me: ASTNode) -> (bool, ASTNode) {
         if !self.is_next_punctuation('(') {
             return (false, me);
         }
         self.tokeniser.read();
 
-        let func_name = match me {
-            ASTNode::Identifier(ident) => ident,
-            _ => panic!("Function call must be an identifier (eg. not 3.14() )")
-        };
-
         let mut args = vec![];
         while !self.tokeniser.eof {
             if self.is_next_punctuation(')') {
@@ -85,7 +166,7 @@ impl Parser {
         }
 
         (true, ASTNode::FunctionCall(ASTFunctionCall {
-            name: func_name,
+            callee: Box::new(me),
             args
         }))
     }
@@ -135,6 +216,25 @@ impl Parser {
         let t = self.tokeniser.read();
 
         if let Token::Punctuation(pnc) = t {
+            // Casts, eg. (char*)p
+            if pnc == '(' && self.is_next_type_name() {
+                let target_type = self.parse_type_name();
+                self.expect_punctuation(')');
+                // Or a compound literal, eg. (struct point){1, 2}
+                if self.is_next_punctuation('{') {
+                    let initial_value = self.parse_initialiser_list();
+                    return ASTNode::CompoundLiteral(ASTCompoundLiteral {
+                        literal_type: completed_type(target_type, &initial_value),
+                        initial_value: Box::new(initial_value)
+                    })
+                }
+                let operand = self.parse_unary_operand();
+                return ASTNode::Cast(ASTCast {
+                    operand: Box::new(operand),
+                    target_type
+                })
+            }
+
             // Bracketed expressions
             if pnc == '(' {
                 let contents = self.parse_component(0);
@@ -147,14 +247,28 @@ impl Parser {
                 let contents = self.parse_block_statement(false, true);
                 return contents
             }
+
+            if pnc == ';' {
+                return ASTNode::EmptyStatement
+            }
         }
 
         match t {
-            Token::Integer(int) => return ASTNode::IntegerLiteral(int),
+            Token::Integer(int, constant_type) => return integer_constant(int, constant_type),
             Token::String(st) => return ASTNode::StringLiteral(st),
             Token::Character(ch) => return ASTNode::IntegerLiteral(ch as isize),
+            Token::Identifier(ident) if self.find_enumerator(&ident).is_some() => {
+                return ASTNode::IntegerLiteral(self.find_enumerator(&ident).unwrap())
+            },
+            // Eg. "T * x;" is a declaration if T is a typedef name
+            Token::Identifier(ident) if self.is_typedef_name(&ident) => {
+                let base = self.parse_type(&ident);
+                return self.parse_declaration(base, StorageClass::Unspecified)
+            },
+            Token::Identifier(ident) if ident == "va_arg" => return self.parse_va_arg(),
             Token::Identifier(ident) => return ASTNode::Identifier(ident),
             Token::Operator(oper) => return self.parse_unary_operation(oper),
+            Token::Keyword(kw) if kw == "sizeof" => return self.parse_sizeof(),
             _ => {}
         }
 
@@ -166,13 +280,45 @@ impl Parser {
             panic!("\"{}\" was used as a unary operator but it isn't one", oper);
         }
 
-        let operand = self.parse_atom();
+        let operand = self.parse_unary_operand();
         ASTNode::UnaryOperation(ASTUnaryOperation {
             operator: oper,
             operand: Box::new(operand)
         })
     }
 
+    // What unary operators, casts and sizeof apply to, which
+    // includes any postfix operations, eg. -a[1] is -(a[1])
+    fn parse_unary_operand (&mut self) -> ASTNode {
+        let atom = self.parse_atom();
+        self.parse_postfix_operations(atom)
+    }
+
+    // After the "sizeof" keyword. Either "sizeof(type)" or "sizeof expr",
+    // which are both worked out here so they can be used as constants
+    fn parse_sizeof (&mut self) -> ASTNode {
+        let expr = if self.is_next_punctuation('(') {
+            self.tokeniser.read();
+            if self.is_next_type_name() {
+                let the_type = self.parse_type_name();
+                self.expect_punctuation(')');
+                return size_of_type(the_type)
+            }
+            let contents = self.parse_component(0);
+            self.expect_punctuation(')');
+            self.parse_postfix_operations(contents)
+        } else {
+            self.parse_unary_operand()
+        };
+
+        // The expression isn't evaluated, only its type matters.
+        // Variable length arrays remember their size from when they were declared.
+        match self.type_of_node(&expr) {
+            Type::VariableArray(_) => ASTNode::VariableArraySize(Box::new(expr)),
+            measured_type => size_of_type(measured_type)
+        }
+    }
+
     // NOTE: "int" is the only type for now
     fn parse_statement (&mut self, t: Token) -> ASTNode {
         if let Token::Keyword(kwd) = &t {
@@ -182,7 +328,29 @@ impl Parser {
                 "if" => return self.parse_if_statement(),
                 "while" => return self.parse_while_loop(),
                 "for" => return self.parse_for_loop(),
-                _ if is_builtin_type_name(kwd) => return self.parse_declaration(kwd),
+                "switch" => return self.parse_switch_statement(),
+                "case" => return self.parse_case_label(),
+                "default" => {
+                    self.expect_punctuation(':');
+                    return ASTNode::CaseLabel(ASTCaseLabel { value: None, id: self.new_id() })
+                },
+                "break" => return ASTNode::BreakStatement,
+                "continue" => return ASTNode::ContinueStatement,
+                "static" | "extern" => {
+                    let storage_class = if kwd == "static" { StorageClass::Static } else { StorageClass::Extern };
+                    let base = self.parse_base_type();
+                    return self.parse_declaration(base, storage_class)
+                },
+                "void" | "struct" | "enum" | "const" | "volatile" | "restrict" => {
+                    let base = self.parse_type(kwd);
+                    return self.parse_declaration(base, StorageClass::Unspecified)
+                },
+                "typedef" => return self.parse_typedef(),
+                "asm" | "__asm__" => return self.parse_inline_asm(),
+                _ if is_builtin_type_name(kwd) || is_builtin_type_modifier(kwd) => {
+                    let base = self.parse_type(kwd);
+                    return self.parse_declaration(base, StorageClass::Unspecified)
+                },
                 _ => panic!("Unexpected keyword \"{}\"", kwd)
             }
         }
@@ -226,9 +394,30 @@ impl Parser {
         })
     }
 
+    fn parse_switch_statement (&mut self) -> ASTNode {
+        self.expect_punctuation('(');
+        let value = Box::new(self.parse_component(0));
+        self.expect_punctuation(')');
+        let body = Box::new(self.parse_component(0));
+
+        ASTNode::SwitchStatement(ASTSwitchStatement {
+            value,
+            body
+        })
+    }
+
+    // After the "case" keyword
+    fn parse_case_label (&mut self) -> ASTNode {
+        let value = get_constant_value_from_node(&self.parse_component(0));
+        self.expect_punctuation(':');
+        ASTNode::CaseLabel(ASTCaseLabel { value: Some(value), id: self.new_id() })
+    }
+
     // Quite complicated
     fn parse_for_loop (&mut self) -> ASTNode {
         self.expect_punctuation('(');
+        // Things declared in the loop header are only in scope in the loop
+        self.begin_scope();
 
         let mut declaration = None;
         if !self.is_next_punctuation(';') {
@@ -248,6 +437,7 @@ impl Parser {
         self.expect_punctuation(')');
 
         let body = Box::new(self.parse_component(0));
+        self.end_scope();
 
         ASTNode::ForLoop(ASTForLoop {
             declaration,
@@ -258,110 +448,477 @@ impl Parser {
     }
 
     fn parse_return_statement (&mut self) -> ASTNode {
+        if self.is_next_punctuation(';') {
+            return ASTNode::ReturnStatement(None)
+        }
         let ret_val = self.parse_component(0);
         // self.expect_punctuation(';');
-        ASTNode::ReturnStatement(Box::new(ret_val))
+        ASTNode::ReturnStatement(Some(Box::new(ret_val)))
     }
 
-    fn parse_type (&mut self, start_keyword: &String) -> Type {
-        // TODO: Modifiers like long/unsigned
-        let mut the_type = match &start_keyword[..] {
-            "char" => Type::Char(IntegerTypeMetadata { signed: true }),
-            "short" => Type::Short(IntegerTypeMetadata { signed: true }),
-            "int" => Type::Int(IntegerTypeMetadata { signed: true }),
-            _ => unimplemented!("Type {}", start_keyword)
+    // Pointers, arrays and so on are part of the declarator
+    pub fn parse_type (&mut self, start_keyword: &str) -> Type {
+        // Qualifiers can come before or after the rest, eg. "const int" or "int const"
+        let mut qualifiers = Qualifiers::default();
+        let mut start = start_keyword.to_string();
+        while is_type_qualifier(&start) {
+            add_qualifier(&mut qualifiers, &start);
+            start = match self.tokeniser.read() {
+                Token::Keyword(kw) => kw,
+                Token::Identifier(ident) if self.is_typedef_name(&ident) => ident,
+                _ => panic!("Expected a type after \"{}\"", start)
+            };
+        }
+
+        let base = match &start[..] {
+            _ if is_builtin_type_name(&start) || is_builtin_type_modifier(&start) => {
+                let (the_type, more_qualifiers) = self.parse_integer_type(start.clone());
+                qualifiers = qualifiers.union(&more_qualifiers);
+                the_type
+            },
+            "void" => void_type(),
+            "struct" => self.parse_struct_type(),
+            "enum" => self.parse_enum_type(),
+            _ => match self.find_typedef(&start) {
+                Some(the_type) => the_type,
+                None => unimplemented!("Type {}", start)
+            }
         };
 
-        while self.is_next_operator("*") {
+        let trailing = self.parse_qualifiers();
+        apply_qualifiers(base, qualifiers.union(&trailing))
+    }
+
+    // Integer types can be made of several keywords in any order, eg.
+    // "unsigned long int" or "long unsigned". Qualifiers can be mixed in too.
+    fn parse_integer_type (&mut self, first: String) -> (Type, Qualifiers) {
+        let mut keywords = vec![first];
+        let mut qualifiers = Qualifiers::default();
+        loop {
+            let more = match self.tokeniser.peek() {
+                Token::Keyword(kw) if is_builtin_type_name(&kw) || is_builtin_type_modifier(&kw) => kw,
+                _ if self.is_next_type_qualifier() => {
+                    qualifiers = qualifiers.union(&self.parse_qualifiers());
+                    continue
+                },
+                _ => break
+            };
             self.tokeniser.read();
-            the_type = Type::Pointer(PointerTypeMetadata {
-                points_to: Box::new(the_type)
-            })
+            keywords.push(more);
         }
 
-        the_type
+        let count = |name: &str| keywords.iter().filter(|kw| *kw == name).count();
+        if count("float") + count("double") > 0 {
+            unimplemented!("Floating point types")
+        }
+        // "short int" is fine, but char can't be combined with either
+        if count("signed") + count("unsigned") > 1 || count("char") + count("short") > 1
+            || count("char") + count("int") > 1 || count("int") > 1 || count("long") > 2 || (count("long") > 0 && count("char") + count("short") > 0) {
+            panic!("Invalid combination of type specifiers \"{}\"", keywords.join(" "))
+        }
+
+        // Plain char is signed or not depending on the target
+        let signed = match (count("signed"), count("unsigned")) {
+            (0, 0) if count("char") > 0 => self.char_is_signed,
+            (_, unsigned) => unsigned == 0
+        };
+        let meta = IntegerTypeMetadata { signed, qualifiers: Qualifiers::default() };
+        let the_type = if count("char") > 0 {
+            Type::Char(meta)
+        } else if count("short") > 0 {
+            Type::Short(meta)
+        } else if count("long") > 0 {
+            // long and long long are both 64-bit on System V
+            Type::LongLongInt(meta)
+        } else {
+            Type::Int(meta)
+        };
+        (the_type, qualifiers)
+    }
+
+    // va_list is laid out however the calling convention says. On
+    // x86-64 it's an array of one of these structs:
+    // struct { unsigned gp_offset; unsigned fp_offset;
+    //          void* overflow_arg_area; void* reg_save_area; }
+    // AArch64's is a struct on its own (so it isn't passed by address):
+    // struct { void* __stack; void* __gr_top; void* __vr_top;
+    //          int __gr_offs; int __vr_offs; }
+    // RISC-V's is a plain pointer to the next argument. Wrapping it in a
+    // struct means va_start and va_arg can find it by address like the
+    // others, and it's still passed in one register.
+    fn va_list_type (&mut self, target: Target) -> Type {
+        let unsigned_int = Type::Int(IntegerTypeMetadata { signed: false, qualifiers: Qualifiers::default() });
+        let char_pointer = pointer_to(char_type());
+        let (tag, members) = match target.arch {
+            Architecture::X86_64 => ("__va_list_tag", vec![
+                ("gp_offset", unsigned_int.clone()),
+                ("fp_offset", unsigned_int),
+                ("overflow_arg_area", char_pointer.clone()),
+                ("reg_save_area", char_pointer)
+            ]),
+            Architecture::AArch64 => ("__va_list", vec![
+                ("__stack", char_pointer.clone()),
+                ("__gr_top", char_pointer.clone()),
+                ("__vr_top", char_pointer),
+                ("__gr_offs", int_type()),
+                ("__vr_offs", int_type())
+            ]),
+            Architecture::RiscV64 => ("__va_list", vec![
+                ("__next", char_pointer)
+            ])
+        };
+
+        let definition = self.structs.entry(tag.to_string()).or_insert_with(|| {
+            let mut def = StructDefinition::new(Some(tag.to_string()));
+            def.complete(members.into_iter().map(|(name, member_type)| (name.to_string(), member_type)).collect());
+            Rc::new(RefCell::new(def))
+        }).clone();
+        let va_list = Type::Struct(StructTypeMetadata { definition, qualifiers: Qualifiers::default() });
+
+        match target.arch {
+            Architecture::X86_64 => Type::Array(ArrayTypeMetadata {
+                element: Box::new(va_list),
+                length: 1
+            }),
+            Architecture::AArch64 | Architecture::RiscV64 => va_list
+        }
     }
 
-    // Declarations of variables and functions start the same (with a type)
-    fn parse_declaration (&mut self, type_start_keyword: &String) -> ASTNode {
-        let var_type = self.parse_type(type_start_keyword);
+    // After the "va_arg" identifier
+    fn parse_va_arg (&mut self) -> ASTNode {
+        self.expect_punctuation('(');
+        let list = self.parse_component(0);
+        self.expect_punctuation(',');
+        let arg_type = self.parse_type_name();
+        self.expect_punctuation(')');
 
-        let name_tk = self.tokeniser.read();
-        let name = match name_tk {
-            Token::Identifier(ident) => ident,
+        ASTNode::VaArg(ASTVaArg {
+            list: Box::new(list),
+            arg_type
+        })
+    }
+
+    // After the "struct" keyword. Either a reference to a tag, or a definition
+    fn parse_struct_type (&mut self) -> Type {
+        let mut tag = None;
+        if let Token::Identifier(ident) = self.tokeniser.peek() {
+            self.tokeniser.read();
+            tag = Some(ident);
+        }
+
+        let definition = match &tag {
+            Some(tag) if self.structs.contains_key(tag) => self.structs[tag].clone(),
             _ => {
-                print_token(&name_tk);
-                panic!("Expected declaration identifier but didn't get one")
+                let def = Rc::new(RefCell::new(StructDefinition::new(tag.clone())));
+                if let Some(tag) = &tag {
+                    self.structs.insert(tag.clone(), def.clone());
+                }
+                def
             }
         };
 
-        if self.is_next_punctuation('(') {
-            // This is a function declaration with a parameter list
+        if self.is_next_punctuation('{') {
             self.tokeniser.read();
+            if definition.borrow().members.is_some() {
+                panic!("Redefinition of struct \"{}\"", struct_tag(&definition.borrow()))
+            }
 
-            // Parse parameters
-            let mut params = vec![];
-            while self.is_next_builtin_type_name() {
-                let tk = self.tokeniser.read();
-                let param_type = match tk {
-                    Token::Keyword(kw) => self.parse_type(&kw),
-                    _ => panic!("Expected a type for function param")
-                };
+            let mut members = vec![];
+            while !self.is_next_punctuation('}') {
+                let base = self.parse_base_type();
+                loop {
+                    let declarator = self.parse_declarator(base.clone());
+                    let name = match declarator.name {
+                        Some(name) => name,
+                        None => panic!("Expected a member name")
+                    };
+                    if is_void(&declarator.declared_type) {
+                        panic!("Struct member \"{}\" can't have type void", name)
+                    }
+                    members.push((name, declarator.declared_type));
+
+                    if !self.is_next_punctuation(',') { break }
+                    self.tokeniser.read();
+                }
+                self.expect_punctuation(';');
+            }
+            self.expect_punctuation('}');
 
-                // For now, we only support named parameters
-                let param_name = match self.tokeniser.read() {
+            definition.borrow_mut().complete(members);
+        }
+
+        Type::Struct(StructTypeMetadata { definition, qualifiers: Qualifiers::default() })
+    }
+
+    // After the "enum" keyword. Enums are just ints, but a definition
+    // declares its enumerators as constants in the current scope
+    fn parse_enum_type (&mut self) -> Type {
+        if let Token::Identifier(_) = self.tokeniser.peek() {
+            // Tags don't mean anything since every enum is an int
+            self.tokeniser.read();
+        }
+
+        if self.is_next_punctuation('{') {
+            self.tokeniser.read();
+
+            let mut next_value = 0;
+            while !self.is_next_punctuation('}') {
+                let name = match self.tokeniser.read() {
                     Token::Identifier(ident) => ident,
-                    _ => panic!("Function parameters must be identifiers")
+                    _ => panic!("Expected an enumerator name")
                 };
-                params.push(ASTNameAndType {
-                    name: param_name,
-                    param_type
-                });
 
-                // NOTE: This doesn't quite match the standard
-                if self.is_next_punctuation(',') {
+                // Values can refer to earlier enumerators, eg. "B = A + 1"
+                if self.is_next_operator("=") {
                     self.tokeniser.read();
+                    next_value = get_constant_value_from_node(&self.parse_component(0));
                 }
+                self.declare_enumerator(name, next_value);
+                next_value += 1;
+
+                if !self.is_next_punctuation(',') { break }
+                self.tokeniser.read();
             }
+            self.expect_punctuation('}');
+        }
 
-            self.expect_punctuation(')');
+        int_type()
+    }
 
-            let mut body = None;
-            if self.is_next_punctuation('{') {
-                let body_node = self.parse_block_statement(true, true);
-                body = match body_node {
-                    ASTNode::BlockStatement(stmts) => Some(stmts),
-                    _ => unreachable!()
-                };
+    // Declarations of variables and functions start the same (with a type),
+    // and can declare several things at once, eg. "int x = 1, *p = &x;"
+    fn parse_declaration (&mut self, base: Type, storage_class: StorageClass) -> ASTNode {
+        // Eg. "struct point { int x; int y; };" which only declares a type
+        if self.is_next_punctuation(';') {
+            return ASTNode::EmptyStatement
+        }
+
+        let mut declarations = vec![];
+        loop {
+            let declarator = self.parse_declarator(base.clone());
+            let name = match declarator.name {
+                Some(name) => name,
+                None => panic!("Expected declaration identifier but didn't get one")
+            };
+
+            self.declare_object(&name, declarator.declared_type.clone());
+
+            match (declarator.declared_type, declarator.params) {
+                (Type::Function(func), params) => {
+                    // Functions can be declared with a typedef'd function type,
+                    // in which case the parameters are unnamed
+                    let (params, variadic) = params.unwrap_or_else(|| {
+                        let unnamed = func.params.iter().map(|param_type| ASTNameAndType {
+                            name: String::new(),
+                            param_type: param_type.clone()
+                        }).collect();
+                        (unnamed, func.variadic)
+                    });
+
+                    let mut body = None;
+                    if self.is_next_punctuation('{') {
+                        // Parameters hide typedefs inside the body
+                        self.begin_scope();
+                        for param in &params {
+                            self.declare_object(&param.name, param.param_type.clone());
+                        }
+                        let body_node = self.parse_block_statement(true, true);
+                        self.end_scope();
+
+                        body = match body_node {
+                            ASTNode::BlockStatement(stmts) => Some(stmts),
+                            _ => unreachable!()
+                        };
+                    }
+                    let has_body = body.is_some();
+
+                    declarations.push(ASTNode::FunctionDefinition(ASTFunctionDefinition {
+                        name,
+                        return_type: *func.return_type,
+                        body,
+                        params,
+                        variadic,
+                        storage_class
+                    }));
+
+                    // Nothing else can be declared after a function body
+                    if has_body { break }
+                },
+                (declared_type, _) => {
+                    declarations.push(self.parse_variable_declaration(name, declared_type, storage_class))
+                }
             }
 
-            return ASTNode::FunctionDefinition(ASTFunctionDefinition {
-                name,
-                return_type: var_type,
-                body,
-                params
-            })
+            if !self.is_next_punctuation(',') { break }
+            self.tokeniser.read();
+        }
+
+        if declarations.len() == 1 {
+            declarations.pop().unwrap()
         } else {
-            // This is a variable declaration
-            let mut initial_value = None;
+            ASTNode::DeclarationList(declarations)
+        }
+    }
 
-            if self.is_next_operator("=") {
-                // It has an initial value
-                self.tokeniser.read();
-                initial_value = Some(Box::new(self.parse_component(0)));
+    // After the "typedef" keyword. Declares names for types instead of variables
+    fn parse_typedef (&mut self) -> ASTNode {
+        let base = self.parse_base_type();
+        loop {
+            let declarator = self.parse_declarator(base.clone());
+            match declarator.name {
+                Some(name) => self.declare_typedef(name, declarator.declared_type),
+                None => panic!("Expected a name for typedef")
             }
 
-            ASTNode::VariableDeclaration(ASTVariableDeclaration {
-                identifier: name,
-                var_type,
-                initial_value
-            })
+            if !self.is_next_punctuation(',') { break }
+            self.tokeniser.read();
         }
+        ASTNode::EmptyStatement
     }
 
-    pub fn new (tokeniser: Tokeniser) -> Parser {
-        Parser {
-            tokeniser
+    fn parse_variable_declaration (&mut self, name: String, mut var_type: Type, storage_class: StorageClass) -> ASTNode {
+        if is_void(&var_type) {
+            panic!("Variable \"{}\" can't have type void", name)
         }
+        let mut initial_value = None;
+
+        if self.is_next_operator("=") {
+            // It has an initial value
+            self.tokeniser.read();
+            let init = if self.is_next_punctuation('{') {
+                self.parse_initialiser_list()
+            } else {
+                self.parse_component(0)
+            };
+
+            // Eg. "int a[] = {1, 2};" gets its length from the initialiser
+            var_type = completed_type(var_type, &init);
+            self.declare_object(&name, var_type.clone());
+            initial_value = Some(Box::new(init));
+        }
+
+        ASTNode::VariableDeclaration(ASTVariableDeclaration {
+            identifier: name,
+            var_type,
+            initial_value,
+            storage_class,
+            id: self.new_id()
+        })
+    }
+
+    // Braced initialisers, which can contain more of themselves
+    fn parse_initialiser_list (&mut self) -> ASTNode {
+        self.expect_punctuation('{');
+
+        let mut values = vec![];
+        while !self.is_next_punctuation('}') {
+            if self.is_next_punctuation('{') {
+                values.push(self.parse_initialiser_list());
+            } else {
+                values.push(self.parse_component(0));
+            }
+
+            // There can be a trailing comma
+            if !self.is_next_punctuation(',') { break }
+            self.tokeniser.read();
+        }
+        self.expect_punctuation('}');
+
+        ASTNode::InitialiserList(values)
+    }
+
+    fn new_id (&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn new (tokeniser: Tokeniser, target: Target) -> Parser {
+        let mut parser = Parser {
+            tokeniser,
+            structs: HashMap::new(),
+            names: vec![HashMap::new()],
+            next_id: 0,
+            char_is_signed: target.char_is_signed()
+        };
+
+        // Things from the standard headers we know about without including them
+        let va_list = parser.va_list_type(target);
+        parser.declare_typedef("va_list".to_string(), va_list);
+
+        parser
+    }
+}
+
+// A literal, cast if C gives the constant a type other than the one
+// its value would, eg. 5u or 0xFFFFFFFF
+fn integer_constant (value: isize, constant_type: Type) -> ASTNode {
+    if constant_type == integer_literal_type(value) {
+        return ASTNode::IntegerLiteral(value)
+    }
+    ASTNode::Cast(ASTCast {
+        operand: Box::new(ASTNode::IntegerLiteral(value)),
+        target_type: constant_type
+    })
+}
+
+// sizeof(type), which is a constant unless it's a variable length array type
+fn size_of_type (measured_type: Type) -> ASTNode {
+    if let Type::VariableArray(arr) = measured_type {
+        return ASTNode::BinaryOperation(ASTBinaryOperation {
+            left_side: Box::new(ASTNode::Cast(ASTCast {
+                operand: arr.length,
+                target_type: size_type()
+            })),
+            operator: "*".to_string(),
+            right_side: Box::new(size_of_type(*arr.element))
+        })
+    }
+
+    ASTNode::Cast(ASTCast {
+        operand: Box::new(ASTNode::IntegerLiteral(size_in_bytes(&measured_type))),
+        target_type: size_type()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::char_stream::CharStream;
+
+    fn declared_types (code: &str) -> Vec<Type> {
+        let mut parser = Parser::new(Tokeniser::new(CharStream::new(code.to_string())), Target::X86_64_LINUX);
+        parser.generate_ast().into_iter().map(|node| match node {
+            ASTNode::VariableDeclaration(decl) => decl.var_type,
+            _ => panic!("Expected only declarations")
+        }).collect()
+    }
+
+    fn is_integer (the_type: &Type, expected: fn(IntegerTypeMetadata) -> Type, signed: bool) -> bool {
+        *the_type == expected(IntegerTypeMetadata { signed, qualifiers: Qualifiers::default() })
+    }
+
+    #[test]
+    fn integer_type_keywords_combine_in_any_order () {
+        let types = declared_types("short int a; int short b; unsigned short int c; long int d; long long int e; long unsigned f; signed char g; unsigned h;");
+        assert!(is_integer(&types[0], Type::Short, true));
+        assert!(is_integer(&types[1], Type::Short, true));
+        assert!(is_integer(&types[2], Type::Short, false));
+        assert!(is_integer(&types[3], Type::LongLongInt, true));
+        assert!(is_integer(&types[4], Type::LongLongInt, true));
+        assert!(is_integer(&types[5], Type::LongLongInt, false));
+        assert!(is_integer(&types[6], Type::Char, true));
+        assert!(is_integer(&types[7], Type::Int, false));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid combination of type specifiers \"char int\"")]
+    fn char_int_is_rejected () {
+        declared_types("char int a;");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid combination of type specifiers \"short long\"")]
+    fn short_long_is_rejected () {
+        declared_types("short long a;");
     }
 }
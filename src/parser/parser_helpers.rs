@@ -1,5 +1,8 @@
-use super::parser::Parser;
+use std::collections::hash_map::HashMap;
+use super::parser::{Parser, OrdinaryName};
 use super::tokens::*;
+use super::types::{Type, Qualifiers, qualified};
+use crate::codegen::typing::Typing;
 
 impl Parser {
     pub fn expect_punctuation(&mut self, c: char) {
@@ -35,8 +38,112 @@ impl Parser {
 
     pub fn is_next_builtin_type_name (&self) -> bool {
         match self.tokeniser.peek() {
-            Token::Keyword(kw) => is_builtin_type_name(&kw),
+            Token::Keyword(kw) => is_builtin_type_name(&kw) || is_builtin_type_modifier(&kw),
             _ => false
         }
     }
+
+    pub fn is_next_type_qualifier (&self) -> bool {
+        match self.tokeniser.peek() {
+            Token::Keyword(kw) => is_type_qualifier(&kw),
+            _ => false
+        }
+    }
+
+    // Any qualifiers that come next, eg. the "const" in "char * const p"
+    pub fn parse_qualifiers (&mut self) -> Qualifiers {
+        let mut qualifiers = Qualifiers::default();
+        while self.is_next_type_qualifier() {
+            if let Token::Keyword(kw) = self.tokeniser.read() {
+                add_qualifier(&mut qualifiers, &kw);
+            }
+        }
+        qualifiers
+    }
+
+    // Anything that can start a type, including struct, enum and typedef names
+    pub fn is_next_type_name (&self) -> bool {
+        self.is_next_builtin_type_name() || self.is_next_type_qualifier() ||
+            self.is_next_keyword("void") || self.is_next_keyword("struct") || self.is_next_keyword("enum") ||
+            matches!(self.tokeniser.peek(), Token::Identifier(ident) if self.is_typedef_name(&ident))
+    }
+
+    pub fn begin_scope (&mut self) {
+        self.names.push(HashMap::new());
+    }
+
+    pub fn end_scope (&mut self) {
+        self.names.pop();
+    }
+
+    fn declare_name (&mut self, name: String, meaning: OrdinaryName) {
+        let latest = self.names.len() - 1;
+        self.names[latest].insert(name, meaning);
+    }
+
+    pub fn declare_typedef (&mut self, name: String, the_type: Type) {
+        self.declare_name(name, OrdinaryName::Typedef(the_type));
+    }
+
+    pub fn declare_enumerator (&mut self, name: String, value: isize) {
+        self.declare_name(name, OrdinaryName::Enumerator(value));
+    }
+
+    // Variables and functions hide typedefs and enumerators with the same
+    // name, eg. "typedef int T; { int T; T = 3; }"
+    pub fn declare_object (&mut self, name: &str, the_type: Type) {
+        self.declare_name(name.to_string(), OrdinaryName::Object(the_type));
+    }
+
+    fn find_name (&self, name: &str) -> Option<&OrdinaryName> {
+        self.names.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    pub fn find_typedef (&self, name: &str) -> Option<Type> {
+        match self.find_name(name) {
+            Some(OrdinaryName::Typedef(the_type)) => Some(the_type.clone()),
+            _ => None
+        }
+    }
+
+    pub fn find_enumerator (&self, name: &str) -> Option<isize> {
+        match self.find_name(name) {
+            Some(OrdinaryName::Enumerator(value)) => Some(*value),
+            _ => None
+        }
+    }
+
+    pub fn is_typedef_name (&self, name: &str) -> bool {
+        self.find_typedef(name).is_some()
+    }
+}
+
+impl Typing for Parser {
+    fn type_of_name (&self, name: &str) -> Option<Type> {
+        match self.find_name(name) {
+            Some(OrdinaryName::Object(the_type)) => Some(the_type.clone()),
+            _ => None
+        }
+    }
+
+    fn char_is_signed (&self) -> bool {
+        self.char_is_signed
+    }
+}
+
+pub fn add_qualifier (qualifiers: &mut Qualifiers, keyword: &str) {
+    match keyword {
+        "const" => qualifiers.is_const = true,
+        "volatile" => qualifiers.is_volatile = true,
+        "restrict" => qualifiers.is_restrict = true,
+        _ => unreachable!("\"{}\" isn't a type qualifier", keyword)
+    }
+}
+
+// restrict only makes sense for pointers
+pub fn apply_qualifiers (the_type: Type, qualifiers: Qualifiers) -> Type {
+    if qualifiers.is_restrict && !matches!(the_type, Type::Pointer(_)) {
+        panic!("Only pointer types can be restrict-qualified")
+    }
+    qualified(the_type, qualifiers)
 }
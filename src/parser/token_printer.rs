@@ -4,7 +4,7 @@ pub fn print_token (token: &Token) {
     match token {
         Token::Keyword(kwd) => eprintln!("Keyword: \"{}\"", kwd),
         Token::Identifier(ident) => eprintln!("Identifier: \"{}\"", ident),
-        Token::Integer(int) => eprintln!("Integer literal: {}", int),
+        Token::Integer(int, _) => eprintln!("Integer literal: {}", int),
         Token::Punctuation(pnc) => eprintln!("Punctuation: {}", pnc),
         Token::Operator(op) => eprintln!("Operator: {}", op),
         Token::Character(ch) => eprint!("Character: {}", ch),
@@ -1,10 +1,13 @@
 use crate::parser::char_stream::CharStream;
 use crate::parser::tokens::*;
+use crate::parser::types::*;
 use std::iter::FromIterator;
 
 pub struct Tokeniser {
     pub code: CharStream,
     pub current: Token,
+    // The token before current, which has already been read
+    pub previous: Option<Token>,
     pub eof: bool
 }
 
@@ -29,7 +32,7 @@ impl Tokeniser {
             }
         }
 
-        if is_number(&c) {
+        if is_number_start(&c) {
             self.current = self.read_number(c);
         } else if is_identifier_start(&c) {
             self.current = self.read_identifier(c);
@@ -96,13 +99,36 @@ impl Tokeniser {
         }
     }
 
+    // Decimal, octal (0755) or hex (0xff), optionally followed by
+    // u and l suffixes in any order, eg. 10UL
     fn read_number (&mut self, first: char) -> Token {
         let mut vc = vec![first];
-        while !self.code.eof && is_number(&self.code.peek()) {
+        while !self.code.eof && (is_number(&self.code.peek()) || is_identifier(&self.code.peek())) {
             vc.push(self.code.read())
         }
         let st = String::from_iter(vc);
-        Token::Integer(st.parse().unwrap())
+
+        let digits = st.trim_end_matches(['u', 'U', 'l', 'L']);
+        let suffix = st[digits.len()..].to_lowercase();
+        let (unsigned, long) = match &suffix[..] {
+            "" => (false, false),
+            "u" => (true, false),
+            "l" | "ll" => (false, true),
+            "ul" | "lu" | "ull" | "llu" => (true, true),
+            _ => panic!("Invalid suffix on integer constant \"{}\"", st)
+        };
+
+        let (radix, digits) = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            Some(hex) => (16, hex),
+            None if digits.len() > 1 && digits.starts_with('0') => (8, &digits[1..]),
+            None => (10, digits)
+        };
+        let value = match u64::from_str_radix(digits, radix) {
+            Ok(value) => value,
+            Err(_) => panic!("Invalid integer constant \"{}\"", st)
+        };
+
+        Token::Integer(value as isize, integer_constant_type(value, radix == 10, unsigned, long))
     }
 
     fn expect_char (&mut self, ch: char) {
@@ -147,6 +173,7 @@ impl Tokeniser {
 
     pub fn read (&mut self) -> Token {
         let tk = self.current.clone();
+        self.previous = Some(tk.clone());
         self.read_next();
         tk
     }
@@ -154,10 +181,31 @@ impl Tokeniser {
     pub fn new (code: CharStream) -> Tokeniser {
         let mut tok = Tokeniser {
             code,
-            current: Token::Integer(0),
+            current: Token::Integer(0, int_type()),
+            previous: None,
             eof: false
         };
         tok.read_next();
         tok
     }
 }
+
+// The first type an integer constant's value fits in, out of int, unsigned
+// int, long and unsigned long. Suffixes rule some out, and decimal ones are
+// only unsigned if they're asked to be, so 3000000000 is a long but
+// 0xFFFFFFFF is an unsigned int.
+fn integer_constant_type (value: u64, decimal: bool, unsigned: bool, long: bool) -> Type {
+    let may_be_unsigned = unsigned || !decimal;
+    if !unsigned && !long && value <= i32::MAX as u64 {
+        int_type()
+    } else if may_be_unsigned && !long && value <= u32::MAX as u64 {
+        Type::Int(IntegerTypeMetadata { signed: false, qualifiers: Qualifiers::default() })
+    } else if !unsigned && value <= i64::MAX as u64 {
+        long_type()
+    } else {
+        if !may_be_unsigned {
+            eprintln!("[warn] Integer constant {} is so large that it is unsigned", value);
+        }
+        size_type()
+    }
+}
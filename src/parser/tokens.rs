@@ -1,8 +1,11 @@
+use crate::parser::types::Type;
+
 #[derive(Clone)]
 pub enum Token {
     Identifier(String),
     Keyword(String),
-    Integer(isize),
+    // The value and the type C gives the constant, eg. long for 5L
+    Integer(isize, Type),
     Operator(String),
     Punctuation(char),
     Character(char),
@@ -23,10 +26,15 @@ fn in_char_string (c: &char, s: &str) -> bool {
 }
 
 pub fn is_whitespace (c: &char) -> bool {
-    let whitespace = vec![' ', '\t', '\n'];
+    let whitespace = [' ', '\t', '\n'];
     whitespace.contains(c)
 }
 
+// '.' can't start a number, it's member access
+pub fn is_number_start(c: &char) -> bool {
+    c.is_ascii_digit()
+}
+
 pub fn is_number(c: &char) -> bool {
     let nums = vec!['0','1','2','3','4','5','6','7','8','9','.'];
     nums.contains(c)
@@ -38,10 +46,12 @@ pub fn is_keyword(s: &String) -> bool {
         "const", "continue", "default", "do",
         "double", "else", "enum", "extern",
         "float", "for", "goto", "if", "int",
-        "long", "register", "return", "short",
+        "long", "register", "restrict", "return", "short",
         "signed", "sizeof", "static", "struct",
         "switch", "typedef", "union", "unsigned",
-        "void", "volatile", "while"
+        "void", "volatile", "while",
+        // GNU extensions
+        "asm", "__asm__", "__volatile__"
     ])
 }
 
@@ -51,6 +61,11 @@ pub fn is_builtin_type_name(s: &String) -> bool {
         "float", "double"
     ])
 }
+pub fn is_type_qualifier(s: &String) -> bool {
+    in_string_vector(s, vec![
+        "const", "volatile", "restrict"
+    ])
+}
 pub fn is_builtin_type_modifier(s: &String) -> bool {
     in_string_vector(s, vec![
         "long", "signed", "unsigned"
@@ -63,7 +78,7 @@ pub fn is_operator(s: &String) -> bool {
         "!=", "%", "&&", "||",
         ">", "<", ">=", "<=",  "!",
         "~", "&", "--", "++", "%=",
-        "*=", "+=", "-=", "/=", "="
+        "*=", "+=", "-=", "/=", "=", "->"
     ])
 }
 pub fn is_assignment_operator (s: &String) -> bool {
@@ -90,7 +105,7 @@ pub fn is_pointer_operator (s: &String) -> bool {
     ])
 }
 pub fn is_binary_operator (s: &String) -> bool {
-    is_operator(s) // TODO: More specific
+    is_operator(s) && s != "->" // TODO: More specific
 }
 // Just a little shortcut because stack-based maths operators
 // can share very similar setup assembly
@@ -101,6 +116,11 @@ pub fn is_binary_stack_operator (s: &String) -> bool {
         ">", "<", ">=", "<="
     ])
 }
+pub fn is_comparison_operator (s: &String) -> bool {
+    in_string_vector(s, vec![
+        "==", "!=", ">", "<", ">=", "<="
+    ])
+}
 pub fn get_operator_precedence (s: &String) -> usize {
     let sstr = &s[..];
     match sstr {
@@ -1,9 +1,15 @@
-use super::types::Type;
-use super::ast_printer::print_at_depth;
+use super::types::{Type, Qualifiers, struct_tag, qualifiers_of};
+use super::ast_printer::{print_at_depth, print_ast_node};
 use super::types::IntegerTypeMetadata;
 
 pub fn print_type (the_type: &Type, depth: isize) {
+    // Arrays show their qualifiers on their element type
+    if !matches!(the_type, Type::Array(_) | Type::VariableArray(_)) {
+        print_qualifiers(&qualifiers_of(the_type), depth);
+    }
+
     match the_type {
+        Type::Void(_) => print_at_depth("Type: void".to_string(), depth),
         Type::Char(meta) => {
             print_at_depth("Type: char".to_string(), depth);
             print_int_meta(meta, depth + 1);
@@ -23,6 +29,30 @@ pub fn print_type (the_type: &Type, depth: isize) {
         Type::Pointer(meta) => {
             print_at_depth("Type: Pointer to:".to_string(), depth);
             print_type(&meta.points_to, depth + 1);
+        },
+        Type::Array(meta) => {
+            print_at_depth(format!("Type: Array of {}:", meta.length), depth);
+            print_type(&meta.element, depth + 1);
+        },
+        Type::VariableArray(meta) => {
+            print_at_depth("Type: Variable length array of:".to_string(), depth);
+            print_type(&meta.element, depth + 1);
+            print_at_depth("Length:".to_string(), depth + 1);
+            print_ast_node(&meta.length, depth + 2);
+        },
+        Type::Struct(meta) => {
+            // Members aren't printed, struct types can contain themselves
+            let def = meta.definition.borrow();
+            print_at_depth(format!("Type: struct {}", struct_tag(&def)), depth);
+        },
+        Type::Function(meta) => {
+            let variadic = if meta.variadic { " (variadic)" } else { "" };
+            print_at_depth(format!("Type: Function{} returning:", variadic), depth);
+            print_type(&meta.return_type, depth + 1);
+            for param in &meta.params {
+                print_at_depth("Parameter:".to_string(), depth + 1);
+                print_type(param, depth + 2);
+            }
         }
     }
 }
@@ -30,4 +60,17 @@ pub fn print_type (the_type: &Type, depth: isize) {
 fn print_int_meta (meta: &IntegerTypeMetadata, depth: isize) {
     let signed_string = if meta.signed { "yes" } else { "no" };
     print_at_depth(format!("Signed: {}", signed_string), depth);
-}
\ No newline at end of file
+}
+
+fn print_qualifiers (qualifiers: &Qualifiers, depth: isize) {
+    let names = [
+        (qualifiers.is_const, "const"),
+        (qualifiers.is_volatile, "volatile"),
+        (qualifiers.is_restrict, "restrict")
+    ];
+    for (present, name) in &names {
+        if *present {
+            print_at_depth(format!("Qualifier: {}", name), depth);
+        }
+    }
+}
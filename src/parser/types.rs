@@ -1,52 +1,404 @@
 /*
  Eg: char** -> Pointer({ points_to: Pointer({ points_to: Char({ signed: true }) }) })
 */
-// This will get quite a bit more complicated when we introduce structs etc.
+use std::cell::RefCell;
+use std::rc::Rc;
+use super::ast_utils::ASTNode;
 
 #[derive(Clone, PartialEq)]
 pub enum Type {
+    // Only used as a function's return type, or through a pointer
+    // (void* can point to anything)
+    Void(Qualifiers),
     Char(IntegerTypeMetadata), // 8-bit
     Short(IntegerTypeMetadata), // 16-bit
-    Int(IntegerTypeMetadata), // 32-bit (for both int and long int)
-    LongLongInt(IntegerTypeMetadata), // 64-bit
-    Pointer(PointerTypeMetadata) // 64-bit pointers
+    Int(IntegerTypeMetadata), // 32-bit
+    LongLongInt(IntegerTypeMetadata), // 64-bit (for both long and long long)
+    Pointer(PointerTypeMetadata), // 64-bit pointers
+    Array(ArrayTypeMetadata),
+    // Eg. "int a[n];" where the length is worked out at run time
+    VariableArray(VariableArrayTypeMetadata),
+    Struct(StructTypeMetadata),
+    // Only ever used through a pointer, or as the type of a function's name
+    Function(FunctionTypeMetadata)
 }
 
 #[derive(Clone, PartialEq)]
 pub struct IntegerTypeMetadata {
     pub signed: bool,
+    pub qualifiers: Qualifiers
 }
 
 #[derive(Clone, PartialEq)]
 pub struct PointerTypeMetadata {
-    pub points_to: Box<Type>
+    pub points_to: Box<Type>,
+    // Of the pointer itself, eg. "char * const p", not what it points to
+    pub qualifiers: Qualifiers
+}
+
+// Arrays don't have qualifiers of their own, their elements do.
+// Functions can't be qualified at all.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Qualifiers {
+    pub is_const: bool,
+    pub is_volatile: bool,
+    // Only allowed on pointers. A promise that nothing else is used to
+    // access what it points to, which we don't make use of (yet)
+    pub is_restrict: bool
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ArrayTypeMetadata {
+    pub element: Box<Type>,
+    pub length: usize
+}
+
+// Only the outermost length can be variable, so the element's size is
+// still known at compile time (and so is pointer arithmetic on it)
+#[derive(Clone, PartialEq)]
+pub struct VariableArrayTypeMetadata {
+    pub element: Box<Type>,
+    pub length: Box<ASTNode>
+}
+
+#[derive(Clone, PartialEq)]
+pub struct FunctionTypeMetadata {
+    pub return_type: Box<Type>,
+    pub params: Vec<Type>,
+    // Takes more arguments after the named ones (eg. printf)
+    pub variadic: bool
+}
+
+// Structs can refer to themselves (struct node { struct node* next; })
+// so the definition is shared, and filled in once the closing brace is parsed
+#[derive(Clone)]
+pub struct StructTypeMetadata {
+    pub definition: Rc<RefCell<StructDefinition>>,
+    pub qualifiers: Qualifiers
+}
+// Two struct types are the same only if they come from the same definition
+impl PartialEq for StructTypeMetadata {
+    fn eq (&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.definition, &other.definition) && self.qualifiers == other.qualifiers
+    }
+}
+
+pub struct StructDefinition {
+    pub tag: Option<String>,
+    // None until the struct has been defined (it's an incomplete type)
+    pub members: Option<Vec<StructMember>>,
+    pub size: isize,
+    pub alignment: isize
+}
+
+#[derive(Clone)]
+pub struct StructMember {
+    pub name: String,
+    pub member_type: Type,
+    pub offset: isize
+}
+
+impl Qualifiers {
+    pub fn union (&self, other: &Qualifiers) -> Qualifiers {
+        Qualifiers {
+            is_const: self.is_const || other.is_const,
+            is_volatile: self.is_volatile || other.is_volatile,
+            is_restrict: self.is_restrict || other.is_restrict
+        }
+    }
+
+    // Has at least every qualifier the other one has
+    pub fn includes (&self, other: &Qualifiers) -> bool {
+        self.union(other) == *self
+    }
+}
+
+impl StructDefinition {
+    pub fn new (tag: Option<String>) -> StructDefinition {
+        StructDefinition {
+            tag,
+            members: None,
+            size: 0,
+            alignment: 1
+        }
+    }
+
+    // Lays out members in order, padding each to its alignment
+    pub fn complete (&mut self, members: Vec<(String, Type)>) {
+        let mut laid_out = vec![];
+        let mut offset = 0;
+        let mut alignment = 1;
+
+        for (name, member_type) in members {
+            let align = alignment_in_bytes(&member_type);
+            offset = align_to(offset, align);
+            alignment = alignment.max(align);
+
+            let size = size_in_bytes(&member_type);
+            laid_out.push(StructMember { name, member_type, offset });
+            offset += size;
+        }
+
+        self.size = align_to(offset, alignment);
+        self.alignment = alignment;
+        self.members = Some(laid_out);
+    }
+}
+
+impl StructTypeMetadata {
+    pub fn find_member (&self, name: &str) -> StructMember {
+        let def = self.definition.borrow();
+        let members = match &def.members {
+            Some(members) => members,
+            None => panic!("Use of incomplete struct type \"{}\"", struct_tag(&def))
+        };
+
+        match members.iter().find(|m| m.name == name) {
+            Some(member) => member.clone(),
+            None => panic!("Struct \"{}\" has no member \"{}\"", struct_tag(&def), name)
+        }
+    }
+}
+
+pub fn struct_tag (def: &StructDefinition) -> String {
+    match &def.tag {
+        Some(tag) => tag.clone(),
+        None => "<anonymous>".to_string()
+    }
+}
+
+pub fn align_to (value: isize, alignment: isize) -> isize {
+    (value + alignment - 1) / alignment * alignment
 }
 
 pub fn size_in_bytes (the_type: &Type) -> isize {
     match the_type {
+        Type::Void(_) => panic!("void has no size"),
         Type::Char(_) => 1,
         Type::Short(_) => 2,
         Type::Int(_) => 4,
         Type::LongLongInt(_) => 8,
-        Type::Pointer(_) => 8
+        Type::Pointer(_) => 8,
+        Type::Array(arr) => size_in_bytes(&arr.element) * arr.length as isize,
+        Type::VariableArray(_) => panic!("The size of a variable length array is only known at run time"),
+        Type::Struct(st) => {
+            let def = st.definition.borrow();
+            if def.members.is_none() {
+                panic!("Size of incomplete struct type \"{}\" is unknown", struct_tag(&def))
+            }
+            def.size
+        },
+        Type::Function(_) => panic!("Functions don't have a size, only pointers to them do")
     }
 }
-// Returns the number that should be put into .align for the type as a global
-pub fn power_of_two_alignment (the_type: &Type) -> usize {
+pub fn alignment_in_bytes (the_type: &Type) -> isize {
     match the_type {
-        Type::Char(_) => 0,
-        Type::Short(_) => 1,
-        Type::Int(_) => 2,
-        Type::LongLongInt(_) => 3,
-        Type::Pointer(_) => 3
+        Type::Array(arr) => alignment_in_bytes(&arr.element),
+        Type::VariableArray(arr) => alignment_in_bytes(&arr.element),
+        Type::Struct(st) => st.definition.borrow().alignment,
+        _ => size_in_bytes(the_type)
     }
 }
+// Returns the number that should be put into .align for the type as a global
+pub fn power_of_two_alignment (the_type: &Type) -> usize {
+    alignment_in_bytes(the_type).trailing_zeros() as usize
+}
 pub fn global_literal_name (the_type: &Type) -> String {
     match the_type {
         Type::Char(_) => ".byte",
         Type::Short(_) => ".short",
         Type::Int(_) => ".long",
         Type::LongLongInt(_) => ".quad",
-        Type::Pointer(_) => ".quad"
+        Type::Pointer(_) => ".quad",
+        _ => unreachable!("Aggregate types don't have a single global literal")
     }.to_string()
 }
+
+// Scalars live in a register, aggregates (and functions) are referred to by their address
+pub fn is_scalar (the_type: &Type) -> bool {
+    !matches!(the_type, Type::Void(_) | Type::Array(_) | Type::VariableArray(_) | Type::Struct(_) | Type::Function(_))
+}
+
+pub fn is_void (the_type: &Type) -> bool {
+    matches!(the_type, Type::Void(_))
+}
+
+// Calls to void functions (and casts to void) don't have a value to use
+pub fn check_not_void (the_type: &Type) {
+    if is_void(the_type) {
+        panic!("void value not ignored as it ought to be")
+    }
+}
+
+pub fn is_signed (the_type: &Type) -> bool {
+    match the_type {
+        Type::Char(meta) | Type::Short(meta) |
+        Type::Int(meta) | Type::LongLongInt(meta) => meta.signed,
+        _ => false
+    }
+}
+
+// The type pointer arithmetic and dereferencing work in terms of
+pub fn pointed_to_type (the_type: &Type) -> Option<Type> {
+    match the_type {
+        Type::Pointer(ptr) => Some(*ptr.points_to.clone()),
+        Type::Array(arr) => Some(*arr.element.clone()),
+        Type::VariableArray(arr) => Some(*arr.element.clone()),
+        _ => None
+    }
+}
+
+pub fn pointer_to (the_type: Type) -> Type {
+    Type::Pointer(PointerTypeMetadata {
+        points_to: Box::new(the_type),
+        qualifiers: Qualifiers::default()
+    })
+}
+
+pub fn qualifiers_of (the_type: &Type) -> Qualifiers {
+    match the_type {
+        Type::Void(qualifiers) => *qualifiers,
+        Type::Char(meta) | Type::Short(meta) |
+        Type::Int(meta) | Type::LongLongInt(meta) => meta.qualifiers,
+        Type::Pointer(ptr) => ptr.qualifiers,
+        Type::Struct(st) => st.qualifiers,
+        Type::Array(arr) => qualifiers_of(&arr.element),
+        Type::VariableArray(arr) => qualifiers_of(&arr.element),
+        Type::Function(_) => Qualifiers::default()
+    }
+}
+
+// The type with extra qualifiers added to whatever it already had
+pub fn qualified (the_type: Type, extra: Qualifiers) -> Type {
+    if extra == Qualifiers::default() {
+        return the_type
+    }
+
+    match the_type {
+        Type::Void(qualifiers) => Type::Void(qualifiers.union(&extra)),
+        Type::Char(meta) => Type::Char(qualified_integer(meta, extra)),
+        Type::Short(meta) => Type::Short(qualified_integer(meta, extra)),
+        Type::Int(meta) => Type::Int(qualified_integer(meta, extra)),
+        Type::LongLongInt(meta) => Type::LongLongInt(qualified_integer(meta, extra)),
+        Type::Pointer(ptr) => Type::Pointer(PointerTypeMetadata {
+            qualifiers: ptr.qualifiers.union(&extra),
+            points_to: ptr.points_to
+        }),
+        Type::Struct(st) => Type::Struct(StructTypeMetadata {
+            qualifiers: st.qualifiers.union(&extra),
+            definition: st.definition
+        }),
+        Type::Array(arr) => Type::Array(ArrayTypeMetadata {
+            element: Box::new(qualified(*arr.element, extra)),
+            length: arr.length
+        }),
+        Type::VariableArray(arr) => Type::VariableArray(VariableArrayTypeMetadata {
+            element: Box::new(qualified(*arr.element, extra)),
+            length: arr.length
+        }),
+        Type::Function(_) => panic!("Function types can't have qualifiers")
+    }
+}
+
+fn qualified_integer (meta: IntegerTypeMetadata, extra: Qualifiers) -> IntegerTypeMetadata {
+    IntegerTypeMetadata {
+        signed: meta.signed,
+        qualifiers: meta.qualifiers.union(&extra)
+    }
+}
+
+// Pointers can gain qualifiers on what they point to when they're implicitly
+// converted, but not lose them. Eg. char* to const char* is fine, but
+// const char* to char* would allow writing to something const.
+pub fn check_qualifiers_kept (from: &Type, to: &Type) {
+    if let (Some(from_target), Type::Pointer(to_ptr)) = (pointed_to_type(from), to) {
+        let (from_qualifiers, to_qualifiers) = (qualifiers_of(&from_target), qualifiers_of(&to_ptr.points_to));
+        if !to_qualifiers.includes(&from_qualifiers) {
+            let lost = if from_qualifiers.is_const && !to_qualifiers.is_const { "const" } else { "volatile" };
+            panic!("Implicit pointer conversion discards the \"{}\" qualifier", lost)
+        }
+    }
+}
+
+// Arrays and functions used as values are really pointers
+// to the first element or the function
+pub fn decayed (the_type: Type) -> Type {
+    match the_type {
+        Type::Array(arr) => pointer_to(*arr.element),
+        Type::VariableArray(arr) => pointer_to(*arr.element),
+        Type::Function(_) => pointer_to(the_type),
+        other => other
+    }
+}
+
+// The function a callee expression calls, either directly or through a pointer
+pub fn called_function_type (the_type: &Type) -> Option<FunctionTypeMetadata> {
+    match the_type {
+        Type::Function(func) => Some(func.clone()),
+        Type::Pointer(ptr) => match &*ptr.points_to {
+            Type::Function(func) => Some(func.clone()),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+pub fn void_type () -> Type {
+    Type::Void(Qualifiers::default())
+}
+
+pub fn char_type () -> Type {
+    Type::Char(IntegerTypeMetadata { signed: true, qualifiers: Qualifiers::default() })
+}
+
+// char on its own, which is a separate type from signed char but
+// behaves like it or unsigned char depending on the target
+pub fn plain_char_type (signed: bool) -> Type {
+    Type::Char(IntegerTypeMetadata { signed, qualifiers: Qualifiers::default() })
+}
+
+pub fn int_type () -> Type {
+    Type::Int(IntegerTypeMetadata { signed: true, qualifiers: Qualifiers::default() })
+}
+
+pub fn long_type () -> Type {
+    Type::LongLongInt(IntegerTypeMetadata { signed: true, qualifiers: Qualifiers::default() })
+}
+
+// Literals in the AST are ints if they fit, and longs otherwise. The
+// parser casts constants that C gives another type, eg. 5u.
+pub fn integer_literal_type (value: isize) -> Type {
+    if (i32::MIN as isize..=i32::MAX as isize).contains(&value) { int_type() } else { long_type() }
+}
+
+// What sizeof gives, size_t (an unsigned long)
+pub fn size_type () -> Type {
+    Type::LongLongInt(IntegerTypeMetadata { signed: false, qualifiers: Qualifiers::default() })
+}
+
+// C's "usual arithmetic conversions", without floating point
+pub fn arithmetic_result_type (left: &Type, right: &Type) -> Type {
+    let rank = |t: &Type| match t {
+        Type::LongLongInt(_) | Type::Pointer(_) | Type::Array(_) | Type::VariableArray(_) => 2,
+        _ => 1
+    };
+    // char and short are promoted to int, which can represent all their values
+    let unsigned = |t: &Type| match t {
+        Type::Int(meta) | Type::LongLongInt(meta) => !meta.signed,
+        Type::Pointer(_) | Type::Array(_) | Type::VariableArray(_) => true,
+        _ => false
+    };
+
+    let signed = match rank(left).cmp(&rank(right)) {
+        std::cmp::Ordering::Greater => !unsigned(left),
+        std::cmp::Ordering::Less => !unsigned(right),
+        std::cmp::Ordering::Equal => !unsigned(left) && !unsigned(right)
+    };
+
+    let meta = IntegerTypeMetadata { signed, qualifiers: Qualifiers::default() };
+    if rank(left).max(rank(right)) == 2 {
+        Type::LongLongInt(meta)
+    } else {
+        Type::Int(meta)
+    }
+}
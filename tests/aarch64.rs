@@ -0,0 +1,11 @@
+// `--target aarch64-linux-gnu` output, assembled and linked with a cross
+// compiler and run under qemu's user-mode emulation, should do the same
+// as the interpreter. Needs aarch64-linux-gnu-gcc and qemu-aarch64, so
+// only runs when asked for, with `cargo test -- --ignored`.
+mod common;
+
+#[test]
+#[ignore = "needs aarch64-linux-gnu-gcc and qemu-aarch64"]
+fn programs_run_under_emulation () {
+    common::run_under_emulation("aarch64-linux-gnu", "qemu-aarch64");
+}
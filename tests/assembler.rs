@@ -0,0 +1,48 @@
+// Objects from ass's own assembler should behave exactly like ones the
+// system assembler makes from the same code. Needs a system C compiler
+// (cc) to assemble the reference and link both.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+use common::{have_tool, run};
+
+#[test]
+fn objects_match_the_system_assembler () {
+    if !cfg!(target_os = "linux") || !have_tool("cc") {
+        eprintln!("Skipping, this needs Linux and a cc to link with");
+        return
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("assembler");
+    fs::create_dir_all(&out_dir).unwrap();
+
+    // print_forever never exits
+    let examples = ["fizzbuzz", "recursive_fizzbuzz", "global_variables", "pointers_and_types", "strings", "print_alphabet", "dead_code"]
+        .iter().map(|name| root.join(format!("examples/{}.c", name)));
+    // Structs, variadic calls and whatever instructions inline asm uses
+    let programs = ["initialisers", "compound_literals_and_vlas", "varargs", "inline_asm"]
+        .iter().map(|name| root.join(format!("tests/programs/{}.c", name)));
+
+    for source in examples.chain(programs) {
+        let name = source.file_stem().unwrap().to_string_lossy().to_string();
+
+        let assembly = run(Command::new(env!("CARGO_BIN_EXE_ass")).arg(&source)).stdout;
+        let assembly_path = out_dir.join(format!("{}.s", name));
+        fs::write(&assembly_path, assembly).unwrap();
+        let reference = out_dir.join(format!("{}_reference", name));
+        run(Command::new("cc").arg(&assembly_path).arg("-o").arg(&reference));
+
+        let object = out_dir.join(format!("{}.o", name));
+        run(Command::new(env!("CARGO_BIN_EXE_ass")).arg("-c").arg(&source).arg("-o").arg(&object));
+        let program = out_dir.join(&name);
+        run(Command::new("cc").arg(&object).arg("-o").arg(&program));
+
+        let expected = Command::new(&reference).output().unwrap();
+        let actual = Command::new(&program).output().unwrap();
+        assert_eq!(actual.stdout, expected.stdout, "{} printed something different", name);
+        assert_eq!(actual.status.code(), expected.status.code(), "{} exited differently", name);
+    }
+}
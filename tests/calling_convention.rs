@@ -0,0 +1,56 @@
+// Calls follow the System V AMD64 calling convention, so that ass's code
+// can call and be called by code from another C compiler: arguments past
+// the sixth go on the stack, small structs are passed and returned in
+// registers, and big ones in memory. Needs a system C compiler (cc) to
+// link with.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+use common::run;
+
+fn sources () -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/calling_convention")
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn calls_pass_arguments_and_return_values () {
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("calling_convention");
+    fs::create_dir_all(&out_dir).unwrap();
+    let program = out_dir.join("calls");
+
+    run(Command::new(env!("CARGO_BIN_EXE_ass")).arg(sources().join("calls.c")).arg("-o").arg(&program));
+
+    let output = run(&mut Command::new(&program));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "\
+        7 321 15 104 1\n\
+        355\n\
+        21 42 30 31 32 1 5 1032\n\
+        69 70 74 87 127 248 612 1705 4985 14826 44350 132923 \n\
+        70108 739\n\
+        1 two 3 x 5 6 7 8 9 10\n");
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn ass_and_cc_functions_call_each_other () {
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("calling_convention");
+    fs::create_dir_all(&out_dir).unwrap();
+
+    let assembly = run(Command::new(env!("CARGO_BIN_EXE_ass")).arg(sources().join("ass_side.c"))).stdout;
+    let assembly_path = out_dir.join("ass_side.s");
+    fs::write(&assembly_path, assembly).unwrap();
+
+    let program = out_dir.join("mixed");
+    run(Command::new("cc")
+        .arg(&assembly_path)
+        .arg(sources().join("cc_side.c"))
+        .arg("-o").arg(&program));
+
+    // Each side calls the other in the same way, so prints the same
+    let output = run(&mut Command::new(&program));
+    let expected = "285 1074351\n11 22 20 22 -4\n";
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected.repeat(2));
+}
@@ -0,0 +1,81 @@
+// Calls that exercise the corners of the calling conventions: structs in
+// registers, on the stack, by reference and split between the two, struct
+// returns, and variadic arguments after all of those
+int printf(char *fmt, ...);
+int vprintf(char *fmt, va_list ap);
+struct s1 { char a; };
+struct s3 { char a; char b; char c; };
+struct s12 { int a; int b; int c; };
+struct s16 { long a; long b; };
+struct s24 { long a; long b; long c; };
+struct s5 { char a[5]; };
+
+long take1 (struct s1 x) { return x.a; }
+long take3 (struct s3 x) { return x.a + x.b * 10 + x.c * 100; }
+long take12 (struct s12 x) { return x.a + x.b + x.c; }
+long take24 (struct s24 x) { x.a = 99; return x.a + x.b + x.c; }
+long many (long a, long b, long c, long d, long e, long f, long g, struct s16 h, long i, struct s24 j, struct s12 k, long l) {
+    return a + b + c + d + e + f + g + h.a * 2 + h.b * 3 + i * 5 + j.a + j.b + j.c * 7 + k.c * 11 + l * 13;
+}
+struct s16 make16 (long a) { struct s16 r; r.a = a; r.b = a * 2; return r; }
+struct s24 make24 (long a) { struct s24 r; r.a = a; r.b = a + 1; r.c = a + 2; return r; }
+struct s5 make5 () { struct s5 r; r.a[0] = 1; r.a[4] = 5; return r; }
+struct s24 pass24 (long pad, struct s24 x) { x.c = x.c + pad; return x; }
+
+long sum (int n, ...) {
+    va_list ap;
+    va_start(ap, n);
+    long total = 0;
+    for (int i = 0; i < n; i = i + 1) total = total * 3 + va_arg(ap, long);
+    struct s24 big = va_arg(ap, struct s24);
+    struct s12 mid = va_arg(ap, struct s12);
+    va_end(ap);
+    return total + big.a + big.b * 2 + big.c * 4 + mid.a + mid.c * 8;
+}
+long after_split (long a, long b, long c, long d, long e, long f, long g, struct s16 h, ...) {
+    va_list ap;
+    va_start(ap, h);
+    long x = va_arg(ap, long);
+    struct s12 y = va_arg(ap, struct s12);
+    va_end(ap);
+    return a + g + h.a * 10 + h.b * 100 + x * 1000 + y.c * 10000;
+}
+long split_last (long a, long b, long c, long d, long e, long f, long g, struct s12 h, long i) {
+    return h.a + h.b * 2 + h.c * 3 + i * 7 + g;
+}
+void say (char *fmt, ...) {
+    va_list ap;
+    va_start(ap, fmt);
+    vprintf(fmt, ap);
+    va_end(ap);
+}
+int main () {
+    struct s1 a; a.a = 7;
+    struct s3 b; b.a = 1; b.b = 2; b.c = 3;
+    struct s12 c; c.a = 4; c.b = 5; c.c = 6;
+    struct s16 d; d.a = 10; d.b = 20;
+    struct s24 e; e.a = 1; e.b = 2; e.c = 3;
+    printf("%ld %ld %ld %ld %ld\n", take1(a), take3(b), take12(c), take24(e), e.a);
+    printf("%ld\n", many(1, 2, 3, 4, 5, 6, 7, d, 8, e, c, 9));
+    struct s16 m = make16(21);
+    struct s24 n = make24(30);
+    struct s5 o = make5();
+    struct s24 p = pass24(1000, n);
+    printf("%ld %ld %ld %ld %ld %d %d %ld\n", m.a, m.b, n.a, n.b, n.c, o.a[0], o.a[4], p.c);
+    printf("%ld ", sum(0, e, c));
+    printf("%ld ", sum(1, (long)1, e, c));
+    printf("%ld ", sum(2, (long)1, (long)2, e, c));
+    printf("%ld ", sum(3, (long)1, (long)2, (long)3, e, c));
+    printf("%ld ", sum(4, (long)1, (long)2, (long)3, (long)4, e, c));
+    printf("%ld ", sum(5, (long)1, (long)2, (long)3, (long)4, (long)5, e, c));
+    printf("%ld ", sum(6, (long)1, (long)2, (long)3, (long)4, (long)5, (long)6, e, c));
+    printf("%ld ", sum(7, (long)1, (long)2, (long)3, (long)4, (long)5, (long)6, (long)7, e, c));
+    printf("%ld ", sum(8, (long)1, (long)2, (long)3, (long)4, (long)5, (long)6, (long)7, (long)8, e, c));
+    printf("%ld ", sum(9, (long)1, (long)2, (long)3, (long)4, (long)5, (long)6, (long)7, (long)8, (long)9, e, c));
+    printf("%ld ", sum(10, (long)1, (long)2, (long)3, (long)4, (long)5, (long)6, (long)7, (long)8, (long)9, (long)10, e, c));
+    printf("%ld ", sum(11, (long)1, (long)2, (long)3, (long)4, (long)5, (long)6, (long)7, (long)8, (long)9, (long)10, (long)11, e, c));
+    printf("\n");
+    printf("%ld %ld\n", after_split(1, 2, 3, 4, 5, 6, 7, d, (long)8, c), split_last(1, 2, 3, 4, 5, 6, 7, c, (long)100));
+    say("%d %s %ld %c %d %d %d %d %d %d\n", 1, "two", (long)3, 'x', 5, 6, 7, 8, 9, 10);
+    return take12(c) - 15 + make24(3).c - 5;
+}
@@ -0,0 +1,47 @@
+// Compiled by the system C compiler, linked with ass_side.c compiled by ass
+#include <stdio.h>
+
+struct small { char a; int b; };
+struct pair { long a; long b; };
+struct mixed { int a; int b; long c; };
+struct big { long a; long b; long c; };
+
+// Defined in ass_side.c
+long ass_many(long a, long b, long c, long d, long e, long f, long g, long h, long i);
+long ass_structs(struct small s, struct pair p, struct big b, struct mixed m, int last);
+struct pair ass_make_pair(long a);
+struct big ass_make_big(long a);
+short ass_narrow(signed char c, unsigned short s);
+
+long cc_many(long a, long b, long c, long d, long e, long f, long g, long h, long i) {
+    return a + b * 2 + c * 3 + d * 4 + e * 5 + f * 6 + g * 7 + h * 8 + i * 9;
+}
+
+long cc_structs(struct small s, struct pair p, struct big b, struct mixed m, int last) {
+    return s.a + s.b * 10 + p.a * 100 + p.b * 1000 + b.a + b.b + b.c * 10000 + m.a + m.c * 100000 + last;
+}
+
+struct pair cc_make_pair(long a) {
+    struct pair r = {a, a * 2};
+    return r;
+}
+
+struct big cc_make_big(long a) {
+    struct big r = {a, a + 1, a + 2};
+    return r;
+}
+
+signed char cc_narrow(signed char c, unsigned short s) {
+    return c + s;
+}
+
+void cc_call_ass(void) {
+    struct small s = {1, 2};
+    struct pair p = {3, 4};
+    struct big b = {5, 6, 7};
+    struct mixed m = {8, 9, 10};
+    struct pair made = ass_make_pair(11);
+    struct big big = ass_make_big(20);
+    printf("%ld %ld\n", ass_many(1, 2, 3, 4, 5, 6, 7, 8, 9), ass_structs(s, p, b, m, 11));
+    printf("%ld %ld %ld %ld %d\n", made.a, made.b, big.a, big.c, ass_narrow(-3, 65535));
+}
@@ -0,0 +1,74 @@
+// Helpers shared by the tests that build and run ass's output with the
+// system's tools. Each test file only uses some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+pub fn have_tool (name: &str) -> bool {
+    Command::new(name).arg("--version").output().is_ok_and(|out| out.status.success())
+}
+
+pub fn run (command: &mut Command) -> Output {
+    let output = command.output().expect("Failed to run command");
+    if !output.status.success() {
+        panic!("{:?} failed:\n{}", command, String::from_utf8_lossy(&output.stderr))
+    }
+    output
+}
+
+// tests/programs that aren't run under emulation
+const NOT_EMULATED: [&str; 9] = [
+    // x86-64 only
+    "inline_asm", "freestanding",
+    // Meant to be rejected
+    "const_assignment", "const_discarded", "missing_return", "vla_inner_length", "vla_pointer",
+    // Calls C library functions the interpreter doesn't have
+    "return_types",
+    // Takes too long to interpret
+    "compound_literals_and_vlas"
+];
+
+// Compiles the examples and test programs for `target` (eg.
+// aarch64-linux-gnu), links them with <target>-gcc, runs them with `qemu`
+// and checks they do the same as the interpreter
+pub fn run_under_emulation (target: &str, qemu: &str) {
+    let gcc = format!("{}-gcc", target);
+    if !have_tool(&gcc) || !have_tool(qemu) {
+        panic!("This needs {} and {} installed", gcc, qemu)
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(target);
+    fs::create_dir_all(&out_dir).unwrap();
+
+    // print_forever never exits
+    let mut sources: Vec<PathBuf> = ["fizzbuzz", "recursive_fizzbuzz", "global_variables", "pointers_and_types", "strings", "print_alphabet", "dead_code"]
+        .iter().map(|name| root.join(format!("examples/{}.c", name))).collect();
+    sources.push(root.join("tests/calling_convention/calls.c"));
+    sources.push(root.join("tests/interpreter/features.c"));
+    sources.push(root.join("tests/interpreter/plain_char.c"));
+    let mut programs: Vec<PathBuf> = fs::read_dir(root.join("tests/programs")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "c"))
+        .filter(|path| !NOT_EMULATED.iter().any(|name| path.file_stem().unwrap() == *name))
+        .collect();
+    programs.sort();
+    sources.extend(programs);
+
+    for source in sources {
+        let name = source.file_stem().unwrap().to_string_lossy().to_string();
+        let assembly = run(Command::new(env!("CARGO_BIN_EXE_ass")).arg("--target").arg(target).arg(&source)).stdout;
+        let assembly_path = out_dir.join(format!("{}.s", name));
+        fs::write(&assembly_path, assembly).unwrap();
+        let program = out_dir.join(&name);
+        run(Command::new(&gcc).arg("-static").arg(&assembly_path).arg("-o").arg(&program));
+
+        // Told the target, so its chars are signed or not like the compiled program's
+        let expected = Command::new(env!("CARGO_BIN_EXE_ass")).arg("--target").arg(target).arg("--interpret").arg(&source).arg("abc").output().unwrap();
+        let actual = Command::new(qemu).arg(&program).arg("abc").output().unwrap();
+        assert_eq!(actual.stdout, expected.stdout, "{} printed something different", name);
+        assert_eq!(actual.status.code(), expected.status.code(), "{} exited differently", name);
+    }
+}
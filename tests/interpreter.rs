@@ -0,0 +1,74 @@
+// `ass --interpret` runs a program by walking its AST, with simulated
+// memory, so it works anywhere and never generates code. Its results
+// should match what the compiled program does.
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn interpret (source: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ass"))
+        .arg("--interpret").arg(source).args(args)
+        .output().expect("Failed to run ass")
+}
+
+#[test]
+fn programs_are_interpreted () {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/interpreter/features.c");
+    let output = interpret(&source, &["abc"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "8,12\n321\n120 6 one many\n[  -42] [ff  ] [00007] [z] [ab]\n0 jello 24\n"
+    );
+    assert_eq!(output.status.code(), Some(12));
+}
+
+#[test]
+fn bad_memory_accesses_are_caught () {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/interpreter/null_pointer.c");
+    let output = interpret(&source, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Segmentation fault: read of 4 bytes at 0x0"), "{}", stderr);
+}
+
+#[test]
+fn plain_char_is_signed_like_on_the_target () {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/interpreter/plain_char.c");
+    for (target, expected) in [("x86_64-linux-gnu", "-56 -56 200 1\n"), ("aarch64-linux-gnu", "200 -56 200 0\n"), ("riscv64-linux-gnu", "200 -56 200 0\n")] {
+        let output = Command::new(env!("CARGO_BIN_EXE_ass"))
+            .arg("--target").arg(target).arg("--interpret").arg(&source)
+            .output().expect("Failed to run ass");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "for {}", target);
+    }
+}
+
+// The interpreter is an oracle for the code generator
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn programs_behave_like_compiled_ones () {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut sources = vec![root.join("tests/interpreter/features.c")];
+    for entry in fs::read_dir(root.join("examples")).unwrap() {
+        let path = entry.unwrap().path();
+        // print_forever never finishes
+        if path.extension().is_some_and(|extension| extension == "c") && !path.ends_with("print_forever.c") {
+            sources.push(path);
+        }
+    }
+
+    let work_dir = std::env::temp_dir().join(format!("ass-interpreter-test-{}", std::process::id()));
+    fs::create_dir_all(&work_dir).unwrap();
+    for source in &sources {
+        let executable = work_dir.join(source.file_stem().unwrap());
+        let build = Command::new(env!("CARGO_BIN_EXE_ass"))
+            .arg(source).arg("-o").arg(&executable)
+            .output().expect("Failed to run ass");
+        assert!(build.status.success(), "{}", String::from_utf8_lossy(&build.stderr));
+
+        let compiled = Command::new(&executable).arg("abc").output().expect("Failed to run the compiled program");
+        let interpreted = interpret(source, &["abc"]);
+        assert_eq!(compiled.stdout, interpreted.stdout, "{:?} printed something different", source);
+        assert_eq!(compiled.status.code(), interpreted.status.code(), "{:?} exited differently", source);
+    }
+    fs::remove_dir_all(&work_dir).unwrap();
+}
@@ -0,0 +1,79 @@
+// Exercises the parts of C the interpreter has to model memory for
+
+void *malloc(unsigned long size);
+int printf(const char *format, ...);
+int putchar(int c);
+
+struct point {
+    int x;
+    int y;
+};
+
+struct list {
+    int value;
+    struct list *next;
+};
+
+struct point scaled (struct point p, int factor) {
+    struct point result = { p.x * factor, p.y * factor };
+    return result;
+}
+
+int factorial (int n) {
+    if (n <= 1) return 1;
+    return n * factorial(n - 1);
+}
+
+int sum (int count, ...) {
+    va_list args;
+    va_start(args, count);
+    int total = 0;
+    for (int i = 0; i < count; i = i + 1) {
+        total = total + va_arg(args, int);
+    }
+    va_end(args);
+    return total;
+}
+
+const char *describe (int n) {
+    switch (n) {
+        case 0: return "none";
+        case 1: return "one";
+    }
+    return "many";
+}
+
+int apply (int (*f)(int), int n) {
+    return f(n);
+}
+
+int counter = 10;
+
+int main (int argc, char **argv) {
+    struct point p = scaled((struct point){ 2, 3 }, 4);
+    printf("%d,%d\n", p.x, p.y);
+
+    struct list *head = 0;
+    for (int i = 1; i <= 3; i = i + 1) {
+        struct list *node = malloc(sizeof(struct list));
+        node->value = i;
+        node->next = head;
+        head = node;
+    }
+    for (struct list *node = head; node; node = node->next) {
+        putchar('0' + node->value);
+    }
+    putchar(10);
+
+    printf("%d %d %s %s\n", factorial(5), sum(3, 1, 2, 3), describe(1), describe(7));
+    printf("[%5d] [%-4x] [%05u] [%c] [%.2s]\n", -42, 255, 7, 'z', argv[1]);
+
+    unsigned char small = 255;
+    small = small + 1;
+    char word[] = "hello";
+    word[0] = 'j';
+    printf("%d %s %d\n", small, word, apply(factorial, 4));
+
+    counter = counter + argc;
+    return counter;
+}
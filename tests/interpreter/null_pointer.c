@@ -0,0 +1,4 @@
+int main () {
+    int *pointer = 0;
+    return *pointer;
+}
@@ -0,0 +1,10 @@
+// Whether char on its own is signed depends on the target
+int printf(char *fmt, ...);
+
+int main () {
+    char c = 200;
+    signed char s = 200;
+    unsigned char u = 200;
+    printf("%d %d %d %d\n", c, s, u, c < 0);
+    return 0;
+}
@@ -0,0 +1,36 @@
+// `ass --run` compiles a program into memory and runs it inside ass's own
+// process, calling into the C library ass itself uses. Only x86-64 Linux
+// can do this.
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn run (source: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ass"))
+        .arg("--run").arg(source).args(args)
+        .output().expect("Failed to run ass")
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn programs_get_their_arguments_and_exit_code () {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/jit/args.c");
+    // Anything after the file belongs to the program, even if it looks like an option
+    let output = run(&source, &["one", "-two", "three four"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello one\nhello -two\nhello three four\n");
+    assert_eq!(output.status.code(), Some(43));
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn examples_run_like_compiled_ones () {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let fizzbuzz = run(&examples.join("fizzbuzz.c"), &[]);
+    assert!(fizzbuzz.status.success());
+    let stdout = String::from_utf8_lossy(&fizzbuzz.stdout);
+    assert!(stdout.starts_with("1\n2\nFizz\n4\nBuzz\n"), "{}", stdout);
+    assert!(stdout.contains("\nFizzBuzz\n"), "{}", stdout);
+
+    // Uses globals, and exits with a value it computes
+    let globals = run(&examples.join("global_variables.c"), &[]);
+    assert_eq!(globals.status.code(), Some(4));
+}
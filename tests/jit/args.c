@@ -0,0 +1,14 @@
+int printf();
+
+int counter;
+char *greeting = "hello";
+
+int main(int argc, char **argv) {
+    int i = 1;
+    while (i < argc) {
+        counter = counter + 1;
+        printf("%s %s\n", greeting, argv[i]);
+        i = i + 1;
+    }
+    return counter + 40;
+}
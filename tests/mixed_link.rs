@@ -0,0 +1,35 @@
+// Globals and functions defined by ass can be used from objects compiled by
+// another C compiler, and the other way around. Needs a system C compiler
+// (cc) to assemble, compile the other half and link.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+use common::{have_tool, run};
+
+#[test]
+fn ass_and_cc_objects_share_globals () {
+    if !have_tool("cc") {
+        eprintln!("Skipping, there's no cc to link with");
+        return
+    }
+
+    let sources = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/mixed_link");
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mixed_link");
+    fs::create_dir_all(&out_dir).unwrap();
+
+    let assembly = run(Command::new(env!("CARGO_BIN_EXE_ass")).arg(sources.join("ass_side.c"))).stdout;
+    let assembly_path = out_dir.join("ass_side.s");
+    fs::write(&assembly_path, assembly).unwrap();
+
+    let program = out_dir.join("program");
+    run(Command::new("cc")
+        .arg(&assembly_path)
+        .arg(sources.join("gcc_side.c"))
+        .arg("-o").arg(&program));
+
+    let output = run(&mut Command::new(&program));
+    let expected = fs::read_to_string(sources.join("expected.txt")).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
@@ -0,0 +1,39 @@
+// Compiled by ass, linked with gcc_side.c compiled by the system C compiler
+int printf(const char *fmt, ...);
+
+struct pair {
+    int first;
+    long second;
+};
+
+// Defined here, used by gcc_side.c
+int ass_counter = 5;
+char ass_name[] = "ass";
+int ass_table[4] = {1, 2, 3, 4};
+struct pair ass_pair = {7, 8};
+long ass_common;
+int *ass_pointer = &ass_table[2];
+
+// Defined in gcc_side.c
+extern int gcc_value;
+extern const char *gcc_message;
+extern int gcc_array[3];
+extern struct pair gcc_pair;
+int gcc_sum_ass_table(void);
+void gcc_bump_ass_globals(void);
+
+int main(void) {
+    printf("%d %s %d %d %d\n", gcc_value, gcc_message, gcc_array[0], gcc_array[1], gcc_array[2]);
+    printf("%d %ld\n", gcc_pair.first, gcc_pair.second);
+
+    gcc_value = gcc_value + 1;
+    gcc_array[2] = 30;
+    gcc_pair.second = 99;
+    int *p = &gcc_value;
+    *p = *p * 2;
+
+    printf("%d\n", gcc_sum_ass_table());
+    gcc_bump_ass_globals();
+    printf("%d %s %ld %d %ld %d\n", ass_counter, ass_name, ass_common, ass_pair.first, ass_pair.second, *ass_pointer);
+    return 0;
+}
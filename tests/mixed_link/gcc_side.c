@@ -0,0 +1,35 @@
+// Compiled by the system C compiler, linked with ass_side.c compiled by ass
+#include <stdio.h>
+
+struct pair {
+    int first;
+    long second;
+};
+
+// Defined here, used by ass_side.c
+int gcc_value = 42;
+const char *gcc_message = "from gcc";
+int gcc_array[3] = {10, 20};
+struct pair gcc_pair = {3, 4};
+
+// Defined in ass_side.c
+extern int ass_counter;
+extern char ass_name[];
+extern int ass_table[4];
+extern struct pair ass_pair;
+extern long ass_common;
+extern int *ass_pointer;
+
+int gcc_sum_ass_table(void) {
+    return ass_table[0] + ass_table[1] + ass_table[2] + ass_table[3];
+}
+
+void gcc_bump_ass_globals(void) {
+    // Sees what ass_side.c changed in gcc's globals
+    printf("%d %d %ld\n", gcc_value, gcc_array[2], gcc_pair.second);
+    ass_counter = ass_counter + 1;
+    ass_name[0] = 'A';
+    ass_common = 123;
+    ass_pair.second = ass_pair.second * 10;
+    *ass_pointer = 33;
+}
@@ -0,0 +1,47 @@
+// Several C files can be compiled separately and linked into one program,
+// sharing what isn't static. Needs a system C compiler (cc) to assemble
+// and link with.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+use common::{have_tool, run};
+
+fn sources () -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/multiple_files")
+}
+
+#[test]
+fn files_are_compiled_separately_and_linked () {
+    if !have_tool("cc") {
+        eprintln!("Skipping, there's no cc to link with");
+        return
+    }
+
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("multiple_files");
+    fs::create_dir_all(&out_dir).unwrap();
+    let program = out_dir.join("program");
+
+    run(Command::new(env!("CARGO_BIN_EXE_ass"))
+        .arg(sources().join("main.c"))
+        .arg(sources().join("counter.c"))
+        .arg("-o").arg(&program));
+
+    let output = run(&mut Command::new(&program));
+    let expected = fs::read_to_string(sources().join("expected.txt")).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn definitions_in_two_files_are_reported () {
+    let output = Command::new(env!("CARGO_BIN_EXE_ass"))
+        .arg(sources().join("counter.c"))
+        .arg(sources().join("duplicate.c"))
+        .arg("-o").arg(PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("duplicate"))
+        .output().unwrap();
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Multiple definitions of \"add\""), "{}", stderr);
+}
@@ -0,0 +1,24 @@
+int total = 30;
+int shared;
+
+static int calls;
+
+// Another static helper, invisible to main.c
+static int helper() {
+    calls = calls + 1;
+    return calls;
+}
+
+int add(int amount) {
+    helper();
+    total = total + amount;
+    return total;
+}
+
+int count_calls() {
+    return calls;
+}
+
+int shared_from_counter() {
+    return shared;
+}
@@ -0,0 +1,6 @@
+// Defines what counter.c does, so they can't be linked together
+int total = 1;
+
+int add(int amount) {
+    return amount;
+}
@@ -0,0 +1,26 @@
+int printf();
+
+// Defined in counter.c
+extern int total;
+int add(int amount);
+int count_calls();
+
+// Both files have one of these, and they're separate variables
+static int calls = 100;
+
+// A tentative definition in both files, which the linker merges
+int shared;
+
+static int helper() {
+    calls = calls + 1;
+    return calls;
+}
+
+int main() {
+    add(5);
+    add(7);
+    shared = 9;
+    printf("%d %d %d\n", total, count_calls(), helper());
+    printf("%d\n", shared_from_counter());
+    return 0;
+}
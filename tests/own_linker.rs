@@ -0,0 +1,77 @@
+// ass can link its own objects into a static executable, without the
+// system linker, using its bundled runtime in place of libc. The result
+// only runs on x86-64 Linux.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn sources () -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/own_linker")
+}
+
+fn out_dir () -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("own_linker");
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn ass () -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ass"));
+    command.arg("-fuse-ld=ass");
+    command
+}
+
+fn check_program (program: &Path) {
+    let output = Command::new(program).output().unwrap();
+    let expected = fs::read_to_string(sources().join("expected.txt")).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn c_files_are_linked_with_the_runtime () {
+    let program = out_dir().join("from_c");
+    let status = ass()
+        .arg(sources().join("main.c"))
+        .arg(sources().join("digits.c"))
+        .arg("-o").arg(&program)
+        .status().unwrap();
+    assert!(status.success());
+    check_program(&program);
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn objects_can_be_linked () {
+    let object = out_dir().join("digits.o");
+    let status = Command::new(env!("CARGO_BIN_EXE_ass"))
+        .arg("-c").arg(sources().join("digits.c"))
+        .arg("-o").arg(&object)
+        .status().unwrap();
+    assert!(status.success());
+
+    let program = out_dir().join("from_object");
+    let status = ass()
+        .arg(sources().join("main.c"))
+        .arg(&object)
+        .arg("-o").arg(&program)
+        .status().unwrap();
+    assert!(status.success());
+    check_program(&program);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn undefined_functions_are_reported () {
+    // printf isn't in the runtime
+    let output = ass()
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/multiple_files/main.c"))
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/multiple_files/counter.c"))
+        .arg("-o").arg(out_dir().join("undefined"))
+        .output().unwrap();
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Undefined references to printf"), "{}", stderr);
+}
@@ -0,0 +1,25 @@
+int putchar(int c);
+
+int printed = 0;
+int shared;
+
+static long digits[20];
+
+int print_number(long n) {
+    int count = 0;
+    if (n == 0) {
+        digits[0] = 0;
+        count = 1;
+    }
+    while (n > 0) {
+        digits[count] = n % 10;
+        n = n / 10;
+        count = count + 1;
+    }
+    while (count > 0) {
+        count = count - 1;
+        putchar(48 + digits[count]);
+        printed = printed + 1;
+    }
+    return shared;
+}
@@ -0,0 +1,23 @@
+int puts(const char *s);
+int putchar(int c);
+void exit(int status);
+
+// Defined in digits.c
+extern int printed;
+int print_number(long n);
+
+// Merged with the one in digits.c
+int shared;
+
+char *greeting = "linked by ass";
+
+int main() {
+    puts(greeting);
+    shared = 40;
+    print_number(shared + 2);
+    putchar(10);
+    print_number(printed);
+    putchar(10);
+    exit(3);
+    return 0;
+}
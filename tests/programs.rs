@@ -0,0 +1,232 @@
+// Small programs that each lean on one part of the language, compiled by
+// ass and run, checking what they print. ass writes assembly for the OS
+// it runs on, which is assembled and linked by the system C compiler (cc),
+// so these only run on x86-64 macOS or Linux.
+#![cfg(all(any(target_os = "macos", target_os = "linux"), target_arch = "x86_64"))]
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn source (name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/programs/{}.c", name))
+}
+
+fn out_dir () -> PathBuf {
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("programs");
+    fs::create_dir_all(&out_dir).unwrap();
+    out_dir
+}
+
+fn compile (name: &str, flags: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ass")).args(flags).arg(source(name)).output().expect("Failed to run ass")
+}
+
+// The assembly ass writes for tests/programs/<name>.c
+fn assembly (name: &str, flags: &[&str]) -> String {
+    let output = compile(name, flags);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+// Compiles a program that shouldn't, returning what ass said about it
+fn compile_error (name: &str, flags: &[&str]) -> String {
+    let output = compile(name, flags);
+    assert!(!output.status.success(), "{} compiled", name);
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+// Whether a name in the assembly is the C symbol's, which may have
+// a prefix
+fn is_symbol (name: &str, symbol: &str) -> bool {
+    name == symbol || name == format!("_{}", symbol) || name == format!("_GLOBAL_VAR_{}", symbol)
+}
+
+// The section the assembly puts a symbol's definition in
+fn section_of (assembly: &str, symbol: &str) -> String {
+    let mut section = ".text";
+    for line in assembly.lines() {
+        if line.starts_with(".section ") || [".text", ".data", ".bss", ".const"].contains(&line) {
+            section = line;
+        }
+        if line.strip_suffix(':').is_some_and(|name| is_symbol(name, symbol)) {
+            return section.to_string()
+        }
+    }
+    panic!("{} isn't defined in:\n{}", symbol, assembly)
+}
+
+// Whether the assembly makes a symbol visible to other files
+fn exports (assembly: &str, symbol: &str) -> bool {
+    assembly.lines()
+        .filter_map(|line| line.strip_prefix(".globl "))
+        .any(|name| is_symbol(name, symbol))
+}
+
+// Builds tests/programs/<name>.c and runs it, returning what it printed
+// and its exit code
+fn compile_and_run (name: &str) -> (String, Option<i32>) {
+    compile_and_run_with(name, &[], &[])
+}
+
+// The same, passing flags to ass and to cc when linking
+fn compile_and_run_with (name: &str, flags: &[&str], link_flags: &[&str]) -> (String, Option<i32>) {
+    let assembly_path = out_dir().join(format!("{}.s", name));
+    fs::write(&assembly_path, assembly(name, flags)).unwrap();
+
+    let program = out_dir().join(name);
+    let link = Command::new("cc").args(link_flags).arg(&assembly_path).arg("-o").arg(&program).output().expect("Failed to run cc");
+    assert!(link.status.success(), "{}", String::from_utf8_lossy(&link.stderr));
+
+    let output = Command::new(&program).output().expect("Failed to run the compiled program");
+    (String::from_utf8_lossy(&output.stdout).to_string(), output.status.code())
+}
+
+#[test]
+fn variadic_functions () {
+    let (stdout, code) = compile_and_run("varargs");
+    assert_eq!(stdout, "[info] 1 + 2 = 3\n[warn] nothing is ok\n13\n6 55\n30\ntwo\n");
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn function_pointers () {
+    let (stdout, _) = compile_and_run("function_pointers");
+    assert_eq!(stdout, "1 3 5 7 9 \n6 + 3 = 9\n6 - 3 = 3\n6 * 3 = 18\n20 6 6\n15\n");
+}
+
+#[test]
+fn declarators () {
+    let (stdout, _) = compile_and_run("declarators");
+    assert_eq!(stdout, "7 1 8\n4 6 2\n1 6\n6 1\n1 4\n5 -1\n");
+}
+
+#[test]
+fn typedefs () {
+    let (stdout, _) = compile_and_run("typedefs");
+    assert_eq!(stdout, "4\n7 30 3\n24\n65\n4\n");
+}
+
+#[test]
+fn enums_and_switch () {
+    let (stdout, _) = compile_and_run("enums_and_switch");
+    assert_eq!(stdout, "0 5 6 12 20\nblue green other\nd1 1 43 3 \n48\n");
+}
+
+#[test]
+fn storage_classes () {
+    let (stdout, _) = compile_and_run("storage_classes");
+    assert_eq!(stdout, "3 0 0 42\n11 13\n101 102 103\n2\n43\n");
+
+    // Static things stay private to the file
+    let assembly = assembly("storage_classes", &[]);
+    assert!(exports(&assembly, "next_id") && exports(&assembly, "later"), "{}", assembly);
+    assert!(!exports(&assembly, "bump") && !exports(&assembly, "hidden"), "{}", assembly);
+}
+
+#[test]
+fn qualifiers () {
+    let (stdout, _) = compile_and_run("qualifiers");
+    assert_eq!(stdout, "6 1 6 9 - 5 bc\n");
+
+    let assembly = assembly("qualifiers", &[]);
+    let read_only = if cfg!(target_os = "macos") { ".const" } else { ".section .rodata" };
+    assert_eq!(section_of(&assembly, "limit"), read_only);
+    let relocated = if cfg!(target_os = "macos") { ".const_data" } else { ".section .data.rel.ro,\"aw\"" };
+    assert_eq!(section_of(&assembly, "names"), relocated);
+    // Both reads of ticks are kept, though nothing uses them
+    let touch_ticks = &assembly[assembly.find("touch_ticks:").unwrap()..];
+    let touch_ticks = &touch_ticks[..touch_ticks.find("ret").unwrap()];
+    assert_eq!(touch_ticks.matches("ticks(%rip)").count(), 2, "{}", touch_ticks);
+}
+
+#[test]
+fn const_objects_cant_be_changed () {
+    let stderr = compile_error("const_assignment", &[]);
+    assert!(stderr.contains("Assignment to a read-only (const) location"), "{}", stderr);
+    let stderr = compile_error("const_discarded", &[]);
+    assert!(stderr.contains("Implicit pointer conversion discards the \"const\" qualifier"), "{}", stderr);
+}
+
+#[test]
+fn casts_and_sizeof () {
+    let (stdout, _) = compile_and_run("casts_and_sizeof");
+    assert_eq!(stdout, "\
+        44 44 4464\n\
+        -2 4294967294 4294967295\n\
+        4294967295 -56\n\
+        5 4\n\
+        4 0\n\
+        24 24 48 1\n\
+        2 8 8 1 8\n");
+}
+
+#[test]
+fn integer_constants () {
+    let (stdout, _) = compile_and_run("integer_constants");
+    assert_eq!(stdout, "\
+        3000000000 3000000001\n\
+        1\n\
+        4 4294967295 2147483647 8\n\
+        8 2147483648 18446744073709551615\n\
+        8 0\n");
+}
+
+#[test]
+fn initialisers () {
+    let (stdout, _) = compile_and_run("initialisers");
+    assert_eq!(stdout, "\
+        20 0 30 20\n\
+        hello word 8 98 0\n\
+        0 0\n\
+        box 4 3 1 0 3\n\
+        box two 5 3\n\
+        one 2 0 0 5\n");
+}
+
+#[test]
+fn compound_literals_and_variable_length_arrays () {
+    let (stdout, _) = compile_and_run("compound_literals_and_vlas");
+    assert_eq!(stdout, "7 20 25\n5 2\n0-10 3-7 \n23 10 48 16\n6656750000000\n");
+}
+
+#[test]
+fn only_outer_array_lengths_can_be_variable () {
+    let stderr = compile_error("vla_inner_length", &[]);
+    assert!(stderr.contains("Only the outermost length of a variable length array can be variable"), "{}", stderr);
+    let stderr = compile_error("vla_pointer", &[]);
+    assert!(stderr.contains("Pointers to variable length arrays, eg. int (*p)[n], aren't supported"), "{}", stderr);
+}
+
+#[test]
+fn inline_asm () {
+    let (stdout, _) = compile_and_run("inline_asm");
+    assert_eq!(stdout, "42\n30\n15 52\n1 14\n");
+
+    // The templates are x86-64 assembly
+    let stderr = compile_error("inline_asm", &["--target", "aarch64-linux-gnu"]);
+    assert!(stderr.contains("Inline assembly is only supported when targeting x86-64"), "{}", stderr);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn freestanding_programs () {
+    let (stdout, code) = compile_and_run_with("freestanding", &["-ffreestanding"], &["-nostdlib", "-static"]);
+    assert_eq!(stdout, "no libc here\ngot a pid\nhas a name\n");
+    // main's return value is the exit code
+    assert_eq!(code, Some(14));
+}
+
+#[test]
+fn void_functions_and_pointers () {
+    let (stdout, _) = compile_and_run("void");
+    assert_eq!(stdout, "15\n0\n1234567890123 -7\n1\n");
+
+    let stderr = compile_error("missing_return", &[]);
+    assert!(stderr.contains("Function \"sign\" can reach its end without returning a value"), "{}", stderr);
+}
+
+#[test]
+fn return_type_conversions () {
+    let (stdout, _) = compile_and_run("return_types");
+    assert_eq!(stdout, "44 -56 255\n-31072 -5 4294967295\n5000000000\nkept\n");
+}
@@ -0,0 +1,34 @@
+// Conversions between integer types and pointers, and sizeof
+int printf(const char *fmt, ...);
+
+struct record { char tag; long value; int count; };
+
+// sizeof is a constant, so it can size a global array
+char buffer[sizeof(struct record) * 2];
+
+int main () {
+    int big = 300;
+    long negative = -2;
+    unsigned int all_ones = (unsigned int)-1;
+
+    // Truncation, then sign or zero extension back up
+    printf("%d %d %d\n", (char)big, (unsigned char)big, (short)70000);
+    printf("%ld %lu %u\n", (long)(int)negative, (unsigned long)(unsigned int)negative, all_ones);
+    printf("%ld %d\n", (long)all_ones, (int)(signed char)200);
+
+    // Integers and pointers convert both ways
+    int x = 5;
+    long address = (long)&x;
+    int *back = (int *)address;
+    printf("%d %d\n", *back, (char *)(&x + 1) - (char *)&x);
+
+    // sizeof an expression doesn't evaluate it
+    int calls = 0;
+    unsigned long size = sizeof(calls = calls + 1);
+    printf("%lu %d\n", size, calls);
+
+    struct record r;
+    printf("%lu %lu %lu %lu\n", sizeof r, sizeof(struct record), sizeof buffer, sizeof(r.tag));
+    printf("%lu %lu %lu %lu %lu\n", sizeof(short int), sizeof(long int), sizeof(unsigned long long), sizeof(signed char), sizeof(int *));
+    return 0;
+}
@@ -0,0 +1,50 @@
+// Compound literals, several declarations in a for loop, and variable
+// length arrays
+int printf(const char *fmt, ...);
+
+struct point { int x; int y; };
+
+// Static storage at file scope
+int *primes = (int[]){2, 3, 5, 7};
+struct point *corner = &(struct point){10, 20};
+
+int length_squared (struct point p) {
+    return p.x * p.x + p.y * p.y;
+}
+
+long fill_and_sum (int n) {
+    int values[n];
+    for (int i = 0; i < n; i = i + 1) values[i] = i * i;
+    long total = 0;
+    for (int i = 0; i < n; i = i + 1) total = total + values[i];
+    return total + sizeof(values);
+}
+
+int main () {
+    printf("%d %d %d\n", primes[3], corner->y, length_squared((struct point){3, 4}));
+
+    // An automatic compound literal is an lvalue that can be changed
+    struct point *p = &(struct point){1, 2};
+    p->x = 5;
+    printf("%d %d\n", p->x, p->y);
+
+    for (int i = 0, j = 10; i < j; i = i + 3) {
+        printf("%d-%d ", i, j);
+        j = j - 3;
+    }
+    printf("\n");
+
+    int rows = 3;
+    int grid[rows][4];
+    for (int r = 0; r < rows; r = r + 1) {
+        for (int c = 0; c < 4; c = c + 1) grid[r][c] = r * 10 + c;
+    }
+    printf("%d %d %lu %lu\n", grid[2][3], grid[1][0], sizeof(grid), sizeof(grid[0]));
+
+    // Each VLA's space is given back when its scope ends, or this would
+    // run out of stack
+    long total = 0;
+    for (int i = 0; i < 20000; i = i + 1) total = total + fill_and_sum(1000);
+    printf("%ld\n", total);
+    return 0;
+}
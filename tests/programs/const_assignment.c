@@ -0,0 +1,8 @@
+// Doesn't compile: writes to a const object through a pointer to const
+const int limit = 3;
+
+int main () {
+    const int *p = &limit;
+    p[0] = 4;
+    return 0;
+}
@@ -0,0 +1,6 @@
+// Doesn't compile: the pointer would let a const object be changed
+int main () {
+    const int fixed = 1;
+    int *p = &fixed;
+    return *p;
+}
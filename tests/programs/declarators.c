@@ -0,0 +1,53 @@
+// Declarators nest, and one declaration can declare several things
+int printf(char *, ...);
+int strlen_of(char *);
+
+int strlen_of (char *s) {
+    int n = 0;
+    while (s[n]) n = n + 1;
+    return n;
+}
+
+int grid[2][3];
+
+int add (int a, int b) { return a + b; }
+int sub (int a, int b) { return a - b; }
+
+// A function taking a char and returning a pointer to a function
+int (*pick (char symbol))(int, int) {
+    if (symbol == '+') return add;
+    return sub;
+}
+
+int main (int argc, char *argv[]) {
+    for (int i = 0; i < 6; i = i + 1) grid[i / 3][i % 3] = i + 1;
+
+    int *a, b, c[2];
+    b = 7;
+    a = &b;
+    c[0] = 1;
+    c[1] = *a + 1;
+    printf("%d %d %d\n", *a, c[0], c[1]);
+
+    // A pointer to a row of the grid, not an array of pointers
+    int (*row)[3] = grid;
+    row = row + 1;
+    printf("%d %d %d\n", (*row)[0], row[0][2], row[-1][1]);
+
+    // An array of pointers
+    int *corners[2];
+    corners[0] = &grid[0][0];
+    corners[1] = &grid[1][2];
+    printf("%d %d\n", *corners[0], *corners[1]);
+
+    // Pointers to pointers, and a pointer to an array of pointers
+    int **indirect = &corners[1];
+    int *(*all)[2] = &corners;
+    printf("%d %d\n", **indirect, *(*all)[0]);
+
+    // A pointer to a function taking a char *
+    int (*measure)(char *) = strlen_of;
+    printf("%d %d\n", measure(argv[0]) > 0, measure("four"));
+    printf("%d %d\n", pick('+')(2, 3), pick('-')(2, 3));
+    return 0;
+}
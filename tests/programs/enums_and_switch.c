@@ -0,0 +1,60 @@
+// Enumerators are constants, usable in case labels and global initialisers
+int printf(char *fmt, ...);
+
+enum colour { RED, GREEN = 5, BLUE, LAST = BLUE * 2 + RED };
+enum { SIZE = GREEN + 3 };
+
+int table[SIZE];
+enum colour favourite = BLUE;
+
+char *name (enum colour c) {
+    switch (c) {
+        case RED: return "red";
+        case GREEN: return "green";
+        case BLUE: return "blue";
+        default: return "other";
+    }
+}
+
+int main () {
+    table[SIZE - 1] = LAST;
+    printf("%d %d %d %d %d\n", RED, GREEN, BLUE, LAST, table[SIZE - 1] + SIZE);
+    printf("%s %s %s\n", name(favourite), name(GREEN), name(LAST));
+
+    // Fallthrough, and a default that isn't last
+    for (int i = 0; i < 4; i = i + 1) {
+        switch (i) {
+            default:
+                printf("d");
+            case 1:
+                printf("1");
+                break;
+            case 2: {
+                int doubled = i * 2;
+                printf("%d", doubled);
+            }
+            case 3:
+                printf("3");
+        }
+        printf(" ");
+    }
+    printf("\n");
+
+    // break leaves the innermost loop or switch, continue the innermost loop
+    int total = 0;
+    for (int i = 0; i < 10; i = i + 1) {
+        if (i % 2 == 0) continue;
+        int j = 0;
+        while (1) {
+            switch (j) {
+                case 2: break;
+                default: total = total + i;
+            }
+            j = j + 1;
+            if (j > 3) break;
+        }
+        if (i > 6) break;
+    }
+    printf("%d\n", total);
+    return 0;
+}
@@ -0,0 +1,15 @@
+// Runs without libc: ass provides _start, and system calls are made directly
+long write_string (const char *s) {
+    long length = 0;
+    while (s[length]) length = length + 1;
+    // write(stdout, s, length)
+    return __builtin_syscall(1, 1, s, length);
+}
+
+int main (int argc, char **argv) {
+    long written = write_string("no libc here\n");
+    // getpid
+    if (__builtin_syscall(39) > 0) write_string("got a pid\n");
+    if (argv[0][0]) write_string("has a name\n");
+    return written + argc;
+}
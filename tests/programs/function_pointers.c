@@ -0,0 +1,70 @@
+// Callbacks, tables of functions and calls through any expression
+int printf(char *fmt, ...);
+
+// An insertion sort that takes its comparison as a callback
+int sort (int *values, int count, int (*compare)(int *, int *)) {
+    for (int i = 1; i < count; i = i + 1) {
+        int j = i;
+        while (j > 0 && compare(&values[j - 1], &values[j]) > 0) {
+            int swap = values[j];
+            values[j] = values[j - 1];
+            values[j - 1] = swap;
+            j = j - 1;
+        }
+    }
+    return count;
+}
+
+int by_value (int *a, int *b) {
+    return *a - *b;
+}
+
+int add (int a, int b) { return a + b; }
+int sub (int a, int b) { return a - b; }
+int mul (int a, int b) { return a * b; }
+
+struct operation {
+    char symbol;
+    int (*apply)(int, int);
+};
+
+struct operation operations[3];
+
+// Calls whichever function goes with the symbol
+int apply_symbol (char symbol, int a, int b) {
+    for (int i = 0; i < 3; i = i + 1) {
+        if (operations[i].symbol == symbol) return operations[i].apply(a, b);
+    }
+    return 0;
+}
+
+int twice (int (*f)(int, int), int x) {
+    return f(f(x, x), x);
+}
+
+int main () {
+    int numbers[5];
+    numbers[0] = 5;
+    numbers[1] = 3;
+    numbers[2] = 9;
+    numbers[3] = 1;
+    numbers[4] = 7;
+    sort(numbers, 5, by_value);
+    for (int i = 0; i < 5; i = i + 1) printf("%d ", numbers[i]);
+    printf("\n");
+
+    operations[0].symbol = '+';
+    operations[0].apply = add;
+    operations[1].symbol = '-';
+    operations[1].apply = sub;
+    operations[2].symbol = '*';
+    operations[2].apply = &mul;
+    for (int i = 0; i < 3; i = i + 1) {
+        printf("6 %c 3 = %d\n", operations[i].symbol, operations[i].apply(6, 3));
+    }
+
+    int (*f)(int, int) = operations[2].apply;
+    printf("%d %d %d\n", f(4, 5), (*f)(2, 3), apply_symbol('-', 10, 4));
+    printf("%d\n", twice(add, 5));
+    return 0;
+}
@@ -0,0 +1,32 @@
+// Globals initialised with addresses, strings and nested braces
+int printf(const char *fmt, ...);
+
+struct point { int x; int y; };
+struct shape { const char *name; struct point corners[2]; char flags[3]; };
+
+int numbers[5] = {10, 20, 30};
+int *third = &numbers[2];
+int *past_start = numbers + 1;
+char *greeting = "hello";
+char word[] = "word";
+char padded[8] = "ab";
+struct point origin;
+struct shape box = { "box", { {0, 0}, {4, 3} }, {1, 2} };
+struct shape *box_address = &box;
+int *box_corner_y = &box.corners[1].y;
+const char *names[] = { "zero", "one", "two" };
+long lengths[] = { sizeof(word), sizeof(names) / sizeof(names[0]) };
+
+int main () {
+    printf("%d %d %d %d\n", numbers[1], numbers[4], *third, *past_start);
+    printf("%s %s %d %d %d\n", greeting, word, (int)sizeof(padded), padded[1], padded[7]);
+    printf("%d %d\n", origin.x, origin.y);
+    printf("%s %d %d %d %d %d\n", box.name, box.corners[1].x, box.corners[1].y, box.flags[0], box.flags[2], *box_corner_y);
+    printf("%s %s %ld %ld\n", box_address->name, names[2], lengths[0], lengths[1]);
+
+    // The same works for locals, where anything left out is zeroed
+    struct shape local = { names[1], { {1, 2} } };
+    int grid[2][3] = { {1}, {4, 5} };
+    printf("%s %d %d %d %d\n", local.name, local.corners[0].y, local.corners[1].x, grid[0][2], grid[1][1]);
+    return 0;
+}
@@ -0,0 +1,35 @@
+// GCC-style asm statements with register, memory and immediate operands
+int printf(const char *fmt, ...);
+
+long counter = 5;
+
+long add_asm (long a, long b) {
+    long result;
+    asm ("mov %1, %0\n\tadd %2, %0" : "=r" (result) : "r" (a), "r" (b));
+    return result;
+}
+
+int main () {
+    printf("%ld\n", add_asm(40, 2));
+
+    // A read-write operand, and an immediate
+    int x = 10;
+    asm ("imul %1, %0" : "+r" (x) : "i" (3));
+    printf("%d\n", x);
+
+    // Memory operands, with a byte-sized register name from %b
+    asm volatile ("addq $10, %0" : "+m" (counter));
+    char low;
+    int value = 4660;
+    asm ("mov %b1, %0" : "=r" (low) : "r" (value));
+    printf("%ld %d\n", counter, low);
+
+    // Fixed registers and clobbers
+    long high;
+    long lowbits;
+    asm volatile ("rdtsc" : "=a" (lowbits), "=d" (high));
+    long quotient;
+    asm ("cqto\n\tidivq %2" : "=a" (quotient) : "a" ((long)100), "r" ((long)7) : "rdx", "cc");
+    printf("%d %ld\n", lowbits >= 0 && high >= 0, quotient);
+    return 0;
+}
@@ -0,0 +1,23 @@
+// Integer constants have the first type their value fits in, so big ones
+// aren't cut down to an int
+int printf(char *fmt, ...);
+
+int is_sum (int count, ...) {
+    va_list ap;
+    va_start(ap, count);
+    long total = 0;
+    for (int i = 0; i < count; i = i + 1) total = total + va_arg(ap, long);
+    va_end(ap);
+    return total == 1234567890124;
+}
+
+int main () {
+    printf("%ld %ld\n", 3000000000, 3000000000 + 1);
+    printf("%d\n", is_sum(2, 1234567890123, 1L));
+
+    // Hex and octal ones can be unsigned without asking
+    printf("%lu %u %d %d\n", sizeof(0xFFFFFFFF), 0xFFFFFFFF, 0x7fffffff, 010);
+    printf("%lu %ld %lu\n", sizeof(5L), 2147483647 + 1L, 18446744073709551615u);
+    printf("%lu %d\n", sizeof(3000000000), -1 < 0u);
+    return 0;
+}
@@ -0,0 +1,11 @@
+// Every case returns, but there's no default, so sign can get to its end
+int sign (int n) {
+    switch (n > 0) {
+        case 0: return -1;
+        case 1: return 1;
+    }
+}
+
+int main () {
+    return sign(3);
+}
@@ -0,0 +1,44 @@
+// const, volatile and restrict
+int printf(const char *fmt, ...);
+
+const int limit = 3;
+const char separator = '-';
+// The pointers need filling in when the program is loaded
+const char *const names[] = {"a", "bc"};
+volatile int ticks;
+
+// Each read of a volatile object happens, even if its value isn't used
+int touch_ticks () {
+    ticks;
+    ticks;
+    return 0;
+}
+
+int copy (int *restrict to, const int *restrict from, int count) {
+    for (int i = 0; i < count; i = i + 1) to[i] = from[i];
+    return count;
+}
+
+int main () {
+    const int local = limit * 2;
+    int values[3];
+    for (int i = 0; i < 3; i = i + 1) values[i] = i + 1;
+    int copied[3];
+    copy(copied, values, limit);
+
+    // A const pointer to non-const ints can still change what it points at
+    int *const fixed = copied;
+    fixed[1] = local;
+
+    volatile int spin = 0;
+    while (spin < 5) spin = spin + 1;
+    ticks = spin;
+    touch_ticks();
+
+    // Adding a qualifier is fine implicitly
+    const int *view = copied;
+    copied[2] = 9;
+
+    printf("%d %d %d %d %c %d %s\n", local, copied[0], copied[1], view[2], separator, ticks, names[1]);
+    return 0;
+}
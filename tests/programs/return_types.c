@@ -0,0 +1,54 @@
+// Return values are converted to the function's declared return type
+int printf(const char *fmt, ...);
+void *malloc(unsigned long size);
+char *strcpy(char *dest, const char *src);
+
+char low_byte (int x) {
+    return x;
+}
+
+unsigned char unsigned_low_byte (int x) {
+    return x;
+}
+
+short low_half (long x) {
+    return x;
+}
+
+long widen (int x) {
+    return x;
+}
+
+unsigned int wrap (long x) {
+    return x;
+}
+
+// Declared before it's defined, so calls use the prototype
+long long big (void);
+
+// Pointers come back whole, even from libc
+char *copy_of (const char *text) {
+    char *copy = malloc(32);
+    strcpy(copy, text);
+    return copy;
+}
+
+int main () {
+    int a = low_byte(300);
+    int b = low_byte(200);
+    int c = unsigned_low_byte(-1);
+    printf("%d %d %d\n", a, b, c);
+
+    int d = low_half(100000);
+    long e = widen(-5);
+    long f = wrap(-1);
+    printf("%d %ld %ld\n", d, e, f);
+
+    printf("%lld\n", big());
+
+    printf("%s\n", copy_of("kept"));
+}
+
+long long big (void) {
+    return 5000000000;
+}
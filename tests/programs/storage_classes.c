@@ -0,0 +1,53 @@
+// static and extern, and globals declared more than once
+int printf(char *fmt, ...);
+
+// Tentative definitions: the first two are completed by the third, and
+// the last one is never initialised, so it's zero
+int defined;
+int defined;
+int defined = 3;
+int zeroed;
+int zeroed_array[4];
+
+// Declared before it's defined further down
+extern int later;
+
+static int hidden = 10;
+
+static int bump (int by) {
+    hidden = hidden + by;
+    return hidden;
+}
+
+int next_id () {
+    // Initialised once, and keeps its value between calls
+    static int id = 100;
+    id = id + 1;
+    return id;
+}
+
+int *counter_address () {
+    static int counter;
+    counter = counter + 1;
+    return &counter;
+}
+
+int main () {
+    printf("%d %d %d %d\n", defined, zeroed, zeroed_array[3], later);
+    int first = bump(1);
+    printf("%d %d\n", first, bump(2));
+    int a = next_id();
+    int b = next_id();
+    printf("%d %d %d\n", a, b, next_id());
+    counter_address();
+    int *counter = counter_address();
+    printf("%d\n", *counter);
+    {
+        extern int later;
+        later = later + 1;
+    }
+    printf("%d\n", later);
+    return 0;
+}
+
+int later = 42;
@@ -0,0 +1,49 @@
+// typedef names are types wherever they're in scope, and can be hidden
+// by variables of the same name
+int printf(char *fmt, ...);
+
+typedef int T;
+typedef struct { int x; int y; } point;
+typedef int (*binary)(int, int);
+typedef T pair[2];
+typedef point *point_ref;
+
+int add (int a, int b) { return a + b; }
+
+T total (pair values) {
+    return values[0] + values[1];
+}
+
+int main () {
+    T y = 3;
+    // A declaration of a pointer, since T is a type here
+    T * x = &y;
+    x[0] = 4;
+    printf("%d\n", y);
+
+    point p;
+    p.x = 3;
+    p.y = 4;
+    point_ref ref = &p;
+    binary f = add;
+    pair values;
+    values[0] = 10;
+    values[1] = 20;
+    printf("%d %d %d\n", f(p.x, ref->y), total(values), f(1, 2));
+
+    {
+        // Now T is a variable, so this is a multiplication
+        int T = 6;
+        int z = T * y;
+        printf("%d\n", z);
+
+        typedef char T2;
+        T2 c = 321;
+        printf("%d\n", c);
+    }
+
+    // Back out here, T is a type again
+    T * again = x;
+    printf("%d\n", *again);
+    return 0;
+}
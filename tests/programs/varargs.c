@@ -0,0 +1,64 @@
+// Variadic functions defined in C, reading their arguments with va_arg
+int printf(char *fmt, ...);
+int vprintf(char *fmt, va_list ap);
+
+// A logging wrapper that passes its arguments on to vprintf
+int log_line (char *level, char *fmt, ...) {
+    va_list ap;
+    va_start(ap, fmt);
+    printf("[%s] ", level);
+    int printed = vprintf(fmt, ap);
+    va_end(ap);
+    printf("\n");
+    return printed;
+}
+
+// Enough arguments that some come from the stack rather than registers
+int sum (int count, ...) {
+    va_list ap;
+    va_start(ap, count);
+    int total = 0;
+    for (int i = 0; i < count; i = i + 1) {
+        total = total + va_arg(ap, int);
+    }
+    va_end(ap);
+    return total;
+}
+
+// va_copy lets the list be walked twice
+int largest_minus_first (int count, ...) {
+    va_list ap;
+    va_list again;
+    va_start(ap, count);
+    va_copy(again, ap);
+    int first = va_arg(ap, int);
+    int largest = first;
+    for (int i = 0; i < count; i = i + 1) {
+        int value = va_arg(again, int);
+        if (value > largest) largest = value;
+    }
+    va_end(again);
+    va_end(ap);
+    return largest - first;
+}
+
+char *nth_string (int n, ...) {
+    va_list ap;
+    va_start(ap, n);
+    char *s = 0;
+    for (int i = 0; i <= n; i = i + 1) {
+        s = va_arg(ap, char *);
+    }
+    va_end(ap);
+    return s;
+}
+
+int main () {
+    log_line("info", "%d + %d = %d", 1, 2, 3);
+    int printed = log_line("warn", "%s is %c%c", "nothing", 'o', 'k');
+    printf("%d\n", printed);
+    printf("%d %d\n", sum(3, 1, 2, 3), sum(10, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10));
+    printf("%d\n", largest_minus_first(4, 10, 40, 30, 20));
+    printf("%s\n", nth_string(2, "zero", "one", "two", "three"));
+    return 0;
+}
@@ -0,0 +1,6 @@
+// Only the outermost length of a variable length array can be variable
+int main (int argc, char *argv[]) {
+    int n = argc + 1;
+    int grid[4][n];
+    return 0;
+}
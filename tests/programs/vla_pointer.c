@@ -0,0 +1,6 @@
+// Pointers to variable length arrays need their size at runtime
+int main (int argc, char *argv[]) {
+    int n = argc + 1;
+    int (*row)[n];
+    return 0;
+}
@@ -0,0 +1,39 @@
+// void functions, (void) parameter lists, bare returns and void pointers
+int printf(const char *fmt, ...);
+
+int total;
+
+void add (int n) {
+    if (n < 0) return;
+    total = total + n;
+}
+
+void reset (void) {
+    total = 0;
+}
+
+// Copies through void pointers, like memcpy
+void copy_bytes (void *to, const void *from, int count) {
+    char *dest = to;
+    const char *src = from;
+    for (int i = 0; i < count; i = i + 1) dest[i] = src[i];
+}
+
+int main (void) {
+    add(5);
+    add(-3);
+    add(10);
+    printf("%d\n", total);
+    reset();
+    printf("%d\n", total);
+
+    long source[2] = {1234567890123, -7};
+    long dest[2];
+    copy_bytes(dest, source, sizeof(source));
+    void *generic = dest;
+    long *back = generic;
+    printf("%ld %ld\n", back[0], back[1]);
+
+    (void)add(1);
+    printf("%d\n", total);
+}
@@ -0,0 +1,13 @@
+// `--target riscv64-linux-gnu` output, assembled and linked with a cross
+// compiler and run under qemu's user-mode emulation, should do the same
+// as the interpreter. The portable test programs are run too, so variadic
+// functions and structs split between a7 and the stack are covered. Needs
+// riscv64-linux-gnu-gcc and qemu-riscv64, so only runs when asked for,
+// with `cargo test -- --ignored`.
+mod common;
+
+#[test]
+#[ignore = "needs riscv64-linux-gnu-gcc and qemu-riscv64"]
+fn programs_run_under_emulation () {
+    common::run_under_emulation("riscv64-linux-gnu", "qemu-riscv64");
+}